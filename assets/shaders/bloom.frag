@@ -0,0 +1,79 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform Args {
+    uint stage;
+    float threshold;
+    float intensity;
+    uint encode_srgb;
+    uint tonemap_mode;
+};
+
+layout(set = 1, binding = 0) uniform sampler bloom_sampler;
+layout(set = 1, binding = 1) uniform texture2D texture_source;
+layout(set = 1, binding = 2) uniform texture2D texture_bloom;
+
+layout(location = 0) out vec4 o_color;
+
+const uint STAGE_BRIGHT = 0;
+const uint STAGE_BLUR_HORIZONTAL = 1;
+const uint STAGE_BLUR_VERTICAL = 2;
+const uint STAGE_COMBINE = 3;
+
+// a 9 tap gaussian, the center tap first
+const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+// the sampler is unnormalized, so the coordinates are in texels of the sampled image
+vec3 sample_source(vec2 coord) {
+    return texture(sampler2D(texture_source, bloom_sampler), coord).rgb;
+}
+
+vec3 blur(vec2 direction) {
+    vec3 color = sample_source(gl_FragCoord.xy) * WEIGHTS[0];
+
+    for(int i = 1; i < 5; i++) {
+        color += sample_source(gl_FragCoord.xy + direction * float(i)) * WEIGHTS[i];
+        color += sample_source(gl_FragCoord.xy - direction * float(i)) * WEIGHTS[i];
+    }
+
+    return color;
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+const uint TONEMAP_NONE = 0;
+const uint TONEMAP_REINHARD = 1;
+const uint TONEMAP_ACES = 2;
+
+// must match tonemap in comp.frag, without the exposure the comp pass already applied
+vec3 tonemap(vec3 color) {
+    if(tonemap_mode == TONEMAP_REINHARD) {
+        return color / (1.0 + color);
+    }
+
+    if(tonemap_mode == TONEMAP_ACES) {
+        return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+    }
+
+    return color;
+}
+
+// must match BloomStage on the cpu side
+void main() {
+    if(stage == STAGE_BRIGHT) {
+        // the target has half the resolution, so this lands between 2x2 texels of the scene
+        vec3 color = sample_source(gl_FragCoord.xy * 2.0);
+        float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+        o_color = vec4(color * max(luminance - threshold, 0.0) / max(luminance, 0.0001), 1.0);
+    } else if(stage == STAGE_BLUR_HORIZONTAL) {
+        o_color = vec4(blur(vec2(1.0, 0.0)), 1.0);
+    } else if(stage == STAGE_BLUR_VERTICAL) {
+        o_color = vec4(blur(vec2(0.0, 1.0)), 1.0);
+    } else {
+        vec4 scene = texture(sampler2D(texture_source, bloom_sampler), gl_FragCoord.xy);
+        vec3 bloom = texture(sampler2D(texture_bloom, bloom_sampler), gl_FragCoord.xy * 0.5).rgb;
+        vec3 rgb = tonemap(scene.rgb + bloom * intensity);
+        o_color = encode_srgb != 0 ? vec4(linear_to_srgb(clamp(rgb, 0.0, 1.0)), scene.a) : vec4(rgb, scene.a);
+    }
+}
@@ -1,11 +1,40 @@
 #version 450
 
 layout(set = 0, binding = 0) uniform samplerCube environment;
+layout(set = 0, binding = 2) uniform samplerCube irradiance;
+
+// must match MAX_LIGHTS on the cpu side
+const uint MAX_LIGHTS = 4;
+
+struct Light {
+    vec3 color;
+    vec3 position;
+    // constant, quadratic, radius
+    vec3 attenuation;
+};
+
 layout(std140, set = 0, binding = 1) uniform Args {
     mat4 inversed_view_matrix;
+    uint normal_encoding;
+    uint encode_srgb;
+    uint alpha_mode;
+    float fresnel_strength;
+    float fresnel_f0;
+    uint override_f0;
+    uint occlusion_enabled;
+    float vignette_intensity;
+    float vignette_radius;
+    float occlusion_strength;
+    float shadow_strength;
+    uint light_count;
+    float roughness;
+    uint tonemap_mode;
+    float exposure;
+    float ambient_intensity;
     vec3 ambient;
-    vec3 light_color;
-    vec3 light_position;
+    Light lights[MAX_LIGHTS];
+    vec3 background_tone;
+    vec3 sphere_tone;
 };
 
 layout(set = 1, binding = 0) uniform sampler fullscreen_sampler;
@@ -18,7 +47,15 @@ layout(set = 1, binding = 6) uniform texture2D texture_shadow;
 
 layout(location = 0) out vec4 o_color;
 
-vec3 normal_from_unorm(vec3 normal) {
+const uint NORMAL_ENCODING_UNORM = 0;
+const uint NORMAL_ENCODING_SNORM = 1;
+
+// must match NormalEncoding on the cpu side
+vec3 decode_normal(vec3 normal) {
+    if(normal_encoding == NORMAL_ENCODING_SNORM) {
+        return normal;
+    }
+
     return vec3(
         (normal.x - 0.5) * 2.0,
         (normal.y - 0.5) * 2.0,
@@ -26,38 +63,124 @@ vec3 normal_from_unorm(vec3 normal) {
     );
 }
 
+vec3 linear_to_srgb(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+const uint ALPHA_MODE_OPAQUE = 0;
+const uint ALPHA_MODE_PREMULTIPLIED = 1;
+const uint ALPHA_MODE_STRAIGHT = 2;
+
+// must match AlphaMode on the cpu side
+vec4 background(vec3 color) {
+    if(alpha_mode == ALPHA_MODE_PREMULTIPLIED) {
+        return vec4(0.0);
+    }
+
+    if(alpha_mode == ALPHA_MODE_STRAIGHT) {
+        return vec4(color, 0.0);
+    }
+
+    return vec4(color, 1.0);
+}
+
+// 1 at the center and inside vignette_radius, so a zero intensity leaves the color untouched
+float vignette(vec2 frag_coord) {
+    vec2 uv = frag_coord / vec2(textureSize(sampler2D(texture_position, fullscreen_sampler), 0));
+    float distance_to_center = length(uv - 0.5) * sqrt(2.0);
+    return 1.0 - vignette_intensity * smoothstep(vignette_radius, 1.0, distance_to_center);
+}
+
+const uint TONEMAP_NONE = 0;
+const uint TONEMAP_REINHARD = 1;
+const uint TONEMAP_ACES = 2;
+
+// must match ToneMapMode on the cpu side
+vec3 tonemap(vec3 color) {
+    color *= exposure;
+
+    if(tonemap_mode == TONEMAP_REINHARD) {
+        return color / (1.0 + color);
+    }
+
+    if(tonemap_mode == TONEMAP_ACES) {
+        return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+    }
+
+    return color;
+}
+
+vec4 encode_output(vec4 color) {
+    vec3 rgb = tonemap(color.rgb);
+    return encode_srgb != 0 ? vec4(linear_to_srgb(clamp(rgb, 0.0, 1.0)), color.a) : vec4(rgb, color.a);
+}
+
+// must match Attenuation on the cpu side
+float attenuation(vec3 params, float squared_distance) {
+    float factor = 1.0 / (params.x + params.y * squared_distance);
+
+    // fades out smoothly until the light reaches zero at its radius
+    if(params.z > 0.0) {
+        float ratio = squared_distance / (params.z * params.z);
+        float window = clamp(1.0 - ratio * ratio, 0.0, 1.0);
+        factor *= window * window;
+    }
+
+    return factor;
+}
+
 void main() {
     vec2 frag_coord = gl_FragCoord.xy;
 
     vec3 position = texture(sampler2D(texture_position, fullscreen_sampler), frag_coord).xyz;
-    vec3 normal =  normal_from_unorm(texture(sampler2D(texture_normal, fullscreen_sampler), frag_coord).xyz);
+    vec3 normal =  decode_normal(texture(sampler2D(texture_normal, fullscreen_sampler), frag_coord).xyz);
     vec3 color = texture(sampler2D(texture_color, fullscreen_sampler), frag_coord).rgb;
     float n = texture(sampler2D(texture_n, fullscreen_sampler), frag_coord).r;
-    float occlusion = texture(sampler2D(texture_occlusion, fullscreen_sampler), frag_coord).r;
+    // nothing writes the occlusion image without dfao passes
+    float occlusion = occlusion_enabled != 0 ? texture(sampler2D(texture_occlusion, fullscreen_sampler), frag_coord).r : 1.0;
     float shadow = texture(sampler2D(texture_shadow, fullscreen_sampler), frag_coord).r;
 
+    occlusion = mix(1.0, occlusion, occlusion_strength);
+    shadow = mix(1.0, shadow, shadow_strength);
+
     float normal_length = dot(normal, normal);
 
     if(normal_length > 0.1) {
         normal = normal / inversesqrt(normal_length);
         vec3 camera_dir = normalize(position);
 
-        vec3 light_dir = light_position - position;
-        float squared_length_light_dir = dot(light_dir, light_dir);
-        vec3 normalized_light_dir = light_dir * inversesqrt(squared_length_light_dir);
-        float light_dot = dot(normalized_light_dir, normal);
-        vec3 diffuse = light_color / squared_length_light_dir * max(light_dot, 0.0);
+        vec3 diffuse = vec3(0.0);
+        float key_light_dot = 0.0;
+
+        for(uint i = 0; i < min(light_count, MAX_LIGHTS); i++) {
+            vec3 light_dir = lights[i].position - position;
+            float squared_length_light_dir = dot(light_dir, light_dir);
+            float light_dot = dot(light_dir * inversesqrt(squared_length_light_dir), normal);
+            vec3 light_diffuse = lights[i].color * attenuation(lights[i].attenuation, squared_length_light_dir) * max(light_dot, 0.0);
+
+            // only the key light casts shadows, the others are darkened by the occlusion instead
+            if(i == 0) {
+                key_light_dot = light_dot;
+                diffuse += light_diffuse * shadow;
+            } else {
+                diffuse += light_diffuse * occlusion;
+            }
+        }
 
         float dot = dot(camera_dir, normal);
         float r = (1.0 - n) / (1.0 + n);
-        float r2 = r*r;
-        float shlick = r2 + (1.0 - r2) * pow(1.0 + dot, 5.0);
+        float r2 = override_f0 != 0 ? fresnel_f0 : r*r;
+        float shlick = (r2 + (1.0 - r2) * pow(1.0 + dot, 5.0)) * fresnel_strength;
+        vec3 world_normal = (inversed_view_matrix * vec4(normal, 0)).xyz;
+        vec3 ambient_diffuse = ambient * ambient_intensity * texture(irradiance, world_normal).rgb;
         vec3 reflection_dir = (inversed_view_matrix * vec4(camera_dir + normal * (-2.0 * dot), 0)).xyz;
 
-        vec3 reflection = texture(environment, reflection_dir).xyz;
+        // the mip levels of the environment map stand in for a roughness prefiltered chain
+        float lod = roughness * float(textureQueryLevels(environment) - 1);
+        vec3 reflection = textureLod(environment, reflection_dir, lod).xyz;
 
-        o_color = vec4(mix((ambient * occlusion + diffuse * shadow) * color, reflection * occlusion * (light_dot > 0.1 ? shadow : 1.0), clamp(shlick, 0.0, 1.0)), 1.0);
+        o_color = encode_output(vec4(mix((ambient_diffuse * occlusion + diffuse) * color, reflection * occlusion * (key_light_dot > 0.1 ? shadow : 1.0), clamp(shlick, 0.0, 1.0)) * sphere_tone * vignette(frag_coord), 1.0));
     } else {
-        o_color = vec4(texture(environment, vec3(0, 0, 1)).xyz, 1.0);
+        o_color = encode_output(background(texture(environment, vec3(0, 0, 1)).xyz * background_tone * vignette(frag_coord)));
     }
 }
\ No newline at end of file
@@ -3,6 +3,7 @@
 layout(std140, set = 0, binding = 0) uniform Args {
     mat4 projection_matrix;
     float offset;
+    uint normal_encoding;
 };
 
 layout(set = 1, binding = 0) uniform sampler fullscreen_sampler;
@@ -14,7 +15,15 @@ layout(location = 1) in float frag_radius;
 
 layout(location = 0) out vec4 out_distance;
 
-vec3 normal_from_unorm(vec3 normal) {
+const uint NORMAL_ENCODING_UNORM = 0;
+const uint NORMAL_ENCODING_SNORM = 1;
+
+// must match NormalEncoding on the cpu side
+vec3 decode_normal(vec3 normal) {
+    if(normal_encoding == NORMAL_ENCODING_SNORM) {
+        return normal;
+    }
+
     return vec3(
         (normal.x - 0.5) * 2.0,
         (normal.y - 0.5) * 2.0,
@@ -24,7 +33,7 @@ vec3 normal_from_unorm(vec3 normal) {
 
 void main() {
     vec3 pos = texture(sampler2D(texture_position, fullscreen_sampler), gl_FragCoord.xy).xyz;
-    vec3 norm = normal_from_unorm(texture(sampler2D(texture_normal, fullscreen_sampler), gl_FragCoord.xy).xyz);
+    vec3 norm = decode_normal(texture(sampler2D(texture_normal, fullscreen_sampler), gl_FragCoord.xy).xyz);
 
     out_distance = vec4(distance(pos + norm * offset, frag_center) - frag_radius, 0.0, 0.0, 0.0);
 }
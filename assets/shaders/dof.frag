@@ -0,0 +1,64 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform Args {
+    float focal_distance;
+    float aperture;
+};
+
+layout(set = 1, binding = 0) uniform sampler fullscreen_sampler;
+layout(set = 1, binding = 1) uniform texture2D texture_color;
+layout(set = 1, binding = 2) uniform texture2D texture_position;
+
+layout(location = 0) out vec4 o_color;
+
+// the largest blur radius in pixels, bounds the samples taken per pixel
+const float MAX_RADIUS = 16.0;
+// how densely the spiral is sampled, the distance between the rings in pixels
+const float RING_SPACING = 1.0;
+const float GOLDEN_ANGLE = 2.39996323;
+const float INFINITE_DEPTH = 1e30;
+
+// the depth along the view direction, the background is cleared to 0 and counts as infinitely
+// far away
+float depth(vec2 coord) {
+    float z = texture(sampler2D(texture_position, fullscreen_sampler), coord).z;
+    return z < 0.0 ? -z : INFINITE_DEPTH;
+}
+
+// the radius of the circle of confusion in pixels
+float blur_radius(float depth) {
+    return min(aperture * abs(1.0 - focal_distance / depth), MAX_RADIUS);
+}
+
+void main() {
+    vec2 frag_coord = gl_FragCoord.xy;
+
+    vec4 center_color = texture(sampler2D(texture_color, fullscreen_sampler), frag_coord);
+    float center_depth = depth(frag_coord);
+    float center_radius = blur_radius(center_depth);
+
+    vec4 color = center_color;
+    float total = 1.0;
+    float radius = RING_SPACING;
+
+    // gathers along a spiral, samples count by how far their own blur reaches, so blurred
+    // foreground bleeds over sharp background but not the other way around
+    for(float angle = 0.0; radius < MAX_RADIUS; angle += GOLDEN_ANGLE) {
+        vec2 coord = frag_coord + vec2(cos(angle), sin(angle)) * radius;
+
+        vec4 sample_color = texture(sampler2D(texture_color, fullscreen_sampler), coord);
+        float sample_depth = depth(coord);
+        float sample_radius = blur_radius(sample_depth);
+
+        if(sample_depth > center_depth) {
+            sample_radius = clamp(sample_radius, 0.0, center_radius * 2.0);
+        }
+
+        float weight = smoothstep(radius - 0.5, radius + 0.5, sample_radius);
+        color += mix(color / total, sample_color, weight);
+        total += 1.0;
+        radius += RING_SPACING / radius;
+    }
+
+    o_color = color / total;
+}
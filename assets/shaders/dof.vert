@@ -0,0 +1,7 @@
+#version 430
+
+layout(location = 0) in vec3 a_pos;
+
+void main() {
+    gl_Position = vec4(a_pos, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,138 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform Args {
+    float edge_threshold;
+    float edge_threshold_min;
+    float subpixel;
+    uint search_steps;
+    uint linear_input;
+    uint decode_srgb;
+};
+
+layout(set = 1, binding = 0) uniform sampler fullscreen_sampler;
+layout(set = 1, binding = 1) uniform texture2D texture_color;
+
+layout(location = 0) out vec4 o_color;
+
+// must be as long as MAX_SEARCH_STEPS on the cpu side
+const float STEP_SIZES[12] = float[](1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+// the sampler is unnormalized, so the coordinates are in pixels
+vec4 sample_color(vec2 coord) {
+    return texture(sampler2D(texture_color, fullscreen_sampler), coord);
+}
+
+float luma(vec2 coord) {
+    vec3 color = sample_color(coord).rgb;
+
+    // linear colors are roughly brought into perceptual space, which the thresholds are made for
+    if(linear_input != 0) {
+        color = sqrt(clamp(color, 0.0, 1.0));
+    }
+
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+vec4 fxaa(vec2 pos) {
+    vec4 color_center = sample_color(pos);
+
+    float luma_center = luma(pos);
+    float luma_down = luma(pos + vec2(0.0, -1.0));
+    float luma_up = luma(pos + vec2(0.0, 1.0));
+    float luma_left = luma(pos + vec2(-1.0, 0.0));
+    float luma_right = luma(pos + vec2(1.0, 0.0));
+
+    float luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    float luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    float luma_range = luma_max - luma_min;
+
+    if(luma_range < max(edge_threshold_min, luma_max * edge_threshold)) {
+        return color_center;
+    }
+
+    float luma_down_left = luma(pos + vec2(-1.0, -1.0));
+    float luma_up_right = luma(pos + vec2(1.0, 1.0));
+    float luma_up_left = luma(pos + vec2(-1.0, 1.0));
+    float luma_down_right = luma(pos + vec2(1.0, -1.0));
+
+    float luma_down_up = luma_down + luma_up;
+    float luma_left_right = luma_left + luma_right;
+    float luma_left_corners = luma_down_left + luma_up_left;
+    float luma_down_corners = luma_down_left + luma_down_right;
+    float luma_right_corners = luma_down_right + luma_up_right;
+    float luma_up_corners = luma_up_right + luma_up_left;
+
+    float edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    float edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    bool horizontal = edge_horizontal >= edge_vertical;
+
+    // the neighbours across the edge, the steeper side is the one the edge lies on
+    float luma_1 = horizontal ? luma_down : luma_left;
+    float luma_2 = horizontal ? luma_up : luma_right;
+    float gradient_1 = luma_1 - luma_center;
+    float gradient_2 = luma_2 - luma_center;
+    bool steepest_1 = abs(gradient_1) >= abs(gradient_2);
+    float gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    float step_length = steepest_1 ? -1.0 : 1.0;
+    float luma_local_average = 0.5 * ((steepest_1 ? luma_1 : luma_2) + luma_center);
+
+    // walks along the edge in both directions until the luma differs from the edge
+    vec2 edge_pos = pos + (horizontal ? vec2(0.0, step_length * 0.5) : vec2(step_length * 0.5, 0.0));
+    vec2 offset = horizontal ? vec2(1.0, 0.0) : vec2(0.0, 1.0);
+
+    vec2 pos_1 = edge_pos - offset * STEP_SIZES[0];
+    vec2 pos_2 = edge_pos + offset * STEP_SIZES[0];
+    float luma_end_1 = luma(pos_1) - luma_local_average;
+    float luma_end_2 = luma(pos_2) - luma_local_average;
+    bool reached_1 = abs(luma_end_1) >= gradient_scaled;
+    bool reached_2 = abs(luma_end_2) >= gradient_scaled;
+
+    for(uint i = 1; i < search_steps && !(reached_1 && reached_2); i++) {
+        if(!reached_1) {
+            pos_1 -= offset * STEP_SIZES[i];
+            luma_end_1 = luma(pos_1) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+
+        if(!reached_2) {
+            pos_2 += offset * STEP_SIZES[i];
+            luma_end_2 = luma(pos_2) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+    }
+
+    float distance_1 = horizontal ? pos.x - pos_1.x : pos.y - pos_1.y;
+    float distance_2 = horizontal ? pos_2.x - pos.x : pos_2.y - pos.y;
+    bool direction_1 = distance_1 < distance_2;
+    float distance_final = min(distance_1, distance_2);
+    float edge_length = distance_1 + distance_2;
+
+    // only blends if the luma at the closer end varies the way the center does
+    bool center_smaller = luma_center < luma_local_average;
+    bool correct_variation = ((direction_1 ? luma_end_1 : luma_end_2) < 0.0) != center_smaller;
+    float pixel_offset = correct_variation ? 0.5 - distance_final / edge_length : 0.0;
+
+    float luma_average = (2.0 * (luma_down_up + luma_left_right) + luma_left_corners + luma_right_corners) / 12.0;
+    float subpixel_offset_1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    float subpixel_offset_2 = (-2.0 * subpixel_offset_1 + 3.0) * subpixel_offset_1 * subpixel_offset_1;
+    float subpixel_offset = subpixel_offset_2 * subpixel_offset_2 * subpixel;
+
+    float final_offset = max(pixel_offset, subpixel_offset) * step_length;
+
+    return sample_color(pos + (horizontal ? vec2(0.0, final_offset) : vec2(final_offset, 0.0)));
+}
+
+void main() {
+    vec4 color = fxaa(gl_FragCoord.xy);
+
+    o_color = decode_srgb != 0 ? vec4(srgb_to_linear(color.rgb), color.a) : color;
+}
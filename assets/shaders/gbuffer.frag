@@ -1,5 +1,13 @@
 #version 430
 
+layout(std140, binding = 0) uniform Args {
+    mat4 proj;
+    uint normal_encoding;
+};
+
+const uint NORMAL_ENCODING_UNORM = 0;
+const uint NORMAL_ENCODING_SNORM = 1;
+
 layout(location = 0) in vec3 frag_pos;
 layout(location = 1) in vec3 frag_norm;
 layout(location = 2) in vec3 frag_color;
@@ -10,7 +18,12 @@ layout(location = 1) out vec4 out_norm;
 layout(location = 2) out vec4 out_color;
 layout(location = 3) out vec4 out_n;
 
-vec3 normal_to_unorm(vec3 normal) {
+// must match NormalEncoding on the cpu side
+vec3 encode_normal(vec3 normal) {
+    if(normal_encoding == NORMAL_ENCODING_SNORM) {
+        return normal;
+    }
+
     return vec3(
         (normal.x * 0.5) + 0.5,
         (normal.y * 0.5) + 0.5,
@@ -20,7 +33,7 @@ vec3 normal_to_unorm(vec3 normal) {
 
 void main() {
     out_pos = vec4(frag_pos, 1.0);
-    out_norm = vec4(normal_to_unorm(normalize(frag_norm)), 1.0);
+    out_norm = vec4(encode_normal(normalize(frag_norm)), 1.0);
     out_color = vec4(frag_color, 1.0);
     out_n = vec4(frag_n, 1.0, 1.0, 1.0);
 }
@@ -2,6 +2,7 @@
 
 layout(std140, binding = 0) uniform Args {
     mat4 proj;
+    uint normal_encoding;
 };
 
 layout(location = 0) in vec3 a_pos;
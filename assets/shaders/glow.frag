@@ -0,0 +1,26 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform Args {
+    mat4 projection_matrix;
+    uint encode_srgb;
+};
+
+layout(location = 0) in vec2 frag_uv;
+layout(location = 1) in vec3 frag_color;
+
+layout(location = 0) out vec4 o_color;
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+void main() {
+    float falloff = 1.0 - clamp(length(frag_uv), 0.0, 1.0);
+    vec3 color = frag_color * falloff * falloff;
+
+    if(encode_srgb != 0) {
+        color = linear_to_srgb(color);
+    }
+
+    o_color = vec4(color, falloff * falloff);
+}
@@ -0,0 +1,22 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform Args {
+    mat4 projection_matrix;
+    uint encode_srgb;
+};
+
+layout(location = 0) in vec3 a_pos;
+layout(location = 1) in vec3 sphere_center;
+layout(location = 2) in float sphere_radius;
+layout(location = 3) in vec3 sphere_color;
+
+layout(location = 0) out vec2 frag_uv;
+layout(location = 1) out vec3 frag_color;
+
+void main() {
+    frag_uv = a_pos.xy;
+    frag_color = sphere_color;
+
+    // the quad lies in the view plane, so it always faces the camera
+    gl_Position = projection_matrix * vec4(sphere_center + a_pos * sphere_radius, 1.0);
+}
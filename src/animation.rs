@@ -147,20 +147,101 @@ impl<T: State> ApplyFactor<T> for LerpFactorGenerator {
     }
 }
 
+/// The indices of the keyframes before and after `frame` and how far `frame` is between them,
+/// from 0 at the first to 1 at the last. Outside of the keyframes both indices are the nearest
+/// keyframe.
+fn segment<T: State>(frame: &Frame, keyframes: &[Keyframe<T>]) -> (usize, usize, f32) {
+    let option_last = keyframes
+        .iter()
+        .position(|keyframe| keyframe.frame().frame() >= frame.frame());
+    let option_first = keyframes
+        .iter()
+        .rposition(|keyframe| keyframe.frame().frame() <= frame.frame());
+
+    match (option_first, option_last) {
+        (None, None) => unreachable!("This should never happen"),
+        (Some(first), None) => (first, first, 0.0),
+        (None, Some(last)) => (last, last, 0.0),
+        (Some(first), Some(last)) => {
+            let first_frame = keyframes[first].frame().frame();
+            let last_frame = keyframes[last].frame().frame();
+
+            let fact = if first_frame != last_frame {
+                (frame.frame() - first_frame) / (last_frame - first_frame)
+            } else {
+                0.0
+            };
+
+            (first, last, fact)
+        }
+    }
+}
+
+/// Blends between the two bounding keyframes like a lerp, but eases in and out of every keyframe
+pub struct SmoothstepFactorGenerator;
+
+impl<T: State> ApplyFactor<T> for SmoothstepFactorGenerator {
+    fn apply_factors(&self, frame: Frame, keyframes: &[Keyframe<T>]) -> T {
+        let (first, last, fact) = segment(&frame, keyframes);
+
+        let fact = fact * fact * (3.0 - 2.0 * fact);
+
+        T::weigth_sum_slice(keyframes, &[(first, 1.0 - fact), (last, fact)])
+    }
+}
+
+/// Catmull-Rom spline through the keyframes. Uses the keyframes around the two bounding ones as
+/// tangents, the first and last keyframe are repeated at the boundaries.
+pub struct CubicFactorGenerator;
+
+impl<T: State> ApplyFactor<T> for CubicFactorGenerator {
+    fn apply_factors(&self, frame: Frame, keyframes: &[Keyframe<T>]) -> T {
+        let (first, last, t) = segment(&frame, keyframes);
+
+        let before = first.saturating_sub(1);
+        let after = (last + 1).min(keyframes.len() - 1);
+
+        let t2 = t * t;
+        let t3 = t2 * t;
+
+        T::weigth_sum_slice(
+            keyframes,
+            &[
+                (before, 0.5 * (-t3 + 2.0 * t2 - t)),
+                (first, 0.5 * (3.0 * t3 - 5.0 * t2 + 2.0)),
+                (last, 0.5 * (-3.0 * t3 + 4.0 * t2 + t)),
+                (after, 0.5 * (t3 - t2)),
+            ],
+        )
+    }
+}
+
 pub enum DynFactorGenerator {
     Lerp(LerpFactorGenerator),
+    Smoothstep(SmoothstepFactorGenerator),
+    Cubic(CubicFactorGenerator),
 }
 
 impl DynFactorGenerator {
     pub fn lerp() -> DynFactorGenerator {
         DynFactorGenerator::Lerp(LerpFactorGenerator)
     }
+
+    pub fn smoothstep() -> DynFactorGenerator {
+        DynFactorGenerator::Smoothstep(SmoothstepFactorGenerator)
+    }
+
+    pub fn cubic() -> DynFactorGenerator {
+        DynFactorGenerator::Cubic(CubicFactorGenerator)
+    }
 }
 
 impl<T: State> ApplyFactor<T> for DynFactorGenerator {
     fn apply_factors(&self, frame: Frame, keyframes: &[Keyframe<T>]) -> T {
         match self {
             DynFactorGenerator::Lerp(fact_gen) => fact_gen.apply_factors(frame, keyframes),
+            DynFactorGenerator::Smoothstep(fact_gen) => fact_gen.apply_factors(frame, keyframes),
+            DynFactorGenerator::Cubic(fact_gen) => fact_gen.apply_factors(frame, keyframes),
         }
     }
 }
@@ -171,6 +252,18 @@ impl From<LerpFactorGenerator> for DynFactorGenerator {
     }
 }
 
+impl From<SmoothstepFactorGenerator> for DynFactorGenerator {
+    fn from(value: SmoothstepFactorGenerator) -> Self {
+        DynFactorGenerator::Smoothstep(value)
+    }
+}
+
+impl From<CubicFactorGenerator> for DynFactorGenerator {
+    fn from(value: CubicFactorGenerator) -> Self {
+        DynFactorGenerator::Cubic(value)
+    }
+}
+
 pub trait LoopingFunction {
     fn loop_value(&self, value: Frame) -> Frame;
 }
@@ -199,9 +292,32 @@ impl LoopingFunction for LoopRepeat {
     }
 }
 
+/// Plays the animation forward and then backward, so it returns to its start every `2 * len`
+pub struct LoopPingPong {
+    len: f32,
+}
+
+impl LoopPingPong {
+    pub fn new(len: f32) -> Self {
+        LoopPingPong { len }
+    }
+}
+
+impl LoopingFunction for LoopPingPong {
+    fn loop_value(&self, value: Frame) -> Frame {
+        let value = value.frame().rem_euclid(2.0 * self.len);
+        if value > self.len {
+            Frame::new(2.0 * self.len - value)
+        } else {
+            Frame::new(value)
+        }
+    }
+}
+
 pub enum DynLoopingFunction {
     Empty(LoopEmpty),
     Repeat(LoopRepeat),
+    PingPong(LoopPingPong),
 }
 
 impl DynLoopingFunction {
@@ -212,6 +328,10 @@ impl DynLoopingFunction {
     pub fn repeat(len: f32) -> DynLoopingFunction {
         DynLoopingFunction::Repeat(LoopRepeat::new(len))
     }
+
+    pub fn ping_pong(len: f32) -> DynLoopingFunction {
+        DynLoopingFunction::PingPong(LoopPingPong::new(len))
+    }
 }
 
 impl LoopingFunction for DynLoopingFunction {
@@ -219,6 +339,7 @@ impl LoopingFunction for DynLoopingFunction {
         match self {
             DynLoopingFunction::Empty(func) => func.loop_value(value),
             DynLoopingFunction::Repeat(func) => func.loop_value(value),
+            DynLoopingFunction::PingPong(func) => func.loop_value(value),
         }
     }
 }
@@ -234,3 +355,90 @@ impl From<LoopRepeat> for DynLoopingFunction {
         DynLoopingFunction::Repeat(value)
     }
 }
+
+impl From<LoopPingPong> for DynLoopingFunction {
+    fn from(value: LoopPingPong) -> Self {
+        DynLoopingFunction::PingPong(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframes() -> Vec<Keyframe<f32>> {
+        vec![
+            Keyframe::new(Frame::new(0.0), 0.0),
+            Keyframe::new(Frame::new(2.0), 1.0),
+            Keyframe::new(Frame::new(6.0), 5.0),
+        ]
+    }
+
+    fn interpolate<F: ApplyFactor<f32>>(factors: F, frame: f32) -> f32 {
+        Animation::with_times(keyframes(), LoopEmpty, factors).interpolate(Frame::new(frame))
+    }
+
+    #[test]
+    fn segment_bounds() {
+        let keyframes = keyframes();
+
+        assert_eq!(segment(&Frame::new(2.0), &keyframes), (1, 1, 0.0));
+        assert_eq!(segment(&Frame::new(4.0), &keyframes), (1, 2, 0.5));
+        assert_eq!(segment(&Frame::new(5.0), &keyframes), (1, 2, 0.75));
+        assert_eq!(segment(&Frame::new(-1.0), &keyframes), (0, 0, 0.0));
+        assert_eq!(segment(&Frame::new(10.0), &keyframes), (2, 2, 0.0));
+    }
+
+    #[test]
+    fn smoothstep_interpolation() {
+        for (frame, expected) in &[(0.0, 0.0), (2.0, 1.0), (6.0, 5.0)] {
+            assert_approx_eq!(interpolate(SmoothstepFactorGenerator, *frame), *expected);
+        }
+
+        assert_approx_eq!(interpolate(SmoothstepFactorGenerator, 4.0), 3.0);
+        assert_approx_eq!(interpolate(SmoothstepFactorGenerator, 3.0), 1.625);
+
+        assert_approx_eq!(interpolate(SmoothstepFactorGenerator, -1.0), 0.0);
+        assert_approx_eq!(interpolate(SmoothstepFactorGenerator, 10.0), 5.0);
+    }
+
+    #[test]
+    fn cubic_interpolation() {
+        for (frame, expected) in &[(0.0, 0.0), (2.0, 1.0), (6.0, 5.0)] {
+            assert_approx_eq!(interpolate(CubicFactorGenerator, *frame), *expected);
+        }
+
+        assert_approx_eq!(interpolate(CubicFactorGenerator, -1.0), 0.0);
+        assert_approx_eq!(interpolate(CubicFactorGenerator, 10.0), 5.0);
+
+        // with evenly spaced keyframes the tangents are exact for a parabola, so the spline
+        // follows it between the keyframes
+        let parabola = Animation::without_times(
+            vec![0.0, 1.0, 4.0, 9.0, 16.0],
+            LoopEmpty,
+            CubicFactorGenerator,
+        );
+
+        for frame in &[1.5, 2.25, 2.5] {
+            assert_approx_eq!(parabola.interpolate(Frame::new(*frame)), frame * frame);
+        }
+    }
+
+    #[test]
+    fn ping_pong_reflects() {
+        let looping = LoopPingPong::new(10.0);
+        let loop_value = |frame| looping.loop_value(Frame::new(frame)).frame();
+
+        assert_eq!(loop_value(0.0), 0.0);
+        assert_eq!(loop_value(3.0), 3.0);
+        assert_eq!(loop_value(10.0), 10.0);
+        assert_eq!(loop_value(13.0), 7.0);
+        assert_eq!(loop_value(20.0), 0.0);
+        assert_eq!(loop_value(25.0), 5.0);
+
+        // frames before the start continue the same back and forth
+        assert_eq!(loop_value(-3.0), 3.0);
+        assert_eq!(loop_value(-10.0), 10.0);
+        assert_eq!(loop_value(-13.0), 7.0);
+    }
+}
@@ -1,64 +1,138 @@
 use crate::audio::{OptionCaptureSource, SamplesBundle};
 use crate::bundle::{Bundle, BundleGroup};
-use crate::physics::PhysicsBundle;
-use crate::world::camera::CameraBundle;
-use crate::world::color_ramp::ColorRamp;
+use crate::graph::node::capture::CapturePool;
+use crate::physics::{physics_system, PhysicsBundle, PhysicsPreRollBundle};
+use crate::prelude::{
+    BloomParams, ColorRamp, DebugRender, DepthOfField, Light, PostEffects, Resolution, Shading,
+    SphereMeshParams, Tonemapping,
+};
+use crate::world::beat::{beat_detection_system, BeatDetector, BeatEvent};
+use crate::world::camera::{CameraBundle, OrbitCamera, Projection};
 use crate::world::environment::EnvironmentBundle;
-use crate::world::light::Light;
-use crate::world::resolution::Resolution;
-use crate::world::sphere::{LoadMode, SphereBundle, SphereBundleParams};
+use crate::world::input::Input;
+use crate::world::scene::SceneDescription;
+use crate::world::sphere::{
+    AgcParams, Analyzer, BandSplit, Layout, LoadMode, RestShape, SphereBundle, SphereBundleParams,
+};
+use crate::world::time::TimeBundle;
 use crate::Mode;
 use crate::ENVIRONMENT_MAP_PATH;
 use anyhow::Error;
-use nalgebra_glm::{identity, pi, translate, vec3};
+use nalgebra_glm::{vec3, Vec3};
 use rendy::command::{Families, Graphics};
 use rendy::factory::Factory;
 use rendy::hal::Backend;
 use rendy::init::winit::window::Window;
 use rodio::{Sample, Source};
-use std::path::Path;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 
+#[derive(Serialize)]
 pub enum ApplicationBundleParams<P> {
     Load {
         path: P,
         load_mode: LoadMode,
+        color_ramp: Option<P>,
+        spring_factor: Option<f32>,
+        damping: f32,
+        seed: Option<u64>,
+        layout: Layout,
+        physics: bool,
     },
     Analyze {
         sphere_count: usize,
         min_radius: f32,
-        low: f32,
-        high: f32,
+        rest_shape: RestShape,
+        analyzer: Analyzer,
+        band_split: BandSplit,
+        stereo: bool,
+        agc: Option<AgcParams>,
         attack: f32,
         release: f32,
         threshold: f32,
+        smoothing: f32,
+        analysis_log: Option<PathBuf>,
+        color_ramp: Option<P>,
+        spring_factor: Option<f32>,
+        damping: f32,
+        seed: Option<u64>,
+        layout: Layout,
+        physics: bool,
     },
 }
 
 impl<P: AsRef<Path>> ApplicationBundleParams<P> {
+    /// The json file the color ramp is loaded from, `None` for the built in ramp
+    pub fn color_ramp(&self) -> Option<&P> {
+        match self {
+            ApplicationBundleParams::Load { color_ramp, .. }
+            | ApplicationBundleParams::Analyze { color_ramp, .. } => color_ramp.as_ref(),
+        }
+    }
+
+    /// `false` keeps the spheres at their layout positions even in the modes simulating them
+    pub fn physics(&self) -> bool {
+        match self {
+            ApplicationBundleParams::Load { physics, .. }
+            | ApplicationBundleParams::Analyze { physics, .. } => *physics,
+        }
+    }
+
     pub fn sphere_bundle_params(self, sample_rate: f32, mode: Mode) -> SphereBundleParams<P> {
         match self {
-            ApplicationBundleParams::Load { path, load_mode } => SphereBundleParams::Load {
+            ApplicationBundleParams::Load {
+                path,
+                load_mode,
+                spring_factor,
+                damping,
+                seed,
+                layout,
+                ..
+            } => SphereBundleParams::Load {
                 path,
                 load_mode,
                 mode,
+                spring_factor,
+                damping,
+                seed,
+                layout,
             },
             ApplicationBundleParams::Analyze {
                 sphere_count,
                 min_radius,
-                low,
-                high,
+                rest_shape,
+                analyzer,
+                band_split,
+                stereo,
+                agc,
                 attack,
                 release,
                 threshold,
+                smoothing,
+                analysis_log,
+                spring_factor,
+                damping,
+                seed,
+                layout,
+                ..
             } => SphereBundleParams::Analyze {
                 sphere_count,
                 min_radius,
-                low,
-                high,
+                rest_shape,
+                analyzer,
+                band_split,
+                stereo,
+                agc,
                 attack,
                 release,
                 threshold,
+                smoothing,
                 sample_rate,
+                analysis_log,
+                spring_factor,
+                damping,
+                seed,
+                layout,
             },
         }
     }
@@ -71,7 +145,14 @@ pub fn application_bundle<B: Backend, P: 'static + AsRef<Path>, S: Source>(
     window: Option<Window>,
     application_bundle_params: ApplicationBundleParams<P>,
     mode: Mode,
+    fps: f32,
     source: S,
+    physics_pre_roll: usize,
+    gravity: Vec3,
+    aspect: Option<f32>,
+    orbit: Option<OrbitCamera>,
+    projection: Option<Projection>,
+    scene: &SceneDescription,
 ) -> Result<(impl Bundle, OptionCaptureSource<S>), Error>
 where
     S::Item: Sample,
@@ -89,62 +170,109 @@ where
     application_bundle.add_resource(resolution);
     if let Some(window) = window {
         application_bundle.add_resource(window);
+        application_bundle.add_resource(Input::new());
+    }
+
+    application_bundle.add_bundle(TimeBundle::new(mode, fps));
+
+    let mut camera_bundle = CameraBundle::new(
+        scene.camera.view_matrix()?,
+        scene.camera.fov(),
+        scene.camera.near(),
+        scene.camera.far(),
+    )
+    .with_aspect(aspect)
+    .with_orbit(orbit);
+
+    if let Some(projection) = projection {
+        camera_bundle = camera_bundle.with_projection(projection);
     }
 
-    let camera_transform = translate(&identity(), &vec3(0.0, 0.0, -10.0));
+    application_bundle.add_bundle(camera_bundle);
 
-    application_bundle.add_bundle(CameraBundle::new(
-        camera_transform,
-        pi::<f32>() / 2.0,
-        0.1,
-        1000.0,
-    ));
+    let light = scene.light.light();
 
-    let light = Light::new(vec3(-10.0, 10.0, 10.0), vec3(400.0, 400.0, 400.0));
+    let fill_light =
+        Light::new(vec3(10.0, 5.0, 10.0), vec3(400.0, 400.0, 400.0)).with_intensity(0.25);
 
-    let ambient_light = vec3(1.0, 1.0, 1.0f32);
+    let ambient_light = scene.ambient();
 
-    application_bundle.add_bundle(EnvironmentBundle::<_, B>::new(
+    let mut environment_bundle = EnvironmentBundle::<_, B>::new(
         ambient_light,
         light,
         ENVIRONMENT_MAP_PATH.clone(),
         graphics_queue,
-    ));
+    )
+    .with_light(fill_light);
 
-    let color_ramp = ColorRamp::new(vec![
-        vec3(0.0, 0.0, 0.0),
-        vec3(0.0, 0.0, 0.0),
-        vec3(0.5, 0.0, 1.0),
-        vec3(0.0, 0.0, 1.0),
-        vec3(0.0, 0.5, 1.0),
-        vec3(0.0, 0.1, 1.0),
-    ]);
+    if let Some(light_animation) = &scene.light_animation {
+        environment_bundle = environment_bundle
+            .with_light_animation(light_animation.light_animation(&scene.light)?, mode);
+    }
+
+    application_bundle.add_bundle(environment_bundle);
+
+    application_bundle.add_resource(Shading::default());
+    application_bundle.add_resource(PostEffects::default());
+    application_bundle.add_resource(Tonemapping::default());
+    application_bundle.add_resource(BloomParams::default());
+    application_bundle.add_resource(DepthOfField::default());
+    application_bundle.add_resource(SphereMeshParams::default());
+    application_bundle.add_resource(DebugRender::default());
+    application_bundle.add_resource(CapturePool::<B>::new());
+
+    let color_ramp = match application_bundle_params.color_ramp() {
+        Some(path) => ColorRamp::from_json(path)?,
+        None => scene.color_ramp()?,
+    };
 
     application_bundle.add_resource(color_ramp);
 
-    match &application_bundle_params {
-        ApplicationBundleParams::Load {
-            load_mode: LoadMode::Radius,
-            ..
-        }
-        | ApplicationBundleParams::Analyze { .. } => {
-            application_bundle.add_bundle(PhysicsBundle::new(vec3(0.0, 0.0, 0.0)));
-        }
-        _ => {}
+    let physics = application_bundle_params.physics()
+        && match &application_bundle_params {
+            ApplicationBundleParams::Load {
+                load_mode: LoadMode::Radius,
+                ..
+            }
+            | ApplicationBundleParams::Analyze { .. } => true,
+            _ => false,
+        };
+
+    if physics {
+        application_bundle.add_bundle(PhysicsBundle::new(gravity, true));
     }
 
-    let source = if let ApplicationBundleParams::Analyze { .. } = &application_bundle_params {
-        let (samples_bundle, source) = SamplesBundle::new(source);
+    let source = if let ApplicationBundleParams::Analyze { stereo, .. } = &application_bundle_params
+    {
+        let (samples_bundle, source) = SamplesBundle::new(source, *stereo);
         application_bundle.add_bundle(samples_bundle);
 
+        // before the sphere bundle, whose analyzer system clears the samples
+        application_bundle.add_resource(BeatDetector::default());
+        application_bundle.add_resource(BeatEvent::new());
+        application_bundle.add_system(beat_detection_system());
+
         OptionCaptureSource::Capture(source)
     } else {
         OptionCaptureSource::Source(source)
     };
 
-    application_bundle.add_bundle(SphereBundle::new(
-        application_bundle_params.sphere_bundle_params(source.sample_rate() as f32, mode),
-    ));
+    application_bundle.add_bundle(
+        SphereBundle::new(
+            application_bundle_params.sphere_bundle_params(source.sample_rate() as f32, mode),
+        )
+        .with_physics(physics)
+        .with_max_sphere_count(scene.max_spheres()),
+    );
+
+    // after sphere_shape_system, so the step collides the radii of the current frame
+    if physics {
+        application_bundle.add_system(physics_system());
+    }
+
+    if physics && physics_pre_roll > 0 {
+        application_bundle.add_bundle(PhysicsPreRollBundle::new(physics_pre_roll));
+    }
 
     Ok((application_bundle, source))
 }
@@ -3,19 +3,24 @@ use crate::world::ResWorld;
 use anyhow::Error;
 use cpal::Sample as CPalSaple;
 use rodio::{Sample, Source};
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::{FFTplanner, FFT};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
 
 pub struct SamplesBundle {
     samples_resource: Arc<Mutex<SamplesResource>>,
 }
 
 impl SamplesBundle {
-    pub fn new<S: Source>(source: S) -> (Self, CaptureSource<S>)
+    /// See `CaptureSource::new`
+    pub fn new<S: Source>(source: S, retain_channels: bool) -> (Self, CaptureSource<S>)
     where
         S::Item: Sample,
     {
-        let source = CaptureSource::new(source);
+        let source = CaptureSource::new(source, retain_channels);
 
         (
             Self {
@@ -38,13 +43,81 @@ impl Bundle for SamplesBundle {
     }
 }
 
-#[derive(Shrinkwrap)]
-#[shrinkwrap(mutable)]
-pub struct SamplesResource(pub Vec<f32>);
+/// Pulls the samples of `source` at its sample rate without playing them, e.g. when the playback
+/// is muted. A `CaptureSource` only analyzes the samples that are pulled through it.
+pub fn drain_in_real_time<S: 'static + Source + Send>(mut source: S) -> JoinHandle<()>
+where
+    S::Item: Sample,
+{
+    spawn(move || {
+        let samples_per_second = source.sample_rate() as f64 * source.channels() as f64;
+        let start = Instant::now();
+        let mut pulled = 0;
+
+        loop {
+            let due = (start.elapsed().as_secs_f64() * samples_per_second) as u64;
+
+            while pulled < due {
+                if let None = source.next() {
+                    return;
+                }
+
+                pulled += 1;
+            }
+
+            sleep(Duration::from_millis(5));
+        }
+    })
+}
+
+/// The samples captured since the last clear, interleaved if there is more than one channel
+pub struct SamplesResource {
+    samples: Vec<f32>,
+    channels: usize,
+}
 
 impl SamplesResource {
-    pub fn new() -> Self {
-        Self(vec![])
+    pub fn new(channels: usize) -> Self {
+        Self {
+            samples: vec![],
+            channels: channels.max(1),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Pushes one sample per channel of the source. The frame is mixed down to mono unless it
+    /// has as many channels as the resource retains.
+    pub fn push_frame(&mut self, frame: &[f32]) {
+        if frame.len() == self.channels {
+            self.samples.extend_from_slice(frame);
+        } else if !frame.is_empty() {
+            let mono = frame.iter().sum::<f32>() / frame.len() as f32;
+            self.samples
+                .extend(std::iter::repeat(mono).take(self.channels));
+        }
+    }
+
+    /// The samples of one channel, the last channel for channels beyond it
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        self.samples
+            .iter()
+            .skip(channel.min(self.channels - 1))
+            .step_by(self.channels)
+            .cloned()
+    }
+
+    /// The samples mixed down to one channel
+    pub fn mono(&self) -> impl Iterator<Item = f32> + '_ {
+        self.samples
+            .chunks(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear()
     }
 }
 
@@ -58,10 +131,18 @@ impl<S: Source> CaptureSource<S>
 where
     S::Item: Sample,
 {
-    pub fn new(source: S) -> Self {
+    /// Captures the samples of the source into the samples resource, mixed down to mono unless
+    /// `retain_channels` is set
+    pub fn new(source: S, retain_channels: bool) -> Self {
+        let channels = if retain_channels {
+            source.channels() as usize
+        } else {
+            1
+        };
+
         CaptureSource {
             source,
-            samples_resource: Arc::new(Mutex::new(SamplesResource::new())),
+            samples_resource: Arc::new(Mutex::new(SamplesResource::new(channels))),
             channel_samples: vec![],
         }
     }
@@ -104,9 +185,7 @@ where
             self.channel_samples.push(x.to_f32());
             if self.channel_samples.len() >= self.source.channels() as usize {
                 let mut samples_resource = self.samples_resource.lock().unwrap();
-                samples_resource.push(
-                    self.channel_samples.iter().sum::<f32>() / self.channel_samples.len() as f32,
-                );
+                samples_resource.push_frame(&self.channel_samples);
                 self.channel_samples.clear()
             }
         }
@@ -129,6 +208,16 @@ impl Filter for () {
     }
 }
 
+/// `None` passes the samples through unchanged
+impl<F: Filter> Filter for Option<F> {
+    fn tick(&mut self, sample: f32) -> f32 {
+        match self {
+            Some(filter) => filter.tick(sample),
+            None => sample,
+        }
+    }
+}
+
 pub struct IIRFilter<F> {
     filter: F,
     buffer_a: Vec<f32>,
@@ -194,6 +283,112 @@ impl<F: Filter> IIRFilter<F> {
 
         Self::new(filter, buffer_a, buffer_b)
     }
+
+    /// band pass with a peak gain of 0 dB at `frequency`
+    pub fn band_pass(filter: F, frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let mut buffer_a = vec![];
+        let mut buffer_b = vec![];
+
+        let w0 = 2.0 * std::f32::consts::PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let norm = 1.0 + alpha;
+        let c = w0.cos();
+        buffer_a.push(1.0);
+        buffer_a.push(-2.0 * c / norm);
+        buffer_a.push((1.0 - alpha) / norm);
+        buffer_b.push(alpha / norm);
+        buffer_b.push(0.0);
+        buffer_b.push(-buffer_b[0]);
+
+        Self::new(filter, buffer_a, buffer_b)
+    }
+
+    /// Removes the frequencies around `frequency`, the width of the notch shrinks with `q`
+    pub fn notch(filter: F, frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let mut buffer_a = vec![];
+        let mut buffer_b = vec![];
+
+        let w0 = 2.0 * std::f32::consts::PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let norm = 1.0 + alpha;
+        let c = w0.cos();
+        buffer_a.push(1.0);
+        buffer_a.push(-2.0 * c / norm);
+        buffer_a.push((1.0 - alpha) / norm);
+        buffer_b.push(1.0 / norm);
+        buffer_b.push(-2.0 * c / norm);
+        buffer_b.push(buffer_b[0]);
+
+        Self::new(filter, buffer_a, buffer_b)
+    }
+
+    /// Boosts (or cuts with a negative gain) the frequencies around `frequency` by `gain_db`
+    pub fn peaking(filter: F, frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let mut buffer_a = vec![];
+        let mut buffer_b = vec![];
+
+        let a = 10.0f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * std::f32::consts::PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let norm = 1.0 + alpha / a;
+        let c = w0.cos();
+        buffer_a.push(1.0);
+        buffer_a.push(-2.0 * c / norm);
+        buffer_a.push((1.0 - alpha / a) / norm);
+        buffer_b.push((1.0 + alpha * a) / norm);
+        buffer_b.push(-2.0 * c / norm);
+        buffer_b.push((1.0 - alpha * a) / norm);
+
+        Self::new(filter, buffer_a, buffer_b)
+    }
+
+    /// Boosts the frequencies below `frequency` by `gain_db`, leaving the ones above unchanged
+    pub fn low_shelf(filter: F, frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let a = 10.0f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * std::f32::consts::PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let c = w0.cos();
+        let sa = 2.0 * a.sqrt() * alpha;
+
+        let norm = (a + 1.0) + (a - 1.0) * c + sa;
+
+        let buffer_a = vec![
+            1.0,
+            -2.0 * ((a - 1.0) + (a + 1.0) * c) / norm,
+            ((a + 1.0) + (a - 1.0) * c - sa) / norm,
+        ];
+        let buffer_b = vec![
+            a * ((a + 1.0) - (a - 1.0) * c + sa) / norm,
+            2.0 * a * ((a - 1.0) - (a + 1.0) * c) / norm,
+            a * ((a + 1.0) - (a - 1.0) * c - sa) / norm,
+        ];
+
+        Self::new(filter, buffer_a, buffer_b)
+    }
+
+    /// Boosts the frequencies above `frequency` by `gain_db`, leaving the ones below unchanged
+    pub fn high_shelf(filter: F, frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let a = 10.0f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * std::f32::consts::PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let c = w0.cos();
+        let sa = 2.0 * a.sqrt() * alpha;
+
+        let norm = (a + 1.0) - (a - 1.0) * c + sa;
+
+        let buffer_a = vec![
+            1.0,
+            2.0 * ((a - 1.0) - (a + 1.0) * c) / norm,
+            ((a + 1.0) - (a - 1.0) * c - sa) / norm,
+        ];
+        let buffer_b = vec![
+            a * ((a + 1.0) + (a - 1.0) * c + sa) / norm,
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * c) / norm,
+            a * ((a + 1.0) + (a - 1.0) * c - sa) / norm,
+        ];
+
+        Self::new(filter, buffer_a, buffer_b)
+    }
 }
 
 impl<F: Filter> Filter for IIRFilter<F> {
@@ -222,10 +417,86 @@ impl<F: Filter> Filter for IIRFilter<F> {
     }
 }
 
+/// Automatic gain control. Scales the samples so their peak level approaches `target`. The peak
+/// estimate follows rising peaks instantly and decays towards quieter ones within about
+/// `adaptation` seconds.
+pub struct Agc<F> {
+    filter: F,
+    target: f32,
+    decay: f32,
+    peak: f32,
+}
+
+impl<F: Filter> Agc<F> {
+    /// levels below this are not amplified further, so silence does not blow up the noise floor
+    pub const MIN_PEAK: f32 = 1e-3;
+
+    pub fn new(filter: F, target: f32, adaptation: f32, sample_rate: f32) -> Self {
+        Self {
+            filter,
+            target,
+            decay: (-1.0 / (adaptation * sample_rate)).exp(),
+            peak: Self::MIN_PEAK,
+        }
+    }
+}
+
+impl<F: Filter> Filter for Agc<F> {
+    fn tick(&mut self, sample: f32) -> f32 {
+        let sample = self.filter.tick(sample);
+
+        self.peak = (self.peak * self.decay)
+            .max(sample.abs())
+            .max(Self::MIN_PEAK);
+
+        sample * self.target / self.peak
+    }
+}
+
+/// How `Envelope` detects the level of the signal before smoothing it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvelopeMode {
+    /// follows the absolute value of every sample
+    PeakFollow,
+    /// like `PeakFollow`, but a peak is held for `hold_samples` samples before it is released
+    PeakHold { hold_samples: usize },
+    /// follows the root mean square over the last `window` samples
+    Rms { window: usize },
+}
+
+enum Detector {
+    PeakFollow,
+    PeakHold {
+        hold_samples: usize,
+        remaining: usize,
+    },
+    Rms {
+        squares: RingBuffer<f32>,
+        sum: f32,
+    },
+}
+
+impl Detector {
+    fn new(mode: EnvelopeMode) -> Self {
+        match mode {
+            EnvelopeMode::PeakFollow => Detector::PeakFollow,
+            EnvelopeMode::PeakHold { hold_samples } => Detector::PeakHold {
+                hold_samples,
+                remaining: 0,
+            },
+            EnvelopeMode::Rms { window } => Detector::Rms {
+                squares: RingBuffer::new(vec![0.0; window.max(1)]),
+                sum: 0.0,
+            },
+        }
+    }
+}
+
 pub struct Envelope<F> {
     filter: F,
     attack: f32,
     release: f32,
+    detector: Detector,
     last_sample: f32,
 }
 
@@ -235,15 +506,45 @@ impl<F: Filter> Envelope<F> {
             filter,
             attack: threshold.powf(1.0 / (attack * sample_rate)),
             release: threshold.powf(1.0 / (release * sample_rate)),
+            detector: Detector::PeakFollow,
             last_sample: 0.0,
         }
     }
+
+    pub fn with_mode(mut self, mode: EnvelopeMode) -> Self {
+        self.detector = Detector::new(mode);
+        self
+    }
 }
 
 impl<F: Filter> Filter for Envelope<F> {
     fn tick(&mut self, sample: f32) -> f32 {
         let sample = self.filter.tick(sample).abs();
 
+        let sample = match &mut self.detector {
+            Detector::PeakFollow => sample,
+            Detector::PeakHold {
+                hold_samples,
+                remaining,
+            } => {
+                if self.last_sample < sample {
+                    *remaining = *hold_samples;
+                } else if *remaining > 0 {
+                    *remaining -= 1;
+                    return self.last_sample;
+                }
+
+                sample
+            }
+            Detector::Rms { squares, sum } => {
+                let square = sample * sample;
+                *sum += square - squares.replace(square);
+
+                // the running sum can drift slightly below zero
+                (sum.max(0.0) / squares.len() as f32).sqrt()
+            }
+        };
+
         let factor = if self.last_sample < sample {
             self.attack
         } else {
@@ -273,6 +574,21 @@ impl<T> RingBuffer<T> {
         self.next_index = (self.next_index + 1) % self.buffer.len();
     }
 
+    /// Pushes the element and returns the oldest one it overwrote
+    pub fn replace(&mut self, element: T) -> T {
+        let oldest = std::mem::replace(&mut self.buffer[self.next_index], element);
+        self.next_index = (self.next_index + 1) % self.buffer.len();
+        oldest
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         self.buffer[self.next_index..self.buffer.len()]
             .iter()
@@ -280,6 +596,88 @@ impl<T> RingBuffer<T> {
     }
 }
 
+/// Computes the amplitude spectrum of the last `size` samples pushed, hann windowed
+pub struct FftAnalyzer {
+    fft: Arc<dyn FFT<f32>>,
+    sample_rate: f32,
+    window: Vec<f32>,
+    samples: RingBuffer<f32>,
+    input: Vec<Complex<f32>>,
+    output: Vec<Complex<f32>>,
+    amplitudes: Vec<f32>,
+    agc: Option<Agc<()>>,
+}
+
+impl FftAnalyzer {
+    pub fn new(size: usize, sample_rate: f32) -> Result<Self, Error> {
+        if size < 2 || !size.is_power_of_two() {
+            bail!("the fft size has to be a power of two, not {}", size);
+        }
+
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos())
+            .collect();
+
+        Ok(Self {
+            fft: FFTplanner::new(false).plan_fft(size),
+            sample_rate,
+            window,
+            samples: RingBuffer::new(vec![0.0; size]),
+            input: vec![Complex::zero(); size],
+            output: vec![Complex::zero(); size],
+            amplitudes: vec![0.0; size / 2 + 1],
+            agc: None,
+        })
+    }
+
+    /// Normalizes the samples before they are analyzed
+    pub fn with_agc(mut self, agc: Option<Agc<()>>) -> Self {
+        self.agc = agc;
+        self
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.samples.push(self.agc.tick(sample))
+    }
+
+    /// Updates the spectrum from the samples pushed so far
+    pub fn analyze(&mut self) {
+        for ((input, sample), window) in self
+            .input
+            .iter_mut()
+            .zip(self.samples.iter())
+            .zip(self.window.iter())
+        {
+            *input = Complex::new(sample * window, 0.0);
+        }
+
+        self.fft.process(&mut self.input, &mut self.output);
+
+        // scaled so a sine filling a bin has its amplitude, the window halves the sum
+        let scale = 2.0 / self.window.iter().sum::<f32>();
+
+        for (amplitude, output) in self.amplitudes.iter_mut().zip(self.output.iter()) {
+            *amplitude = output.norm() * scale;
+        }
+    }
+
+    /// The highest amplitude between the frequencies, at least the one of the nearest bin
+    pub fn band(&self, low: f32, high: f32) -> f32 {
+        let bin_width = self.sample_rate / self.samples.len() as f32;
+        let last_bin = self.amplitudes.len() - 1;
+
+        let low_bin = ((low / bin_width).round() as usize).min(last_bin);
+        let high_bin = ((high / bin_width).round() as usize)
+            .min(last_bin)
+            .max(low_bin);
+
+        self.amplitudes[low_bin..=high_bin]
+            .iter()
+            .cloned()
+            .fold(0.0, f32::max)
+    }
+}
+
 pub enum OptionCaptureSource<S> {
     Capture(CaptureSource<S>),
     Source(S),
@@ -338,3 +736,164 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn muted_samples_are_pulled_at_the_sample_rate() {
+        // 100 samples at 1000 samples per second take 100 ms to pull
+        let source =
+            rodio::source::Zero::<f32>::new(1, 1000).take_duration(Duration::from_millis(100));
+
+        let start = Instant::now();
+        drain_in_real_time(source).join().unwrap();
+
+        // the thread ends once the source is empty, and not before the samples were due
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    fn impulse_response<F: Filter>(filter: &mut F, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| filter.tick(if i == 0 { 1.0 } else { 0.0 }))
+            .collect()
+    }
+
+    // at a quarter of the sample rate cos(w0) is 0 and sin(w0) is 1, so with a q of 1 alpha is
+    // 0.5 and the coefficients are a = [1, 0, 1/3]
+
+    #[test]
+    fn band_pass_impulse_response() {
+        // b = [1/3, 0, -1/3]
+        let mut filter = IIRFilter::band_pass((), 11025.0, 1.0, 44100.0);
+        let expected = [1.0 / 3.0, 0.0, -4.0 / 9.0, 0.0, 4.0 / 27.0, 0.0];
+
+        for (sample, expected) in impulse_response(&mut filter, expected.len())
+            .into_iter()
+            .zip(&expected)
+        {
+            assert_approx_eq!(sample, *expected, 1e-6);
+        }
+    }
+
+    #[test]
+    fn notch_impulse_response() {
+        // b = [2/3, 0, 2/3]
+        let mut filter = IIRFilter::notch((), 11025.0, 1.0, 44100.0);
+        let expected = [2.0 / 3.0, 0.0, 4.0 / 9.0, 0.0, -4.0 / 27.0, 0.0];
+
+        for (sample, expected) in impulse_response(&mut filter, expected.len())
+            .into_iter()
+            .zip(&expected)
+        {
+            assert_approx_eq!(sample, *expected, 1e-6);
+        }
+    }
+
+    /// The last sample of `input` repeated until the filter settled. At dc, nyquist and the
+    /// center of a peaking filter the response is real, so the output settles to the input
+    /// scaled by the gain.
+    fn settled<F: Filter>(filter: &mut F, input: impl Fn(usize) -> f32) -> f32 {
+        (0..4096).map(|i| filter.tick(input(i))).last().unwrap()
+    }
+
+    fn dc(_: usize) -> f32 {
+        1.0
+    }
+
+    fn nyquist(i: usize) -> f32 {
+        if i % 2 == 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    #[test]
+    fn low_shelf_gain() {
+        let gain = 10.0f32.powf(12.0 / 20.0);
+
+        let mut filter = IIRFilter::low_shelf((), 1000.0, 0.707, 12.0, 44100.0);
+        assert_approx_eq!(settled(&mut filter, dc), gain, 1e-3);
+
+        let mut filter = IIRFilter::low_shelf((), 1000.0, 0.707, 12.0, 44100.0);
+        assert_approx_eq!(settled(&mut filter, nyquist), -1.0, 1e-3);
+    }
+
+    #[test]
+    fn high_shelf_gain() {
+        let gain = 10.0f32.powf(12.0 / 20.0);
+
+        let mut filter = IIRFilter::high_shelf((), 1000.0, 0.707, 12.0, 44100.0);
+        assert_approx_eq!(settled(&mut filter, dc), 1.0, 1e-3);
+
+        let mut filter = IIRFilter::high_shelf((), 1000.0, 0.707, 12.0, 44100.0);
+        assert_approx_eq!(settled(&mut filter, nyquist), -gain, 1e-3);
+    }
+
+    #[test]
+    fn peaking_gain() {
+        let gain = 10.0f32.powf(-6.0 / 20.0);
+
+        // a cosine at the center frequency, ending on the -1.0 sample
+        let mut filter = IIRFilter::peaking((), 11025.0, 2.0, -6.0, 44100.0);
+        let output = (0..4095)
+            .map(|i| filter.tick([1.0, 0.0, -1.0, 0.0][i % 4]))
+            .last()
+            .unwrap();
+        assert_approx_eq!(output, -gain, 1e-3);
+
+        // far from the center the peak leaves the signal unchanged
+        let mut filter = IIRFilter::peaking((), 11025.0, 2.0, -6.0, 44100.0);
+        assert_approx_eq!(settled(&mut filter, dc), 1.0, 1e-3);
+    }
+
+    #[test]
+    fn agc_converges_to_target() {
+        let sample_rate = 1000.0;
+        let mut agc = Agc::new((), 0.5, 0.1, sample_rate);
+
+        // a loud burst, followed by a quiet signal for five adaptation periods
+        for i in 0..100 {
+            agc.tick(nyquist(i));
+        }
+        let quiet = (0..500)
+            .map(|i| agc.tick(0.01 * nyquist(i)))
+            .last()
+            .unwrap();
+        assert_approx_eq!(quiet, -0.5, 1e-3);
+
+        // a rising peak is followed instantly
+        assert_approx_eq!(agc.tick(0.2), 0.5, 1e-6);
+    }
+
+    #[test]
+    fn agc_clamps_the_gain() {
+        let min_peak = Agc::<()>::MIN_PEAK;
+        let mut agc = Agc::new((), 0.5, 0.1, 1000.0);
+
+        assert_eq!(agc.tick(0.0), 0.0);
+
+        // signals below the minimum peak are amplified by at most target / MIN_PEAK
+        let quiet = (0..1000).map(|_| agc.tick(min_peak / 10.0)).last().unwrap();
+        assert_approx_eq!(quiet, 0.05, 1e-6);
+    }
+
+    #[test]
+    fn constructors_are_normalized() {
+        let filters = vec![
+            IIRFilter::low_pass((), 1000.0, 0.707, 44100.0),
+            IIRFilter::high_pass((), 1000.0, 0.707, 44100.0),
+            IIRFilter::band_pass((), 1000.0, 0.707, 44100.0),
+            IIRFilter::notch((), 1000.0, 0.707, 44100.0),
+            IIRFilter::peaking((), 1000.0, 0.707, 6.0, 44100.0),
+            IIRFilter::low_shelf((), 1000.0, 0.707, 6.0, 44100.0),
+            IIRFilter::high_shelf((), 1000.0, 0.707, 6.0, 44100.0),
+        ];
+
+        for filter in filters {
+            assert_eq!(filter.buffer_a[0], 1.0);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Error;
+use rendy::init::EnabledBackend;
+use std::fmt::{Debug, Display};
+
+/// The enabled backends in the order they are tried
+pub fn preferred_backends() -> Vec<EnabledBackend> {
+    let mut backends = vec![];
+
+    #[cfg(feature = "vulkan")]
+    backends.push(EnabledBackend::Vulkan);
+
+    #[cfg(feature = "dx12")]
+    backends.push(EnabledBackend::Dx12);
+
+    #[cfg(feature = "metal")]
+    backends.push(EnabledBackend::Metal);
+
+    backends
+}
+
+/// Calls `init` with one backend after the other until it succeeds. Failed backends are
+/// reported, and if none succeeds the error lists why every one of them failed.
+pub fn init_first<T: Debug, R, E: Display>(
+    backends: impl IntoIterator<Item = T>,
+    mut init: impl FnMut(&T) -> Result<R, E>,
+) -> Result<(T, R), Error> {
+    let mut failures = vec![];
+
+    for backend in backends {
+        match init(&backend) {
+            Ok(rendy) => return Ok((backend, rendy)),
+            Err(err) => {
+                println!("failed to initialize {:?}: {}", backend, err);
+                failures.push(format!("{:?}: {}", backend, err));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        bail!("no backend is enabled");
+    }
+
+    bail!("could not initialize any backend ({})", failures.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_first_tries_in_order() {
+        let mut tried = vec![];
+
+        let (backend, rendy) = init_first(vec!["a", "b", "c", "d"], |backend| {
+            tried.push(*backend);
+
+            match *backend {
+                "c" | "d" => Ok(backend.to_uppercase()),
+                _ => Err("not available"),
+            }
+        })
+        .unwrap();
+
+        assert_eq!((backend, rendy.as_str()), ("c", "C"));
+        assert_eq!(tried, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn init_first_lists_every_failure() {
+        let err = init_first(vec!["a", "b"], |backend| {
+            Err::<(), _>(format!("{} failed", backend))
+        })
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "could not initialize any backend (\"a\": a failed, \"b\": b failed)"
+        );
+
+        let err = init_first(Vec::<&str>::new(), |_| Ok::<_, String>(())).unwrap_err();
+        assert_eq!(err.to_string(), "no backend is enabled");
+    }
+}
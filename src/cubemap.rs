@@ -7,6 +7,7 @@ use rendy::factory::{Factory, ImageState};
 use rendy::resource::CubeFace;
 use rendy::texture::pixel::Rgb32Sfloat;
 use rendy::texture::{MipLevels, Texture, TextureBuilder};
+use std::f32::consts::PI;
 use std::fs::File;
 use std::io::BufReader;
 use std::num::NonZeroU8;
@@ -72,6 +73,121 @@ impl HdrCubeMapBuilder {
         Ok(self)
     }
 
+    /// Samples a single equirectangular panorama into the six faces of a cubemap with the given
+    /// side length
+    pub fn from_equirectangular<P: AsRef<Path>>(path: P, face_size: u32) -> Result<Self, Error> {
+        if face_size == 0 {
+            bail!("the face size of a cubemap has to be at least 1");
+        }
+
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let (width, height) = (metadata.width as usize, metadata.height as usize);
+
+        let mut panorama = vec![Rgb32Sfloat::default(); width * height];
+        decoder.read_image_transform(
+            |pixel| Rgb32Sfloat {
+                repr: pixel.to_hdr().0,
+            },
+            &mut panorama,
+        )?;
+
+        let size = face_size as usize;
+        let mut data = vec![Rgb32Sfloat::default(); size * size * 6];
+        for (i, pixel) in data.iter_mut().enumerate() {
+            let [x, y, z] = texel_direction(i, size);
+
+            let u = 0.5 + x.atan2(-z) / (2.0 * PI);
+            let v = 0.5 - (y / (x * x + y * y + z * z).sqrt()).asin() / PI;
+            *pixel = sample_bilinear(&panorama, width, height, u, v);
+        }
+
+        Ok(Self {
+            face_width: Some(face_size),
+            face_height: Some(face_size),
+            data: Some(data),
+            ..Self::new()
+        })
+    }
+
+    /// Convolves the cubemap with a cosine lobe into an irradiance cubemap with the given side
+    /// length, divided by pi so that multiplying it with a diffuse color gives the reflected
+    /// radiance. The cubemap is box filtered down to `IRRADIANCE_SOURCE_SIZE` texels per side
+    /// first, which is plenty for such a smooth result.
+    pub fn irradiance(&self, face_size: u32) -> Result<Self, Error> {
+        if face_size == 0 {
+            bail!("the face size of a cubemap has to be at least 1");
+        }
+
+        let data = self
+            .data
+            .as_deref()
+            .ok_or(anyhow!("no cubemap data provided"))?;
+        let width = self.face_width.ok_or(anyhow!("no cubemap data provided"))?;
+        let height = self
+            .face_height
+            .ok_or(anyhow!("no cubemap data provided"))?;
+        if width != height {
+            bail!("the faces of a cubemap have to be square");
+        }
+
+        let width = width as usize;
+        let source_size = IRRADIANCE_SOURCE_SIZE.min(width);
+        let mut sums = vec![([0.0f32; 3], 0u32); source_size * source_size * 6];
+        for (i, pixel) in data.iter().enumerate() {
+            let face = i / (width * width);
+            let x = i % width * source_size / width;
+            let y = i / width % width * source_size / width;
+
+            let (sum, count) = &mut sums[(face * source_size + y) * source_size + x];
+            for (sum, value) in sum.iter_mut().zip(pixel.repr.iter()) {
+                *sum += value;
+            }
+            *count += 1;
+        }
+
+        // the radiance of every source texel weighted by the solid angle it covers
+        let texel_area = (2.0 / source_size as f32).powi(2);
+        let samples: Vec<_> = sums
+            .iter()
+            .enumerate()
+            .map(|(i, (sum, count))| {
+                let direction = texel_direction(i, source_size);
+                let squared_length = dot(&direction, &direction);
+                let solid_angle = texel_area / (squared_length * squared_length.sqrt());
+
+                let mut radiance = *sum;
+                for value in radiance.iter_mut() {
+                    *value *= solid_angle / *count as f32;
+                }
+
+                (normalize(direction), radiance)
+            })
+            .collect();
+
+        let size = face_size as usize;
+        let mut irradiance = vec![Rgb32Sfloat::default(); size * size * 6];
+        for (i, pixel) in irradiance.iter_mut().enumerate() {
+            let normal = normalize(texel_direction(i, size));
+
+            for (direction, radiance) in samples.iter() {
+                let cos = dot(&normal, direction);
+                if cos > 0.0 {
+                    for (value, radiance) in pixel.repr.iter_mut().zip(radiance.iter()) {
+                        *value += radiance * cos / PI;
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            face_width: Some(face_size),
+            face_height: Some(face_size),
+            data: Some(irradiance),
+            ..Self::new()
+        })
+    }
+
     pub fn with_sampler_info(mut self, sampler_desc: SamplerDesc) -> Self {
         self.sampler_desc = Some(sampler_desc);
         self
@@ -82,6 +198,12 @@ impl HdrCubeMapBuilder {
         self
     }
 
+    /// Generates the full mip chain after the upload by blitting each level of every face from
+    /// the one above it
+    pub fn with_generated_mips(self) -> Self {
+        self.with_mip_levels(MipLevels::GenerateAuto)
+    }
+
     pub fn with_premultiplied_alpha(mut self, premultiplied_alpha: bool) -> Self {
         self.premultiplied_alpha = premultiplied_alpha;
         self
@@ -101,8 +223,7 @@ impl HdrCubeMapBuilder {
         let width = self.face_width.ok_or(anyhow!("no cubemap data provided"))?;
         let height = self
             .face_height
-            .ok_or(anyhow!("no cubemap data provided"))?
-            * 6;
+            .ok_or(anyhow!("no cubemap data provided"))?;
 
         let sampler_desc = self
             .sampler_desc
@@ -112,7 +233,7 @@ impl HdrCubeMapBuilder {
             .with_data(data)
             .with_data_width(width)
             .with_data_height(height)
-            .with_kind(IKind::D2(width, height, 1, 1))
+            .with_kind(IKind::D2(width, height, 6, 1))
             .with_mip_levels(self.mip_levels)
             .with_view_kind(ViewKind::Cube)
             .with_sampler_info(sampler_desc)
@@ -122,3 +243,233 @@ impl HdrCubeMapBuilder {
             .map_err(|e| anyhow!("{:?}", e))?)
     }
 }
+
+/// The side length the cubemap is reduced to before it is convolved into an irradiance map
+pub const IRRADIANCE_SOURCE_SIZE: usize = 16;
+
+/// In the order of the faces in the cubemap data
+const CUBE_FACES: [CubeFace; 6] = [
+    CubeFace::PosX,
+    CubeFace::NegX,
+    CubeFace::PosY,
+    CubeFace::NegY,
+    CubeFace::PosZ,
+    CubeFace::NegZ,
+];
+
+/// The (not normalized) direction through the center of the texel at `index` of cubemap data
+/// with the given side length
+fn texel_direction(index: usize, size: usize) -> [f32; 3] {
+    let face = CUBE_FACES[index / (size * size)];
+    let s = 2.0 * ((index % size) as f32 + 0.5) / size as f32 - 1.0;
+    let t = 2.0 * ((index / size % size) as f32 + 0.5) / size as f32 - 1.0;
+    face_direction(face, s, t)
+}
+
+fn dot(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = dot(&a, &a).sqrt();
+    [a[0] / length, a[1] / length, a[2] / length]
+}
+
+/// The direction through the point `(s, t)` in `[-1, 1]` of a cube face, using the usual cubemap
+/// convention where `t` points down on every side face
+fn face_direction(face: CubeFace, s: f32, t: f32) -> [f32; 3] {
+    match face {
+        CubeFace::PosX => [1.0, -t, -s],
+        CubeFace::NegX => [-1.0, -t, s],
+        CubeFace::PosY => [s, 1.0, t],
+        CubeFace::NegY => [s, -1.0, -t],
+        CubeFace::PosZ => [s, -t, 1.0],
+        CubeFace::NegZ => [-s, -t, -1.0],
+    }
+}
+
+/// Wraps around horizontally and clamps vertically
+fn sample_bilinear(
+    image: &[Rgb32Sfloat],
+    width: usize,
+    height: usize,
+    u: f32,
+    v: f32,
+) -> Rgb32Sfloat {
+    let x = u * width as f32 - 0.5;
+    let y = (v * height as f32 - 0.5).max(0.0).min((height - 1) as f32);
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let x0 = (x0 as isize).rem_euclid(width as isize) as usize;
+    let x1 = (x0 + 1) % width;
+    let y0 = y0 as usize;
+    let y1 = (y0 + 1).min(height - 1);
+
+    let mut repr = [0.0; 3];
+    for (c, value) in repr.iter_mut().enumerate() {
+        let top = image[y0 * width + x0].repr[c] * (1.0 - fx) + image[y0 * width + x1].repr[c] * fx;
+        let bottom =
+            image[y1 * width + x0].repr[c] * (1.0 - fx) + image[y1 * width + x1].repr[c] * fx;
+        *value = top * (1.0 - fy) + bottom * fy;
+    }
+
+    Rgb32Sfloat { repr }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use crate::ext::CUBEMAP_SAMPLER_DESC;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use crate::test_support::headless;
+    use image::hdr::HdrEncoder;
+    use image::Rgb;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use rendy::command::{Families, Graphics};
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use rendy::core::hal::image::{Access as IAccess, Layout as ILayout};
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use rendy::core::hal::pso::PipelineStage;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    fn assert_color(pixel: &Rgb32Sfloat, expected: [f32; 3]) {
+        for (value, expected) in pixel.repr.iter().zip(expected.iter()) {
+            assert_approx_eq!(value, expected, 1e-4);
+        }
+    }
+
+    #[test]
+    fn panorama_is_sampled_along_the_face_directions() {
+        let directory = std::env::temp_dir().join("rendy-sphere-visualizer-cubemap");
+        create_dir_all(&directory).unwrap();
+
+        // red above and blue below the horizon, green grows with the longitude
+        let green = [0.0, 1.0, 2.0, 4.0];
+        let panorama: Vec<_> = [[10.0, 0.0], [0.0, 10.0]]
+            .iter()
+            .flat_map(|[red, blue]| green.iter().map(move |green| Rgb([*red, *green, *blue])))
+            .collect();
+
+        let path = directory.join("panorama.hdr");
+        HdrEncoder::new(File::create(&path).unwrap())
+            .encode(&panorama, 4, 2)
+            .unwrap();
+
+        // a single texel per face looks straight along its axis
+        let builder = HdrCubeMapBuilder::from_equirectangular(&path, 1).unwrap();
+        let faces = builder.data.unwrap();
+
+        assert_color(&faces[CubeFace::PosX as usize], [5.0, 3.0, 5.0]);
+        assert_color(&faces[CubeFace::NegX as usize], [5.0, 0.5, 5.0]);
+
+        // the longitude is arbitrary at the poles, so only red and blue are known there
+        let [red, _, blue] = faces[CubeFace::PosY as usize].repr;
+        assert_eq!((red, blue), (10.0, 0.0));
+        let [red, _, blue] = faces[CubeFace::NegY as usize].repr;
+        assert_eq!((red, blue), (0.0, 10.0));
+
+        // the panorama wraps around behind the camera
+        assert_color(&faces[CubeFace::PosZ as usize], [5.0, 2.0, 5.0]);
+        assert_color(&faces[CubeFace::NegZ as usize], [5.0, 1.5, 5.0]);
+
+        assert!(HdrCubeMapBuilder::from_equirectangular(&path, 0).is_err());
+
+        remove_dir_all(directory).unwrap();
+    }
+
+    fn uniform_cubemap(size: u32, faces: [f32; 6]) -> HdrCubeMapBuilder {
+        let texels = (size * size) as usize;
+        let data = faces
+            .iter()
+            .flat_map(|value| vec![Rgb32Sfloat { repr: [*value; 3] }; texels])
+            .collect();
+
+        HdrCubeMapBuilder {
+            face_width: Some(size),
+            face_height: Some(size),
+            data: Some(data),
+            ..HdrCubeMapBuilder::new()
+        }
+    }
+
+    #[test]
+    fn uniform_environment_irradiates_its_own_radiance() {
+        // the cosine integrates to pi over the hemisphere, which the irradiance is divided by
+        let irradiance = uniform_cubemap(32, [2.0; 6]).irradiance(4).unwrap();
+
+        assert_eq!(irradiance.face_width, Some(4));
+        for pixel in irradiance.data.unwrap().iter() {
+            for value in pixel.repr.iter() {
+                assert_approx_eq!(value, 2.0, 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn irradiance_faces_the_bright_side() {
+        // only the sky shines
+        let builder = uniform_cubemap(16, [0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        let irradiance = builder.irradiance(1).unwrap().data.unwrap();
+
+        let up = irradiance[CubeFace::PosY as usize].repr[0];
+        let side = irradiance[CubeFace::PosX as usize].repr[0];
+        let down = irradiance[CubeFace::NegY as usize].repr[0];
+
+        assert!(up > side && side > down);
+        assert_eq!(down, 0.0);
+
+        assert!(builder.irradiance(0).is_err());
+    }
+
+    /// Uploads a white cubemap with `size` texels per side and returns the number of mip levels
+    /// and array layers of its image
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn levels_and_layers<B: Backend>(
+        mut factory: Factory<B>,
+        families: Families<B>,
+        size: u32,
+        generated_mips: bool,
+    ) -> (u8, u16) {
+        let graphics_family = families
+            .with_capability::<Graphics>()
+            .expect("the device has no graphics queue");
+
+        let state = ImageState {
+            queue: families.family(graphics_family).queue(0).id(),
+            stage: PipelineStage::FRAGMENT_SHADER,
+            access: IAccess::SHADER_READ,
+            layout: ILayout::ShaderReadOnlyOptimal,
+        };
+
+        let mut builder = uniform_cubemap(size, [1.0; 6]).with_sampler_info(CUBEMAP_SAMPLER_DESC);
+
+        if generated_mips {
+            builder = builder.with_generated_mips();
+        }
+
+        let texture = builder
+            .build(state, &mut factory)
+            .expect("could not build the cubemap");
+
+        let image = texture.image();
+        (image.levels(), image.kind().num_layers())
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn generated_mips_reach_down_to_a_single_texel() {
+        let (levels, layers) = with_any_rendy!((headless()) (factory, families) => {
+            levels_and_layers(factory, families, 16, false)
+        });
+        assert_eq!((levels, layers), (1, 6));
+
+        // 16, 8, 4, 2 and 1 texels per side, for each of the six faces
+        let (levels, layers) = with_any_rendy!((headless()) (factory, families) => {
+            levels_and_layers(factory, families, 16, true)
+        });
+        assert_eq!((levels, layers), (5, 6));
+    }
+}
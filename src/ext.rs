@@ -54,18 +54,22 @@ impl<B: Backend> GraphContextExt<B> for GraphContext<B> {
     }
 }
 
-pub const FULLSCREEN_SAMPLER_DESC: SamplerDesc = SamplerDesc {
-    min_filter: Filter::Nearest,
-    mag_filter: Filter::Nearest,
-    mip_filter: Filter::Nearest,
-    wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
-    lod_bias: Lod(0.0),
-    lod_range: Lod(0.0)..Lod(100.0),
-    comparison: None,
-    border: PackedColor(0),
-    normalized: false,
-    anisotropic: Anisotropic::Off,
-};
+pub const FULLSCREEN_SAMPLER_DESC: SamplerDesc = fullscreen_sampler_desc(Filter::Nearest);
+
+pub const fn fullscreen_sampler_desc(filter: Filter) -> SamplerDesc {
+    SamplerDesc {
+        min_filter: filter,
+        mag_filter: filter,
+        mip_filter: Filter::Nearest,
+        wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
+        lod_bias: Lod(0.0),
+        lod_range: Lod(0.0)..Lod(100.0),
+        comparison: None,
+        border: PackedColor(0),
+        normalized: false,
+        anisotropic: Anisotropic::Off,
+    }
+}
 
 pub const CUBEMAP_SAMPLER_DESC: SamplerDesc = SamplerDesc {
     min_filter: Filter::Linear,
@@ -0,0 +1,118 @@
+use anyhow::Error;
+use rendy::hal::format::Format;
+use std::convert::TryInto;
+
+pub fn to_rgba8(format: Format, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut result = vec![];
+
+    match format {
+        Format::Rgba8Unorm | Format::Rgba8Srgb => result.extend_from_slice(data),
+        Format::Bgra8Unorm | Format::Bgra8Srgb => {
+            for pixel in data.chunks_exact(4) {
+                result.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+            }
+        }
+        Format::Rgba16Unorm => {
+            for pixel in data.chunks_exact(8) {
+                for channel in pixel.chunks_exact(2) {
+                    result.push(channel[1]);
+                }
+            }
+        }
+        Format::Rgba8Snorm => {
+            for channel in data {
+                result.push(unorm(*channel as i8 as f32 / 127.0 * 0.5 + 0.5));
+            }
+        }
+        Format::Rgba16Snorm => {
+            for channel in data.chunks_exact(2) {
+                let value = i16::from_le_bytes([channel[0], channel[1]]) as f32 / 32767.0;
+                result.push(unorm(value * 0.5 + 0.5));
+            }
+        }
+        Format::A2r10g10b10Unorm | Format::A2b10g10r10Unorm => {
+            for pixel in data.chunks_exact(4) {
+                let pixel = u32::from_le_bytes(pixel.try_into()?);
+                let high = ((pixel >> 20) & 0x3ff) >> 2;
+                let middle = ((pixel >> 10) & 0x3ff) >> 2;
+                let low = (pixel & 0x3ff) >> 2;
+                let alpha = (pixel >> 30) * 85;
+
+                let (r, b) = match format {
+                    Format::A2r10g10b10Unorm => (high, low),
+                    _ => (low, high),
+                };
+
+                result.extend_from_slice(&[r as u8, middle as u8, b as u8, alpha as u8]);
+            }
+        }
+        Format::Rgba16Sfloat | Format::Rgba32Sfloat | Format::Rgba64Sfloat => {
+            for pixel in floats(format, data)?.chunks_exact(4) {
+                result.extend(pixel.iter().map(|channel| unorm(*channel)));
+            }
+        }
+        Format::R16Sfloat | Format::R32Sfloat | Format::R64Sfloat => {
+            for channel in floats(format, data)? {
+                let value = unorm(channel);
+                result.extend_from_slice(&[value, value, value, 255]);
+            }
+        }
+        _ => bail!("can not convert {:?} to rgba8", format),
+    }
+
+    Ok(result)
+}
+
+fn floats(format: Format, data: &[u8]) -> Result<Vec<f32>, Error> {
+    Ok(match format {
+        Format::R16Sfloat | Format::Rgba16Sfloat => data
+            .chunks_exact(2)
+            .map(|bytes| f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])))
+            .collect(),
+        Format::R32Sfloat | Format::Rgba32Sfloat => data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect(),
+        Format::R64Sfloat | Format::Rgba64Sfloat => data
+            .chunks_exact(8)
+            .map(|bytes| Ok(f64::from_le_bytes(bytes.try_into()?) as f32))
+            .collect::<Result<_, Error>>()?,
+        _ => bail!("{:?} is not a float format", format),
+    })
+}
+
+fn unorm(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2.0f32.powi(-24),
+        0x1f if mantissa == 0.0 => std::f32::INFINITY,
+        0x1f => std::f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2.0f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bgra_swaps_red_and_blue() {
+        let bgra = [10, 20, 30, 255, 40, 50, 60, 128];
+
+        for format in [Format::Bgra8Unorm, Format::Bgra8Srgb].iter().cloned() {
+            assert_eq!(
+                to_rgba8(format, &bgra).unwrap(),
+                vec![30, 20, 10, 255, 60, 50, 40, 128]
+            );
+        }
+
+        assert_eq!(to_rgba8(Format::Rgba8Unorm, &bgra).unwrap(), bgra.to_vec());
+    }
+}
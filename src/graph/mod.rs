@@ -1,15 +1,25 @@
-use crate::graph::node::capture::{CaptureAction, CaptureDesc};
-use crate::graph::node::comp::CompDesc;
+use crate::graph::node::bloom::{BloomDesc, BloomStage};
+use crate::graph::node::capture::{CaptureAction, CaptureDesc, CapturePool};
+use crate::graph::node::comp::{AlphaMode, CompDesc};
 use crate::graph::node::dfao::join::DFAOJoinDesc;
 use crate::graph::node::dfao::sphere::DFAOSphereDesc;
-use crate::graph::node::dfao::DFAOParams;
-use crate::graph::node::gbuffer::GBufferDesc;
+use crate::graph::node::dfao::DFAOConfig;
+use crate::graph::node::dof::DOFDesc;
+use crate::graph::node::fxaa::{Antialiasing, FxaaDesc, FxaaQuality};
+use crate::graph::node::gbuffer::{DepthConvention, GBufferDesc, NormalEncoding};
+use crate::graph::node::glow::GlowDesc;
+use crate::graph::node::proxy::ProxyParams;
 use crate::graph::node::rtsh::sphere::RTSHSphereDesc;
+use crate::graph::node::timestamp::TimestampDesc;
+use crate::graph::shader::ShaderWatcher;
 use anyhow::Error;
+use exr::prelude::{f16, IntoSample};
 use futures::executor::{LocalPool, ThreadPool};
 use futures::future::RemoteHandle;
 use futures::task::SpawnExt;
 
+use image::bmp::BmpEncoder;
+use image::jpeg::JpegEncoder;
 use image::png::PngEncoder;
 use image::ColorType;
 
@@ -18,30 +28,61 @@ use rendy::factory::Factory;
 use rendy::init::winit::window::Window;
 
 use rendy::graph::render::{RenderGroupBuilder, SimpleGraphicsPipelineDesc, SubpassBuilder};
-use rendy::graph::{Graph, GraphBuilder, NodeDesc};
+use rendy::graph::{Graph, GraphBuilder, NodeBuilder, NodeDesc, NodeId};
 use rendy::hal::adapter::PhysicalDevice;
 use rendy::hal::command::{ClearColor, ClearDepthStencil, ClearValue};
-use rendy::hal::format::{Format, ImageFeature};
+use rendy::hal::format::{ChannelType, Format, ImageFeature};
+use rendy::hal::image::Kind;
 
 use rendy::hal::window::Extent2D;
-use rendy::hal::Backend;
-use rendy::resource::Tiling;
+use rendy::hal::{Backend, Features};
+use rendy::resource::{Filter, Tiling};
 
 use rendy::wsi::Surface;
 use serde::export::PhantomData;
 
 use crate::event::StateId;
+use crate::manifest::OutputFiles;
+use crate::world::debug_render::{DebugRender, PolygonMode};
+use crate::world::gpu_profiler::GpuProfiler;
 use crate::world::resolution::Resolution;
+use crate::world::sphere::SphereLimits;
 use crate::world::ResWorld;
+use std::collections::VecDeque;
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::BufWriter;
+use std::fs::{create_dir_all, File};
+use std::future::Future;
+use std::io::{BufWriter, Write};
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex};
 
+pub mod convert;
 pub mod node;
+pub mod shader;
 
 pub trait Output<B: Backend> {
+    /// The format the comp pass, or the bloom pass behind it, renders into
+    fn format(&mut self, world: &ResWorld, factory: &mut Factory<B>) -> Result<Format, Error>;
+
+    fn build(
+        &mut self,
+        world: &ResWorld,
+        factory: &mut Factory<B>,
+        graph_builder: &mut GraphBuilder<B, ResWorld>,
+        comp_subpass: SubpassBuilder<B, ResWorld>,
+        resolution: &Resolution,
+        /// what the output is cleared to, `None` if the subpass covers every pixel anyway
+        clear_color: Option<ClearColor>,
+    ) -> Result<NodeId, Error>;
+}
+
+impl<B: Backend> Output<B> for Box<dyn Output<B>> {
+    fn format(&mut self, world: &ResWorld, factory: &mut Factory<B>) -> Result<Format, Error> {
+        self.as_mut().format(world, factory)
+    }
+
     fn build(
         &mut self,
         world: &ResWorld,
@@ -49,7 +90,17 @@ pub trait Output<B: Backend> {
         graph_builder: &mut GraphBuilder<B, ResWorld>,
         comp_subpass: SubpassBuilder<B, ResWorld>,
         resolution: &Resolution,
-    ) -> Result<(), Error>;
+        clear_color: Option<ClearColor>,
+    ) -> Result<NodeId, Error> {
+        self.as_mut().build(
+            world,
+            factory,
+            graph_builder,
+            comp_subpass,
+            resolution,
+            clear_color,
+        )
+    }
 }
 
 pub struct SurfaceOutput<B: Backend> {
@@ -63,6 +114,28 @@ impl<B: Backend> SurfaceOutput<B> {
 }
 
 impl<B: Backend> Output<B> for SurfaceOutput<B> {
+    fn format(&mut self, world: &ResWorld, factory: &mut Factory<B>) -> Result<Format, Error> {
+        let surface = match self.surface.take() {
+            Some(surface) => surface,
+            None => {
+                let window = world
+                    .resources
+                    .get::<Window>()
+                    .expect("window was not inserted into world");
+
+                factory.create_surface(window.deref())?
+            }
+        };
+
+        let format = surface.format(factory.physical());
+
+        println!("surface format: {:?}", format);
+
+        self.surface = Some(surface);
+
+        Ok(format)
+    }
+
     fn build(
         &mut self,
         world: &ResWorld,
@@ -70,7 +143,8 @@ impl<B: Backend> Output<B> for SurfaceOutput<B> {
         graph_builder: &mut GraphBuilder<B, ResWorld>,
         comp_subpass: SubpassBuilder<B, ResWorld>,
         resolution: &Resolution,
-    ) -> Result<(), Error> {
+        clear_color: Option<ClearColor>,
+    ) -> Result<NodeId, Error> {
         let extend = Extent2D {
             width: resolution.width(),
             height: resolution.height(),
@@ -86,29 +160,29 @@ impl<B: Backend> Output<B> for SurfaceOutput<B> {
             None => factory.create_surface(window.deref())?,
         };
 
-        let _comp =
+        let comp =
             graph_builder.add_node(comp_subpass.with_color_surface().into_pass().with_surface(
                 surface,
                 extend,
                 Some(ClearValue {
-                    color: ClearColor {
+                    color: clear_color.unwrap_or(ClearColor {
                         float32: [1.0, 0.0, 1.0, 0.0],
-                    },
+                    }),
                 }),
             ));
 
-        Ok(())
+        Ok(comp)
     }
 }
 
-pub struct CaptureOutput<G: FnMut() -> Result<A, Error>, A: CaptureAction<D>, D> {
+pub struct CaptureOutput<G: FnMut(&Resolution) -> Result<A, Error>, A: CaptureAction<D>, D> {
     action_generator: G,
     format: Format,
     phantom_data: PhantomData<(A, D)>,
 }
 
 impl<
-        G: FnMut() -> Result<A, Error>,
+        G: FnMut(&Resolution) -> Result<A, Error>,
         A: 'static + CaptureAction<D> + Debug + Send + Sync,
         D: 'static + Copy + Debug + Send + Sync,
     > CaptureOutput<G, A, D>
@@ -124,11 +198,15 @@ impl<
 
 impl<
         B: Backend,
-        G: FnMut() -> Result<A, Error>,
+        G: FnMut(&Resolution) -> Result<A, Error>,
         A: 'static + CaptureAction<D> + Debug + Send + Sync,
         D: 'static + Copy + Debug + Send + Sync,
     > Output<B> for CaptureOutput<G, A, D>
 {
+    fn format(&mut self, _world: &ResWorld, _factory: &mut Factory<B>) -> Result<Format, Error> {
+        Ok(self.format)
+    }
+
     fn build(
         &mut self,
         _world: &ResWorld,
@@ -136,28 +214,29 @@ impl<
         graph_builder: &mut GraphBuilder<B, ResWorld>,
         comp_subpass: SubpassBuilder<B, ResWorld>,
         resolution: &Resolution,
-    ) -> Result<(), Error> {
+        clear_color: Option<ClearColor>,
+    ) -> Result<NodeId, Error> {
         let comp_image = graph_builder.create_image(
             resolution.kind(),
             1,
             self.format,
             Some(ClearValue {
-                color: ClearColor {
+                color: clear_color.unwrap_or(ClearColor {
                     float32: [1.0, 1.0, 1.0, 1.0],
-                },
+                }),
             }),
         );
 
         let comp = graph_builder.add_node(comp_subpass.with_color(comp_image).into_pass());
 
         let _capture = graph_builder.add_node(
-            CaptureDesc::new((self.action_generator)()?)
+            CaptureDesc::new((self.action_generator)(resolution)?)
                 .builder()
                 .with_dependency(comp)
                 .with_image(comp_image),
         );
 
-        Ok(())
+        Ok(comp)
     }
 }
 
@@ -193,26 +272,160 @@ pub fn format_supported<B: Backend>(
     supported_features & features == features
 }
 
+/// The file format headless frames are saved in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    /// `quality` ranges from 1 to 100
+    Jpeg {
+        quality: u8,
+    },
+    Bmp,
+}
+
+impl ImageFormat {
+    pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "png" => Some(ImageFormat::Png),
+            "jpeg" | "jpg" => Some(ImageFormat::Jpeg {
+                quality: Self::DEFAULT_JPEG_QUALITY,
+            }),
+            "bmp" => Some(ImageFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg { .. } => "jpg",
+            ImageFormat::Bmp => "bmp",
+        }
+    }
+
+    pub fn supports_alpha(&self) -> bool {
+        match self {
+            ImageFormat::Png | ImageFormat::Bmp => true,
+            ImageFormat::Jpeg { .. } => false,
+        }
+    }
+}
+
+/// Writes the frames of a capture action on a thread pool. Once `max_pending` frames wait to be
+/// written the render loop waits for the oldest one, so a gpu that renders faster than the disk
+/// writes can not fill the memory with frames.
+#[derive(Debug)]
+pub struct SaveQueue {
+    thread_pool: ThreadPool,
+    handles: VecDeque<RemoteHandle<Result<(), Error>>>,
+    max_pending: usize,
+}
+
+impl SaveQueue {
+    pub const DEFAULT_MAX_PENDING: usize = 8;
+
+    pub fn new() -> Result<Self, Error> {
+        Ok(SaveQueue {
+            thread_pool: ThreadPool::builder().create()?,
+            handles: VecDeque::new(),
+            max_pending: Self::DEFAULT_MAX_PENDING,
+        })
+    }
+
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.set_max_pending(max_pending);
+        self
+    }
+
+    pub fn max_pending(&self) -> usize {
+        self.max_pending
+    }
+
+    /// At least one frame can be pending
+    pub fn set_max_pending(&mut self, max_pending: usize) {
+        self.max_pending = max_pending.max(1)
+    }
+
+    /// The frames that were queued but not confirmed written yet
+    pub fn pending(&self) -> usize {
+        self.handles.len()
+    }
+
+    fn spawn<F: 'static + Future<Output = Result<(), Error>> + Send>(
+        &mut self,
+        save: F,
+    ) -> Result<(), Error> {
+        while self.handles.len() >= self.max_pending {
+            if let Some(handle) = self.handles.pop_front() {
+                LocalPool::new().run_until(handle)?;
+            }
+        }
+
+        self.handles
+            .push_back(self.thread_pool.spawn_with_handle(save)?);
+
+        Ok(())
+    }
+}
+
+impl Drop for SaveQueue {
+    fn drop(&mut self) {
+        let mut local_pool = LocalPool::new();
+
+        for handle in self.handles.drain(..) {
+            if let Err(err) = local_pool.run_until(handle) {
+                println!("{:?}", err)
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct SavePng<P> {
+pub struct SaveImage<P> {
     directory: P,
     color_type: ColorType,
-    thread_pool: ThreadPool,
-    handles: Vec<RemoteHandle<Result<(), Error>>>,
+    format: ImageFormat,
+    queue: SaveQueue,
+    output_files: Option<OutputFiles>,
+    source_format: Option<Format>,
 }
 
-impl<P: AsRef<Path>> SavePng<P> {
+impl<P: AsRef<Path>> SaveImage<P> {
     pub fn new(directory: P, color_type: ColorType) -> Result<Self, Error> {
-        let thread_pool = ThreadPool::builder().create()?;
-
-        Ok(SavePng {
+        Ok(SaveImage {
             directory,
             color_type,
-            thread_pool,
-            handles: vec![],
+            format: ImageFormat::Png,
+            queue: SaveQueue::new()?,
+            output_files: None,
+            source_format: None,
         })
     }
 
+    /// The number of frames that can wait to be written, see `SaveQueue`
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.queue.set_max_pending(max_pending);
+        self
+    }
+
+    pub fn with_source_format(mut self, source_format: Format) -> Self {
+        self.source_format = Some(source_format);
+        self.color_type = ColorType::Rgba8;
+        self
+    }
+
+    pub fn with_format(mut self, format: ImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_output_files(mut self, output_files: OutputFiles) -> Self {
+        self.output_files = Some(output_files);
+        self
+    }
+
     async fn save_file(
         data: Vec<u8>,
         frame: u64,
@@ -220,331 +433,1379 @@ impl<P: AsRef<Path>> SavePng<P> {
         width: u32,
         height: u32,
         color_type: ColorType,
+        format: ImageFormat,
+        output_files: Option<OutputFiles>,
     ) -> Result<(), Error> {
-        let encoder = PngEncoder::new(BufWriter::new(File::create(
-            directory.as_ref().join(format!("{:08}.png", frame)),
-        )?));
+        let file_name = format!("{:08}.{}", frame, format.extension());
+        let path = directory.as_ref().join(&file_name);
+
+        encode_image(
+            BufWriter::new(File::create(&path)?),
+            &data,
+            width,
+            height,
+            color_type,
+            format,
+        )?;
 
-        encoder.encode(&data, width, height, color_type)?;
+        println!("Saved Frame: {}", file_name);
 
-        println!("Saved Frame: {:08}.png", frame);
+        if let Some(output_files) = output_files {
+            output_files.push(path);
+        }
 
         Ok(())
     }
 }
 
-impl<P: 'static + AsRef<Path> + Send + Sync + Clone> CaptureAction<u8> for SavePng<P> {
+impl<P: 'static + AsRef<Path> + Send + Sync + Clone> CaptureAction<u8> for SaveImage<P> {
     fn exec(&mut self, world: &ResWorld, image_data: &[u8], frame: u64) -> Result<(), Error> {
-        let data = image_data.to_vec();
+        let data = match self.source_format {
+            Some(format) => convert::to_rgba8(format, image_data)?,
+            None => image_data.to_vec(),
+        };
         let resolution = world
             .resources
             .get::<Resolution>()
             .expect("Resolution was not inserted into world");
 
-        self.handles
-            .push(self.thread_pool.spawn_with_handle(Self::save_file(
-                data,
-                frame,
-                self.directory.clone(),
-                resolution.width(),
-                resolution.height(),
-                self.color_type,
-            ))?);
+        self.queue.spawn(Self::save_file(
+            data,
+            frame,
+            self.directory.clone(),
+            resolution.width(),
+            resolution.height(),
+            self.color_type,
+            self.format,
+            self.output_files.clone(),
+        ))
+    }
+}
+
+fn encode_image<W: Write>(
+    mut writer: W,
+    data: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    format: ImageFormat,
+) -> Result<(), Error> {
+    match format {
+        ImageFormat::Png => {
+            PngEncoder::new(writer).encode(data, width, height, color_type)?;
+        }
+        ImageFormat::Jpeg { quality } => {
+            // jpeg has no alpha channel, the gpu may still hand out rgba frames
+            let (data, color_type) = match color_type {
+                ColorType::Rgba8 => (
+                    data.chunks_exact(4)
+                        .flat_map(|pixel| pixel[..3].iter().cloned())
+                        .collect(),
+                    ColorType::Rgb8,
+                ),
+                _ => (data.to_vec(), color_type),
+            };
+
+            JpegEncoder::new_with_quality(&mut writer, quality)
+                .encode(&data, width, height, color_type)?;
+        }
+        ImageFormat::Bmp => {
+            BmpEncoder::new(&mut writer).encode(data, width, height, color_type)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Saves the first captured frame to a single file instead of a numbered sequence, e.g. for
+/// screenshots. Every later frame is ignored.
+#[derive(Debug)]
+pub struct SaveSingleImage<P> {
+    path: P,
+    color_type: ColorType,
+    format: ImageFormat,
+    thread_pool: ThreadPool,
+    handle: Option<RemoteHandle<Result<(), Error>>>,
+}
+
+impl<P: AsRef<Path>> SaveSingleImage<P> {
+    pub fn new(path: P, color_type: ColorType) -> Result<Self, Error> {
+        let thread_pool = ThreadPool::builder().create()?;
+
+        Ok(SaveSingleImage {
+            path,
+            color_type,
+            format: ImageFormat::Png,
+            thread_pool,
+            handle: None,
+        })
+    }
+
+    pub fn with_format(mut self, format: ImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    async fn save_file(
+        data: Vec<u8>,
+        path: P,
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        format: ImageFormat,
+    ) -> Result<(), Error> {
+        encode_image(
+            BufWriter::new(File::create(&path)?),
+            &data,
+            width,
+            height,
+            color_type,
+            format,
+        )?;
+
+        println!("Saved Frame: {:?}", path.as_ref());
 
         Ok(())
     }
 }
 
-impl<P> Drop for SavePng<P> {
-    fn drop(&mut self) {
-        let mut local_pool = LocalPool::new();
+impl<P: 'static + AsRef<Path> + Send + Sync + Clone> CaptureAction<u8> for SaveSingleImage<P> {
+    fn exec(&mut self, world: &ResWorld, image_data: &[u8], _frame: u64) -> Result<(), Error> {
+        if self.handle.is_some() {
+            return Ok(());
+        }
 
-        for handle in self.handles.drain(..) {
-            if let Err(err) = local_pool.run_until(handle) {
+        let resolution = world
+            .resources
+            .get::<Resolution>()
+            .expect("Resolution was not inserted into world");
+
+        self.handle = Some(self.thread_pool.spawn_with_handle(Self::save_file(
+            image_data.to_vec(),
+            self.path.clone(),
+            resolution.width(),
+            resolution.height(),
+            self.color_type,
+            self.format,
+        ))?);
+
+        Ok(())
+    }
+}
+
+impl<P> Drop for SaveSingleImage<P> {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            if let Err(err) = LocalPool::new().run_until(handle) {
                 println!("{:?}", err)
             }
         }
     }
 }
 
-pub trait GraphCreator<B: Backend> {
-    fn rebuild(&mut self, world: &ResWorld) -> bool;
+/// The bytes of the last frame a `CollectFrame` captured, `None` until one arrived or after it was
+/// taken
+pub type CollectedFrame = Arc<Mutex<Option<Vec<u8>>>>;
+
+/// Keeps the last captured frame in memory instead of saving it, e.g. to embed the visualizer in
+/// another application. The capture node reads a frame back once its slot is used again, so after
+/// a render the collected frame is `frames_in_flight` renders old. `RenderingSystem::flush` reads
+/// back the frames still in flight.
+#[derive(Debug, Clone)]
+pub struct CollectFrame {
+    frame: CollectedFrame,
+}
 
-    fn build(
-        &mut self,
-        world: &ResWorld,
-        factory: &mut Factory<B>,
-        families: &mut Families<B>,
-    ) -> Result<Graph<B, ResWorld>, Error>;
+impl CollectFrame {
+    pub fn new(frame: CollectedFrame) -> Self {
+        CollectFrame { frame }
+    }
+
+    pub fn frame(&self) -> &CollectedFrame {
+        &self.frame
+    }
 }
 
-pub struct SphereVisualizerGraphCreator<B: Backend, O: Output<B>> {
-    state_id: StateId,
-    output: O,
-    phantom_data: PhantomData<B>,
+impl CaptureAction<u8> for CollectFrame {
+    fn exec(&mut self, _world: &ResWorld, image_data: &[u8], _frame: u64) -> Result<(), Error> {
+        let mut frame = self
+            .frame
+            .lock()
+            .map_err(|_| anyhow!("the collected frame was poisoned"))?;
+
+        // reuses the buffer if the last frame was not taken
+        let buffer = frame.get_or_insert_with(Vec::new);
+        buffer.clear();
+        buffer.extend_from_slice(image_data);
+
+        Ok(())
+    }
 }
 
-impl<B: Backend, O: Output<B>> SphereVisualizerGraphCreator<B, O> {
-    pub fn new(world: &ResWorld, output: O) -> Self {
-        let resolution = world
-            .resources
-            .get::<Resolution>()
-            .expect("resolution was not inserted into world");
+/// Saves frames as OpenEXR files with float channels, so the linear colors of the comp pass
+/// survive unclamped. The comp pass renders into the first of `FORMATS` the gpu can blend into,
+/// half floats are captured as `u16` bits and written as 16 bit channels, 32 bit floats as `f32`.
+#[derive(Debug)]
+pub struct SaveExr<P> {
+    directory: P,
+    queue: SaveQueue,
+    output_files: Option<OutputFiles>,
+}
 
-        SphereVisualizerGraphCreator {
-            state_id: resolution.changed().register(),
-            output,
-            phantom_data: PhantomData,
+impl<P: AsRef<Path>> SaveExr<P> {
+    /// Blending into 32 bit float attachments is optional, so half floats are preferred
+    pub const FORMATS: [Format; 2] = [Format::Rgba16Sfloat, Format::Rgba32Sfloat];
+
+    pub fn new(directory: P) -> Result<Self, Error> {
+        Ok(SaveExr {
+            directory,
+            queue: SaveQueue::new()?,
+            output_files: None,
+        })
+    }
+
+    /// The number of frames that can wait to be written, see `SaveQueue`
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.queue.set_max_pending(max_pending);
+        self
+    }
+
+    pub fn with_output_files(mut self, output_files: OutputFiles) -> Self {
+        self.output_files = Some(output_files);
+        self
+    }
+
+    async fn save_file<S: 'static + IntoSample + Copy + Send + Sync>(
+        data: Vec<S>,
+        frame: u64,
+        directory: P,
+        width: u32,
+        height: u32,
+        output_files: Option<OutputFiles>,
+    ) -> Result<(), Error> {
+        let file_name = format!("{:08}.exr", frame);
+        let path = directory.as_ref().join(&file_name);
+
+        exr::prelude::write_rgba_file(&path, width as usize, height as usize, |x, y| {
+            let index = (y * width as usize + x) * 4;
+            (
+                data[index],
+                data[index + 1],
+                data[index + 2],
+                data[index + 3],
+            )
+        })?;
+
+        println!("Saved Frame: {}", file_name);
+
+        if let Some(output_files) = output_files {
+            output_files.push(path);
         }
+
+        Ok(())
     }
 }
 
-impl<B: Backend, O: Output<B>> GraphCreator<B> for SphereVisualizerGraphCreator<B, O> {
-    fn rebuild(&mut self, world: &ResWorld) -> bool {
+impl<P: 'static + AsRef<Path> + Send + Sync + Clone> CaptureAction<f32> for SaveExr<P> {
+    fn exec(&mut self, world: &ResWorld, image_data: &[f32], frame: u64) -> Result<(), Error> {
         let resolution = world
             .resources
             .get::<Resolution>()
-            .expect("resolution was not inserted into world");
+            .expect("Resolution was not inserted into world");
 
-        resolution.changed().has_changed(&mut self.state_id)
+        self.queue.spawn(Self::save_file(
+            image_data.to_vec(),
+            frame,
+            self.directory.clone(),
+            resolution.width(),
+            resolution.height(),
+            self.output_files.clone(),
+        ))
     }
+}
 
-    fn build(
-        &mut self,
-        world: &ResWorld,
-        factory: &mut Factory<B>,
-        families: &mut Families<B>,
-    ) -> Result<Graph<B, ResWorld>, Error> {
+/// The bits of `Format::Rgba16Sfloat` channels
+impl<P: 'static + AsRef<Path> + Send + Sync + Clone> CaptureAction<u16> for SaveExr<P> {
+    fn exec(&mut self, world: &ResWorld, image_data: &[u16], frame: u64) -> Result<(), Error> {
         let resolution = world
             .resources
             .get::<Resolution>()
-            .expect("resoulution was not inserted into world");
+            .expect("Resolution was not inserted into world");
 
-        let mut graph_builder = GraphBuilder::new().with_frames_in_flight(3);
+        self.queue.spawn(Self::save_file(
+            image_data
+                .iter()
+                .map(|bits| f16::from_bits(*bits))
+                .collect::<Vec<_>>(),
+            frame,
+            self.directory.clone(),
+            resolution.width(),
+            resolution.height(),
+            self.output_files.clone(),
+        ))
+    }
+}
 
-        let shalf_4d_format = choose_format(
-            factory,
-            &[
-                Format::Rgba16Sfloat,
-                Format::Rgba32Sfloat,
-                Format::Rgba64Sfloat,
-            ],
-            Tiling::Optimal,
-            ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
-        )
-        .ok_or(anyhow!("could not find any 4d sfloat format"))?;
+/// Encodes the frames into an H.264 video by piping them into an ffmpeg child process, which has
+/// to be on the path. Relies on the capture node handing out the frames in order.
+#[derive(Debug)]
+pub struct SaveVideo {
+    path: PathBuf,
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+    output_files: Option<OutputFiles>,
+}
 
-        let sfloat_1d_format = choose_format(
-            factory,
-            &[Format::R32Sfloat, Format::R64Sfloat, Format::R16Sfloat],
-            Tiling::Optimal,
-            ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
-        )
-        .ok_or(anyhow!("could not find any 1d sfloat format"))?;
+impl SaveVideo {
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        color_type: ColorType,
+        resolution: &Resolution,
+        fps: f32,
+    ) -> Result<Self, Error> {
+        let pixel_format = match color_type {
+            ColorType::Rgb8 => "rgb24",
+            ColorType::Rgba8 => "rgba",
+            _ => bail!("{:?} frames can not be encoded into a video", color_type),
+        };
 
-        let byte_unorm_4d_format = choose_format(
-            factory,
-            &[Format::Rgba8Unorm, Format::Bgra8Unorm],
-            Tiling::Optimal,
-            ImageFeature::COLOR_ATTACHMENT,
-        )
-        .ok_or(anyhow!("could not find any 4d byte unorm format"))?;
+        let mut child = Command::new("ffmpeg")
+            .args(&["-y", "-loglevel", "error"])
+            .args(&["-f", "rawvideo", "-pix_fmt", pixel_format])
+            .args(&[
+                "-s",
+                &format!("{}x{}", resolution.width(), resolution.height()),
+            ])
+            .args(&["-r", &fps.to_string(), "-i", "-"])
+            // yuv420p needs an even resolution
+            .args(&["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+            .args(&["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(path.as_ref())
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow!("could not start ffmpeg: {}", err))?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or(anyhow!("the stdin of ffmpeg could not be acquired"))?;
+
+        Ok(SaveVideo {
+            path: path.as_ref().to_path_buf(),
+            child,
+            stdin: Some(BufWriter::new(stdin)),
+            output_files: None,
+        })
+    }
 
-        let normal_format = choose_format(
-            factory,
-            &[
-                Format::A2r10g10b10Unorm,
-                Format::A2b10g10r10Unorm,
-                Format::Rgba16Unorm,
-                Format::Rgba8Unorm,
-                Format::Bgra8Unorm,
-            ],
-            Tiling::Optimal,
-            ImageFeature::COLOR_ATTACHMENT,
-        )
-        .ok_or(anyhow!("could not find any 4d word unorm format"))?;
+    pub fn with_output_files(mut self, output_files: OutputFiles) -> Self {
+        self.output_files = Some(output_files);
+        self
+    }
 
-        let depth_stencil_format = choose_format(
-            factory,
-            &[
-                Format::D32Sfloat,
-                Format::D32SfloatS8Uint,
-                Format::D24UnormS8Uint,
-            ],
-            Tiling::Optimal,
-            ImageFeature::DEPTH_STENCIL_ATTACHMENT,
-        )
-        .ok_or(anyhow!("could not find any depth stencil format"))?;
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(mut stdin) = self.stdin.take() {
+            stdin.flush()?;
+        }
 
-        let gbuffer_pos = graph_builder.create_image(
-            resolution.kind(),
-            1,
-            shalf_4d_format,
-            Some(ClearValue {
-                color: ClearColor {
-                    float32: [0.0, 0.0, 0.0, 1.0],
-                },
-            }),
-        );
+        let status = self.child.wait()?;
+        if !status.success() {
+            bail!("ffmpeg exited with {}", status);
+        }
 
-        let gbuffer_norm = graph_builder.create_image(
-            resolution.kind(),
-            1,
-            normal_format,
-            Some(ClearValue {
-                color: ClearColor {
+        println!("Saved Video: {}", self.path.display());
+
+        if let Some(output_files) = &self.output_files {
+            output_files.push(self.path.clone());
+        }
+
+        Ok(())
+    }
+}
+
+impl CaptureAction<u8> for SaveVideo {
+    fn exec(&mut self, _world: &ResWorld, image_data: &[u8], _frame: u64) -> Result<(), Error> {
+        self.stdin
+            .as_mut()
+            .ok_or(anyhow!("the video was already finished"))?
+            .write_all(image_data)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for SaveVideo {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            println!("{:?}", err)
+        }
+    }
+}
+
+pub trait GraphCreator<B: Backend> {
+    fn rebuild(&mut self, world: &ResWorld) -> bool;
+
+    fn build(
+        &mut self,
+        world: &ResWorld,
+        factory: &mut Factory<B>,
+        families: &mut Families<B>,
+    ) -> Result<Graph<B, ResWorld>, Error>;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GBufferTarget {
+    Position,
+    Normal,
+    Albedo,
+    N,
+}
+
+impl GBufferTarget {
+    pub const ALL: [GBufferTarget; 4] = [
+        GBufferTarget::Position,
+        GBufferTarget::Normal,
+        GBufferTarget::Albedo,
+        GBufferTarget::N,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GBufferTarget::Position => "position",
+            GBufferTarget::Normal => "normal",
+            GBufferTarget::Albedo => "albedo",
+            GBufferTarget::N => "n",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|target| target.name() == name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GBufferDump {
+    pub directory: PathBuf,
+    pub targets: Vec<GBufferTarget>,
+}
+
+/// How the spheres are drawn
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RenderStyle {
+    /// Lit 3D spheres with ambient occlusion and shadows, shaded from the gbuffer
+    Lit,
+    /// Additively blended radial gradients drawn straight into the output, skipping the gbuffer,
+    /// dfao and rtsh passes. Cheaper and softer than `Lit`.
+    Glow,
+}
+
+impl RenderStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lit" => Some(RenderStyle::Lit),
+            "glow" => Some(RenderStyle::Glow),
+            _ => None,
+        }
+    }
+}
+
+/// Combinations of the `GraphConfig` fields trading quality for speed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+impl QualityPreset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(QualityPreset::Low),
+            "medium" => Some(QualityPreset::Medium),
+            "high" => Some(QualityPreset::High),
+            "ultra" => Some(QualityPreset::Ultra),
+            _ => None,
+        }
+    }
+
+    /// The dfao and rtsh passes cost per covered pixel, so the more pixels are rendered the
+    /// cheaper the preset
+    pub fn for_resolution(width: u32, height: u32) -> Self {
+        match width as u64 * height as u64 {
+            pixels if pixels <= 1280 * 720 => QualityPreset::Ultra,
+            pixels if pixels <= 1920 * 1080 => QualityPreset::High,
+            pixels if pixels <= 2560 * 1440 => QualityPreset::Medium,
+            _ => QualityPreset::Low,
+        }
+    }
+
+    pub fn dfao_iterations(&self) -> u32 {
+        match self {
+            QualityPreset::Low => 2,
+            QualityPreset::Medium => 3,
+            QualityPreset::High => 5,
+            QualityPreset::Ultra => 7,
+        }
+    }
+
+    pub fn proxy(&self) -> ProxyParams {
+        let (dfao_subdivisions, rtsh_sides) = match self {
+            QualityPreset::Low => (0, 4),
+            QualityPreset::Medium => (1, 6),
+            QualityPreset::High => (2, 8),
+            QualityPreset::Ultra => (3, 12),
+        };
+
+        ProxyParams {
+            dfao_subdivisions,
+            rtsh_sides,
+            ..Default::default()
+        }
+    }
+}
+
+/// Everything that decides which passes the graph consists of and how they are set up
+#[derive(Clone, Debug)]
+pub struct GraphConfig {
+    pub render_style: RenderStyle,
+    pub comp_filter: Filter,
+    /// The alpha the comp pass writes. Anything but `AlphaMode::Opaque` needs an output format
+    /// with an alpha channel to be of any use.
+    pub alpha_mode: AlphaMode,
+    pub depth_convention: DepthConvention,
+    pub proxy: ProxyParams,
+    pub dfao: DFAOConfig,
+    /// Bounds the number of frames submitted to the gpu that have not completed yet. Every node
+    /// allocates its per frame resources (including the capture download images) per frame in
+    /// flight, so this also bounds their memory. Has to be at least 1, fewer frames lower the
+    /// latency of the realtime mode.
+    pub frames_in_flight: u32,
+    pub gbuffer_dump: Option<GBufferDump>,
+    /// Renders the scene into a float image and adds its blurred bright parts back before it is
+    /// written to the output, see `BloomParams`. Without it the scene is rendered straight into
+    /// the output.
+    pub bloom: bool,
+    /// Blurs the comp output by the distance from the focal plane, see `DepthOfField`. Needs the
+    /// gbuffer, so it is only supported by `RenderStyle::Lit`.
+    pub depth_of_field: bool,
+    pub antialiasing: Antialiasing,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        GraphConfig {
+            render_style: RenderStyle::Lit,
+            comp_filter: Filter::Nearest,
+            alpha_mode: AlphaMode::Opaque,
+            depth_convention: DepthConvention::Standard,
+            proxy: ProxyParams::default(),
+            dfao: DFAOConfig::default(),
+            frames_in_flight: 3,
+            gbuffer_dump: None,
+            bloom: false,
+            depth_of_field: false,
+            antialiasing: Antialiasing::None,
+        }
+    }
+}
+
+impl GraphConfig {
+    /// Sets the fields covered by the preset
+    pub fn with_quality(mut self, preset: QualityPreset) -> Self {
+        self.dfao.iterations = preset.dfao_iterations();
+        self.proxy = preset.proxy();
+        self
+    }
+}
+
+/// Assembles the passes selected by `config` and hands the final subpass to `output`
+pub fn build_from_config<B: Backend, O: Output<B>>(
+    config: &GraphConfig,
+    output: &mut O,
+    world: &ResWorld,
+    factory: &mut Factory<B>,
+    families: &mut Families<B>,
+) -> Result<Graph<B, ResWorld>, Error> {
+    let (graph_builder, _) = graph_builder_from_config(config, output, world, factory)?;
+
+    let graph = graph_builder
+        .build(factory, families, world)
+        .map_err(|e| anyhow!("{:?}", e))?;
+
+    factory.maintain(families);
+
+    Ok(graph)
+}
+
+/// Adds the passes selected by `config` to a new graph builder, along with the names of the nodes
+/// it added
+fn graph_builder_from_config<B: Backend, O: Output<B>>(
+    config: &GraphConfig,
+    output: &mut O,
+    world: &ResWorld,
+    factory: &mut Factory<B>,
+) -> Result<(GraphBuilder<B, ResWorld>, GraphNodes), Error> {
+    let resolution = world
+        .resources
+        .get::<Resolution>()
+        .expect("resoulution was not inserted into world");
+
+    if config.frames_in_flight < 1 {
+        bail!("at least one frame has to be in flight");
+    }
+
+    if config.depth_of_field && config.render_style != RenderStyle::Lit {
+        bail!("depth of field needs the lit render style");
+    }
+
+    let mut graph_builder = GraphBuilder::new().with_frames_in_flight(config.frames_in_flight);
+
+    let output_format = output.format(world, factory)?;
+
+    // the format of the image the antialiasing samples, the comp or bloom pass writes it tone
+    // mapped and encoded just like it would write the output
+    let antialiasing_input_format = match config.antialiasing {
+        Antialiasing::None => output_format,
+        Antialiasing::Fxaa(_) => fxaa_input_format(factory, output_format)?,
+    };
+
+    let scene_format = if config.bloom {
+        choose_format(
+            factory,
+            &[Format::Rgba16Sfloat, Format::Rgba32Sfloat],
+            Tiling::Optimal,
+            ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
+        )
+        .ok_or(anyhow!("could not find any 4d sfloat format for the bloom"))?
+    } else {
+        antialiasing_input_format
+    };
+
+    let polygon_mode = polygon_mode(world, factory);
+
+    let mut nodes = GraphNodes::default();
+    let mut timestamps = Timestamps::new(world, factory);
+
+    let (subpass, clear_color) = match config.render_style {
+        RenderStyle::Lit => (
+            build_lit(
+                config,
+                factory,
+                &mut graph_builder,
+                &resolution,
+                scene_format,
+                polygon_mode,
+                &mut nodes,
+                &mut timestamps,
+            )?,
+            None,
+        ),
+        RenderStyle::Glow => {
+            let mut glow = GlowDesc::new(scene_format).builder();
+
+            if let Some(start) = timestamps.add(&mut graph_builder, "start", &[]) {
+                glow.add_dependency(start);
+            }
+
+            (
+                glow.into_subpass(),
+                Some(ClearColor {
                     float32: [0.0, 0.0, 0.0, 1.0],
-                },
-            }),
-        );
+                }),
+            )
+        }
+    };
 
-        let gbuffer_color = graph_builder.create_image(
-            resolution.kind(),
-            1,
-            byte_unorm_4d_format,
-            Some(ClearValue {
-                color: ClearColor {
-                    float32: [1.0, 1.0, 1.0, 1.0],
-                },
-            }),
-        );
+    let (subpass, clear_color) = if config.bloom {
+        (
+            build_bloom(
+                &mut graph_builder,
+                &mut nodes,
+                &resolution,
+                subpass,
+                clear_color,
+                scene_format,
+                antialiasing_input_format,
+            ),
+            None,
+        )
+    } else {
+        (subpass, clear_color)
+    };
 
-        let gbuffer_n = graph_builder.create_image(
-            resolution.kind(),
-            1,
-            sfloat_1d_format,
-            Some(ClearValue {
-                color: ClearColor {
-                    float32: [1.0, 1.0, 1.0, 1.0],
-                },
-            }),
-        );
+    let (subpass, clear_color) = match config.antialiasing {
+        Antialiasing::None => (subpass, clear_color),
+        Antialiasing::Fxaa(quality) => (
+            build_fxaa(
+                &mut graph_builder,
+                &mut nodes,
+                &resolution,
+                subpass,
+                clear_color,
+                quality,
+                antialiasing_input_format,
+                output_format,
+            ),
+            None,
+        ),
+    };
 
-        let gbuffer_depth_stencil = graph_builder.create_image(
-            resolution.kind(),
-            1,
-            depth_stencil_format,
-            Some(ClearValue {
-                depth_stencil: ClearDepthStencil {
-                    depth: 1.0,
-                    stencil: 0,
-                },
-            }),
-        );
+    let output_node = output.build(
+        world,
+        factory,
+        &mut graph_builder,
+        subpass,
+        &resolution,
+        clear_color,
+    )?;
+    nodes.names.push("output");
+
+    let last_pass = match config.render_style {
+        RenderStyle::Lit => "comp",
+        RenderStyle::Glow => "glow",
+    };
 
-        let gbuffer = graph_builder.add_node(
-            GBufferDesc
-                .builder()
-                .into_subpass()
-                .with_color(gbuffer_pos)
-                .with_color(gbuffer_norm)
-                .with_color(gbuffer_color)
-                .with_color(gbuffer_n)
-                .with_depth_stencil(gbuffer_depth_stencil)
-                .into_pass(),
-        );
+    timestamps.add(&mut graph_builder, last_pass, &[output_node]);
+    timestamps.finish(world);
 
-        let dfao_occlusion = graph_builder.create_image(
-            resolution.kind(),
-            1,
-            sfloat_1d_format,
-            Some(ClearValue {
-                color: ClearColor {
-                    float32: [1.0, 1.0, 1.0, 1.0],
-                },
-            }),
+    Ok((graph_builder, nodes))
+}
+
+/// The names of the nodes a graph is built from, in the order they were added. The timestamps are
+/// left out, they only profile the other nodes.
+#[derive(Debug, Default)]
+struct GraphNodes {
+    names: Vec<&'static str>,
+}
+
+impl GraphNodes {
+    /// Adds `builder` to the graph as a node named `name`
+    fn add<B: Backend, N: NodeBuilder<B, ResWorld> + 'static>(
+        &mut self,
+        graph_builder: &mut GraphBuilder<B, ResWorld>,
+        name: &'static str,
+        builder: N,
+    ) -> NodeId {
+        self.names.push(name);
+
+        graph_builder.add_node(builder)
+    }
+}
+
+/// The timestamps written for the `GpuProfiler`. Nothing is added if there is no profiler in the
+/// world or the gpu can not write timestamps.
+struct Timestamps {
+    enabled: bool,
+    labels: Vec<&'static str>,
+}
+
+impl Timestamps {
+    fn new<B: Backend>(world: &ResWorld, factory: &Factory<B>) -> Self {
+        let profile = world.resources.get::<GpuProfiler>().is_some();
+        let supported = factory.physical().limits().timestamp_compute_and_graphics;
+
+        if profile && !supported {
+            println!("warning: the gpu can not write timestamps, the passes are not profiled");
+        }
+
+        Timestamps {
+            enabled: profile && supported,
+            labels: vec![],
+        }
+    }
+
+    /// Adds a timestamp written once `dependencies` finished, the time since the previous
+    /// timestamp is reported as `label`
+    fn add<B: Backend>(
+        &mut self,
+        graph_builder: &mut GraphBuilder<B, ResWorld>,
+        label: &'static str,
+        dependencies: &[NodeId],
+    ) -> Option<NodeId> {
+        if !self.enabled {
+            return None;
+        }
+
+        let mut timestamp = TimestampDesc::new(self.labels.len()).builder();
+
+        for dependency in dependencies {
+            timestamp.add_dependency(*dependency);
+        }
+
+        self.labels.push(label);
+
+        Some(graph_builder.add_node(timestamp))
+    }
+
+    fn finish(self, world: &ResWorld) {
+        if let (true, Some(mut gpu_profiler)) =
+            (self.enabled, world.resources.get_mut::<GpuProfiler>())
+        {
+            gpu_profiler.set_labels(self.labels);
+        }
+    }
+}
+
+/// The polygon mode of the `DebugRender` resource, or `PolygonMode::Fill` if the gpu can not
+/// draw anything else
+fn polygon_mode<B: Backend>(world: &ResWorld, factory: &Factory<B>) -> PolygonMode {
+    let polygon_mode = world
+        .resources
+        .get::<DebugRender>()
+        .map(|debug_render| debug_render.polygon_mode())
+        .unwrap_or(PolygonMode::Fill);
+
+    if polygon_mode != PolygonMode::Fill
+        && !factory
+            .physical()
+            .features()
+            .contains(Features::NON_FILL_POLYGON_MODE)
+    {
+        println!(
+            "warning: the gpu can not draw {:?} polygons, falling back to fill",
+            polygon_mode
         );
+        return PolygonMode::Fill;
+    }
 
-        let mut comp_desc = CompDesc.builder();
+    polygon_mode
+}
 
-        for dfao_iter in 1..=5 {
-            let params = DFAOParams {
-                offset: dfao_iter as f32 * 0.35,
-                factor: 1.0 / 2.0f32.powi(dfao_iter),
-            };
+/// Adds the gbuffer, dfao and rtsh passes and returns the comp subpass shading them, or the
+/// depth of field subpass behind it
+fn build_lit<B: Backend>(
+    config: &GraphConfig,
+    factory: &mut Factory<B>,
+    graph_builder: &mut GraphBuilder<B, ResWorld>,
+    resolution: &Resolution,
+    output_format: Format,
+    polygon_mode: PolygonMode,
+    nodes: &mut GraphNodes,
+    timestamps: &mut Timestamps,
+) -> Result<SubpassBuilder<B, ResWorld>, Error> {
+    let shalf_4d_format = choose_format(
+        factory,
+        &[
+            Format::Rgba16Sfloat,
+            Format::Rgba32Sfloat,
+            Format::Rgba64Sfloat,
+        ],
+        Tiling::Optimal,
+        ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
+    )
+    .ok_or(anyhow!("could not find any 4d sfloat format"))?;
+
+    let sfloat_1d_format = choose_format(
+        factory,
+        &[Format::R32Sfloat, Format::R64Sfloat, Format::R16Sfloat],
+        Tiling::Optimal,
+        ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
+    )
+    .ok_or(anyhow!("could not find any 1d sfloat format"))?;
+
+    let byte_unorm_4d_format = choose_format(
+        factory,
+        &[Format::Rgba8Unorm, Format::Bgra8Unorm],
+        Tiling::Optimal,
+        ImageFeature::COLOR_ATTACHMENT,
+    )
+    .ok_or(anyhow!("could not find any 4d byte unorm format"))?;
+
+    let normal_format = choose_format(
+        factory,
+        &NormalEncoding::FORMATS,
+        Tiling::Optimal,
+        ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
+    )
+    .ok_or(anyhow!("could not find any normal format"))?;
+
+    let normal_encoding = NormalEncoding::from_format(normal_format)
+        .ok_or(anyhow!("no normal encoding for {:?}", normal_format))?;
+
+    let depth_stencil_format = choose_format(
+        factory,
+        &[
+            Format::D32Sfloat,
+            Format::D32SfloatS8Uint,
+            Format::D24UnormS8Uint,
+        ],
+        Tiling::Optimal,
+        ImageFeature::DEPTH_STENCIL_ATTACHMENT,
+    )
+    .ok_or(anyhow!("could not find any depth stencil format"))?;
+
+    let gbuffer_pos = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        shalf_4d_format,
+        Some(ClearValue {
+            color: ClearColor {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+        }),
+    );
+
+    let gbuffer_norm = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        normal_format,
+        Some(ClearValue {
+            color: ClearColor {
+                float32: normal_encoding.clear_color(),
+            },
+        }),
+    );
+
+    let gbuffer_color = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        byte_unorm_4d_format,
+        Some(ClearValue {
+            color: ClearColor {
+                float32: [1.0, 1.0, 1.0, 1.0],
+            },
+        }),
+    );
+
+    let gbuffer_n = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        sfloat_1d_format,
+        Some(ClearValue {
+            color: ClearColor {
+                float32: [1.0, 1.0, 1.0, 1.0],
+            },
+        }),
+    );
+
+    let gbuffer_depth_stencil = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        depth_stencil_format,
+        Some(ClearValue {
+            depth_stencil: ClearDepthStencil {
+                depth: config.depth_convention.clear_depth(),
+                stencil: 0,
+            },
+        }),
+    );
+
+    let mut gbuffer_desc = GBufferDesc::new(normal_encoding, config.depth_convention)
+        .with_polygon_mode(polygon_mode)
+        .builder();
+
+    if let Some(start) = timestamps.add(graph_builder, "start", &[]) {
+        gbuffer_desc.add_dependency(start);
+    }
 
-            let dfao_distance = graph_builder.create_image(
-                resolution.kind(),
-                1,
-                sfloat_1d_format,
-                Some(ClearValue {
-                    color: ClearColor {
-                        float32: [params.offset, params.offset, params.offset, params.offset],
-                    },
-                }),
-            );
+    let gbuffer = nodes.add(
+        graph_builder,
+        "gbuffer",
+        gbuffer_desc
+            .into_subpass()
+            .with_color(gbuffer_pos)
+            .with_color(gbuffer_norm)
+            .with_color(gbuffer_color)
+            .with_color(gbuffer_n)
+            .with_depth_stencil(gbuffer_depth_stencil)
+            .into_pass(),
+    );
+
+    if let Some(gbuffer_dump) = &config.gbuffer_dump {
+        for target in &gbuffer_dump.targets {
+            let (image, format) = match target {
+                GBufferTarget::Position => (gbuffer_pos, shalf_4d_format),
+                GBufferTarget::Normal => (gbuffer_norm, normal_format),
+                GBufferTarget::Albedo => (gbuffer_color, byte_unorm_4d_format),
+                GBufferTarget::N => (gbuffer_n, sfloat_1d_format),
+            };
 
-            let dfao_sphere = graph_builder.add_node(
-                DFAOSphereDesc::new(params.clone())
-                    .builder()
-                    .with_dependency(gbuffer)
-                    .with_image(gbuffer_pos)
-                    .with_image(gbuffer_norm)
-                    .into_subpass()
-                    .with_color(dfao_distance)
-                    .into_pass(),
-            );
+            let directory = gbuffer_dump.directory.join(target.name());
+            create_dir_all(&directory)?;
 
-            let dfao_join = graph_builder.add_node(
-                DFAOJoinDesc::new(params.clone())
-                    .builder()
-                    .with_dependency(dfao_sphere)
-                    .with_image(dfao_distance)
-                    .into_subpass()
-                    .with_color(dfao_occlusion)
-                    .into_pass(),
+            let _capture = nodes.add(
+                graph_builder,
+                "gbuffer capture",
+                CaptureDesc::new(
+                    SaveImage::new(directory, ColorType::Rgba8)?.with_source_format(format),
+                )
+                .builder()
+                .with_dependency(gbuffer)
+                .with_image(image),
             );
-
-            comp_desc.add_dependency(dfao_join);
         }
+    }
 
-        let rtsh_shadow = graph_builder.create_image(
+    let gbuffer_timestamp = timestamps.add(graph_builder, "gbuffer", &[gbuffer]);
+
+    let dfao_occlusion = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        sfloat_1d_format,
+        Some(ClearValue {
+            color: ClearColor {
+                float32: [1.0, 1.0, 1.0, 1.0],
+            },
+        }),
+    );
+
+    let mut comp_desc = CompDesc::new(
+        config.comp_filter,
+        normal_encoding,
+        output_format,
+        config.alpha_mode,
+    )
+    .with_occlusion(config.dfao.iterations > 0)
+    .builder();
+
+    let mut dfao_joins = vec![];
+
+    // without iterations nothing writes the occlusion image, the comp pass ignores it then
+    for dfao_iter in 1..=config.dfao.iterations {
+        let params = config.dfao.params(dfao_iter);
+
+        let dfao_distance = graph_builder.create_image(
             resolution.kind(),
             1,
             sfloat_1d_format,
             Some(ClearValue {
                 color: ClearColor {
-                    float32: [1.0, 1.0, 1.0, 1.0],
+                    float32: [params.offset, params.offset, params.offset, params.offset],
                 },
             }),
         );
 
-        let rtsh_sphere = graph_builder.add_node(
-            RTSHSphereDesc
-                .builder()
+        let mut dfao_sphere_desc =
+            DFAOSphereDesc::new(params.clone(), normal_encoding, config.proxy).builder();
+
+        if let Some(gbuffer_timestamp) = gbuffer_timestamp {
+            dfao_sphere_desc.add_dependency(gbuffer_timestamp);
+        }
+
+        let dfao_sphere = nodes.add(
+            graph_builder,
+            "dfao sphere",
+            dfao_sphere_desc
                 .with_dependency(gbuffer)
                 .with_image(gbuffer_pos)
+                .with_image(gbuffer_norm)
+                .into_subpass()
+                .with_color(dfao_distance)
+                .into_pass(),
+        );
+
+        let dfao_join = nodes.add(
+            graph_builder,
+            "dfao join",
+            DFAOJoinDesc::new(params.clone())
+                .builder()
+                .with_dependency(dfao_sphere)
+                .with_image(dfao_distance)
                 .into_subpass()
-                .with_color(rtsh_shadow)
+                .with_color(dfao_occlusion)
                 .into_pass(),
         );
 
-        let comp_subpass = comp_desc
+        comp_desc.add_dependency(dfao_join);
+        dfao_joins.push(dfao_join);
+    }
+
+    let dfao_timestamp = if dfao_joins.is_empty() {
+        gbuffer_timestamp
+    } else {
+        timestamps.add(graph_builder, "dfao", &dfao_joins)
+    };
+
+    let rtsh_shadow = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        sfloat_1d_format,
+        Some(ClearValue {
+            color: ClearColor {
+                float32: [1.0, 1.0, 1.0, 1.0],
+            },
+        }),
+    );
+
+    let mut rtsh_sphere_desc = RTSHSphereDesc::new(config.proxy).builder();
+
+    if let Some(dfao_timestamp) = dfao_timestamp {
+        rtsh_sphere_desc.add_dependency(dfao_timestamp);
+    }
+
+    let rtsh_sphere = nodes.add(
+        graph_builder,
+        "rtsh",
+        rtsh_sphere_desc
             .with_dependency(gbuffer)
-            .with_dependency(rtsh_sphere)
             .with_image(gbuffer_pos)
-            .with_image(gbuffer_norm)
-            .with_image(gbuffer_color)
-            .with_image(gbuffer_n)
-            .with_image(dfao_occlusion)
-            .with_image(rtsh_shadow)
-            .into_subpass();
-
-        self.output.build(
-            world,
-            factory,
-            &mut graph_builder,
-            comp_subpass,
-            &resolution,
-        )?;
+            .into_subpass()
+            .with_color(rtsh_shadow)
+            .into_pass(),
+    );
 
-        let graph = graph_builder
-            .build(factory, families, world)
-            .map_err(|e| anyhow!("{:?}", e))?;
+    if let Some(rtsh_timestamp) = timestamps.add(graph_builder, "rtsh", &[rtsh_sphere]) {
+        comp_desc.add_dependency(rtsh_timestamp);
+    }
+
+    let comp_subpass = comp_desc
+        .with_dependency(gbuffer)
+        .with_dependency(rtsh_sphere)
+        .with_image(gbuffer_pos)
+        .with_image(gbuffer_norm)
+        .with_image(gbuffer_color)
+        .with_image(gbuffer_n)
+        .with_image(dfao_occlusion)
+        .with_image(rtsh_shadow)
+        .into_subpass();
+
+    if !config.depth_of_field {
+        return Ok(comp_subpass);
+    }
 
-        factory.maintain(families);
+    // the depth of field keeps the encoding, so the comp pass writes what it would write into
+    // the output
+    let features = ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED;
 
-        Ok(graph)
+    if !format_supported(factory, output_format, Tiling::Optimal, features) {
+        bail!("the depth of field can not sample {:?}", output_format);
+    }
+
+    let comp_color = graph_builder.create_image(resolution.kind(), 1, output_format, None);
+
+    let comp = nodes.add(
+        graph_builder,
+        "comp",
+        comp_subpass.with_color(comp_color).into_pass(),
+    );
+
+    Ok(DOFDesc::new()
+        .builder()
+        .with_dependency(comp)
+        .with_dependency(gbuffer)
+        .with_image(comp_color)
+        .with_image(gbuffer_pos)
+        .into_subpass())
+}
+
+/// Renders `subpass` into a scene image of `scene_format` and adds the bloom passes behind it.
+/// Returns the combine subpass, which writes the scene with the bloom into the output.
+fn build_bloom<B: Backend>(
+    graph_builder: &mut GraphBuilder<B, ResWorld>,
+    nodes: &mut GraphNodes,
+    resolution: &Resolution,
+    subpass: SubpassBuilder<B, ResWorld>,
+    clear_color: Option<ClearColor>,
+    scene_format: Format,
+    output_format: Format,
+) -> SubpassBuilder<B, ResWorld> {
+    let scene = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        scene_format,
+        clear_color.map(|color| ClearValue { color }),
+    );
+
+    let scene_pass = nodes.add(
+        graph_builder,
+        "bloom scene",
+        subpass.with_color(scene).into_pass(),
+    );
+
+    let half_kind = Kind::D2(
+        (resolution.width() / 2).max(1),
+        (resolution.height() / 2).max(1),
+        1,
+        1,
+    );
+
+    let bright = graph_builder.create_image(half_kind, 1, scene_format, None);
+
+    let bright_pass = nodes.add(
+        graph_builder,
+        "bloom bright",
+        BloomDesc::new(BloomStage::Bright)
+            .builder()
+            .with_dependency(scene_pass)
+            .with_image(scene)
+            .into_subpass()
+            .with_color(bright)
+            .into_pass(),
+    );
+
+    let blur_horizontal = graph_builder.create_image(half_kind, 1, scene_format, None);
+
+    let blur_horizontal_pass = nodes.add(
+        graph_builder,
+        "bloom blur horizontal",
+        BloomDesc::new(BloomStage::BlurHorizontal)
+            .builder()
+            .with_dependency(bright_pass)
+            .with_image(bright)
+            .into_subpass()
+            .with_color(blur_horizontal)
+            .into_pass(),
+    );
+
+    let blur_vertical = graph_builder.create_image(half_kind, 1, scene_format, None);
+
+    let blur_vertical_pass = nodes.add(
+        graph_builder,
+        "bloom blur vertical",
+        BloomDesc::new(BloomStage::BlurVertical)
+            .builder()
+            .with_dependency(blur_horizontal_pass)
+            .with_image(blur_horizontal)
+            .into_subpass()
+            .with_color(blur_vertical)
+            .into_pass(),
+    );
+
+    BloomDesc::combine(output_format)
+        .builder()
+        .with_dependency(scene_pass)
+        .with_dependency(blur_vertical_pass)
+        .with_image(scene)
+        .with_image(blur_vertical)
+        .into_subpass()
+}
+
+/// FXAA finds edges by their luma, which bands in 8 bits per channel, so 16 bit unorm is
+/// preferred. The pass in front of it encodes to srgb into unorm formats, which gives the
+/// perceptual luma FXAA is made for. Float outputs keep their linear colors through a float image.
+fn fxaa_input_format<B: Backend>(
+    factory: &Factory<B>,
+    output_format: Format,
+) -> Result<Format, Error> {
+    let formats: &[Format] = match output_format.base_format().1 {
+        ChannelType::Sfloat | ChannelType::Ufloat => &[Format::Rgba16Sfloat, Format::Rgba32Sfloat],
+        _ => &[Format::Rgba16Unorm, Format::Rgba8Unorm],
+    };
+
+    choose_format(
+        factory,
+        formats,
+        Tiling::Optimal,
+        ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
+    )
+    .ok_or(anyhow!("could not find any 4d format for the fxaa"))
+}
+
+/// Renders `subpass` into an image of `input_format` and returns the fxaa subpass, which writes
+/// the antialiased image into the output.
+fn build_fxaa<B: Backend>(
+    graph_builder: &mut GraphBuilder<B, ResWorld>,
+    nodes: &mut GraphNodes,
+    resolution: &Resolution,
+    subpass: SubpassBuilder<B, ResWorld>,
+    clear_color: Option<ClearColor>,
+    quality: FxaaQuality,
+    input_format: Format,
+    output_format: Format,
+) -> SubpassBuilder<B, ResWorld> {
+    let color = graph_builder.create_image(
+        resolution.kind(),
+        1,
+        input_format,
+        clear_color.map(|color| ClearValue { color }),
+    );
+
+    let color_pass = nodes.add(
+        graph_builder,
+        "fxaa input",
+        subpass.with_color(color).into_pass(),
+    );
+
+    FxaaDesc::new(quality, input_format, output_format)
+        .builder()
+        .with_dependency(color_pass)
+        .with_image(color)
+        .into_subpass()
+}
+
+pub struct SphereVisualizerGraphCreator<B: Backend, O: Output<B>> {
+    state_id: StateId,
+    sphere_state_id: StateId,
+    shader_state_id: Option<StateId>,
+    output: O,
+    config: GraphConfig,
+    phantom_data: PhantomData<B>,
+}
+
+impl<B: Backend, O: Output<B>> SphereVisualizerGraphCreator<B, O> {
+    pub fn new(world: &ResWorld, output: O) -> Self {
+        let resolution = world
+            .resources
+            .get::<Resolution>()
+            .expect("resolution was not inserted into world");
+
+        let sphere_limits = world
+            .resources
+            .get::<SphereLimits>()
+            .expect("limits was not inserted into world");
+
+        let shader_state_id = world
+            .resources
+            .get::<ShaderWatcher>()
+            .map(|shader_watcher| shader_watcher.changed().register());
+
+        SphereVisualizerGraphCreator {
+            state_id: resolution.changed().register(),
+            sphere_state_id: sphere_limits.changed().register(),
+            shader_state_id,
+            output,
+            config: GraphConfig::default(),
+            phantom_data: PhantomData,
+        }
+    }
+
+    pub fn with_config(mut self, config: GraphConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+impl<B: Backend, O: Output<B>> GraphCreator<B> for SphereVisualizerGraphCreator<B, O> {
+    fn rebuild(&mut self, world: &ResWorld) -> bool {
+        let resolution = world
+            .resources
+            .get::<Resolution>()
+            .expect("resolution was not inserted into world");
+
+        let sphere_limits = world
+            .resources
+            .get::<SphereLimits>()
+            .expect("limits was not inserted into world");
+
+        let shaders_changed = match (
+            &mut self.shader_state_id,
+            world.resources.get::<ShaderWatcher>(),
+        ) {
+            (Some(shader_state_id), Some(shader_watcher)) => {
+                shader_watcher.changed().has_changed(shader_state_id)
+            }
+            _ => false,
+        };
+
+        let resolution_changed = resolution.changed().has_changed(&mut self.state_id);
+        let spheres_changed = sphere_limits
+            .changed()
+            .has_changed(&mut self.sphere_state_id);
+
+        resolution_changed || spheres_changed || shaders_changed
+    }
+
+    fn build(
+        &mut self,
+        world: &ResWorld,
+        factory: &mut Factory<B>,
+        families: &mut Families<B>,
+    ) -> Result<Graph<B, ResWorld>, Error> {
+        build_from_config(&self.config, &mut self.output, world, factory, families)
     }
 }
 
@@ -601,6 +1862,51 @@ impl<B: Backend, G: GraphCreator<B>> RenderingSystem<B, G> {
         Ok(())
     }
 
+    /// Reads back every frame still in flight by disposing the graph and building it again. A
+    /// rebuild is expensive, so this is meant for single frames and not for every render.
+    pub fn flush(&mut self, world: &mut ResWorld) -> Result<(), Error> {
+        let mut factory = world
+            .resources
+            .get_mut::<Factory<B>>()
+            .expect("factory was not inserted into world");
+
+        let mut families = world
+            .resources
+            .get_mut::<Families<B>>()
+            .expect("families was not inserted into world");
+
+        if let Some(graph) = self.graph.take() {
+            graph.dispose(&mut factory, world);
+        }
+        self.graph = Some(
+            self.graph_creator
+                .build(world, &mut factory, &mut families)?,
+        );
+
+        Ok(())
+    }
+
+    /// Renders a frame and returns its bytes synchronously. The graph has to capture into a
+    /// `CollectFrame` holding `frame`. The graph is flushed after the render, to avoid the rebuild
+    /// call `render` and take the frame from `frame` directly, which lags behind as described at
+    /// `CollectFrame`.
+    pub fn render_to_buffer(
+        &mut self,
+        world: &mut ResWorld,
+        frame: &CollectedFrame,
+    ) -> Result<Vec<u8>, Error> {
+        self.render(world)?;
+        self.flush(world)?;
+
+        let mut frame = frame
+            .lock()
+            .map_err(|_| anyhow!("the collected frame was poisoned"))?;
+
+        frame.take().ok_or(anyhow!(
+            "no frame was collected, is the output a CollectFrame?"
+        ))
+    }
+
     pub fn dispose(&mut self, world: &mut ResWorld) {
         let mut factory = world
             .resources
@@ -610,5 +1916,678 @@ impl<B: Backend, G: GraphCreator<B>> RenderingSystem<B, G> {
         if let Some(graph) = self.graph.take() {
             graph.dispose(&mut factory, world)
         }
+
+        if let Some(mut capture_pool) = world.resources.get_mut::<CapturePool<B>>() {
+            capture_pool.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use crate::golden_scene;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use crate::test_support::{empty_world, headless};
+    use image::GenericImageView;
+    use legion::prelude::{Resources, Universe};
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use rendy::hal::pso::{BlendState, ColorBlendDesc, ColorMask};
+    use std::fs::remove_dir_all;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    /// What the writers and the thread queueing one frame too many report, in the order it happened
+    #[derive(Debug, PartialEq)]
+    enum SaveEvent {
+        Written(usize),
+        Spawning,
+        Spawned,
+    }
+
+    #[test]
+    fn save_queue_blocks_once_max_pending_frames_wait() {
+        let max_pending = 2;
+        let mut queue = SaveQueue::new().unwrap().with_max_pending(max_pending);
+
+        let (event_sender, events) = channel();
+        let mut releases = vec![];
+
+        for frame in 0..max_pending {
+            let (release, released) = channel::<()>();
+            let event_sender = event_sender.clone();
+
+            queue
+                .spawn(async move {
+                    released.recv()?;
+                    event_sender.send(SaveEvent::Written(frame))?;
+                    Ok::<_, Error>(())
+                })
+                .unwrap();
+
+            releases.push(release);
+        }
+
+        assert_eq!(queue.pending(), max_pending);
+
+        // one frame more than fits has to wait until the oldest frame is written
+        let pushing = thread::spawn(move || {
+            event_sender.send(SaveEvent::Spawning).unwrap();
+            queue.spawn(async { Ok::<_, Error>(()) }).unwrap();
+            event_sender.send(SaveEvent::Spawned).unwrap();
+            queue
+        });
+
+        assert_eq!(events.recv().unwrap(), SaveEvent::Spawning);
+        releases.remove(0).send(()).unwrap();
+
+        // both events arrive either way, the order is only checked once every frame was written
+        let order = vec![events.recv().unwrap(), events.recv().unwrap()];
+
+        let queue = pushing.join().unwrap();
+        let pending = queue.pending();
+
+        releases.remove(0).send(()).unwrap();
+        drop(queue);
+        assert_eq!(events.recv().unwrap(), SaveEvent::Written(1));
+
+        assert_eq!(order, vec![SaveEvent::Written(0), SaveEvent::Spawned]);
+        assert_eq!(pending, max_pending);
+    }
+
+    #[test]
+    fn save_queue_returns_failed_writes() {
+        let mut queue = SaveQueue::new().unwrap().with_max_pending(1);
+
+        queue
+            .spawn(async { Err::<(), _>(anyhow!("could not write the frame")) })
+            .unwrap();
+
+        let err = queue.spawn(async { Ok::<_, Error>(()) }).unwrap_err();
+        assert_eq!(err.to_string(), "could not write the frame");
+    }
+
+    #[test]
+    fn every_image_format_encodes_a_readable_frame() {
+        let rgba = [255; 2 * 2 * 4];
+
+        for name in &["png", "jpg", "bmp"] {
+            let format = ImageFormat::from_name(name).unwrap();
+            assert_eq!(format.extension(), *name);
+
+            let mut encoded = vec![];
+            encode_image(&mut encoded, &rgba, 2, 2, ColorType::Rgba8, format).unwrap();
+
+            let decoded = image::load_from_memory(&encoded).unwrap();
+            assert_eq!(decoded.dimensions(), (2, 2));
+            assert_eq!(decoded.color().has_alpha(), format.supports_alpha());
+        }
+    }
+
+    #[test]
+    fn screenshot_keeps_only_the_first_frame() {
+        let path = std::env::temp_dir().join("rendy-sphere-visualizer-screenshot.bmp");
+
+        let mut world = ResWorld::new(Resources::default(), Universe::new().create_world());
+        world.resources.insert(Resolution::new(2, 2));
+
+        {
+            let mut save_single_image = SaveSingleImage::new(path.clone(), ColorType::Rgba8)
+                .unwrap()
+                .with_format(ImageFormat::Bmp);
+
+            let (white, black) = ([255; 2 * 2 * 4], [0; 2 * 2 * 4]);
+            save_single_image.exec(&world, &white, 0).unwrap();
+            save_single_image.exec(&world, &black, 1).unwrap();
+
+            // dropping waits until the file is written
+        }
+
+        let screenshot = image::open(&path).unwrap().to_rgba();
+        assert_eq!(screenshot.dimensions(), (2, 2));
+        assert!(screenshot.pixels().all(|pixel| pixel.0 == [255; 4]));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn collect_frame_keeps_the_last_frame_until_it_is_taken() {
+        let world = ResWorld::new(Resources::default(), Universe::new().create_world());
+
+        let frame = CollectedFrame::default();
+        let mut collect_frame = CollectFrame::new(frame.clone());
+
+        collect_frame.exec(&world, &[1, 2, 3, 4], 0).unwrap();
+        collect_frame.exec(&world, &[5, 6], 1).unwrap();
+        assert_eq!(frame.lock().unwrap().as_deref(), Some(&[5, 6][..]));
+
+        assert_eq!(frame.lock().unwrap().take(), Some(vec![5, 6]));
+        assert_eq!(*collect_frame.frame().lock().unwrap(), None);
+
+        collect_frame.exec(&world, &[7], 2).unwrap();
+        assert_eq!(frame.lock().unwrap().take(), Some(vec![7]));
+    }
+
+    #[test]
+    fn exr_frames_keep_half_float_channels_unclamped() {
+        let directory = std::env::temp_dir().join("rendy-sphere-visualizer-exr");
+        create_dir_all(&directory).unwrap();
+
+        // two pixels of Rgba16Sfloat bits, as the capture node hands them out
+        let pixels = [4.0, 0.5, 0.25, 1.0, 0.0, 2.5, 0.125, 0.5];
+        let data = pixels
+            .iter()
+            .map(|channel| f16::from_f32(*channel))
+            .collect::<Vec<_>>();
+
+        futures::executor::block_on(SaveExr::save_file(data, 3, directory.clone(), 2, 1, None))
+            .unwrap();
+
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            directory.join("00000003.exr"),
+            |resolution, _| vec![(0.0, 0.0, 0.0, 0.0); resolution.width() * resolution.height()],
+            |read: &mut Vec<(f32, f32, f32, f32)>, position, pixel| read[position.x()] = pixel,
+        )
+        .unwrap();
+
+        let channels = &image.layer_data.channel_data;
+        assert_eq!(
+            channels.channels.0.sample_type,
+            exr::prelude::SampleType::F16
+        );
+        assert_eq!(
+            channels.pixels,
+            vec![(4.0, 0.5, 0.25, 1.0), (0.0, 2.5, 0.125, 0.5)]
+        );
+
+        remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn video_rejects_frames_ffmpeg_can_not_read() {
+        let path = std::env::temp_dir().join("rendy-sphere-visualizer-rejected.mp4");
+        let err = SaveVideo::new(path, ColorType::L8, &Resolution::new(2, 2), 60.0).unwrap_err();
+
+        assert_eq!(err.to_string(), "L8 frames can not be encoded into a video");
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg on the path"]
+    fn video_is_listed_once_every_frame_is_encoded() {
+        let path = std::env::temp_dir().join("rendy-sphere-visualizer-video.mp4");
+        let world = ResWorld::new(Resources::default(), Universe::new().create_world());
+        let output_files = OutputFiles::new();
+
+        {
+            let mut video = SaveVideo::new(&path, ColorType::Rgba8, &Resolution::new(3, 3), 30.0)
+                .unwrap()
+                .with_output_files(output_files.clone());
+
+            for frame in 0..3 {
+                video
+                    .exec(&world, &[frame as u8 * 100; 3 * 3 * 4], frame)
+                    .unwrap();
+            }
+
+            // dropping closes the stream and waits for ffmpeg
+        }
+
+        assert_eq!(output_files.sorted(), vec![path.clone()]);
+        assert!(path.metadata().unwrap().len() > 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn low_preset_is_cheaper_than_high() {
+        let presets = [
+            QualityPreset::Low,
+            QualityPreset::Medium,
+            QualityPreset::High,
+            QualityPreset::Ultra,
+        ];
+
+        for pair in presets.windows(2) {
+            let (cheaper, finer) = (pair[0], pair[1]);
+
+            assert!(cheaper.dfao_iterations() < finer.dfao_iterations());
+            assert!(cheaper.proxy().dfao_subdivisions < finer.proxy().dfao_subdivisions);
+            assert!(cheaper.proxy().rtsh_sides < finer.proxy().rtsh_sides);
+        }
+
+        let config = GraphConfig::default().with_quality(QualityPreset::Low);
+        assert_eq!(config.dfao.iterations, QualityPreset::Low.dfao_iterations());
+        assert_eq!(config.proxy.dfao_subdivisions, 0);
+        assert_eq!(config.proxy.rtsh_sides, 4);
+    }
+
+    #[test]
+    fn larger_resolutions_get_cheaper_presets() {
+        for (width, height, preset) in &[
+            (1280, 720, QualityPreset::Ultra),
+            (1920, 1080, QualityPreset::High),
+            (2560, 1440, QualityPreset::Medium),
+            (3840, 2160, QualityPreset::Low),
+        ] {
+            assert_eq!(QualityPreset::for_resolution(*width, *height), *preset);
+        }
+    }
+
+    /// The names of the nodes `config` adds to a 64x64 graph, without building the graph
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn node_names<B: Backend>(factory: &mut Factory<B>, config: &GraphConfig) -> Vec<&'static str> {
+        let mut world = empty_world();
+        world.resources.insert(Resolution::new(64, 64));
+
+        let mut output = CaptureOutput::new(
+            |_| Ok(CollectFrame::new(CollectedFrame::default())),
+            Format::Rgba8Unorm,
+        );
+
+        let (_, nodes) = graph_builder_from_config(config, &mut output, &world, factory)
+            .expect("could not add the nodes");
+
+        nodes.names
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn gbuffer_dump_captures_every_selected_target() {
+        let directory = std::env::temp_dir().join("rendy-sphere-visualizer-gbuffer-dump");
+        let _ = remove_dir_all(&directory);
+
+        let config = GraphConfig {
+            gbuffer_dump: Some(GBufferDump {
+                directory: directory.clone(),
+                targets: vec![GBufferTarget::Normal, GBufferTarget::N],
+            }),
+            ..GraphConfig::default()
+        };
+
+        let rendy = headless();
+
+        let names = with_any_rendy!((rendy) (factory, families) => {
+            let mut factory = factory;
+            let names = node_names(&mut factory, &config);
+            drop(families);
+            names
+        });
+
+        let gbuffer = names.iter().position(|name| *name == "gbuffer").unwrap();
+        let captures: Vec<_> = names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| **name == "gbuffer capture")
+            .map(|(index, _)| index)
+            .collect();
+
+        assert_eq!(captures, vec![gbuffer + 1, gbuffer + 2]);
+        assert!(directory.join("normal").is_dir());
+        assert!(directory.join("n").is_dir());
+        assert!(!directory.join("position").exists());
+        assert!(!directory.join("albedo").exists());
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn every_dfao_iteration_adds_a_sphere_and_a_join_pass() {
+        let iterations = [0, 1, 3];
+
+        let names = with_any_rendy!((headless()) (factory, families) => {
+            let mut factory = factory;
+            let names: Vec<_> = iterations
+                .iter()
+                .map(|iterations| {
+                    let config = GraphConfig {
+                        dfao: DFAOConfig {
+                            iterations: *iterations,
+                            ..DFAOConfig::default()
+                        },
+                        ..GraphConfig::default()
+                    };
+
+                    node_names(&mut factory, &config)
+                })
+                .collect();
+            drop(families);
+            names
+        });
+
+        for (iterations, names) in iterations.iter().zip(names) {
+            let count = |node| names.iter().filter(|name| **name == node).count();
+
+            assert_eq!(count("dfao sphere"), *iterations as usize);
+            assert_eq!(count("dfao join"), *iterations as usize);
+            assert_eq!(count("output"), 1);
+        }
+    }
+
+    /// The blend states of the glow pass for the backend of `_factory`
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn glow_blend<B: Backend>(_factory: &Factory<B>) -> Vec<ColorBlendDesc> {
+        SimpleGraphicsPipelineDesc::<B, ResWorld>::colors(&GlowDesc::new(Format::Rgba8Unorm))
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn glow_is_a_single_additive_pass() {
+        let config = GraphConfig {
+            render_style: RenderStyle::Glow,
+            ..GraphConfig::default()
+        };
+
+        let (names, blend) = with_any_rendy!((headless()) (factory, families) => {
+            let mut factory = factory;
+            let names = node_names(&mut factory, &config);
+            let blend = glow_blend(&factory);
+            drop(families);
+            (names, blend)
+        });
+
+        // the glow subpass is part of the output pass, there is no gbuffer, dfao or rtsh
+        assert_eq!(names, vec!["output"]);
+        assert_eq!(
+            blend,
+            vec![ColorBlendDesc {
+                mask: ColorMask::ALL,
+                blend: Some(BlendState::ADD),
+            }]
+        );
+    }
+
+    /// The golden scene config with every optional pass disabled
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn minimal_config() -> GraphConfig {
+        GraphConfig {
+            dfao: DFAOConfig {
+                iterations: 0,
+                ..DFAOConfig::default()
+            },
+            ..GraphConfig::default()
+        }
+    }
+
+    /// Renders the golden scene with the `minimal_config` and returns the frame and the passes
+    /// that were profiled, or `None` if the device can not write timestamps
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn render_minimal<B: Backend>(
+        factory: Factory<B>,
+        families: Families<B>,
+    ) -> Result<(Vec<u8>, Option<Vec<&'static str>>), Error> {
+        let timestamps = factory.physical().limits().timestamp_compute_and_graphics;
+
+        let mut world = empty_world();
+        let mut schedule = golden_scene(&mut world, factory, families)?;
+        world.resources.insert(GpuProfiler::new(1));
+
+        let frame = CollectedFrame::default();
+        let collect_frame = CollectFrame::new(frame.clone());
+
+        let graph_creator = SphereVisualizerGraphCreator::<B, _>::new(
+            &world,
+            CaptureOutput::new(move |_| Ok(collect_frame.clone()), Format::Rgba8Unorm),
+        )
+        .with_config(minimal_config());
+
+        let mut rendering_system = RenderingSystem::new(graph_creator, &mut world)?;
+
+        schedule.execute(&mut world.world, &mut world.resources);
+
+        let image_data = rendering_system.render_to_buffer(&mut world, &frame)?;
+
+        rendering_system.dispose(&mut world);
+
+        let labels = world
+            .resources
+            .get::<GpuProfiler>()
+            .expect("gpu profiler was not inserted into world")
+            .labels()
+            .to_vec();
+
+        Ok((image_data, Some(labels).filter(|_| timestamps)))
+    }
+
+    /// Counts the frames read back, which are the frames the gpu completed
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[derive(Debug, Clone, Default)]
+    struct CountFrames(Arc<Mutex<u64>>);
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    impl CaptureAction<u8> for CountFrames {
+        fn exec(
+            &mut self,
+            _world: &ResWorld,
+            _image_data: &[u8],
+            _frame: u64,
+        ) -> Result<(), Error> {
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    /// Renders the golden scene `renders` times and returns how many frames were submitted but
+    /// not completed after every render
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn pending_frames<B: Backend>(
+        factory: Factory<B>,
+        families: Families<B>,
+        frames_in_flight: u32,
+        renders: u64,
+    ) -> Result<Vec<u64>, Error> {
+        let mut world = empty_world();
+        let mut schedule = golden_scene(&mut world, factory, families)?;
+
+        let completed = CountFrames::default();
+        let count_frames = completed.clone();
+
+        let graph_creator = SphereVisualizerGraphCreator::<B, _>::new(
+            &world,
+            CaptureOutput::new(move |_| Ok(count_frames.clone()), Format::Rgba8Unorm),
+        )
+        .with_config(GraphConfig {
+            frames_in_flight,
+            ..minimal_config()
+        });
+
+        let mut rendering_system = RenderingSystem::new(graph_creator, &mut world)?;
+        let mut pending = vec![];
+
+        for submitted in 1..=renders {
+            schedule.execute(&mut world.world, &mut world.resources);
+            rendering_system.render(&mut world)?;
+
+            pending.push(submitted - *completed.0.lock().unwrap());
+        }
+
+        rendering_system.dispose(&mut world);
+
+        Ok(pending)
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn pending_frames_stay_within_frames_in_flight() {
+        for frames_in_flight in [1, 2].iter().cloned() {
+            let pending = with_any_rendy!((headless()) (factory, families) => {
+                pending_frames(factory, families, frames_in_flight, 6).unwrap()
+            });
+
+            assert!(
+                pending
+                    .iter()
+                    .all(|pending| *pending <= frames_in_flight as u64),
+                "{} frames in flight: {:?}",
+                frames_in_flight,
+                pending
+            );
+        }
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn graphs_without_a_frame_in_flight_are_rejected() {
+        let config = GraphConfig {
+            frames_in_flight: 0,
+            ..minimal_config()
+        };
+
+        let err = with_any_rendy!((headless()) (factory, families) => {
+            let mut factory = factory;
+
+            let mut world = empty_world();
+            world.resources.insert(Resolution::new(64, 64));
+            let mut output = CaptureOutput::new(
+                |_| Ok(CollectFrame::new(CollectedFrame::default())),
+                Format::Rgba8Unorm,
+            );
+            let err = graph_builder_from_config(&config, &mut output, &world, &mut factory)
+                .err()
+                .expect("a graph needs a frame in flight");
+
+            drop(families);
+            err
+        });
+
+        assert_eq!(err.to_string(), "at least one frame has to be in flight");
+    }
+
+    /// Asks the graph creator for a rebuild after every change of the sphere count in `counts`
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn rebuilds<B: Backend>(_factory: &Factory<B>, counts: &[usize]) -> Vec<bool> {
+        let mut world = empty_world();
+        world.resources.insert(Resolution::new(64, 64));
+        world
+            .resources
+            .insert(SphereLimits::with_max_sphere_count(4, None, 8).unwrap());
+
+        let mut graph_creator = SphereVisualizerGraphCreator::<B, _>::new(
+            &world,
+            CaptureOutput::new(
+                |_| Ok(CollectFrame::new(CollectedFrame::default())),
+                Format::Rgba8Unorm,
+            ),
+        );
+
+        counts
+            .iter()
+            .map(|count| {
+                world
+                    .resources
+                    .get_mut::<SphereLimits>()
+                    .unwrap()
+                    .set_sphere_count(*count)
+                    .unwrap();
+
+                graph_creator.rebuild(&world)
+            })
+            .collect()
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn graph_is_rebuilt_when_the_sphere_count_changes() {
+        let rebuilds = with_any_rendy!((headless()) (factory, families) => {
+            let rebuilds = rebuilds(&factory, &[4, 5, 5, 3]);
+            drop(families);
+            rebuilds
+        });
+
+        assert_eq!(rebuilds, vec![false, true, false, true]);
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn minimal_graph_is_gbuffer_comp_output() {
+        let names = with_any_rendy!((headless()) (factory, families) => {
+            let mut factory = factory;
+            let names = node_names(&mut factory, &minimal_config());
+            drop(families);
+            names
+        });
+
+        // the comp subpass is part of the output pass
+        assert_eq!(names, vec!["gbuffer", "rtsh", "output"]);
+
+        let (image_data, labels) = with_any_rendy!((headless()) (factory, families) => {
+            render_minimal(factory, families).unwrap()
+        });
+
+        assert_eq!(image_data.len(), 64 * 64 * 4);
+
+        match labels {
+            Some(labels) => assert_eq!(labels, vec!["start", "gbuffer", "rtsh", "comp"]),
+            None => println!("skipping the profiled passes, the device can not write timestamps"),
+        }
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn line_polygons_fall_back_to_fill_without_the_feature() {
+        let (supported, default, line) = with_any_rendy!((headless()) (factory, families) => {
+            let supported = factory
+                .physical()
+                .features()
+                .contains(Features::NON_FILL_POLYGON_MODE);
+
+            let mut world = empty_world();
+            let default = polygon_mode(&world, &factory);
+
+            world
+                .resources
+                .insert(DebugRender::new().with_polygon_mode(PolygonMode::Line));
+            let line = polygon_mode(&world, &factory);
+
+            drop(families);
+            (supported, default, line)
+        });
+
+        assert_eq!(default, PolygonMode::Fill);
+        assert_eq!(
+            line,
+            if supported {
+                PolygonMode::Line
+            } else {
+                PolygonMode::Fill
+            }
+        );
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn depth_of_field_follows_the_comp_pass_of_the_lit_style() {
+        let lit = GraphConfig {
+            depth_of_field: true,
+            ..minimal_config()
+        };
+
+        let glow = GraphConfig {
+            render_style: RenderStyle::Glow,
+            ..lit.clone()
+        };
+
+        let (names, err) = with_any_rendy!((headless()) (factory, families) => {
+            let mut factory = factory;
+            let names = node_names(&mut factory, &lit);
+
+            let mut world = empty_world();
+            world.resources.insert(Resolution::new(64, 64));
+            let mut output = CaptureOutput::new(
+                |_| Ok(CollectFrame::new(CollectedFrame::default())),
+                Format::Rgba8Unorm,
+            );
+            let err = graph_builder_from_config(&glow, &mut output, &world, &mut factory)
+                .err()
+                .expect("the glow style has no gbuffer to focus with");
+
+            drop(families);
+            (names, err)
+        });
+
+        // the comp pass gets its own pass, the depth of field subpass writes the output
+        assert_eq!(names, vec!["gbuffer", "rtsh", "comp", "output"]);
+        assert_eq!(err.to_string(), "depth of field needs the lit render style");
     }
 }
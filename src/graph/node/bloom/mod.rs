@@ -0,0 +1,484 @@
+use crate::ext::{create_fullscreen_triangle, fullscreen_sampler_desc};
+use crate::ext::{GraphContextExt, SAMPLED_IMAGE_IMAGE_ACCESS};
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, CombinedBufferCalculator, DirtyTracker};
+use crate::world::bloom::BloomParams;
+use crate::world::tonemapping::{ToneMapMode, Tonemapping};
+use crate::world::ResWorld;
+
+use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
+use rendy::core::hal::adapter::PhysicalDevice;
+use rendy::core::hal::buffer::Usage as BUsage;
+use rendy::core::hal::device::Device;
+use rendy::core::hal::format::{ChannelType, Format, Swizzle};
+use rendy::core::hal::pso::{
+    BlendState, ColorBlendDesc, ColorMask, CreationError, DepthStencilDesc, Descriptor,
+    DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, Face, Rasterizer,
+    ShaderStageFlags, VertexInputRate,
+};
+use rendy::core::hal::Backend;
+use rendy::factory::Factory;
+use rendy::graph::render::{
+    Layout, PrepareResult, SetLayout, SimpleGraphicsPipeline, SimpleGraphicsPipelineDesc,
+};
+use rendy::graph::{GraphContext, ImageAccess, NodeBuffer, NodeImage};
+use rendy::memory::Dynamic;
+use rendy::mesh::{AsVertex, Mesh, Position};
+use rendy::resource::{
+    Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Filter, Handle, ImageView,
+    Sampler, ViewKind,
+};
+use rendy::shader::{ShaderSet, SpirvShader};
+use std::mem::size_of;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Args {
+    stage: u32,
+    threshold: f32,
+    intensity: f32,
+    encode_srgb: u32,
+    tonemap_mode: u32,
+}
+
+lazy_static::lazy_static! {
+    static ref VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/bloom.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).expect("failed to load vertex shader");
+
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/bloom.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).expect("failed to load fragment shader");
+
+    static ref SHADERS: rendy::shader::ShaderSetBuilder = rendy::shader::ShaderSetBuilder::default()
+        .with_vertex(&*VERTEX).expect("failed to add vertex shader to shader set")
+        .with_fragment(&*FRAGMENT).expect("failed to add fragment shader to shader set");
+}
+
+/// The passes of the bloom, in the order the graph runs them. The values match `STAGE_*` in the
+/// shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BloomStage {
+    /// Downsamples the scene to half the resolution and keeps only what is above the threshold
+    Bright = 0,
+    /// Blurs the bright parts along the x axis
+    BlurHorizontal = 1,
+    /// Blurs the bright parts along the y axis
+    BlurVertical = 2,
+    /// Adds the blurred bright parts onto the scene and encodes the result for the output
+    Combine = 3,
+}
+
+/// One fullscreen pass of the bloom. Every stage samples the image of the previous one, the
+/// combine stage additionally samples the scene.
+#[derive(Debug)]
+pub struct BloomDesc {
+    stage: BloomStage,
+    encode_srgb: bool,
+    hdr: bool,
+}
+
+impl BloomDesc {
+    /// A stage working on the linear colors of the scene
+    pub fn new(stage: BloomStage) -> Self {
+        BloomDesc {
+            stage,
+            encode_srgb: false,
+            hdr: true,
+        }
+    }
+
+    /// The combine stage, which takes over the tone mapping and srgb encoding of the comp pass
+    /// for the output format, see `CompDesc::new`
+    pub fn combine(output_format: Format) -> Self {
+        let channel_type = output_format.base_format().1;
+        let hdr = match channel_type {
+            ChannelType::Sfloat | ChannelType::Ufloat => true,
+            _ => false,
+        };
+
+        BloomDesc {
+            stage: BloomStage::Combine,
+            encode_srgb: channel_type != ChannelType::Srgb && !hdr,
+            hdr,
+        }
+    }
+
+    /// The uniforms of a frame. The comp pass already applied the exposure, so only the curve is
+    /// left to apply.
+    fn args(&self, bloom_params: &BloomParams, tonemapping: &Tonemapping) -> Args {
+        let tonemap_mode = if self.hdr {
+            ToneMapMode::None
+        } else {
+            tonemapping.mode()
+        };
+
+        Args {
+            stage: self.stage as u32,
+            threshold: bloom_params.threshold(),
+            intensity: bloom_params.intensity(),
+            encode_srgb: self.encode_srgb as u32,
+            tonemap_mode: tonemap_mode as u32,
+        }
+    }
+}
+
+impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for BloomDesc {
+    type Pipeline = Bloom<B>;
+
+    fn images(&self) -> Vec<ImageAccess> {
+        match self.stage {
+            BloomStage::Combine => vec![
+                // scene
+                SAMPLED_IMAGE_IMAGE_ACCESS,
+                // bloom
+                SAMPLED_IMAGE_IMAGE_ACCESS,
+            ],
+            _ => vec![SAMPLED_IMAGE_IMAGE_ACCESS],
+        }
+    }
+
+    fn colors(&self) -> Vec<ColorBlendDesc> {
+        vec![ColorBlendDesc {
+            mask: ColorMask::ALL,
+            blend: Some(BlendState::REPLACE),
+        }]
+    }
+
+    fn depth_stencil(&self) -> Option<DepthStencilDesc> {
+        None
+    }
+
+    fn rasterizer(&self) -> Rasterizer {
+        Rasterizer {
+            cull_face: Face::BACK,
+            ..Rasterizer::FILL
+        }
+    }
+
+    fn vertices(&self) -> Vec<(Vec<Element<Format>>, u32, VertexInputRate)> {
+        vec![Position::vertex().gfx_vertex_input_desc(VertexInputRate::Vertex)]
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            sets: vec![
+                SetLayout {
+                    bindings: vec![DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty: DescriptorType::UniformBuffer,
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    }],
+                },
+                SetLayout {
+                    bindings: vec![
+                        DescriptorSetLayoutBinding {
+                            binding: 0,
+                            ty: DescriptorType::Sampler,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                        DescriptorSetLayoutBinding {
+                            binding: 1,
+                            ty: DescriptorType::SampledImage,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                        DescriptorSetLayoutBinding {
+                            binding: 2,
+                            ty: DescriptorType::SampledImage,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                    ],
+                },
+            ],
+            push_constants: vec![],
+        }
+    }
+
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "bloom")
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        _aux: &ResWorld,
+        _buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+        set_layouts: &[Handle<DescriptorSetLayout<B>>],
+    ) -> Result<Self::Pipeline, CreationError> {
+        let source = &images[0];
+
+        let frames = ctx.frames_in_flight;
+        let align = factory
+            .physical()
+            .limits()
+            .min_uniform_buffer_offset_alignment;
+
+        let uniform_indirect_calculator = CombinedBufferCalculator::new(
+            vec![element::<Args>(), element::<DrawIndexedCommand>()],
+            frames as u64,
+            align,
+        );
+
+        let mut uniform_indirect_buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: uniform_indirect_calculator.size(),
+                    usage: BUsage::UNIFORM | BUsage::INDIRECT,
+                },
+                Dynamic,
+            )
+            .expect("could not create buffer");
+
+        let uniform_sets = factory
+            .create_descriptor_sets::<Vec<_>>(set_layouts[0].clone(), frames)
+            .expect("failed to create descriptor set");
+
+        let image_set = factory
+            .create_descriptor_set(set_layouts[1].clone())
+            .expect("failed to create descriptor set");
+
+        let source_view = ctx
+            .create_image_view(factory, source, ViewKind::D2, Swizzle::NO)
+            .expect("failed to create image view");
+
+        // the shader declares the bloom image for every stage, the stages before the combine
+        // stage get a second view of their source image bound to it
+        let bloom = images.get(1).unwrap_or(source);
+
+        let bloom_view = ctx
+            .create_image_view(factory, bloom, ViewKind::D2, Swizzle::NO)
+            .expect("failed to create image view");
+
+        // linear filtering makes the downsampling average 2x2 texels and smooths the upsampling
+        let sampler = factory
+            .create_sampler(fullscreen_sampler_desc(Filter::Linear))
+            .expect("failed to create sampler");
+
+        unsafe {
+            factory.write_descriptor_sets(uniform_sets.iter().enumerate().map(
+                |(frame, uniform_set)| DescriptorSetWrite {
+                    set: uniform_set.raw(),
+                    binding: 0,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Buffer(
+                        uniform_indirect_buffer.raw(),
+                        uniform_indirect_calculator.option_range(0, frame),
+                    )),
+                },
+            ));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Sampler(sampler.raw())),
+            }));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Image(source_view.raw(), source.layout)),
+            }));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 2,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Image(bloom_view.raw(), bloom.layout)),
+            }));
+        }
+
+        let fullscreen_triangle = create_fullscreen_triangle(factory, queue)
+            .expect("failed to create fullscreen triangle");
+
+        for frame in 0..frames {
+            let draw_indexed_command = DrawIndexedCommand {
+                first_index: 0,
+                first_instance: 0,
+                vertex_offset: 0,
+                index_count: fullscreen_triangle.len(),
+                instance_count: 1,
+            };
+
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut uniform_indirect_buffer,
+                        uniform_indirect_calculator.offset(1, frame as usize),
+                        &[draw_indexed_command],
+                    )
+                    .expect("failed to upload indirect draw commands");
+            }
+        }
+
+        Ok(Bloom {
+            uniform_indirect_calculator,
+            uniform_indirect_buffer,
+            fullscreen_triangle,
+            uniform_sets,
+            image_set,
+            source_view,
+            bloom_view,
+            sampler,
+            desc: self,
+            uploads: DirtyTracker::new(frames as usize),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Bloom<B: Backend> {
+    uniform_indirect_calculator: CombinedBufferCalculator,
+    uniform_indirect_buffer: Escape<Buffer<B>>,
+    fullscreen_triangle: Mesh<B>,
+    uniform_sets: Vec<Escape<DescriptorSet<B>>>,
+    image_set: Escape<DescriptorSet<B>>,
+    source_view: Escape<ImageView<B>>,
+    bloom_view: Escape<ImageView<B>>,
+    sampler: Escape<Sampler<B>>,
+    desc: BloomDesc,
+    uploads: DirtyTracker<Args>,
+}
+
+impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for Bloom<B> {
+    type Desc = BloomDesc;
+
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        _set_layouts: &[Handle<DescriptorSetLayout<B>>],
+        index: usize,
+        aux: &ResWorld,
+    ) -> PrepareResult {
+        let bloom_params = aux
+            .resources
+            .get::<BloomParams>()
+            .expect("bloom params were not inserted into world");
+
+        let tonemapping = aux
+            .resources
+            .get::<Tonemapping>()
+            .expect("tonemapping was not inserted into world");
+
+        let args = self.desc.args(&bloom_params, &tonemapping);
+
+        if let Some(args) = self.uploads.update(index, args) {
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_buffer,
+                        self.uniform_indirect_calculator.offset(0, index),
+                        &[*args],
+                    )
+                    .expect("failed to upload uniforms");
+            }
+        }
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw(
+        &mut self,
+        layout: &<B as Backend>::PipelineLayout,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _aux: &ResWorld,
+    ) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                layout,
+                0,
+                Some(self.uniform_sets[index].raw()),
+                None,
+            );
+
+            encoder.bind_graphics_descriptor_sets(layout, 1, Some(self.image_set.raw()), None);
+        }
+
+        self.fullscreen_triangle
+            .bind(0, &[Position::vertex()], &mut encoder)
+            .expect("failed to create fullscreen triangle");
+
+        unsafe {
+            encoder.draw_indexed_indirect(
+                self.uniform_indirect_buffer.raw(),
+                self.uniform_indirect_calculator.offset(1, 0),
+                1,
+                size_of::<DrawIndexedCommand>() as u32,
+            )
+        }
+    }
+
+    fn dispose(self, _factory: &mut Factory<B>, _aux: &ResWorld) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_match_the_shader() {
+        let shader = include_str!("../../../../assets/shaders/bloom.frag");
+
+        for (stage, name) in [
+            (BloomStage::Bright, "BRIGHT"),
+            (BloomStage::BlurHorizontal, "BLUR_HORIZONTAL"),
+            (BloomStage::BlurVertical, "BLUR_VERTICAL"),
+            (BloomStage::Combine, "COMBINE"),
+        ]
+        .iter()
+        .cloned()
+        {
+            assert!(shader.contains(&format!("const uint STAGE_{} = {};", name, stage as u32)));
+            assert_eq!(
+                BloomDesc::new(stage)
+                    .args(&BloomParams::default(), &Tonemapping::default())
+                    .stage,
+                stage as u32
+            );
+        }
+    }
+
+    #[test]
+    fn only_the_combine_stage_tone_maps_and_encodes() {
+        let bloom_params = BloomParams::new().with_threshold(2.0).with_intensity(0.25);
+        let tonemapping = Tonemapping::new(ToneMapMode::Aces).with_exposure(4.0);
+
+        let args = BloomDesc::new(BloomStage::Bright).args(&bloom_params, &tonemapping);
+        assert_eq!(args.threshold, 2.0);
+        assert_eq!(args.intensity, 0.25);
+        assert_eq!(args.tonemap_mode, ToneMapMode::None as u32);
+        assert_eq!(args.encode_srgb, 0);
+
+        let args = BloomDesc::combine(Format::Rgba8Unorm).args(&bloom_params, &tonemapping);
+        assert_eq!(args.tonemap_mode, ToneMapMode::Aces as u32);
+        assert_eq!(args.encode_srgb, 1);
+
+        // a srgb output encodes on store
+        let args = BloomDesc::combine(Format::Rgba8Srgb).args(&bloom_params, &tonemapping);
+        assert_eq!(args.tonemap_mode, ToneMapMode::Aces as u32);
+        assert_eq!(args.encode_srgb, 0);
+
+        // a float output keeps the full range
+        let args = BloomDesc::combine(Format::Rgba16Sfloat).args(&bloom_params, &tonemapping);
+        assert_eq!(args.tonemap_mode, ToneMapMode::None as u32);
+        assert_eq!(args.encode_srgb, 0);
+    }
+}
@@ -1,8 +1,8 @@
 use anyhow::Error;
 
 use rendy::command::{
-    CommandBuffer, CommandPool, ExecutableState, Family, InitialState, MultiShot, PendingState,
-    QueueType, SimultaneousUse, Submit, Transfer,
+    CommandBuffer, CommandPool, ExecutableState, Family, Graphics, InitialState, MultiShot,
+    PendingState, QueueType, SimultaneousUse, Submit,
 };
 use rendy::factory::Factory;
 use rendy::frame::Frames;
@@ -10,24 +10,31 @@ use rendy::graph::{
     gfx_acquire_barriers, gfx_release_barriers, GraphContext, ImageAccess, Node, NodeBuffer,
     NodeBuildError, NodeDesc, NodeImage, NodeSubmittable,
 };
-use rendy::hal::command::ImageCopy;
+use rendy::hal::command::{ImageBlit, ImageCopy};
+use rendy::hal::format::Format;
 
-use rendy::hal::image::{Access as IAccess, Layout as ILayout, Offset as IOffset, Usage as IUsage};
+use rendy::hal::image::{
+    Access as IAccess, Extent, Layout as ILayout, Offset as IOffset, Usage as IUsage,
+};
 use rendy::hal::memory::{Barrier, Dependencies};
 use rendy::hal::pso::PipelineStage;
 use rendy::hal::Backend;
 use rendy::memory::{Block, Download};
 use rendy::resource::{
-    Escape, Image, ImageInfo, Kind, SubresourceLayers, SubresourceRange, Tiling, ViewCapabilities,
+    Escape, Filter, Image, ImageInfo, Kind, SubresourceLayers, SubresourceRange, Tiling,
+    ViewCapabilities,
 };
 use serde::export::PhantomData;
 
+use crate::world::time::HeadlessTime;
 use crate::world::ResWorld;
 use std::fmt::Debug;
+use std::mem::{discriminant, size_of};
 
 #[derive(Debug)]
 pub struct CaptureDesc<A, D> {
     action: A,
+    format: Option<Format>,
     phantom_data: PhantomData<D>,
 }
 
@@ -39,9 +46,17 @@ impl<
     pub fn new(action: A) -> Self {
         CaptureDesc {
             action,
+            format: None,
             phantom_data: PhantomData,
         }
     }
+
+    /// Reads the image back in `format` instead of the format it was rendered in. The image is
+    /// then blitted instead of copied, so both formats have to support blitting.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
 }
 
 impl<
@@ -67,13 +82,15 @@ impl<
         factory: &mut Factory<B>,
         family: &mut Family<B, QueueType>,
         _queue: usize,
-        _aux: &ResWorld,
+        aux: &ResWorld,
         buffers: Vec<NodeBuffer>,
         images: Vec<NodeImage>,
     ) -> Result<Self::Node, NodeBuildError> {
         assert_eq!(buffers.len(), 0);
         assert_eq!(images.len(), 1);
 
+        let mut capture_pool = aux.resources.get_mut::<CapturePool<B>>();
+
         let frames = ctx.frames_in_flight;
 
         let mut command_pool = factory
@@ -86,8 +103,15 @@ impl<
 
         for command_buffer in command_buffers {
             per_frame.push(
-                PerFrame::new(factory, ctx, &images[0], command_buffer)
-                    .expect("per frame creation failed"),
+                PerFrame::new(
+                    factory,
+                    ctx,
+                    &images[0],
+                    self.format,
+                    command_buffer,
+                    capture_pool.as_deref_mut(),
+                )
+                .expect("per frame creation failed"),
             )
         }
 
@@ -106,6 +130,9 @@ struct PerFrame<B: Backend> {
     command_buffer:
         CommandBuffer<B, QueueType, PendingState<ExecutableState<MultiShot<SimultaneousUse>>>>,
     image: Escape<Image<B>>,
+    /// the region of `image` the frame is copied to, the image can be larger if it came from the
+    /// `CapturePool`
+    extent: Extent,
     dirty: Option<u64>,
 }
 
@@ -114,7 +141,9 @@ impl<B: Backend> PerFrame<B> {
         factory: &Factory<B>,
         ctx: &GraphContext<B>,
         node_image: &NodeImage,
+        format: Option<Format>,
         command_buffer: CommandBuffer<B, QueueType, InitialState>,
+        capture_pool: Option<&mut CapturePool<B>>,
     ) -> Result<Self, Error> {
         let src_image = ctx
             .get_image(node_image.id)
@@ -126,16 +155,12 @@ impl<B: Backend> PerFrame<B> {
             Kind::D3(width, height, depth) => Kind::D3(width, height, depth),
         };
 
-        let image_info = ImageInfo {
-            kind,
-            levels: 1,
-            format: src_image.format(),
-            tiling: Tiling::Linear,
-            view_caps: ViewCapabilities::MUTABLE_FORMAT,
-            usage: IUsage::TRANSFER_DST,
-        };
+        let format = format.unwrap_or(src_image.format());
 
-        let dst_image = factory.create_image(image_info, Download)?;
+        let dst_image = match capture_pool {
+            Some(capture_pool) => capture_pool.acquire(factory, kind, format)?,
+            None => create_download_image(factory, kind, format)?,
+        };
 
         let mut command_buffer = command_buffer.begin(MultiShot(SimultaneousUse), ());
 
@@ -165,29 +190,58 @@ impl<B: Backend> PerFrame<B> {
                 }
             }
 
-            unsafe {
-                encoder.copy_image(
-                    src_image.raw(),
-                    node_image.layout.clone(),
-                    dst_image.raw(),
-                    ILayout::TransferDstOptimal,
-                    Some(ImageCopy {
-                        src_subresource: SubresourceLayers {
-                            aspects: node_image.range.aspects.clone(),
-                            layers: node_image.range.layers.start
-                                ..node_image.range.layers.start + 1,
-                            level: 0,
-                        },
-                        src_offset: IOffset::ZERO,
-                        dst_subresource: SubresourceLayers {
-                            aspects: node_image.range.aspects.clone(),
-                            layers: 0..1,
-                            level: 0,
-                        },
-                        dst_offset: IOffset::ZERO,
-                        extent: dst_image.kind().extent(),
-                    }),
-                );
+            let src_subresource = SubresourceLayers {
+                aspects: node_image.range.aspects.clone(),
+                layers: node_image.range.layers.start..node_image.range.layers.start + 1,
+                level: 0,
+            };
+            let dst_subresource = SubresourceLayers {
+                aspects: node_image.range.aspects.clone(),
+                layers: 0..1,
+                level: 0,
+            };
+            let extent = kind.extent();
+
+            // the acquire barriers leave the source in `TransferSrcOptimal`, which blitting
+            // expects as well as copying
+            if dst_image.format() != src_image.format() {
+                let bounds = IOffset::ZERO..IOffset {
+                    x: extent.width as i32,
+                    y: extent.height as i32,
+                    z: extent.depth as i32,
+                };
+
+                unsafe {
+                    encoder.blit_image(
+                        src_image.raw(),
+                        node_image.layout.clone(),
+                        dst_image.raw(),
+                        ILayout::TransferDstOptimal,
+                        Filter::Nearest,
+                        Some(ImageBlit {
+                            src_subresource,
+                            src_bounds: bounds.clone(),
+                            dst_subresource,
+                            dst_bounds: bounds,
+                        }),
+                    );
+                }
+            } else {
+                unsafe {
+                    encoder.copy_image(
+                        src_image.raw(),
+                        node_image.layout.clone(),
+                        dst_image.raw(),
+                        ILayout::TransferDstOptimal,
+                        Some(ImageCopy {
+                            src_subresource,
+                            src_offset: IOffset::ZERO,
+                            dst_subresource,
+                            dst_offset: IOffset::ZERO,
+                            extent,
+                        }),
+                    );
+                }
             }
 
             {
@@ -220,6 +274,7 @@ impl<B: Backend> PerFrame<B> {
             submit,
             command_buffer,
             image: dst_image,
+            extent: kind.extent(),
             dirty: None,
         })
     }
@@ -231,6 +286,9 @@ impl<B: Backend> PerFrame<B> {
         action: &mut A,
     ) -> Result<(), Error> {
         if let Some(frame) = &self.dirty {
+            let image_extent = self.image.kind().extent();
+            let texel_size = self.image.format().surface_desc().bits as usize / 8;
+
             let block = unsafe {
                 self.image
                     .block_mut()
@@ -242,9 +300,20 @@ impl<B: Backend> PerFrame<B> {
 
             let mut mapping = block.map(factory, range.clone())?;
 
-            let data = unsafe { mapping.read(factory, range)? };
+            // the mapped bytes are reinterpreted as `D`, so the image format has to store every
+            // channel as exactly one `D`, e.g. `u8` for `Rgba8Srgb` or `f32` for `Rgba32Sfloat`.
+            // Half floats like `Rgba16Sfloat` have no matching `D`, they are read as their `u16`
+            // bits and converted by the action.
+            let data = unsafe { mapping.read::<D>(factory, range)? };
 
-            action.exec(world, data, frame.clone())?;
+            if image_extent == self.extent {
+                action.exec(world, data, frame.clone())?;
+            } else {
+                let texel_length = texel_size / size_of::<D>();
+                let frame_data = pack_frame(data, image_extent, self.extent, texel_length);
+
+                action.exec(world, &frame_data, frame.clone())?;
+            }
 
             block.unmap(factory)
         }
@@ -278,7 +347,8 @@ impl<
         D: 'static + Copy + Debug + Send + Sync,
     > Node<B, ResWorld> for Capture<B, A, D>
 {
-    type Capability = Transfer;
+    // blitting needs a graphics queue
+    type Capability = Graphics;
 
     fn run<'a>(
         &'a mut self,
@@ -291,8 +361,16 @@ impl<
             per_frame, action, ..
         } = self;
 
-        let frame = frames.next().index();
-        let index = frame % ctx.frames_in_flight as u64;
+        let index = frames.next().index() % ctx.frames_in_flight as u64;
+
+        // headless renders are numbered by the animation frame, so a render started at a later
+        // frame or skipping frames keeps the numbers of a full render
+        let frame = aux
+            .resources
+            .get::<HeadlessTime>()
+            .map_or(frames.next().index(), |time| {
+                time.current_frame().frame() as u64
+            });
 
         let for_frame = &mut per_frame[index as usize];
 
@@ -312,22 +390,394 @@ impl<
             ..
         } = self;
 
-        for for_frame in &mut per_frame {
-            for_frame
-                .save(aux, factory, &mut action)
-                .expect("could not save frame")
-        }
+        save_in_order(
+            &mut per_frame,
+            |for_frame| for_frame.dirty,
+            |for_frame| for_frame.save(aux, factory, &mut action),
+        )
+        .expect("could not save frame");
 
-        command_pool.free_buffers(
-            per_frame
-                .into_iter()
-                .map(|for_frame| for_frame.command_buffer.mark_complete()),
-        );
+        let mut capture_pool = aux.resources.get_mut::<CapturePool<B>>();
+
+        command_pool.free_buffers(per_frame.into_iter().map(|for_frame| {
+            if let Some(capture_pool) = &mut capture_pool {
+                capture_pool.release(for_frame.image);
+            }
+
+            for_frame.command_buffer.mark_complete()
+        }));
 
         command_pool.dispose(factory);
     }
 }
 
 pub trait CaptureAction<D> {
+    /// Called for every captured frame in increasing `frame` order
     fn exec(&mut self, world: &ResWorld, image_data: &[D], frame: u64) -> Result<(), Error>;
 }
+
+/// Saves the frames still held by `slots` in increasing frame order. The slots are reused round
+/// robin, so the frames still in flight sit in an order that wraps around.
+fn save_in_order<T>(
+    slots: &mut [T],
+    frame: impl Fn(&T) -> Option<u64>,
+    mut save: impl FnMut(&mut T) -> Result<(), Error>,
+) -> Result<(), Error> {
+    slots.sort_by_key(|slot| frame(slot));
+
+    slots.iter_mut().try_for_each(|slot| save(slot))
+}
+
+/// Copies the `extent` region out of the rows of an image of `image_extent`, so the frame is
+/// packed tightly again. A texel is `texel_length` elements long.
+fn pack_frame<D: Copy>(
+    data: &[D],
+    image_extent: Extent,
+    extent: Extent,
+    texel_length: usize,
+) -> Vec<D> {
+    let row_length = image_extent.width as usize * texel_length;
+    let frame_row_length = extent.width as usize * texel_length;
+
+    let mut frame_data =
+        Vec::with_capacity(frame_row_length * (extent.height * extent.depth) as usize);
+
+    for z in 0..extent.depth {
+        for y in 0..extent.height {
+            let start = (z * image_extent.height + y) as usize * row_length;
+
+            frame_data.extend_from_slice(&data[start..start + frame_row_length]);
+        }
+    }
+
+    frame_data
+}
+
+fn create_download_image<B: Backend>(
+    factory: &Factory<B>,
+    kind: Kind,
+    format: Format,
+) -> Result<Escape<Image<B>>, Error> {
+    let image_info = ImageInfo {
+        kind,
+        levels: 1,
+        format,
+        tiling: Tiling::Linear,
+        view_caps: ViewCapabilities::MUTABLE_FORMAT,
+        usage: IUsage::TRANSFER_DST,
+    };
+
+    Ok(factory.create_image(image_info, Download)?)
+}
+
+/// Keeps the download images of the capture nodes between graph rebuilds. Every rebuild, e.g. on
+/// a resolution change, would allocate new images otherwise. The images grow to the largest
+/// resolution seen and only the region of the current resolution is copied to, smaller images
+/// are freed once a larger one was allocated.
+#[derive(Debug)]
+pub struct CapturePool<B: Backend> {
+    images: Vec<Escape<Image<B>>>,
+    max_extent: Extent,
+    allocations: usize,
+}
+
+impl<B: Backend> CapturePool<B> {
+    pub fn new() -> Self {
+        CapturePool {
+            images: vec![],
+            max_extent: Extent {
+                width: 0,
+                height: 0,
+                depth: 0,
+            },
+            allocations: 0,
+        }
+    }
+
+    /// Takes an image of `format` that `kind` fits into, or allocates one sized to the largest
+    /// extent seen so far
+    fn acquire(
+        &mut self,
+        factory: &Factory<B>,
+        kind: Kind,
+        format: Format,
+    ) -> Result<Escape<Image<B>>, Error> {
+        let extent = kind.extent();
+
+        let fitting = self.images.iter().position(|image| {
+            let image_extent = image.kind().extent();
+
+            image.format() == format
+                && discriminant(&image.kind()) == discriminant(&kind)
+                && image_extent.width >= extent.width
+                && image_extent.height >= extent.height
+                && image_extent.depth >= extent.depth
+        });
+
+        if let Some(index) = fitting {
+            return Ok(self.images.swap_remove(index));
+        }
+
+        self.max_extent = Extent {
+            width: self.max_extent.width.max(extent.width),
+            height: self.max_extent.height.max(extent.height),
+            depth: self.max_extent.depth.max(extent.depth),
+        };
+
+        let max_extent = self.max_extent;
+
+        // every image allocated from now on fits the frames these fit
+        self.images
+            .retain(|image| image.kind().extent() == max_extent);
+
+        let kind = match kind {
+            Kind::D1(_, layers) => Kind::D1(self.max_extent.width, layers),
+            Kind::D2(_, _, layers, samples) => Kind::D2(
+                self.max_extent.width,
+                self.max_extent.height,
+                layers,
+                samples,
+            ),
+            Kind::D3(_, _, _) => Kind::D3(
+                self.max_extent.width,
+                self.max_extent.height,
+                self.max_extent.depth,
+            ),
+        };
+
+        self.allocations += 1;
+
+        create_download_image(factory, kind, format)
+    }
+
+    /// Returns an image once the node that used it was disposed
+    fn release(&mut self, image: Escape<Image<B>>) {
+        self.images.push(image);
+    }
+
+    /// The number of images allocated by the pool so far
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    /// Frees the pooled images, has to be called before the factory is dropped
+    pub fn clear(&mut self) {
+        self.images.clear();
+    }
+}
+
+impl<B: Backend> Default for CapturePool<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use crate::graph::{CollectFrame, CollectedFrame};
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use crate::test_support::{empty_world, headless, image};
+    use legion::prelude::*;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use rendy::command::Families;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use rendy::graph::GraphBuilder;
+
+    #[derive(Debug, Default)]
+    struct RecordFrames(Vec<u64>);
+
+    impl CaptureAction<u8> for RecordFrames {
+        fn exec(&mut self, _world: &ResWorld, _image_data: &[u8], frame: u64) -> Result<(), Error> {
+            self.0.push(frame);
+            Ok(())
+        }
+    }
+
+    /// Stands in for `PerFrame`, with the pending frame but without the image
+    struct Slot(Option<u64>);
+
+    impl Slot {
+        fn save(&mut self, world: &ResWorld, action: &mut RecordFrames) -> Result<(), Error> {
+            if let Some(frame) = self.0.take() {
+                action.exec(world, &[], frame)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frames_reach_the_action_in_order() {
+        let world = ResWorld::new(Resources::default(), Universe::new().create_world());
+        let mut action = RecordFrames::default();
+        let frames_in_flight = 3;
+        let mut slots: Vec<_> = (0..frames_in_flight).map(|_| Slot(None)).collect();
+
+        // like `Capture::run`, every frame first saves the frame its slot still holds
+        for frame in 0..8 {
+            let slot = &mut slots[frame as usize % frames_in_flight];
+            slot.save(&world, &mut action).unwrap();
+            slot.0 = Some(frame);
+        }
+
+        // like `Capture::dispose`, the slots hold the frames 6, 7 and 5 now
+        save_in_order(
+            &mut slots,
+            |slot| slot.0,
+            |slot| slot.save(&world, &mut action),
+        )
+        .unwrap();
+
+        assert_eq!(action.0, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pooled_rows_are_packed_tightly_again() {
+        let extent = |width, height| Extent {
+            width,
+            height,
+            depth: 1,
+        };
+
+        // a 2x2 frame with 2 elements per texel in a 3x3 image
+        let data = (0..18).collect::<Vec<u8>>();
+        assert_eq!(
+            pack_frame(&data, extent(3, 3), extent(2, 2), 2),
+            vec![0, 1, 2, 3, 6, 7, 8, 9]
+        );
+
+        assert_eq!(pack_frame(&data, extent(3, 3), extent(3, 3), 2), data);
+    }
+
+    /// Renders one frame of a `format` image and reads it back in `read_back`
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn capture<B: Backend>(
+        mut factory: Factory<B>,
+        mut families: Families<B>,
+        format: Format,
+        read_back: Format,
+    ) -> Vec<u8> {
+        let world = empty_world();
+        let mut graph_builder = GraphBuilder::new();
+
+        let image = image(&mut graph_builder, 64, 64, format, [1.0; 4]);
+        let frame = CollectedFrame::default();
+
+        graph_builder.add_node(
+            CaptureDesc::new(CollectFrame::new(frame.clone()))
+                .with_format(read_back)
+                .builder()
+                .with_image(image),
+        );
+
+        let mut graph = graph_builder
+            .build(&mut factory, &mut families, &world)
+            .expect("could not build the capture");
+
+        graph.run(&mut factory, &mut families, &world);
+
+        // reads back the frame still in flight
+        graph.dispose(&mut factory, &world);
+
+        let data = frame.lock().unwrap().take();
+        data.expect("no frame was captured")
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn differing_formats_are_blitted_into_the_read_back_format() {
+        let copied = with_any_rendy!((headless()) (factory, families) => {
+            capture(factory, families, Format::Rgba8Unorm, Format::Rgba8Unorm)
+        });
+        assert_eq!(copied.len(), 64 * 64 * 4);
+
+        // half floats take 8 bytes per pixel, the blit converts them to 4
+        let blitted = with_any_rendy!((headless()) (factory, families) => {
+            capture(factory, families, Format::Rgba16Sfloat, Format::Rgba8Unorm)
+        });
+        assert_eq!(blitted.len(), 64 * 64 * 4);
+    }
+
+    /// Captures a square frame of every size in `sizes`, rebuilding the graph in between like a
+    /// resize does. Returns the images allocated by the `CapturePool` and the captured lengths.
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn capture_resizes<B: Backend>(
+        mut factory: Factory<B>,
+        mut families: Families<B>,
+        frames_in_flight: u32,
+        sizes: &[u32],
+    ) -> (usize, Vec<usize>) {
+        let mut world = empty_world();
+        world.resources.insert(CapturePool::<B>::new());
+
+        let lengths = sizes
+            .iter()
+            .map(|size| {
+                let mut graph_builder = GraphBuilder::new().with_frames_in_flight(frames_in_flight);
+
+                let image = image(
+                    &mut graph_builder,
+                    *size,
+                    *size,
+                    Format::Rgba8Unorm,
+                    [1.0; 4],
+                );
+                let frame = CollectedFrame::default();
+
+                graph_builder.add_node(
+                    CaptureDesc::new(CollectFrame::new(frame.clone()))
+                        .builder()
+                        .with_image(image),
+                );
+
+                let mut graph = graph_builder
+                    .build(&mut factory, &mut families, &world)
+                    .expect("could not build the capture");
+
+                graph.run(&mut factory, &mut families, &world);
+                graph.dispose(&mut factory, &world);
+
+                let data = frame.lock().unwrap().take();
+                data.expect("no frame was captured").len()
+            })
+            .collect();
+
+        let mut capture_pool = world
+            .resources
+            .get_mut::<CapturePool<B>>()
+            .expect("capture pool was not inserted into world");
+        let allocations = capture_pool.allocations();
+        capture_pool.clear();
+
+        (allocations, lengths)
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn resize_storm_allocates_only_when_the_frame_grows() {
+        let sizes = [64, 48, 64, 32, 80, 16, 64, 80];
+
+        let (allocations, lengths) = with_any_rendy!((headless()) (factory, families) => {
+            capture_resizes(factory, families, 2, &sizes)
+        });
+
+        // without the pool every rebuild allocates an image per frame in flight
+        println!(
+            "{} capture images allocated over {} rebuilds, {} without the pool",
+            allocations,
+            sizes.len(),
+            sizes.len() * 2
+        );
+
+        // only the first frame and the growth to 80 allocate
+        assert_eq!(allocations, 2 * 2);
+
+        let expected = sizes
+            .iter()
+            .map(|size| (size * size * 4) as usize)
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, expected);
+    }
+}
@@ -1,14 +1,16 @@
-use crate::ext::{create_fullscreen_triangle, transform_point, Std140, FULLSCREEN_SAMPLER_DESC};
+use crate::ext::{create_fullscreen_triangle, fullscreen_sampler_desc, Std140};
 use crate::ext::{GraphContextExt, SAMPLED_IMAGE_IMAGE_ACCESS};
-use crate::mem::{element, CombinedBufferCalculator};
+use crate::graph::node::gbuffer::NormalEncoding;
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, CombinedBufferCalculator, DirtyTracker};
 //use crate::world::SceneView;
 
-use nalgebra_glm::{inverse, Mat4, Vec3};
+use nalgebra_glm::{inverse, vec3, zero, Mat4, Vec3};
 use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
 use rendy::core::hal::adapter::PhysicalDevice;
 use rendy::core::hal::buffer::Usage as BUsage;
 use rendy::core::hal::device::Device;
-use rendy::core::hal::format::{Format, Swizzle};
+use rendy::core::hal::format::{ChannelType, Format, Swizzle};
 
 use rendy::core::hal::pso::{
     BlendState, ColorBlendDesc, ColorMask, CreationError, DepthStencilDesc, Descriptor,
@@ -25,23 +27,70 @@ use rendy::hal::image::Layout as ILayout;
 use rendy::memory::Dynamic;
 use rendy::mesh::{AsVertex, Mesh, Position};
 use rendy::resource::{
-    Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle, ImageView, Sampler,
-    ViewKind,
+    Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Filter, Handle, ImageView,
+    Sampler, SamplerDesc, ViewKind,
 };
 use rendy::shader::{ShaderSet, SpirvShader};
 
 use crate::world::camera::Camera;
 use crate::world::environment::Environment;
+use crate::world::light::Light;
+use crate::world::post_effects::PostEffects;
+use crate::world::shading::Shading;
+use crate::world::tonemapping::{ToneMapMode, Tonemapping};
 use crate::world::ResWorld;
 use std::mem::size_of;
 
+/// The most lights the comp pass shades with, must match `MAX_LIGHTS` in comp.frag. `Args` holds
+/// this many `LightArgs`, so raising it grows the uniform, which `CombinedBufferCalculator`
+/// picks up through the size of `Args`.
+pub const MAX_LIGHTS: usize = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightArgs {
+    color: Std140<Vec3>,
+    position: Std140<Vec3>,
+    /// constant, quadratic and radius of the `Attenuation`, a radius of 0 reaches infinitely
+    attenuation: Std140<Vec3>,
+}
+
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Args {
     inversed_view_matrix: Std140<Mat4>,
+    normal_encoding: u32,
+    encode_srgb: u32,
+    alpha_mode: u32,
+    fresnel_strength: f32,
+    fresnel_f0: f32,
+    override_f0: u32,
+    occlusion_enabled: u32,
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    occlusion_strength: f32,
+    shadow_strength: f32,
+    light_count: u32,
+    roughness: f32,
+    tonemap_mode: u32,
+    exposure: f32,
+    ambient_intensity: f32,
     ambient: Std140<Vec3>,
-    light_color: Std140<Vec3>,
-    light_position: Std140<Vec3>,
+    lights: [LightArgs; MAX_LIGHTS],
+    background_tone: Std140<Vec3>,
+    sphere_tone: Std140<Vec3>,
+}
+
+/// The resources the comp pass reads its uniforms from every frame. `lights` starts with the key
+/// light like `Environment::lights`.
+struct CompScene<'a> {
+    ambient_light: &'a Vec3,
+    ambient_intensity: f32,
+    lights: &'a [Light],
+    camera: &'a Camera,
+    shading: &'a Shading,
+    post_effects: &'a PostEffects,
+    tonemapping: &'a Tonemapping,
 }
 
 lazy_static::lazy_static! {
@@ -62,8 +111,148 @@ lazy_static::lazy_static! {
         .with_fragment(&*FRAGMENT).expect("failed to add fragment shader to shader set");
 }
 
+/// What the comp pass writes into the alpha channel of the output.
+///
+/// Every pixel is either covered by a sphere or background, so the coverage is 1 or 0. Opaque
+/// writes 1 everywhere. Premultiplied writes the coverage and clears the color of the
+/// background. Straight writes the coverage but keeps the environment as the background color,
+/// so filtering the image later does not darken the edges. The values match `ALPHA_MODE_*` in the
+/// shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque = 0,
+    Premultiplied = 1,
+    Straight = 2,
+}
+
+impl AlphaMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "opaque" => Some(AlphaMode::Opaque),
+            "premultiplied" => Some(AlphaMode::Premultiplied),
+            "straight" => Some(AlphaMode::Straight),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct CompDesc;
+pub struct CompDesc {
+    filter: Filter,
+    normal_encoding: NormalEncoding,
+    encode_srgb: bool,
+    hdr: bool,
+    alpha_mode: AlphaMode,
+    occlusion: bool,
+}
+
+impl CompDesc {
+    /// The comp pass shades in linear space. If the output format does not encode to srgb on
+    /// store, the shader does it itself. Float output formats keep the linear colors without
+    /// tone mapping. The channel order of the output format (e.g. bgra surfaces) needs no
+    /// handling, since color attachment writes do not depend on it.
+    pub fn new(
+        filter: Filter,
+        normal_encoding: NormalEncoding,
+        output_format: Format,
+        alpha_mode: AlphaMode,
+    ) -> Self {
+        let channel_type = output_format.base_format().1;
+        let hdr = match channel_type {
+            ChannelType::Sfloat | ChannelType::Ufloat => true,
+            _ => false,
+        };
+
+        CompDesc {
+            filter,
+            normal_encoding,
+            encode_srgb: channel_type != ChannelType::Srgb && !hdr,
+            hdr,
+            alpha_mode,
+            occlusion: true,
+        }
+    }
+
+    /// Whether the occlusion image is sampled. Without it the spheres are unoccluded, so the
+    /// image does not have to be written.
+    pub fn with_occlusion(mut self, occlusion: bool) -> Self {
+        self.occlusion = occlusion;
+        self
+    }
+
+    /// The sampler the gbuffer, occlusion and shadow images are read with
+    fn sampler_desc(&self) -> SamplerDesc {
+        fullscreen_sampler_desc(self.filter)
+    }
+
+    /// The shader already writes the alpha of the `alpha_mode`, so every mode replaces the
+    /// output instead of blending over its clear color
+    fn color_blend(&self) -> ColorBlendDesc {
+        ColorBlendDesc {
+            mask: ColorMask::ALL,
+            blend: Some(BlendState::REPLACE),
+        }
+    }
+
+    /// The uniforms of a frame, only the first `MAX_LIGHTS` lights are shaded
+    fn args(&self, scene: &CompScene) -> Args {
+        let tonemap_mode = if self.hdr {
+            ToneMapMode::None
+        } else {
+            scene.tonemapping.mode()
+        };
+
+        let light_count = scene.lights.len().min(MAX_LIGHTS);
+
+        let mut lights = [LightArgs {
+            color: zero::<Vec3>().into(),
+            position: zero::<Vec3>().into(),
+            attenuation: zero::<Vec3>().into(),
+        }; MAX_LIGHTS];
+
+        for (light_args, light) in lights.iter_mut().zip(scene.lights) {
+            let attenuation = light.get_attenuation();
+
+            *light_args = LightArgs {
+                color: light.get_radiance().into(),
+                position: light.view_position(scene.camera.get_view_matrix()).into(),
+                attenuation: vec3(
+                    attenuation.constant,
+                    attenuation.quadratic,
+                    attenuation.radius.unwrap_or(0.0),
+                )
+                .into(),
+            };
+        }
+
+        let shading = scene.shading;
+        let post_effects = scene.post_effects;
+
+        Args {
+            ambient: scene.ambient_light.component_mul(shading.ambient()).into(),
+            ambient_intensity: scene.ambient_intensity,
+            light_count: light_count as u32,
+            lights,
+            background_tone: shading.background_tone().scale().into(),
+            sphere_tone: shading.sphere_tone().scale().into(),
+            inversed_view_matrix: inverse(scene.camera.get_view_matrix()).into(),
+            normal_encoding: self.normal_encoding as u32,
+            encode_srgb: self.encode_srgb as u32,
+            alpha_mode: self.alpha_mode as u32,
+            fresnel_strength: shading.fresnel_strength(),
+            fresnel_f0: shading.fresnel_f0().unwrap_or(0.0),
+            override_f0: shading.fresnel_f0().is_some() as u32,
+            occlusion_enabled: self.occlusion as u32,
+            vignette_intensity: post_effects.vignette_intensity(),
+            vignette_radius: post_effects.vignette_radius(),
+            occlusion_strength: shading.occlusion_strength(),
+            shadow_strength: shading.shadow_strength(),
+            roughness: shading.roughness(),
+            tonemap_mode: tonemap_mode as u32,
+            exposure: scene.tonemapping.exposure(),
+        }
+    }
+}
 
 impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for CompDesc {
     type Pipeline = Comp<B>;
@@ -86,10 +275,7 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for CompDesc {
     }
 
     fn colors(&self) -> Vec<ColorBlendDesc> {
-        vec![ColorBlendDesc {
-            mask: ColorMask::ALL,
-            blend: Some(BlendState::REPLACE),
-        }]
+        vec![self.color_blend()]
     }
 
     fn depth_stencil(&self) -> Option<DepthStencilDesc> {
@@ -126,6 +312,13 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for CompDesc {
                             stage_flags: ShaderStageFlags::FRAGMENT,
                             immutable_samplers: false,
                         },
+                        DescriptorSetLayoutBinding {
+                            binding: 2,
+                            ty: DescriptorType::CombinedImageSampler,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
                     ],
                 },
                 SetLayout {
@@ -186,10 +379,8 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for CompDesc {
         }
     }
 
-    fn load_shader_set(&self, factory: &mut Factory<B>, _aux: &ResWorld) -> ShaderSet<B> {
-        SHADERS
-            .build(factory, Default::default())
-            .expect("could not compile shader set")
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "comp")
     }
 
     fn build<'a>(
@@ -266,7 +457,7 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for CompDesc {
             .expect("failed to create image view");
 
         let sampler = factory
-            .create_sampler(FULLSCREEN_SAMPLER_DESC)
+            .create_sampler(self.sampler_desc())
             .expect("failed to create sampler");
 
         unsafe {
@@ -368,6 +559,8 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for CompDesc {
             occlusion_view,
             shadow_view,
             sampler,
+            desc: self,
+            uploads: DirtyTracker::new(frames as usize),
         })
     }
 }
@@ -386,6 +579,8 @@ pub struct Comp<B: Backend> {
     occlusion_view: Escape<ImageView<B>>,
     shadow_view: Escape<ImageView<B>>,
     sampler: Escape<Sampler<B>>,
+    desc: CompDesc,
+    uploads: DirtyTracker<Args>,
 }
 
 impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for Comp<B> {
@@ -409,26 +604,44 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for Comp<B> {
             .get::<Camera>()
             .expect("camera was not inserted into world");
 
-        let args = Args {
-            ambient: environment.ambient_light().clone().into(),
-            light_color: environment.light().get_color().clone().into(),
-            light_position: transform_point(
-                environment.light().get_position(),
-                camera.get_view_matrix(),
-            )
-            .into(),
-            inversed_view_matrix: inverse(camera.get_view_matrix()).into(),
-        };
+        let shading = aux
+            .resources
+            .get::<Shading>()
+            .expect("shading was not inserted into world");
 
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.uniform_indirect_buffer,
-                    self.uniform_indirect_calculator.offset(0, index),
-                    &[args],
-                )
-                .expect("failed to upload uniforms");
+        let post_effects = aux
+            .resources
+            .get::<PostEffects>()
+            .expect("post effects were not inserted into world");
+
+        let tonemapping = aux
+            .resources
+            .get::<Tonemapping>()
+            .expect("tonemapping was not inserted into world");
+
+        let args = self.desc.args(&CompScene {
+            ambient_light: environment.ambient_light(),
+            ambient_intensity: environment.ambient_intensity(),
+            lights: environment.lights(),
+            camera: &camera,
+            shading: &shading,
+            post_effects: &post_effects,
+            tonemapping: &tonemapping,
+        });
 
+        if let Some(args) = self.uploads.update(index, args) {
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_buffer,
+                        self.uniform_indirect_calculator.offset(0, index),
+                        &[*args],
+                    )
+                    .expect("failed to upload uniforms");
+            }
+        }
+
+        unsafe {
             factory.write_descriptor_sets(Some(DescriptorSetWrite {
                 set: self.environment_sets[index].raw(),
                 binding: 0,
@@ -439,6 +652,17 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for Comp<B> {
                     environment.environment_map().sampler().raw(),
                 )),
             }));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: self.environment_sets[index].raw(),
+                binding: 2,
+                array_offset: 0,
+                descriptors: Some(Descriptor::CombinedImageSampler(
+                    environment.irradiance_map().view().raw(),
+                    ILayout::ShaderReadOnlyOptimal,
+                    environment.irradiance_map().sampler().raw(),
+                )),
+            }));
         }
 
         PrepareResult::DrawRecord
@@ -478,3 +702,368 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for Comp<B> {
 
     fn dispose(self, _factory: &mut Factory<B>, _aux: &ResWorld) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext::transform_point;
+    use crate::world::camera::Projection;
+    use crate::world::light::{Attenuation, LightSpace};
+    use crate::world::shading::Tone;
+    use nalgebra_glm::{identity, translation};
+
+    /// Owns what a `CompScene` borrows, with the defaults of a run
+    struct TestScene {
+        ambient_light: Vec3,
+        ambient_intensity: f32,
+        lights: Vec<Light>,
+        camera: Camera,
+        shading: Shading,
+        post_effects: PostEffects,
+        tonemapping: Tonemapping,
+    }
+
+    impl TestScene {
+        fn new() -> Self {
+            Self {
+                ambient_light: vec3(1.0, 1.0, 1.0),
+                ambient_intensity: 1.0,
+                lights: vec![Light::new(vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0))],
+                camera: Camera::new(
+                    identity(),
+                    Projection::Perspective { fov: 1.0 },
+                    0.1,
+                    100.0,
+                    64,
+                    64,
+                ),
+                shading: Shading::default(),
+                post_effects: PostEffects::default(),
+                tonemapping: Tonemapping::default(),
+            }
+        }
+
+        fn args(&self, desc: &CompDesc) -> Args {
+            desc.args(&CompScene {
+                ambient_light: &self.ambient_light,
+                ambient_intensity: self.ambient_intensity,
+                lights: &self.lights,
+                camera: &self.camera,
+                shading: &self.shading,
+                post_effects: &self.post_effects,
+                tonemapping: &self.tonemapping,
+            })
+        }
+    }
+
+    fn desc(output_format: Format) -> CompDesc {
+        CompDesc::new(
+            Filter::Nearest,
+            NormalEncoding::Unorm,
+            output_format,
+            AlphaMode::Opaque,
+        )
+    }
+
+    #[test]
+    fn ambient_reaches_the_uniform() {
+        let mut scene = TestScene::new();
+
+        // the default leaves room for the occlusion to show
+        assert!(scene.shading.ambient().amax() < 1.0);
+
+        scene.shading.set_ambient(vec3(0.1, 0.2, 0.3));
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(*args.ambient, vec3(0.1, 0.2, 0.3));
+
+        // the environment tints the ambient term
+        scene.ambient_light = vec3(0.5, 1.0, 2.0);
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(*args.ambient, vec3(0.05, 0.2, 0.6));
+    }
+
+    #[test]
+    fn ambient_intensity_reaches_the_uniform_apart_from_the_tint() {
+        let mut scene = TestScene::new();
+        scene.ambient_light = vec3(0.5, 1.0, 2.0);
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.ambient_intensity, 1.0);
+        let ambient = *args.ambient;
+
+        // the shader scales by the intensity, the tint is uploaded as it is
+        scene.ambient_intensity = 2.5;
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.ambient_intensity, 2.5);
+        assert_eq!(*args.ambient, ambient);
+    }
+
+    #[test]
+    fn fresnel_reaches_the_uniform() {
+        let mut scene = TestScene::new();
+
+        // by default the reflectance comes from the index of refraction of the spheres
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.fresnel_strength, 1.0);
+        assert_eq!(args.override_f0, 0);
+
+        scene.shading.set_fresnel_strength(0.5);
+        scene.shading.set_fresnel_f0(Some(0.04));
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.fresnel_strength, 0.5);
+        assert_eq!(args.fresnel_f0, 0.04);
+        assert_eq!(args.override_f0, 1);
+    }
+
+    #[test]
+    fn roughness_reaches_the_uniform() {
+        let mut scene = TestScene::new();
+
+        // the default keeps the reflections sharp
+        assert_eq!(scene.args(&desc(Format::Rgba8Srgb)).roughness, 0.0);
+
+        scene.shading.set_roughness(0.7);
+        assert_eq!(scene.args(&desc(Format::Rgba8Srgb)).roughness, 0.7);
+    }
+
+    #[test]
+    fn key_and_fill_light_reach_the_uniform() {
+        let mut scene = TestScene::new();
+        scene.lights = vec![
+            Light::new(vec3(-10.0, 5.0, -10.0), vec3(400.0, 400.0, 400.0)),
+            Light::new(vec3(10.0, 5.0, 10.0), vec3(400.0, 400.0, 400.0)).with_intensity(0.25),
+        ];
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.light_count, 2);
+
+        // the view matrix is the identity, so the positions are uploaded as they are
+        assert_eq!(*args.lights[0].position, vec3(-10.0, 5.0, -10.0));
+        assert_eq!(*args.lights[0].color, vec3(400.0, 400.0, 400.0));
+        assert_eq!(*args.lights[1].position, vec3(10.0, 5.0, 10.0));
+        assert_eq!(*args.lights[1].color, vec3(100.0, 100.0, 100.0));
+
+        for light_args in &args.lights[2..] {
+            assert_eq!(*light_args.color, zero::<Vec3>());
+        }
+    }
+
+    #[test]
+    fn lights_beyond_max_lights_are_dropped() {
+        let mut scene = TestScene::new();
+        scene.lights = (0..MAX_LIGHTS + 2)
+            .map(|index| Light::new(vec3(index as f32, 0.0, 0.0), vec3(1.0, 1.0, 1.0)))
+            .collect();
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.light_count, MAX_LIGHTS as u32);
+
+        for (index, light_args) in args.lights.iter().enumerate() {
+            assert_eq!(*light_args.position, vec3(index as f32, 0.0, 0.0));
+        }
+
+        // std140 aligns array elements to vec4s, which `LightArgs` has to match
+        assert_eq!(size_of::<LightArgs>() % 16, 0);
+    }
+
+    #[test]
+    fn attenuation_reaches_the_uniform() {
+        let mut scene = TestScene::new();
+        let attenuation = Attenuation {
+            constant: 1.0,
+            quadratic: 0.5,
+            radius: Some(20.0),
+        };
+        scene.lights = vec![
+            Light::new(vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0)).with_attenuation(attenuation),
+            Light::new(vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0)),
+        ];
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(*args.lights[0].attenuation, vec3(1.0, 0.5, 20.0));
+
+        // the default is the inverse square law, with a radius of 0 reaching infinitely
+        assert_eq!(*args.lights[1].attenuation, vec3(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn light_space_decides_whether_the_camera_moves_the_light() {
+        let mut scene = TestScene::new();
+        let view_matrix = translation(&vec3(1.0, 2.0, 3.0));
+        scene.camera.set_view_matrix(view_matrix);
+        scene.lights = vec![
+            Light::new(vec3(4.0, 5.0, 6.0), vec3(1.0, 1.0, 1.0)).with_space(LightSpace::World),
+            Light::new(vec3(4.0, 5.0, 6.0), vec3(1.0, 1.0, 1.0)).with_space(LightSpace::View),
+        ];
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+
+        // the shader works in view space, a world space light is back at its untransformed
+        // position once the view is undone
+        assert_eq!(*args.lights[0].position, vec3(5.0, 7.0, 9.0));
+        assert_eq!(
+            transform_point(&args.lights[0].position, &args.inversed_view_matrix),
+            vec3(4.0, 5.0, 6.0)
+        );
+
+        // a view space light is uploaded untransformed and follows the camera
+        assert_eq!(*args.lights[1].position, vec3(4.0, 5.0, 6.0));
+    }
+
+    #[test]
+    fn background_and_sphere_tone_reach_the_uniform() {
+        let mut scene = TestScene::new();
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(*args.background_tone, vec3(1.0, 1.0, 1.0));
+        assert_eq!(*args.sphere_tone, vec3(1.0, 1.0, 1.0));
+
+        scene
+            .shading
+            .set_background_tone(Tone::new(0.5, vec3(1.0, 0.5, 0.25)));
+        scene
+            .shading
+            .set_sphere_tone(Tone::new(2.0, vec3(1.0, 1.0, 0.5)));
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(*args.background_tone, vec3(0.5, 0.25, 0.125));
+        assert_eq!(*args.sphere_tone, vec3(2.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn occlusion_and_shadow_strength_reach_the_uniform() {
+        let mut scene = TestScene::new();
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.occlusion_strength, 1.0);
+        assert_eq!(args.shadow_strength, 1.0);
+
+        scene.shading.set_occlusion_strength(0.25);
+        scene.shading.set_shadow_strength(0.0);
+
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.occlusion_strength, 0.25);
+        assert_eq!(args.shadow_strength, 0.0);
+    }
+
+    #[test]
+    fn vignette_reaches_the_uniform() {
+        let mut scene = TestScene::new();
+
+        // the default leaves the image untouched
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.vignette_intensity, 0.0);
+
+        scene.post_effects = PostEffects::new().with_vignette(0.6, 0.3);
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.vignette_intensity, 0.6);
+        assert_eq!(args.vignette_radius, 0.3);
+    }
+
+    #[test]
+    fn exposure_and_tone_mapping_reach_the_uniform() {
+        let mut scene = TestScene::new();
+        let shader = include_str!("../../../../assets/shaders/comp.frag");
+
+        for (mode, name) in [
+            (ToneMapMode::None, "NONE"),
+            (ToneMapMode::Reinhard, "REINHARD"),
+            (ToneMapMode::Aces, "ACES"),
+        ]
+        .iter()
+        .cloned()
+        {
+            assert!(shader.contains(&format!("const uint TONEMAP_{} = {};", name, mode as u32)));
+        }
+
+        // the default keeps the current images
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.tonemap_mode, ToneMapMode::None as u32);
+        assert_eq!(args.exposure, 1.0);
+
+        scene.tonemapping = Tonemapping::new(ToneMapMode::Aces).with_exposure(2.0);
+        let args = scene.args(&desc(Format::Rgba8Srgb));
+        assert_eq!(args.tonemap_mode, ToneMapMode::Aces as u32);
+        assert_eq!(args.exposure, 2.0);
+
+        // float outputs keep the full range, they are neither tone mapped nor encoded
+        let args = scene.args(&desc(Format::Rgba16Sfloat));
+        assert_eq!(args.tonemap_mode, ToneMapMode::None as u32);
+        assert_eq!(args.exposure, 2.0);
+        assert_eq!(args.encode_srgb, 0);
+    }
+
+    #[test]
+    fn bgra_output_is_encoded_like_rgba() {
+        let scene = TestScene::new();
+
+        // the channel order only matters for the readback, a bgra surface gets the same shading
+        assert_eq!(scene.args(&desc(Format::Bgra8Unorm)).encode_srgb, 1);
+        assert_eq!(scene.args(&desc(Format::Rgba8Unorm)).encode_srgb, 1);
+        assert_eq!(scene.args(&desc(Format::Bgra8Srgb)).encode_srgb, 0);
+        assert_eq!(scene.args(&desc(Format::Rgba8Srgb)).encode_srgb, 0);
+    }
+
+    #[test]
+    fn unchanged_scene_is_not_uploaded_again() {
+        let mut scene = TestScene::new();
+        let desc = desc(Format::Rgba8Srgb);
+        let mut uploads = DirtyTracker::new(1);
+
+        assert!(uploads.update(0, scene.args(&desc)).is_some());
+        assert!(uploads.update(0, scene.args(&desc)).is_none());
+
+        scene.shading.set_ambient(vec3(0.1, 0.2, 0.3));
+        assert!(uploads.update(0, scene.args(&desc)).is_some());
+    }
+
+    #[test]
+    fn alpha_mode_reaches_the_uniform_and_the_blend() {
+        let scene = TestScene::new();
+        let shader = include_str!("../../../../assets/shaders/comp.frag");
+
+        for (alpha_mode, name) in [
+            (AlphaMode::Opaque, "OPAQUE"),
+            (AlphaMode::Premultiplied, "PREMULTIPLIED"),
+            (AlphaMode::Straight, "STRAIGHT"),
+        ]
+        .iter()
+        .cloned()
+        {
+            assert!(shader.contains(&format!(
+                "const uint ALPHA_MODE_{} = {};",
+                name, alpha_mode as u32
+            )));
+
+            let desc = CompDesc::new(
+                Filter::Nearest,
+                NormalEncoding::Unorm,
+                Format::Rgba8Srgb,
+                alpha_mode,
+            );
+
+            assert_eq!(scene.args(&desc).alpha_mode, alpha_mode as u32);
+
+            // blending would apply the coverage a second time
+            let color_blend = desc.color_blend();
+            assert_eq!(color_blend.mask, ColorMask::ALL);
+            assert_eq!(color_blend.blend, Some(BlendState::REPLACE));
+        }
+    }
+
+    #[test]
+    fn sampler_uses_the_chosen_filter() {
+        for filter in &[Filter::Nearest, Filter::Linear] {
+            let desc = CompDesc::new(
+                *filter,
+                NormalEncoding::Unorm,
+                Format::Rgba8Srgb,
+                AlphaMode::Opaque,
+            );
+
+            let sampler_desc = desc.sampler_desc();
+            assert_eq!(sampler_desc.min_filter, *filter);
+            assert_eq!(sampler_desc.mag_filter, *filter);
+        }
+    }
+}
@@ -3,6 +3,7 @@ use crate::ext::{
     SAMPLED_IMAGE_IMAGE_ACCESS,
 };
 use crate::graph::node::dfao::DFAOParams;
+use crate::graph::shader::load_shader_set;
 use crate::mem::{element, CombinedBufferCalculator};
 use crate::world::ResWorld;
 use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
@@ -137,10 +138,8 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for DFAOJoinDesc {
         }
     }
 
-    fn load_shader_set(&self, factory: &mut Factory<B>, _aux: &ResWorld) -> ShaderSet<B> {
-        SHADERS
-            .build(factory, Default::default())
-            .expect("failed to compile shader set")
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "dfao_join")
     }
 
     fn build<'a>(
@@ -311,3 +310,55 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for DFAOJoin<B> {
 
     fn dispose(self, _factory: &mut Factory<B>, _aux: &ResWorld) {}
 }
+
+#[cfg(all(test, any(feature = "dx12", feature = "metal", feature = "vulkan")))]
+mod tests {
+    use super::*;
+    use crate::graph::choose_format;
+    use crate::graph::node::dfao::DFAOConfig;
+    use crate::test_support::{build_and_dispose, empty_world, headless, image};
+    use rendy::command::Families;
+    use rendy::graph::render::RenderGroupBuilder;
+    use rendy::graph::GraphBuilder;
+    use rendy::hal::format::ImageFeature;
+    use rendy::resource::Tiling;
+
+    /// Joins the distance image of the first iteration into the occlusion, like `build_lit`
+    fn build_dfao_join<B: Backend>(mut factory: Factory<B>, mut families: Families<B>) {
+        let format = choose_format(
+            &factory,
+            &[Format::R32Sfloat, Format::R64Sfloat, Format::R16Sfloat],
+            Tiling::Optimal,
+            ImageFeature::COLOR_ATTACHMENT | ImageFeature::SAMPLED,
+        )
+        .expect("could not find any 1d sfloat format");
+
+        let params = DFAOConfig::default().params(1);
+        let world = empty_world();
+        let mut graph_builder = GraphBuilder::new();
+
+        let distance = image(&mut graph_builder, 64, 64, format, [params.offset; 4]);
+        let occlusion = image(&mut graph_builder, 64, 64, format, [1.0; 4]);
+
+        graph_builder.add_node(
+            DFAOJoinDesc::new(params)
+                .builder()
+                .with_image(distance)
+                .into_subpass()
+                .with_color(occlusion)
+                .into_pass(),
+        );
+
+        build_and_dispose(graph_builder, &mut factory, &mut families, &world)
+            .expect("could not build the dfao join");
+    }
+
+    #[test]
+    fn dfao_join_builds() {
+        let rendy = headless();
+
+        with_any_rendy!((rendy) (factory, families) => {
+            build_dfao_join(factory, families)
+        });
+    }
+}
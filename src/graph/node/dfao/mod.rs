@@ -6,3 +6,63 @@ pub struct DFAOParams {
     pub offset: f32,
     pub factor: f32,
 }
+
+/// The dfao passes, one per iteration. Iteration `i` (counting from 1) samples the distance field
+/// `i * base_offset` away from the surface and weights its occlusion by `falloff^i`.
+#[derive(Clone, Copy, Debug)]
+pub struct DFAOConfig {
+    /// 0 skips the dfao passes, leaving the spheres unoccluded
+    pub iterations: u32,
+    pub base_offset: f32,
+    pub falloff: f32,
+}
+
+impl Default for DFAOConfig {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            base_offset: 0.35,
+            falloff: 0.5,
+        }
+    }
+}
+
+impl DFAOConfig {
+    pub fn params(&self, iteration: u32) -> DFAOParams {
+        DFAOParams {
+            offset: iteration as f32 * self.base_offset,
+            factor: self.falloff.powi(iteration as i32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_params_keep_the_original_ramp() {
+        let config = DFAOConfig::default();
+        assert_eq!(config.iterations, 5);
+
+        for iteration in 1..=config.iterations {
+            let params = config.params(iteration);
+
+            assert_approx_eq!(params.offset, iteration as f32 * 0.35);
+            assert_approx_eq!(params.factor, 1.0 / 2.0f32.powi(iteration as i32));
+        }
+    }
+
+    #[test]
+    fn params_follow_the_base_offset_and_falloff() {
+        let config = DFAOConfig {
+            iterations: 3,
+            base_offset: 0.1,
+            falloff: 0.25,
+        };
+
+        let params = config.params(3);
+        assert_approx_eq!(params.offset, 0.3);
+        assert_approx_eq!(params.factor, 0.25 * 0.25 * 0.25);
+    }
+}
@@ -1,13 +1,14 @@
 use crate::ext::{
-    create_mesh_from_shape, transform_point, GraphContextExt, Std140, FULLSCREEN_SAMPLER_DESC,
-    SAMPLED_IMAGE_IMAGE_ACCESS,
+    transform_point, GraphContextExt, Std140, FULLSCREEN_SAMPLER_DESC, SAMPLED_IMAGE_IMAGE_ACCESS,
 };
 use crate::graph::node::dfao::DFAOParams;
-use crate::mem::{element, element_multi, CombinedBufferCalculator};
+use crate::graph::node::gbuffer::NormalEncoding;
+use crate::graph::node::proxy::ProxyParams;
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, element_multi, CombinedBufferCalculator, DirtyTracker};
 use crate::world::camera::Camera;
 use crate::world::sphere::{PositionComponent, Sphere, SphereLimits};
 use crate::world::ResWorld;
-use genmesh::generators::Cube;
 use legion::prelude::*;
 use nalgebra_glm::{Mat4, Vec3};
 use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
@@ -23,11 +24,11 @@ use rendy::hal::format::{Format, Swizzle};
 use rendy::hal::image::ViewKind;
 use rendy::hal::pso::{
     BlendOp, BlendState, ColorBlendDesc, ColorMask, CreationError, DepthStencilDesc, Descriptor,
-    DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, Face, Rasterizer,
-    ShaderStageFlags, VertexInputRate,
+    DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, Face, Primitive,
+    Rasterizer, ShaderStageFlags, VertexInputRate,
 };
 use rendy::hal::Backend;
-use rendy::memory::{Dynamic, Write};
+use rendy::memory::Dynamic;
 use rendy::mesh::{AsVertex, Mesh, Position, VertexFormat};
 use rendy::resource::{
     Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle, ImageView, Sampler,
@@ -37,10 +38,11 @@ use std::mem::size_of;
 use std::ops::Deref;
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Args {
     projection_matrix: Std140<Mat4>,
     offset: f32,
+    normal_encoding: u32,
 }
 
 #[repr(C)]
@@ -80,11 +82,17 @@ lazy_static::lazy_static! {
 #[derive(Debug)]
 pub struct DFAOSphereDesc {
     params: DFAOParams,
+    normal_encoding: NormalEncoding,
+    proxy: ProxyParams,
 }
 
 impl DFAOSphereDesc {
-    pub fn new(params: DFAOParams) -> Self {
-        DFAOSphereDesc { params }
+    pub fn new(params: DFAOParams, normal_encoding: NormalEncoding, proxy: ProxyParams) -> Self {
+        DFAOSphereDesc {
+            params,
+            normal_encoding,
+            proxy,
+        }
     }
 }
 
@@ -170,10 +178,8 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for DFAOSphereDesc {
         }
     }
 
-    fn load_shader_set(&self, factory: &mut Factory<B>, _aux: &ResWorld) -> ShaderSet<B> {
-        SHADERS
-            .build(factory, Default::default())
-            .expect("failed to compile shader set")
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "dfao_sphere")
     }
 
     fn build<'a>(
@@ -279,10 +285,14 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for DFAOSphereDesc {
             }));
         }
 
-        let cube_mesh = create_mesh_from_shape(Cube::new(), queue, factory, |vertex| {
-            Position([vertex.pos.x, vertex.pos.y, vertex.pos.z])
-        })
-        .expect("failed to create cube mesh");
+        let (proxy_vertices, proxy_indices) = self.proxy.dfao_proxy();
+
+        let proxy_mesh = Mesh::<B>::builder()
+            .with_prim_type(Primitive::TriangleList)
+            .with_vertices(proxy_vertices)
+            .with_indices(proxy_indices)
+            .build(queue, factory)
+            .expect("failed to create proxy mesh");
 
         Ok(DFAOSphere {
             sampler,
@@ -292,8 +302,10 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for DFAOSphereDesc {
             uniform_indirect_instance_buffer,
             uniform_sets,
             image_set,
-            cube_mesh,
+            proxy_mesh,
             params: self.params,
+            normal_encoding: self.normal_encoding,
+            uploads: DirtyTracker::new(frames as usize),
         })
     }
 }
@@ -307,8 +319,10 @@ pub struct DFAOSphere<B: Backend> {
     uniform_indirect_instance_buffer: Escape<Buffer<B>>,
     uniform_sets: Vec<Escape<DescriptorSet<B>>>,
     image_set: Escape<DescriptorSet<B>>,
-    cube_mesh: Mesh<B>,
+    proxy_mesh: Mesh<B>,
     params: DFAOParams,
+    normal_encoding: NormalEncoding,
+    uploads: DirtyTracker<(Args, Vec<Instance>)>,
 }
 
 impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for DFAOSphere<B> {
@@ -334,66 +348,54 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for DFAOSphere<B> {
 
         let args = Args {
             offset: self.params.offset.into(),
+            normal_encoding: self.normal_encoding as u32,
             projection_matrix: camera.get_proj_matrix().clone().into(),
         };
 
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.uniform_indirect_instance_buffer,
-                    self.uniform_indirect_instance_calculator.offset(0, index),
-                    &[args],
-                )
-                .expect("failed to upload uniforms");
-        }
-
-        let draw_indexed_command = DrawIndexedCommand {
-            first_index: 0,
-            first_instance: 0,
-            vertex_offset: 0,
-            index_count: self.cube_mesh.len(),
-            instance_count: limits.sphere_count() as u32,
-        };
-
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.uniform_indirect_instance_buffer,
-                    self.uniform_indirect_instance_calculator.offset(1, index),
-                    &[draw_indexed_command],
-                )
-                .expect("failed to upload draw indirect commands");
-        }
-
-        {
-            let mut instance_mapping = self
-                .uniform_indirect_instance_buffer
-                .map(
-                    factory,
-                    self.uniform_indirect_instance_calculator.range(2, index),
-                )
-                .expect("failed to map buffer");
-
-            let mut instance_write = unsafe {
-                instance_mapping
-                    .write::<Instance>(
-                        factory,
-                        0..self.uniform_indirect_instance_calculator.element(2).size as u64,
-                    )
-                    .expect("failed to write to mapping")
+        let query = <(Read<Sphere>, Read<PositionComponent>)>::query();
+
+        let instances = query
+            .iter(aux.deref())
+            .take(limits.sphere_count())
+            .map(|(sphere, position)| Instance {
+                center: transform_point(&position.0, camera.get_view_matrix()),
+                radius: sphere.radius(),
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((args, instances)) = self.uploads.update(index, (args, instances)) {
+            let draw_indexed_command = DrawIndexedCommand {
+                first_index: 0,
+                first_instance: 0,
+                vertex_offset: 0,
+                index_count: self.proxy_mesh.len(),
+                instance_count: instances.len() as u32,
             };
 
-            let instance_slice = unsafe { instance_write.slice() };
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(0, index),
+                        &[*args],
+                    )
+                    .expect("failed to upload uniforms");
 
-            let query = <(Read<Sphere>, Read<PositionComponent>)>::query();
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(1, index),
+                        &[draw_indexed_command],
+                    )
+                    .expect("failed to upload draw indirect commands");
 
-            for (instance, (sphere, position)) in
-                instance_slice.iter_mut().zip(query.iter(aux.deref()))
-            {
-                *instance = Instance {
-                    center: transform_point(&position.0, camera.get_view_matrix()),
-                    radius: sphere.radius(),
-                }
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(2, index),
+                        instances,
+                    )
+                    .expect("failed to upload instances");
             }
         }
 
@@ -418,9 +420,9 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for DFAOSphere<B> {
             encoder.bind_graphics_descriptor_sets(layout, 1, Some(self.image_set.raw()), None);
         }
 
-        self.cube_mesh
+        self.proxy_mesh
             .bind(0, &[Position::vertex()], &mut encoder)
-            .expect("failed to bind cube mesh");
+            .expect("failed to bind proxy mesh");
 
         unsafe {
             encoder.bind_vertex_buffers(
@@ -0,0 +1,363 @@
+use crate::ext::{create_fullscreen_triangle, FULLSCREEN_SAMPLER_DESC};
+use crate::ext::{GraphContextExt, SAMPLED_IMAGE_IMAGE_ACCESS};
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, CombinedBufferCalculator, DirtyTracker};
+use crate::world::depth_of_field::DepthOfField;
+use crate::world::ResWorld;
+
+use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
+use rendy::core::hal::adapter::PhysicalDevice;
+use rendy::core::hal::buffer::Usage as BUsage;
+use rendy::core::hal::device::Device;
+use rendy::core::hal::format::{Format, Swizzle};
+use rendy::core::hal::pso::{
+    BlendState, ColorBlendDesc, ColorMask, CreationError, DepthStencilDesc, Descriptor,
+    DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, Face, Rasterizer,
+    ShaderStageFlags, VertexInputRate,
+};
+use rendy::core::hal::Backend;
+use rendy::factory::Factory;
+use rendy::graph::render::{
+    Layout, PrepareResult, SetLayout, SimpleGraphicsPipeline, SimpleGraphicsPipelineDesc,
+};
+use rendy::graph::{GraphContext, ImageAccess, NodeBuffer, NodeImage};
+use rendy::memory::Dynamic;
+use rendy::mesh::{AsVertex, Mesh, Position};
+use rendy::resource::{
+    Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle, ImageView, Sampler,
+    ViewKind,
+};
+use rendy::shader::{ShaderSet, SpirvShader};
+use std::mem::size_of;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Args {
+    focal_distance: f32,
+    aperture: f32,
+}
+
+lazy_static::lazy_static! {
+    static ref VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/dof.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).expect("failed to load vertex shader");
+
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/dof.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).expect("failed to load fragment shader");
+
+    static ref SHADERS: rendy::shader::ShaderSetBuilder = rendy::shader::ShaderSetBuilder::default()
+        .with_vertex(&*VERTEX).expect("failed to add vertex shader to shader set")
+        .with_fragment(&*FRAGMENT).expect("failed to add fragment shader to shader set");
+}
+
+/// Blurs the image by the distance of every pixel from the focal plane of `DepthOfField`. The
+/// distance is taken from the view space position of the gbuffer, the background counts as
+/// infinitely far away. The pass keeps the encoding of the colors, so it can sample the image of
+/// any pass writing the output and write the output in its place.
+#[derive(Debug, Default)]
+pub struct DOFDesc;
+
+impl DOFDesc {
+    pub fn new() -> Self {
+        DOFDesc
+    }
+}
+
+impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for DOFDesc {
+    type Pipeline = DOF<B>;
+
+    fn images(&self) -> Vec<ImageAccess> {
+        vec![
+            // color
+            SAMPLED_IMAGE_IMAGE_ACCESS,
+            // pos
+            SAMPLED_IMAGE_IMAGE_ACCESS,
+        ]
+    }
+
+    fn colors(&self) -> Vec<ColorBlendDesc> {
+        vec![ColorBlendDesc {
+            mask: ColorMask::ALL,
+            blend: Some(BlendState::REPLACE),
+        }]
+    }
+
+    fn depth_stencil(&self) -> Option<DepthStencilDesc> {
+        None
+    }
+
+    fn rasterizer(&self) -> Rasterizer {
+        Rasterizer {
+            cull_face: Face::BACK,
+            ..Rasterizer::FILL
+        }
+    }
+
+    fn vertices(&self) -> Vec<(Vec<Element<Format>>, u32, VertexInputRate)> {
+        vec![Position::vertex().gfx_vertex_input_desc(VertexInputRate::Vertex)]
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            sets: vec![
+                SetLayout {
+                    bindings: vec![DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty: DescriptorType::UniformBuffer,
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    }],
+                },
+                SetLayout {
+                    bindings: vec![
+                        DescriptorSetLayoutBinding {
+                            binding: 0,
+                            ty: DescriptorType::Sampler,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                        DescriptorSetLayoutBinding {
+                            binding: 1,
+                            ty: DescriptorType::SampledImage,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                        DescriptorSetLayoutBinding {
+                            binding: 2,
+                            ty: DescriptorType::SampledImage,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                    ],
+                },
+            ],
+            push_constants: vec![],
+        }
+    }
+
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "dof")
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        _aux: &ResWorld,
+        _buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+        set_layouts: &[Handle<DescriptorSetLayout<B>>],
+    ) -> Result<Self::Pipeline, CreationError> {
+        assert_eq!(images.len(), 2);
+
+        let color = &images[0];
+        let pos = &images[1];
+
+        let frames = ctx.frames_in_flight;
+        let align = factory
+            .physical()
+            .limits()
+            .min_uniform_buffer_offset_alignment;
+
+        let uniform_indirect_calculator = CombinedBufferCalculator::new(
+            vec![element::<Args>(), element::<DrawIndexedCommand>()],
+            frames as u64,
+            align,
+        );
+
+        let mut uniform_indirect_buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: uniform_indirect_calculator.size(),
+                    usage: BUsage::UNIFORM | BUsage::INDIRECT,
+                },
+                Dynamic,
+            )
+            .expect("could not create buffer");
+
+        let uniform_sets = factory
+            .create_descriptor_sets::<Vec<_>>(set_layouts[0].clone(), frames)
+            .expect("failed to create descriptor set");
+
+        let image_set = factory
+            .create_descriptor_set(set_layouts[1].clone())
+            .expect("failed to create descriptor set");
+
+        let color_view = ctx
+            .create_image_view(factory, color, ViewKind::D2, Swizzle::NO)
+            .expect("failed to create image view");
+
+        let pos_view = ctx
+            .create_image_view(factory, pos, ViewKind::D2, Swizzle::NO)
+            .expect("failed to create image view");
+
+        // the positions must not be filtered across the silhouettes of the spheres
+        let sampler = factory
+            .create_sampler(FULLSCREEN_SAMPLER_DESC)
+            .expect("failed to create sampler");
+
+        unsafe {
+            factory.write_descriptor_sets(uniform_sets.iter().enumerate().map(
+                |(frame, uniform_set)| DescriptorSetWrite {
+                    set: uniform_set.raw(),
+                    binding: 0,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Buffer(
+                        uniform_indirect_buffer.raw(),
+                        uniform_indirect_calculator.option_range(0, frame),
+                    )),
+                },
+            ));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Sampler(sampler.raw())),
+            }));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Image(color_view.raw(), color.layout)),
+            }));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 2,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Image(pos_view.raw(), pos.layout)),
+            }));
+        }
+
+        let fullscreen_triangle = create_fullscreen_triangle(factory, queue)
+            .expect("failed to create fullscreen triangle");
+
+        for frame in 0..frames {
+            let draw_indexed_command = DrawIndexedCommand {
+                first_index: 0,
+                first_instance: 0,
+                vertex_offset: 0,
+                index_count: fullscreen_triangle.len(),
+                instance_count: 1,
+            };
+
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut uniform_indirect_buffer,
+                        uniform_indirect_calculator.offset(1, frame as usize),
+                        &[draw_indexed_command],
+                    )
+                    .expect("failed to upload indirect draw commands");
+            }
+        }
+
+        Ok(DOF {
+            uniform_indirect_calculator,
+            uniform_indirect_buffer,
+            fullscreen_triangle,
+            uniform_sets,
+            image_set,
+            color_view,
+            pos_view,
+            sampler,
+            uploads: DirtyTracker::new(frames as usize),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DOF<B: Backend> {
+    uniform_indirect_calculator: CombinedBufferCalculator,
+    uniform_indirect_buffer: Escape<Buffer<B>>,
+    fullscreen_triangle: Mesh<B>,
+    uniform_sets: Vec<Escape<DescriptorSet<B>>>,
+    image_set: Escape<DescriptorSet<B>>,
+    color_view: Escape<ImageView<B>>,
+    pos_view: Escape<ImageView<B>>,
+    sampler: Escape<Sampler<B>>,
+    uploads: DirtyTracker<Args>,
+}
+
+impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for DOF<B> {
+    type Desc = DOFDesc;
+
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        _set_layouts: &[Handle<DescriptorSetLayout<B>>],
+        index: usize,
+        aux: &ResWorld,
+    ) -> PrepareResult {
+        let depth_of_field = aux
+            .resources
+            .get::<DepthOfField>()
+            .expect("depth of field was not inserted into world");
+
+        let args = Args {
+            focal_distance: depth_of_field.focal_distance(),
+            aperture: depth_of_field.aperture(),
+        };
+
+        if let Some(args) = self.uploads.update(index, args) {
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_buffer,
+                        self.uniform_indirect_calculator.offset(0, index),
+                        &[*args],
+                    )
+                    .expect("failed to upload uniforms");
+            }
+        }
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw(
+        &mut self,
+        layout: &<B as Backend>::PipelineLayout,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _aux: &ResWorld,
+    ) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                layout,
+                0,
+                Some(self.uniform_sets[index].raw()),
+                None,
+            );
+
+            encoder.bind_graphics_descriptor_sets(layout, 1, Some(self.image_set.raw()), None);
+        }
+
+        self.fullscreen_triangle
+            .bind(0, &[Position::vertex()], &mut encoder)
+            .expect("failed to create fullscreen triangle");
+
+        unsafe {
+            encoder.draw_indexed_indirect(
+                self.uniform_indirect_buffer.raw(),
+                self.uniform_indirect_calculator.offset(1, 0),
+                1,
+                size_of::<DrawIndexedCommand>() as u32,
+            )
+        }
+    }
+
+    fn dispose(self, _factory: &mut Factory<B>, _aux: &ResWorld) {}
+}
@@ -0,0 +1,451 @@
+use crate::ext::{
+    create_fullscreen_triangle, fullscreen_sampler_desc, GraphContextExt,
+    SAMPLED_IMAGE_IMAGE_ACCESS,
+};
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, CombinedBufferCalculator};
+use crate::world::ResWorld;
+use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
+use rendy::factory::Factory;
+use rendy::graph::render::{Layout, SetLayout, SimpleGraphicsPipeline, SimpleGraphicsPipelineDesc};
+use rendy::graph::{GraphContext, ImageAccess, NodeBuffer, NodeImage};
+use rendy::hal::adapter::PhysicalDevice;
+use rendy::hal::buffer::Usage as BUsage;
+use rendy::hal::device::Device;
+use rendy::hal::format::{ChannelType, Format, Swizzle};
+use rendy::hal::image::ViewKind;
+use rendy::hal::pso::{
+    BlendState, ColorBlendDesc, ColorMask, CreationError, DepthStencilDesc, Descriptor,
+    DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, Face, Rasterizer,
+    ShaderStageFlags, VertexInputRate,
+};
+use rendy::hal::Backend;
+use rendy::memory::Dynamic;
+use rendy::mesh::{AsVertex, Mesh, Position};
+use rendy::resource::{
+    Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Filter, Handle, ImageView,
+    Sampler,
+};
+use rendy::shader::{ShaderSet, SpirvShader};
+use std::mem::size_of;
+
+/// The most edge search steps of a preset, must match the length of `STEP_SIZES` in fxaa.frag
+const MAX_SEARCH_STEPS: u32 = 12;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Args {
+    edge_threshold: f32,
+    edge_threshold_min: f32,
+    subpixel: f32,
+    search_steps: u32,
+    linear_input: u32,
+    decode_srgb: u32,
+}
+
+lazy_static::lazy_static! {
+    static ref VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/fxaa.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).expect("failed to load vertex shader");
+
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/fxaa.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).expect("failed to load fragment shader");
+
+    static ref SHADERS: rendy::shader::ShaderSetBuilder = rendy::shader::ShaderSetBuilder::default()
+        .with_vertex(&*VERTEX).expect("failed to add vertex shader to shader set")
+        .with_fragment(&*FRAGMENT).expect("failed to add fragment shader to shader set");
+}
+
+/// How thoroughly the fxaa pass looks for edges, the values follow the presets of FXAA 3.11
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FxaaQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl FxaaQuality {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(FxaaQuality::Low),
+            "medium" => Some(FxaaQuality::Medium),
+            "high" => Some(FxaaQuality::High),
+            _ => None,
+        }
+    }
+
+    /// The smallest local contrast, relative to the brightest neighbour, that counts as an edge
+    pub fn edge_threshold(&self) -> f32 {
+        match self {
+            FxaaQuality::Low => 0.25,
+            FxaaQuality::Medium => 0.166,
+            FxaaQuality::High => 0.125,
+        }
+    }
+
+    /// The smallest absolute local contrast that counts as an edge, so dark areas are skipped
+    pub fn edge_threshold_min(&self) -> f32 {
+        match self {
+            FxaaQuality::Low => 0.0833,
+            FxaaQuality::Medium => 0.0833,
+            FxaaQuality::High => 0.0625,
+        }
+    }
+
+    /// How much details thinner than a pixel are blurred away
+    pub fn subpixel(&self) -> f32 {
+        match self {
+            FxaaQuality::Low => 0.5,
+            FxaaQuality::Medium => 0.75,
+            FxaaQuality::High => 0.75,
+        }
+    }
+
+    /// How many steps the end of an edge is searched for in both directions
+    pub fn search_steps(&self) -> u32 {
+        match self {
+            FxaaQuality::Low => 4,
+            FxaaQuality::Medium => 8,
+            FxaaQuality::High => MAX_SEARCH_STEPS,
+        }
+    }
+}
+
+/// Which antialiasing runs on the final image before it is written to the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Antialiasing {
+    None,
+    Fxaa(FxaaQuality),
+}
+
+/// Smooths the edges of the image it samples with fast approximate antialiasing. FXAA finds the
+/// edges by their luma, which has to be perceptual, so the input is expected to be srgb encoded
+/// unless it is a float format, whose linear colors are encoded in the shader for the luma.
+#[derive(Debug)]
+pub struct FxaaDesc {
+    quality: FxaaQuality,
+    linear_input: bool,
+    decode_srgb: bool,
+}
+
+impl FxaaDesc {
+    /// The pass writes the colors as it read them, so an output format that encodes to srgb on
+    /// store gets them decoded first.
+    pub fn new(quality: FxaaQuality, input_format: Format, output_format: Format) -> Self {
+        let linear_input = match input_format.base_format().1 {
+            ChannelType::Sfloat | ChannelType::Ufloat => true,
+            _ => false,
+        };
+
+        FxaaDesc {
+            quality,
+            linear_input,
+            decode_srgb: !linear_input && output_format.base_format().1 == ChannelType::Srgb,
+        }
+    }
+
+    fn args(&self) -> Args {
+        Args {
+            edge_threshold: self.quality.edge_threshold(),
+            edge_threshold_min: self.quality.edge_threshold_min(),
+            subpixel: self.quality.subpixel(),
+            search_steps: self.quality.search_steps(),
+            linear_input: self.linear_input as u32,
+            decode_srgb: self.decode_srgb as u32,
+        }
+    }
+}
+
+impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for FxaaDesc {
+    type Pipeline = Fxaa<B>;
+
+    fn images(&self) -> Vec<ImageAccess> {
+        vec![SAMPLED_IMAGE_IMAGE_ACCESS]
+    }
+
+    fn colors(&self) -> Vec<ColorBlendDesc> {
+        vec![ColorBlendDesc {
+            mask: ColorMask::ALL,
+            blend: Some(BlendState::REPLACE),
+        }]
+    }
+
+    fn depth_stencil(&self) -> Option<DepthStencilDesc> {
+        None
+    }
+
+    fn rasterizer(&self) -> Rasterizer {
+        Rasterizer {
+            cull_face: Face::BACK,
+            ..Rasterizer::FILL
+        }
+    }
+
+    fn vertices(&self) -> Vec<(Vec<Element<Format>>, u32, VertexInputRate)> {
+        vec![Position::vertex().gfx_vertex_input_desc(VertexInputRate::Vertex)]
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            sets: vec![
+                SetLayout {
+                    bindings: vec![DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty: DescriptorType::UniformBuffer,
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    }],
+                },
+                SetLayout {
+                    bindings: vec![
+                        DescriptorSetLayoutBinding {
+                            binding: 0,
+                            ty: DescriptorType::Sampler,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                        DescriptorSetLayoutBinding {
+                            binding: 1,
+                            ty: DescriptorType::SampledImage,
+                            count: 1,
+                            stage_flags: ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                    ],
+                },
+            ],
+            push_constants: vec![],
+        }
+    }
+
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "fxaa")
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        _aux: &ResWorld,
+        _buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+        set_layouts: &[Handle<DescriptorSetLayout<B>>],
+    ) -> Result<Self::Pipeline, CreationError> {
+        assert_eq!(images.len(), 1);
+
+        let color = &images[0];
+
+        let color_view = ctx
+            .create_image_view(factory, color, ViewKind::D2, Swizzle::NO)
+            .expect("failed to create image view");
+
+        let align = factory
+            .physical()
+            .limits()
+            .min_uniform_buffer_offset_alignment;
+
+        let uniform_indirect_calculator = CombinedBufferCalculator::new(
+            vec![element::<Args>(), element::<DrawIndexedCommand>()],
+            1,
+            align,
+        );
+
+        let mut uniform_indirect_buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: uniform_indirect_calculator.size(),
+                    usage: BUsage::UNIFORM | BUsage::INDIRECT,
+                },
+                Dynamic,
+            )
+            .expect("failed to create buffer");
+
+        let fullscreen_triangle = create_fullscreen_triangle(factory, queue)
+            .expect("failed to create fullscreen triangle");
+
+        let args = self.args();
+
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut uniform_indirect_buffer,
+                    uniform_indirect_calculator.offset(0, 0),
+                    &[args],
+                )
+                .expect("failed to upload uniforms");
+        }
+
+        let draw_indexed_command = DrawIndexedCommand {
+            first_index: 0,
+            first_instance: 0,
+            vertex_offset: 0,
+            index_count: fullscreen_triangle.len(),
+            instance_count: 1,
+        };
+
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut uniform_indirect_buffer,
+                    uniform_indirect_calculator.offset(1, 0),
+                    &[draw_indexed_command],
+                )
+                .expect("failed to upload indirect draw commands");
+        }
+
+        let uniform_set = factory
+            .create_descriptor_set(set_layouts[0].clone())
+            .expect("failed to create descriptor set");
+
+        let image_set = factory
+            .create_descriptor_set(set_layouts[1].clone())
+            .expect("failed to create descriptor set");
+
+        // the edge search samples between texels to look at two of them at once
+        let sampler = factory
+            .create_sampler(fullscreen_sampler_desc(Filter::Linear))
+            .expect("failed to create fullscreen sampler");
+
+        unsafe {
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: uniform_set.raw(),
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Buffer(
+                    uniform_indirect_buffer.raw(),
+                    uniform_indirect_calculator.option_range(0, 0),
+                )),
+            }));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Sampler(sampler.raw())),
+            }));
+
+            factory.write_descriptor_sets(Some(DescriptorSetWrite {
+                set: image_set.raw(),
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(Descriptor::Image(color_view.raw(), color.layout)),
+            }));
+        }
+
+        Ok(Fxaa {
+            sampler,
+            color_view,
+            uniform_indirect_calculator,
+            uniform_indirect_buffer,
+            uniform_set,
+            image_set,
+            fullscreen_triangle,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Fxaa<B: Backend> {
+    sampler: Escape<Sampler<B>>,
+    color_view: Escape<ImageView<B>>,
+    uniform_indirect_calculator: CombinedBufferCalculator,
+    uniform_indirect_buffer: Escape<Buffer<B>>,
+    uniform_set: Escape<DescriptorSet<B>>,
+    image_set: Escape<DescriptorSet<B>>,
+    fullscreen_triangle: Mesh<B>,
+}
+
+impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for Fxaa<B> {
+    type Desc = FxaaDesc;
+
+    fn draw(
+        &mut self,
+        layout: &<B as Backend>::PipelineLayout,
+        mut encoder: RenderPassEncoder<'_, B>,
+        _index: usize,
+        _aux: &ResWorld,
+    ) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(layout, 0, Some(self.uniform_set.raw()), None);
+
+            encoder.bind_graphics_descriptor_sets(layout, 1, Some(self.image_set.raw()), None);
+        }
+
+        self.fullscreen_triangle
+            .bind(0, &[Position::vertex()], &mut encoder)
+            .expect("failed to create fullscreen triangle");
+
+        unsafe {
+            encoder.draw_indexed_indirect(
+                self.uniform_indirect_buffer.raw(),
+                self.uniform_indirect_calculator.offset(1, 0),
+                1,
+                size_of::<DrawIndexedCommand>() as u32,
+            )
+        }
+    }
+
+    fn dispose(self, _factory: &mut Factory<B>, _aux: &ResWorld) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn higher_quality_searches_further_for_finer_edges() {
+        let shader = include_str!("../../../../assets/shaders/fxaa.frag");
+        assert!(shader.contains(&format!("const float STEP_SIZES[{}]", MAX_SEARCH_STEPS)));
+
+        let presets = ["low", "medium", "high"]
+            .iter()
+            .map(|name| FxaaQuality::from_name(name).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            presets,
+            vec![FxaaQuality::Low, FxaaQuality::Medium, FxaaQuality::High]
+        );
+        assert_eq!(FxaaQuality::from_name("ultra"), None);
+
+        for pair in presets.windows(2) {
+            assert!(pair[0].edge_threshold() > pair[1].edge_threshold());
+            assert!(pair[0].edge_threshold_min() >= pair[1].edge_threshold_min());
+            assert!(pair[0].search_steps() < pair[1].search_steps());
+        }
+
+        assert!(presets
+            .iter()
+            .all(|preset| preset.search_steps() <= MAX_SEARCH_STEPS));
+    }
+
+    #[test]
+    fn only_srgb_outputs_of_encoded_inputs_are_decoded() {
+        let args = FxaaDesc::new(FxaaQuality::High, Format::Rgba16Unorm, Format::Rgba8Srgb).args();
+        assert_eq!(args.linear_input, 0);
+        assert_eq!(args.decode_srgb, 1);
+        assert_eq!(args.search_steps, MAX_SEARCH_STEPS);
+
+        let args = FxaaDesc::new(FxaaQuality::High, Format::Rgba8Unorm, Format::Rgba8Unorm).args();
+        assert_eq!(args.linear_input, 0);
+        assert_eq!(args.decode_srgb, 0);
+
+        // a float input holds linear colors, which are written back as they are
+        let args = FxaaDesc::new(
+            FxaaQuality::High,
+            Format::Rgba16Sfloat,
+            Format::Rgba32Sfloat,
+        )
+        .args();
+        assert_eq!(args.linear_input, 1);
+        assert_eq!(args.decode_srgb, 0);
+    }
+}
@@ -1,23 +1,27 @@
-use crate::mem::{element, element_multi, CombinedBufferCalculator};
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, element_multi, CombinedBufferCalculator, DirtyTracker};
 use crate::world::camera::Camera;
 use crate::world::color_ramp::ColorRamp;
-use crate::world::sphere::{PositionComponent, Sphere, SphereLimits};
+use crate::world::debug_render::PolygonMode;
+use crate::world::sphere::{ColorComponent, Material, PositionComponent, Sphere, SphereLimits};
+use crate::world::sphere_mesh::SphereMeshParams;
 use crate::world::ResWorld;
 use genmesh::generators::{IndexedPolygon, SharedVertex, SphereUv};
 use genmesh::EmitTriangles;
 use legion::prelude::*;
 use nalgebra_glm::{
-    identity, inverse_transpose, mat4_to_mat3, scale, translate, vec3, Mat3, Mat4, Vec3,
+    identity, inverse_transpose, mat4, mat4_to_mat3, scale, translate, vec3, Mat3, Mat4, Vec3,
 };
 use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
 use rendy::core::hal::adapter::PhysicalDevice;
 use rendy::core::hal::buffer::Usage;
 use rendy::core::hal::device::Device;
 use rendy::core::hal::format::Format;
+use rendy::core::hal::pso;
 use rendy::core::hal::pso::{
-    BlendState, ColorBlendDesc, ColorMask, CreationError, Descriptor, DescriptorSetLayoutBinding,
-    DescriptorSetWrite, DescriptorType, Element, Face, Primitive, Rasterizer, ShaderStageFlags,
-    VertexInputRate,
+    BlendState, ColorBlendDesc, ColorMask, Comparison, CreationError, DepthStencilDesc, DepthTest,
+    Descriptor, DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, Face,
+    Primitive, Rasterizer, ShaderStageFlags, VertexInputRate,
 };
 use rendy::core::hal::Backend;
 use rendy::factory::Factory;
@@ -25,7 +29,7 @@ use rendy::graph::render::{
     Layout, PrepareResult, SetLayout, SimpleGraphicsPipeline, SimpleGraphicsPipelineDesc,
 };
 use rendy::graph::{GraphContext, NodeBuffer, NodeImage};
-use rendy::memory::{Dynamic, Write};
+use rendy::memory::Dynamic;
 use rendy::mesh::{AsVertex, Mesh, Normal, PosNorm, Position, VertexFormat};
 use rendy::resource::{Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle};
 use rendy::shader::{ShaderSet, SpirvShader};
@@ -72,9 +76,104 @@ impl AsVertex for Instance {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Args {
     proj: Mat4,
+    normal_encoding: u32,
+}
+
+/// How view space normals are stored in the normal target of the gbuffer.
+///
+/// Unorm targets can not hold negative values, so x and y are written as `n * 0.5 + 0.5` and
+/// read back as `n * 2.0 - 1.0`. z is written as is, because visible normals face the camera.
+/// Snorm targets hold the normal unchanged. The values match `NORMAL_ENCODING_*` in the shaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalEncoding {
+    Unorm = 0,
+    Snorm = 1,
+}
+
+impl NormalEncoding {
+    /// normal formats in order of preference
+    pub const FORMATS: [Format; 7] = [
+        Format::Rgba16Snorm,
+        Format::A2r10g10b10Unorm,
+        Format::A2b10g10r10Unorm,
+        Format::Rgba16Unorm,
+        Format::Rgba8Snorm,
+        Format::Rgba8Unorm,
+        Format::Bgra8Unorm,
+    ];
+
+    pub fn from_format(format: Format) -> Option<Self> {
+        match format {
+            Format::Rgba16Snorm | Format::Rgba8Snorm => Some(NormalEncoding::Snorm),
+            Format::A2r10g10b10Unorm
+            | Format::A2b10g10r10Unorm
+            | Format::Rgba16Unorm
+            | Format::Rgba8Unorm
+            | Format::Bgra8Unorm => Some(NormalEncoding::Unorm),
+            _ => None,
+        }
+    }
+
+    /// the encoded zero normal, which the composition treats as background
+    pub fn clear_color(&self) -> [f32; 4] {
+        match self {
+            NormalEncoding::Unorm => [0.5, 0.5, 0.0, 1.0],
+            NormalEncoding::Snorm => [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// Which end of the depth range is near.
+///
+/// Reversed maps the near plane to 1 and the far plane to 0, which spreads the float precision
+/// of the depth buffer more evenly over the distance. The depth clear and compare function have
+/// to match the direction, so they are chosen together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthConvention {
+    Standard,
+    Reversed,
+}
+
+impl DepthConvention {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "standard" => Some(DepthConvention::Standard),
+            "reversed" => Some(DepthConvention::Reversed),
+            _ => None,
+        }
+    }
+
+    /// the depth of the far plane, which the depth buffer is cleared to
+    pub fn clear_depth(&self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.0,
+            DepthConvention::Reversed => 0.0,
+        }
+    }
+
+    /// the function a fragment passes the depth test with
+    pub fn comparison(&self) -> Comparison {
+        match self {
+            DepthConvention::Standard => Comparison::Less,
+            DepthConvention::Reversed => Comparison::GreaterEqual,
+        }
+    }
+
+    /// applied on top of the projection matrix, reversed replaces the clip space z with `w - z`
+    pub fn depth_matrix(&self) -> Mat4 {
+        match self {
+            DepthConvention::Standard => identity(),
+            DepthConvention::Reversed => mat4(
+                1.0, 0.0, 0.0, 0.0, //
+                0.0, 1.0, 0.0, 0.0, //
+                0.0, 0.0, -1.0, 1.0, //
+                0.0, 0.0, 0.0, 1.0,
+            ),
+        }
+    }
 }
 
 lazy_static::lazy_static! {
@@ -96,7 +195,27 @@ lazy_static::lazy_static! {
 }
 
 #[derive(Debug)]
-pub struct GBufferDesc;
+pub struct GBufferDesc {
+    normal_encoding: NormalEncoding,
+    depth_convention: DepthConvention,
+    polygon_mode: PolygonMode,
+}
+
+impl GBufferDesc {
+    pub fn new(normal_encoding: NormalEncoding, depth_convention: DepthConvention) -> Self {
+        GBufferDesc {
+            normal_encoding,
+            depth_convention,
+            polygon_mode: PolygonMode::Fill,
+        }
+    }
+
+    /// `PolygonMode::Line` needs `Features::NON_FILL_POLYGON_MODE`
+    pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+}
 
 impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GBufferDesc {
     type Pipeline = GBuffer<B>;
@@ -126,8 +245,23 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GBufferDesc {
         ]
     }
 
+    fn depth_stencil(&self) -> Option<DepthStencilDesc> {
+        Some(DepthStencilDesc {
+            depth: Some(DepthTest {
+                fun: self.depth_convention.comparison(),
+                write: true,
+            }),
+            depth_bounds: false,
+            stencil: None,
+        })
+    }
+
     fn rasterizer(&self) -> Rasterizer {
         Rasterizer {
+            polygon_mode: match self.polygon_mode {
+                PolygonMode::Fill => pso::PolygonMode::Fill,
+                PolygonMode::Line => pso::PolygonMode::Line,
+            },
             cull_face: Face::BACK,
             ..Rasterizer::FILL
         }
@@ -147,7 +281,7 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GBufferDesc {
                     binding: 0,
                     ty: DescriptorType::UniformBuffer,
                     count: 1,
-                    stage_flags: ShaderStageFlags::VERTEX,
+                    stage_flags: ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
                     immutable_samplers: false,
                 }],
             }],
@@ -155,10 +289,8 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GBufferDesc {
         }
     }
 
-    fn load_shader_set(&self, factory: &mut Factory<B>, _aux: &ResWorld) -> ShaderSet<B> {
-        SHADERS
-            .build(factory, Default::default())
-            .expect("failed to compile shader set")
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "gbuffer")
     }
 
     fn build<'a>(
@@ -221,7 +353,17 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GBufferDesc {
             ));
         }
 
-        let sphere = SphereUv::new(32, 16);
+        let sphere = {
+            let sphere_mesh_params = aux
+                .resources
+                .get::<SphereMeshParams>()
+                .expect("sphere mesh params were not inserted into world");
+
+            SphereUv::new(
+                sphere_mesh_params.u_segments(),
+                sphere_mesh_params.v_segments(),
+            )
+        };
 
         let sphere_vertices = sphere
             .shared_vertex_iter()
@@ -248,6 +390,9 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GBufferDesc {
             .expect("failed to create sphere mesh");
 
         Ok(GBuffer {
+            normal_encoding: self.normal_encoding,
+            depth_convention: self.depth_convention,
+            uploads: DirtyTracker::new(frames as usize),
             uniform_indirect_instance_calculator,
             uniform_indirect_instance_buffer,
             uniform_sets,
@@ -258,6 +403,9 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GBufferDesc {
 
 #[derive(Debug)]
 pub struct GBuffer<B: Backend> {
+    normal_encoding: NormalEncoding,
+    depth_convention: DepthConvention,
+    uploads: DirtyTracker<(Args, Vec<Instance>)>,
     uniform_indirect_instance_calculator: CombinedBufferCalculator,
     uniform_indirect_instance_buffer: Escape<Buffer<B>>,
     uniform_sets: Vec<Escape<DescriptorSet<B>>>,
@@ -291,64 +439,23 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for GBuffer<B> {
             .expect("color ramp was not inserted into world");
 
         let args = Args {
-            proj: camera.get_proj_matrix().clone(),
+            proj: self.depth_convention.depth_matrix() * camera.get_proj_matrix(),
+            normal_encoding: self.normal_encoding as u32,
         };
 
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.uniform_indirect_instance_buffer,
-                    self.uniform_indirect_instance_calculator.offset(0, index),
-                    &[args],
-                )
-                .expect("failed to upload uniforms");
-        }
+        let view = camera.get_view_matrix();
 
-        let draw_indexed_command = DrawIndexedCommand {
-            first_index: 0,
-            first_instance: 0,
-            vertex_offset: 0,
-            index_count: self.sphere_mesh.len(),
-            instance_count: limits.sphere_count() as u32,
-        };
+        let query = <(
+            Read<Sphere>,
+            Read<PositionComponent>,
+            TryRead<ColorComponent>,
+            TryRead<Material>,
+        )>::query();
 
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.uniform_indirect_instance_buffer,
-                    self.uniform_indirect_instance_calculator.offset(1, index),
-                    &[draw_indexed_command],
-                )
-                .expect("failed to upload draw indirect commands");
-        }
-
-        {
-            let mut instance_mapping = self
-                .uniform_indirect_instance_buffer
-                .map(
-                    factory.device(),
-                    self.uniform_indirect_instance_calculator.range(2, index),
-                )
-                .expect("failed to map buffer");
-
-            let mut instance_write = unsafe {
-                instance_mapping
-                    .write::<Instance>(
-                        factory.device(),
-                        0..self.uniform_indirect_instance_calculator.element(2).size as u64,
-                    )
-                    .expect("failed to write to mapping")
-            };
-
-            let instance_slice = unsafe { instance_write.slice() };
-
-            let view = camera.get_view_matrix();
-
-            let query = <(Read<Sphere>, Read<PositionComponent>)>::query();
-
-            for (instance, (sphere, position)) in
-                instance_slice.iter_mut().zip(query.iter(aux.deref()))
-            {
+        let instances = query
+            .iter(aux.deref())
+            .take(limits.sphere_count())
+            .map(|(sphere, position, color, material)| {
                 let radius = sphere.radius();
 
                 let model = scale(
@@ -356,9 +463,50 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for GBuffer<B> {
                     &vec3(radius, radius, radius),
                 );
 
-                let color = color_ramp.interpolate(radius);
+                let color = match color {
+                    Some(color) => color.0,
+                    None => color_ramp.color(radius),
+                };
+
+                let material = material.map_or_else(Material::default, |material| *material);
 
-                *instance = Instance::new(&model, view, color, 1.45);
+                Instance::new(&model, view, color, material.n)
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((args, instances)) = self.uploads.update(index, (args, instances)) {
+            let draw_indexed_command = DrawIndexedCommand {
+                first_index: 0,
+                first_instance: 0,
+                vertex_offset: 0,
+                index_count: self.sphere_mesh.len(),
+                instance_count: instances.len() as u32,
+            };
+
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(0, index),
+                        &[*args],
+                    )
+                    .expect("failed to upload uniforms");
+
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(1, index),
+                        &[draw_indexed_command],
+                    )
+                    .expect("failed to upload draw indirect commands");
+
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(2, index),
+                        instances,
+                    )
+                    .expect("failed to upload instances");
             }
         }
 
@@ -407,3 +555,102 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for GBuffer<B> {
 
     fn dispose(self, _factory: &mut Factory<B>, _aux: &ResWorld) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::vec4;
+    use rendy::core::hal::format::ChannelType;
+
+    /// `decode_normal` of the shaders
+    fn decode(normal_encoding: NormalEncoding, normal: [f32; 3]) -> [f32; 3] {
+        match normal_encoding {
+            NormalEncoding::Unorm => [(normal[0] - 0.5) * 2.0, (normal[1] - 0.5) * 2.0, normal[2]],
+            NormalEncoding::Snorm => normal,
+        }
+    }
+
+    #[test]
+    fn normal_formats_pair_with_their_encoding() {
+        for format in NormalEncoding::FORMATS.iter().cloned() {
+            let normal_encoding = match format.base_format().1 {
+                ChannelType::Unorm => NormalEncoding::Unorm,
+                ChannelType::Snorm => NormalEncoding::Snorm,
+                channel_type => panic!("{:?} has {:?} channels", format, channel_type),
+            };
+
+            assert_eq!(
+                NormalEncoding::from_format(format),
+                Some(normal_encoding),
+                "{:?}",
+                format
+            );
+        }
+
+        assert_eq!(NormalEncoding::from_format(Format::Rgba32Sfloat), None);
+    }
+
+    #[test]
+    fn shaders_use_the_same_encodings() {
+        let shaders = [
+            include_str!("../../../../assets/shaders/gbuffer.frag"),
+            include_str!("../../../../assets/shaders/comp.frag"),
+            include_str!("../../../../assets/shaders/dfao_sphere.frag"),
+        ];
+
+        for shader in shaders.iter() {
+            assert!(shader.contains(&format!(
+                "const uint NORMAL_ENCODING_UNORM = {};",
+                NormalEncoding::Unorm as u32
+            )));
+            assert!(shader.contains(&format!(
+                "const uint NORMAL_ENCODING_SNORM = {};",
+                NormalEncoding::Snorm as u32
+            )));
+        }
+    }
+
+    #[test]
+    fn clear_color_decodes_to_the_zero_normal() {
+        for normal_encoding in [NormalEncoding::Unorm, NormalEncoding::Snorm]
+            .iter()
+            .cloned()
+        {
+            let [x, y, z, _] = normal_encoding.clear_color();
+
+            assert_eq!(decode(normal_encoding, [x, y, z]), [0.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn reversed_depth_clears_to_zero_and_passes_greater_equal() {
+        assert_eq!(
+            DepthConvention::from_name("reversed"),
+            Some(DepthConvention::Reversed)
+        );
+        assert_eq!(DepthConvention::Reversed.clear_depth(), 0.0);
+        assert_eq!(
+            DepthConvention::Reversed.comparison(),
+            Comparison::GreaterEqual
+        );
+
+        assert_eq!(DepthConvention::Standard.clear_depth(), 1.0);
+        assert_eq!(DepthConvention::Standard.comparison(), Comparison::Less);
+    }
+
+    #[test]
+    fn reversed_depth_swaps_the_near_and_far_plane() {
+        let depth = |depth_convention: DepthConvention, z: f32| {
+            let clip = depth_convention.depth_matrix() * vec4(0.0, 0.0, z, 1.0);
+            clip.z / clip.w
+        };
+
+        assert_eq!(depth(DepthConvention::Standard, 0.0), 0.0);
+        assert_eq!(depth(DepthConvention::Standard, 1.0), 1.0);
+        assert_eq!(depth(DepthConvention::Reversed, 0.0), 1.0);
+        assert_eq!(
+            depth(DepthConvention::Reversed, 1.0),
+            DepthConvention::Reversed.clear_depth()
+        );
+    }
+}
@@ -0,0 +1,424 @@
+use crate::ext::{transform_point, Std140};
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, element_multi, CombinedBufferCalculator, DirtyTracker};
+use crate::world::camera::Camera;
+use crate::world::color_ramp::ColorRamp;
+use crate::world::sphere::{PositionComponent, Sphere, SphereLimits};
+use crate::world::ResWorld;
+use legion::prelude::*;
+use nalgebra_glm::{Mat4, Vec3};
+use rendy::command::{DrawIndexedCommand, QueueId, RenderPassEncoder};
+use rendy::factory::Factory;
+use rendy::graph::render::{
+    Layout, PrepareResult, SetLayout, SimpleGraphicsPipeline, SimpleGraphicsPipelineDesc,
+};
+use rendy::graph::{GraphContext, NodeBuffer, NodeImage};
+use rendy::hal::adapter::PhysicalDevice;
+use rendy::hal::buffer::Usage as BUsage;
+use rendy::hal::device::Device;
+use rendy::hal::format::{ChannelType, Format};
+use rendy::hal::pso::{
+    BlendState, ColorBlendDesc, ColorMask, CreationError, DepthStencilDesc, Descriptor,
+    DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, Face, Primitive,
+    Rasterizer, ShaderStageFlags, VertexInputRate,
+};
+use rendy::hal::Backend;
+use rendy::memory::Dynamic;
+use rendy::mesh::{AsVertex, Mesh, Position, VertexFormat};
+use rendy::resource::{Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle};
+use rendy::shader::{ShaderSet, SpirvShader};
+use std::mem::size_of;
+use std::ops::Deref;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Args {
+    projection_matrix: Std140<Mat4>,
+    encode_srgb: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
+pub struct Instance {
+    center: Vec3,
+    radius: f32,
+    color: Vec3,
+}
+
+impl AsVertex for Instance {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            (Format::Rgb32Sfloat, "center"),
+            (Format::R32Sfloat, "radius"),
+            (Format::Rgb32Sfloat, "color"),
+        ))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/glow.vert.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).expect("failed to load vertex shader");
+
+    static ref FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../../../../assets/shaders/glow.frag.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).expect("failed to load fragment shader");
+
+    static ref SHADERS: rendy::shader::ShaderSetBuilder = rendy::shader::ShaderSetBuilder::default()
+        .with_vertex(&*VERTEX).expect("failed to add vertex shader to shader set")
+        .with_fragment(&*FRAGMENT).expect("failed to add fragment shader to shader set");
+}
+
+/// The billboard every sphere is drawn as, a view aligned quad spanning the unit circle
+pub fn billboard() -> (Vec<Position>, Vec<u32>) {
+    (
+        vec![
+            Position([-1.0, -1.0, 0.0]),
+            Position([1.0, -1.0, 0.0]),
+            Position([1.0, 1.0, 0.0]),
+            Position([-1.0, 1.0, 0.0]),
+        ],
+        vec![0, 1, 2, 0, 2, 3],
+    )
+}
+
+/// One billboard for each of the first `sphere_count` spheres, centered on the sphere in view
+/// space
+fn instances(
+    world: &World,
+    camera: &Camera,
+    color_ramp: &ColorRamp,
+    sphere_count: usize,
+) -> Vec<Instance> {
+    let query = <(Read<Sphere>, Read<PositionComponent>)>::query();
+
+    query
+        .iter(world)
+        .take(sphere_count)
+        .map(|(sphere, position)| Instance {
+            center: transform_point(&position.0, camera.get_view_matrix()),
+            radius: sphere.radius(),
+            color: color_ramp.color(sphere.radius()),
+        })
+        .collect()
+}
+
+/// Draws every sphere as an additively blended radial gradient straight into the output. There
+/// is no lighting, occlusion or shadowing, so the pass needs none of the gbuffer images.
+#[derive(Debug)]
+pub struct GlowDesc {
+    encode_srgb: bool,
+}
+
+impl GlowDesc {
+    /// Like the comp pass, the glow is computed in linear space and encoded to srgb in the
+    /// shader if the output format does not do it on store. Float output formats keep the
+    /// linear colors.
+    pub fn new(output_format: Format) -> Self {
+        let encode_srgb = match output_format.base_format().1 {
+            ChannelType::Srgb | ChannelType::Sfloat | ChannelType::Ufloat => false,
+            _ => true,
+        };
+
+        GlowDesc { encode_srgb }
+    }
+}
+
+impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for GlowDesc {
+    type Pipeline = Glow<B>;
+
+    fn colors(&self) -> Vec<ColorBlendDesc> {
+        vec![ColorBlendDesc {
+            mask: ColorMask::ALL,
+            blend: Some(BlendState::ADD),
+        }]
+    }
+
+    fn depth_stencil(&self) -> Option<DepthStencilDesc> {
+        None
+    }
+
+    fn rasterizer(&self) -> Rasterizer {
+        Rasterizer {
+            cull_face: Face::NONE,
+            ..Rasterizer::FILL
+        }
+    }
+
+    fn vertices(&self) -> Vec<(Vec<Element<Format>>, u32, VertexInputRate)> {
+        vec![
+            Position::vertex().gfx_vertex_input_desc(VertexInputRate::Vertex),
+            Instance::vertex().gfx_vertex_input_desc(VertexInputRate::Instance(1)),
+        ]
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            sets: vec![SetLayout {
+                bindings: vec![DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                }],
+            }],
+            push_constants: vec![],
+        }
+    }
+
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "glow")
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        aux: &ResWorld,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+        set_layouts: &[Handle<DescriptorSetLayout<B>>],
+    ) -> Result<Self::Pipeline, CreationError> {
+        let frames = ctx.frames_in_flight;
+        let align = factory
+            .physical()
+            .limits()
+            .min_uniform_buffer_offset_alignment;
+
+        let limits = aux
+            .resources
+            .get::<SphereLimits>()
+            .expect("limits was not inserted into world");
+
+        let sphere_count = limits.sphere_count();
+
+        let uniform_indirect_instance_calculator = CombinedBufferCalculator::new(
+            vec![
+                element::<Args>(),
+                element::<DrawIndexedCommand>(),
+                element_multi::<Instance>(sphere_count),
+            ],
+            frames as u64,
+            align,
+        );
+
+        let uniform_indirect_instance_buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: uniform_indirect_instance_calculator.size(),
+                    usage: BUsage::UNIFORM | BUsage::INDIRECT | BUsage::VERTEX,
+                },
+                Dynamic,
+            )
+            .expect("failed to create buffer");
+
+        let uniform_sets = factory
+            .create_descriptor_sets::<Vec<_>>(set_layouts[0].clone(), frames)
+            .expect("failed to create descriptor set");
+
+        unsafe {
+            factory.write_descriptor_sets(uniform_sets.iter().enumerate().map(
+                |(frame, uniform_set)| DescriptorSetWrite {
+                    set: uniform_set.raw(),
+                    binding: 0,
+                    array_offset: 0,
+                    descriptors: Some(Descriptor::Buffer(
+                        uniform_indirect_instance_buffer.raw(),
+                        uniform_indirect_instance_calculator.option_range(0, frame),
+                    )),
+                },
+            ));
+        }
+
+        let (billboard_vertices, billboard_indices) = billboard();
+
+        let billboard_mesh = Mesh::<B>::builder()
+            .with_prim_type(Primitive::TriangleList)
+            .with_vertices(billboard_vertices)
+            .with_indices(billboard_indices)
+            .build(queue, factory)
+            .expect("failed to create billboard mesh");
+
+        Ok(Glow {
+            uniform_indirect_instance_calculator,
+            uniform_indirect_instance_buffer,
+            uniform_sets,
+            billboard_mesh,
+            encode_srgb: self.encode_srgb,
+            uploads: DirtyTracker::new(frames as usize),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Glow<B: Backend> {
+    uniform_indirect_instance_calculator: CombinedBufferCalculator,
+    uniform_indirect_instance_buffer: Escape<Buffer<B>>,
+    uniform_sets: Vec<Escape<DescriptorSet<B>>>,
+    billboard_mesh: Mesh<B>,
+    encode_srgb: bool,
+    uploads: DirtyTracker<(Args, Vec<Instance>)>,
+}
+
+impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for Glow<B> {
+    type Desc = GlowDesc;
+
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        _set_layouts: &[Handle<DescriptorSetLayout<B>>],
+        index: usize,
+        aux: &ResWorld,
+    ) -> PrepareResult {
+        let camera = aux
+            .resources
+            .get::<Camera>()
+            .expect("camera was not inserted into world");
+
+        let limits = aux
+            .resources
+            .get::<SphereLimits>()
+            .expect("limits was not inserted into world");
+
+        let color_ramp = aux
+            .resources
+            .get::<ColorRamp>()
+            .expect("color ramp was not inserted into world");
+
+        let args = Args {
+            projection_matrix: camera.get_proj_matrix().clone().into(),
+            encode_srgb: self.encode_srgb as u32,
+        };
+
+        let instances = instances(aux.deref(), &camera, &color_ramp, limits.sphere_count());
+
+        if let Some((args, instances)) = self.uploads.update(index, (args, instances)) {
+            let draw_indexed_command = DrawIndexedCommand {
+                first_index: 0,
+                first_instance: 0,
+                vertex_offset: 0,
+                index_count: self.billboard_mesh.len(),
+                instance_count: instances.len() as u32,
+            };
+
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(0, index),
+                        &[*args],
+                    )
+                    .expect("failed to upload uniforms");
+
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(1, index),
+                        &[draw_indexed_command],
+                    )
+                    .expect("failed to upload draw indirect commands");
+
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(2, index),
+                        instances,
+                    )
+                    .expect("failed to upload instances");
+            }
+        }
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw(
+        &mut self,
+        layout: &<B as Backend>::PipelineLayout,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _aux: &ResWorld,
+    ) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                layout,
+                0,
+                Some(self.uniform_sets[index].raw()),
+                None,
+            );
+        }
+
+        self.billboard_mesh
+            .bind(0, &[Position::vertex()], &mut encoder)
+            .expect("failed to bind billboard mesh");
+
+        unsafe {
+            encoder.bind_vertex_buffers(
+                1,
+                Some((
+                    self.uniform_indirect_instance_buffer.raw(),
+                    self.uniform_indirect_instance_calculator.offset(2, index),
+                )),
+            )
+        }
+
+        unsafe {
+            encoder.draw_indexed_indirect(
+                self.uniform_indirect_instance_buffer.raw(),
+                self.uniform_indirect_instance_calculator.offset(1, index),
+                1,
+                size_of::<DrawIndexedCommand>() as u32,
+            )
+        }
+    }
+
+    fn dispose(self, _factory: &mut Factory<B>, _aux: &ResWorld) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::camera::Projection;
+    use nalgebra_glm::{identity, vec3};
+
+    #[test]
+    fn one_billboard_per_sphere() {
+        let mut world = Universe::new().create_world();
+        world.insert(
+            (),
+            (0..3).map(|i| {
+                (
+                    Sphere::new(0.5),
+                    PositionComponent(vec3(i as f32, 0.0, 0.0)),
+                )
+            }),
+        );
+
+        let camera = Camera::new(
+            identity(),
+            Projection::Perspective { fov: 1.0 },
+            0.1,
+            100.0,
+            64,
+            64,
+        );
+        let color_ramp = ColorRamp::new(vec![vec3(1.0, 1.0, 1.0)]);
+
+        let billboards = instances(&world, &camera, &color_ramp, 3);
+        assert_eq!(billboards.len(), 3);
+        assert_eq!(billboards[2].center, vec3(2.0, 0.0, 0.0));
+        assert_eq!(billboards[2].radius, 0.5);
+
+        // spheres beyond the limit have no room in the instance buffer
+        assert_eq!(instances(&world, &camera, &color_ramp, 2).len(), 2);
+
+        let (vertices, indices) = billboard();
+        assert_eq!((vertices.len(), indices.len()), (4, 6));
+    }
+}
@@ -1,5 +1,11 @@
+pub mod bloom;
 pub mod capture;
 pub mod comp;
 pub mod dfao;
+pub mod dof;
+pub mod fxaa;
 pub mod gbuffer;
+pub mod glow;
+pub mod proxy;
 pub mod rtsh;
+pub mod timestamp;
@@ -0,0 +1,146 @@
+use genmesh::generators::{Cube, IcoSphere, IndexedPolygon, SharedVertex};
+use genmesh::{EmitTriangles, Vertex};
+use nalgebra_glm::{cross, dot, vec3, Vec3};
+use rendy::mesh::Position;
+use std::f32::consts::PI;
+
+/// Tessellation of the proxy geometry the dfao and rtsh passes rasterize per sphere. The proxies
+/// always enclose the volume they stand for, finer proxies just cover fewer pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct ProxyParams {
+    /// 0 uses a cube, higher values an icosphere subdivided `dfao_subdivisions - 1` times
+    pub dfao_subdivisions: usize,
+    /// number of sides of the pyramid enclosing the shadow cone, at least 3
+    pub rtsh_sides: usize,
+    /// scale applied on top of the enclosing size, values above 1 add a margin
+    pub scale: f32,
+}
+
+impl Default for ProxyParams {
+    fn default() -> Self {
+        Self {
+            dfao_subdivisions: 0,
+            rtsh_sides: 4,
+            scale: 1.0,
+        }
+    }
+}
+
+impl ProxyParams {
+    /// A proxy enclosing the unit sphere
+    pub fn dfao_proxy(&self) -> (Vec<Position>, Vec<u32>) {
+        let (vertices, indices) = match self.dfao_subdivisions {
+            0 => shape(Cube::new()),
+            subdivisions => shape(IcoSphere::subdivide(subdivisions - 1)),
+        };
+
+        let enclosing_scale = 1.0 / inner_radius(&vertices, &indices);
+
+        (positions(&vertices, enclosing_scale * self.scale), indices)
+    }
+
+    /// A pyramid with its apex in the origin, enclosing the cone through the unit circle at
+    /// `z = -1`
+    pub fn rtsh_proxy(&self) -> (Vec<Position>, Vec<u32>) {
+        let sides = self.rtsh_sides.max(3);
+        let radius = self.scale / (PI / sides as f32).cos();
+
+        let mut vertices = vec![vec3(0.0, 0.0, 0.0)];
+        let mut indices = vec![];
+
+        for side in 0..sides {
+            let angle = (side as f32 + 0.5) * 2.0 * PI / sides as f32 + PI;
+            vertices.push(vec3(angle.cos() * radius, angle.sin() * radius, -1.0));
+
+            indices.extend_from_slice(&[
+                0,
+                side as u32 + 1,
+                ((side + 1) % sides) as u32 + 1,
+            ]);
+        }
+
+        (positions(&vertices, 1.0), indices)
+    }
+}
+
+fn shape<P: EmitTriangles<Vertex = usize>, S: SharedVertex<Vertex> + IndexedPolygon<P>>(
+    shape: S,
+) -> (Vec<Vec3>, Vec<u32>) {
+    let vertices = shape
+        .shared_vertex_iter()
+        .map(|vertex| vec3(vertex.pos.x, vertex.pos.y, vertex.pos.z))
+        .collect::<Vec<_>>();
+
+    let mut indices = vec![];
+    shape.indexed_polygon_iter().for_each(|p| {
+        p.emit_triangles(|t| {
+            indices.push(t.x as u32);
+            indices.push(t.y as u32);
+            indices.push(t.z as u32);
+        })
+    });
+
+    (vertices, indices)
+}
+
+/// The smallest distance of the origin to the plane of any triangle
+fn inner_radius(vertices: &[Vec3], indices: &[u32]) -> f32 {
+    indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let a = &vertices[triangle[0] as usize];
+            let b = &vertices[triangle[1] as usize];
+            let c = &vertices[triangle[2] as usize];
+
+            dot(&cross(&(b - a), &(c - a)).normalize(), a).abs()
+        })
+        .fold(std::f32::MAX, f32::min)
+}
+
+fn positions(vertices: &[Vec3], scale: f32) -> Vec<Position> {
+    vertices
+        .iter()
+        .map(|vertex| Position([vertex.x * scale, vertex.y * scale, vertex.z * scale]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(dfao_subdivisions: usize, rtsh_sides: usize) -> ProxyParams {
+        ProxyParams {
+            dfao_subdivisions,
+            rtsh_sides,
+            ..ProxyParams::default()
+        }
+    }
+
+    #[test]
+    fn finer_proxies_have_more_vertices() {
+        let dfao_vertices = |subdivisions| params(subdivisions, 4).dfao_proxy().0.len();
+        let rtsh_vertices = |sides| params(0, sides).rtsh_proxy().0.len();
+
+        assert!(dfao_vertices(1) < dfao_vertices(2));
+        assert!(dfao_vertices(2) < dfao_vertices(3));
+
+        // the apex and one vertex per side, with at least 3 sides
+        assert_eq!(rtsh_vertices(2), 4);
+        assert_eq!(rtsh_vertices(4), 5);
+        assert_eq!(rtsh_vertices(8), 9);
+    }
+
+    #[test]
+    fn dfao_proxies_enclose_the_unit_sphere() {
+        for subdivisions in 0..4 {
+            let (positions, indices) = params(subdivisions, 4).dfao_proxy();
+
+            let vertices = positions
+                .iter()
+                .map(|Position(position)| vec3(position[0], position[1], position[2]))
+                .collect::<Vec<_>>();
+
+            assert_approx_eq!(inner_radius(&vertices, &indices), 1.0, 1e-4);
+        }
+    }
+}
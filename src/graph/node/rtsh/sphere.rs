@@ -1,7 +1,9 @@
 use crate::ext::{
     transform_point, GraphContextExt, Std140, FULLSCREEN_SAMPLER_DESC, SAMPLED_IMAGE_IMAGE_ACCESS,
 };
-use crate::mem::{element, element_multi, CombinedBufferCalculator};
+use crate::graph::node::proxy::ProxyParams;
+use crate::graph::shader::load_shader_set;
+use crate::mem::{element, element_multi, CombinedBufferCalculator, DirtyTracker};
 
 use crate::world::camera::Camera;
 use crate::world::environment::Environment;
@@ -28,7 +30,7 @@ use rendy::hal::pso::{
     Rasterizer, ShaderStageFlags, VertexInputRate,
 };
 use rendy::hal::Backend;
-use rendy::memory::{Dynamic, Write};
+use rendy::memory::Dynamic;
 use rendy::mesh::{AsVertex, Mesh, Position, VertexFormat};
 use rendy::resource::{
     Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle, ImageView, Sampler,
@@ -38,7 +40,7 @@ use std::mem::size_of;
 use std::ops::Deref;
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 struct Args {
     light_position: Std140<Vec3>,
 }
@@ -113,7 +115,15 @@ lazy_static::lazy_static! {
 }
 
 #[derive(Debug)]
-pub struct RTSHSphereDesc;
+pub struct RTSHSphereDesc {
+    proxy: ProxyParams,
+}
+
+impl RTSHSphereDesc {
+    pub fn new(proxy: ProxyParams) -> Self {
+        RTSHSphereDesc { proxy }
+    }
+}
 
 impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for RTSHSphereDesc {
     type Pipeline = RTSHSphere<B>;
@@ -185,10 +195,8 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for RTSHSphereDesc {
         }
     }
 
-    fn load_shader_set(&self, factory: &mut Factory<B>, _aux: &ResWorld) -> ShaderSet<B> {
-        SHADERS
-            .build(factory, Default::default())
-            .expect("failed to compile shader set")
+    fn load_shader_set(&self, factory: &mut Factory<B>, aux: &ResWorld) -> ShaderSet<B> {
+        load_shader_set(factory, aux, &SHADERS, "rtsh_sphere")
     }
 
     fn build<'a>(
@@ -282,16 +290,12 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for RTSHSphereDesc {
             }));
         }
 
+        let (proxy_vertices, proxy_indices) = self.proxy.rtsh_proxy();
+
         let cone_mesh = Mesh::<B>::builder()
             .with_prim_type(Primitive::TriangleList)
-            .with_vertices(vec![
-                Position([0.0, 0.0, 0.0]),
-                Position([-1.0, -1.0, -1.0]),
-                Position([1.0, -1.0, -1.0]),
-                Position([1.0, 1.0, -1.0]),
-                Position([-1.0, 1.0, -1.0]),
-            ])
-            .with_indices(vec![0u32, 1, 2, 0, 2, 3, 0, 3, 4, 0, 4, 1])
+            .with_vertices(proxy_vertices)
+            .with_indices(proxy_indices)
             .build(queue, factory)
             .expect("failed to create cone mesh");
 
@@ -303,6 +307,7 @@ impl<B: Backend> SimpleGraphicsPipelineDesc<B, ResWorld> for RTSHSphereDesc {
             uniform_sets,
             image_set,
             cone_mesh,
+            uploads: DirtyTracker::new(frames as usize),
         })
     }
 }
@@ -316,6 +321,7 @@ pub struct RTSHSphere<B: Backend> {
     uniform_sets: Vec<Escape<DescriptorSet<B>>>,
     image_set: Escape<DescriptorSet<B>>,
     cone_mesh: Mesh<B>,
+    uploads: DirtyTracker<(Args, Vec<Instance>)>,
 }
 
 impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for RTSHSphere<B> {
@@ -345,69 +351,63 @@ impl<B: Backend> SimpleGraphicsPipeline<B, ResWorld> for RTSHSphere<B> {
             .expect("camera was not inserted into world");
 
         let args = Args {
-            light_position: environment.light().get_position().clone().into(),
+            light_position: environment
+                .light()
+                .view_position(camera.get_view_matrix())
+                .into(),
         };
 
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.uniform_indirect_instance_buffer,
-                    self.uniform_indirect_instance_calculator.offset(0, index),
-                    &[args],
-                )
-                .expect("failed to upload uniforms");
-        }
-
-        let draw_indexed_command = DrawIndexedCommand {
-            first_index: 0,
-            first_instance: 0,
-            vertex_offset: 0,
-            index_count: self.cone_mesh.len(),
-            instance_count: limits.sphere_count() as u32,
-        };
+        let light_position = environment.light().world_position(camera.get_view_matrix());
 
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.uniform_indirect_instance_buffer,
-                    self.uniform_indirect_instance_calculator.offset(1, index),
-                    &[draw_indexed_command],
-                )
-                .expect("failed to upload indirect draw commands");
-        }
+        let query = <(Read<Sphere>, Read<PositionComponent>)>::query();
 
-        {
-            let mut instance_mapping = self
-                .uniform_indirect_instance_buffer
-                .map(
-                    factory,
-                    self.uniform_indirect_instance_calculator.range(2, index),
+        let instances = query
+            .iter(aux.deref())
+            .take(limits.sphere_count())
+            .map(|(sphere, position)| {
+                Instance::new(
+                    camera.get_view_matrix(),
+                    camera.get_proj_matrix(),
+                    &light_position,
+                    &position.0,
+                    sphere.radius(),
                 )
-                .expect("failed to map buffer");
-
-            let mut instance_write = unsafe {
-                instance_mapping
-                    .write::<Instance>(
-                        factory,
-                        0..self.uniform_indirect_instance_calculator.element(2).size as u64,
-                    )
-                    .expect("failed to write to mapping")
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((args, instances)) = self.uploads.update(index, (args, instances)) {
+            let draw_indexed_command = DrawIndexedCommand {
+                first_index: 0,
+                first_instance: 0,
+                vertex_offset: 0,
+                index_count: self.cone_mesh.len(),
+                instance_count: instances.len() as u32,
             };
 
-            let instance_slice = unsafe { instance_write.slice() };
+            unsafe {
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(0, index),
+                        &[*args],
+                    )
+                    .expect("failed to upload uniforms");
 
-            let query = <(Read<Sphere>, Read<PositionComponent>)>::query();
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(1, index),
+                        &[draw_indexed_command],
+                    )
+                    .expect("failed to upload indirect draw commands");
 
-            for (instance, (sphere, position)) in
-                instance_slice.iter_mut().zip(query.iter(aux.deref()))
-            {
-                *instance = Instance::new(
-                    camera.get_view_matrix(),
-                    camera.get_proj_matrix(),
-                    environment.light().get_position(),
-                    &position.0,
-                    sphere.radius(),
-                );
+                factory
+                    .upload_visible_buffer(
+                        &mut self.uniform_indirect_instance_buffer,
+                        self.uniform_indirect_instance_calculator.offset(2, index),
+                        instances,
+                    )
+                    .expect("failed to upload instances");
             }
         }
 
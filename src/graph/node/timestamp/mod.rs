@@ -0,0 +1,194 @@
+use rendy::command::{
+    CommandBuffer, CommandPool, ExecutableState, Family, Graphics, MultiShot, PendingState,
+    QueueType, SimultaneousUse, Submit,
+};
+use rendy::factory::Factory;
+use rendy::frame::Frames;
+use rendy::graph::{
+    GraphContext, Node, NodeBuffer, NodeBuildError, NodeDesc, NodeImage, NodeSubmittable,
+};
+use rendy::hal::command::CommandBuffer as RawCommandBuffer;
+use rendy::hal::device::Device;
+use rendy::hal::pso::PipelineStage;
+use rendy::hal::query::{Query, ResultFlags, Type as QueryType};
+use rendy::hal::Backend;
+
+use crate::world::gpu_profiler::GpuProfiler;
+use crate::world::ResWorld;
+
+/// Writes a gpu timestamp once every node it depends on finished and hands it to the
+/// `GpuProfiler`. The graph only orders nodes by their dependencies, so the next profiled pass has
+/// to depend on the timestamp as well.
+#[derive(Debug)]
+pub struct TimestampDesc {
+    index: usize,
+}
+
+impl TimestampDesc {
+    /// `index` is the position of the timestamp in the frame, see `GpuProfiler::set_labels`
+    pub fn new(index: usize) -> Self {
+        TimestampDesc { index }
+    }
+}
+
+impl<B: Backend> NodeDesc<B, ResWorld> for TimestampDesc {
+    type Node = Timestamp<B>;
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        family: &mut Family<B, QueueType>,
+        _queue: usize,
+        _aux: &ResWorld,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Self::Node, NodeBuildError> {
+        assert_eq!(buffers.len(), 0);
+        assert_eq!(images.len(), 0);
+
+        let frames = ctx.frames_in_flight;
+
+        // one query per frame in flight, so a query is only read once its frame completed
+        let query_pool = unsafe {
+            factory
+                .device()
+                .create_query_pool(QueryType::Timestamp, frames)
+                .expect("query pool creation failed")
+        };
+
+        let mut command_pool = factory
+            .create_command_pool(family)
+            .expect("command pool creation failed");
+
+        let per_frame = command_pool
+            .allocate_buffers(frames as usize)
+            .into_iter()
+            .enumerate()
+            .map(|(query, command_buffer)| {
+                let query = query as u32;
+
+                let mut command_buffer = command_buffer.begin(MultiShot(SimultaneousUse), ());
+
+                unsafe {
+                    let raw = command_buffer.raw();
+
+                    raw.reset_query_pool(&query_pool, query..query + 1);
+                    raw.write_timestamp(
+                        PipelineStage::BOTTOM_OF_PIPE,
+                        Query {
+                            pool: &query_pool,
+                            id: query,
+                        },
+                    );
+                }
+
+                let (submit, command_buffer) = command_buffer.finish().submit();
+
+                PerFrame {
+                    submit,
+                    command_buffer,
+                    dirty: None,
+                }
+            })
+            .collect();
+
+        Ok(Timestamp {
+            index: self.index,
+            per_frame,
+            query_pool,
+            command_pool,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct PerFrame<B: Backend> {
+    submit: Submit<B, SimultaneousUse>,
+    command_buffer:
+        CommandBuffer<B, QueueType, PendingState<ExecutableState<MultiShot<SimultaneousUse>>>>,
+    /// the frame the query was last written in
+    dirty: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Timestamp<B: Backend> {
+    index: usize,
+    per_frame: Vec<PerFrame<B>>,
+    query_pool: B::QueryPool,
+    command_pool: CommandPool<B>,
+}
+
+impl<B: Backend> Timestamp<B> {
+    fn read(&self, factory: &Factory<B>, query: u32) -> u64 {
+        let mut data = [0u8; 8];
+
+        unsafe {
+            factory
+                .device()
+                .get_query_pool_results(
+                    &self.query_pool,
+                    query..query + 1,
+                    &mut data,
+                    data.len() as u64,
+                    ResultFlags::BITS_64 | ResultFlags::WAIT,
+                )
+                .expect("could not read the timestamp");
+        }
+
+        u64::from_ne_bytes(data)
+    }
+}
+
+impl<'a, B: Backend> NodeSubmittable<'a, B> for Timestamp<B> {
+    type Submittable = &'a Submit<B, SimultaneousUse>;
+    type Submittables = Option<Self::Submittable>;
+}
+
+impl<B: Backend> Node<B, ResWorld> for Timestamp<B> {
+    type Capability = Graphics;
+
+    fn run<'a>(
+        &'a mut self,
+        ctx: &GraphContext<B>,
+        factory: &Factory<B>,
+        aux: &ResWorld,
+        frames: &'a Frames<B>,
+    ) -> <Self as NodeSubmittable<'a, B>>::Submittables {
+        let frame = frames.next().index();
+        let query = (frame % ctx.frames_in_flight as u64) as usize;
+
+        // the frame that last used the query completed before its slot is reused
+        if let Some(dirty) = self.per_frame[query].dirty {
+            let ticks = self.read(factory, query as u32);
+
+            if let Some(mut gpu_profiler) = aux.resources.get_mut::<GpuProfiler>() {
+                gpu_profiler.record(dirty, self.index, ticks);
+            }
+        }
+
+        let for_frame = &mut self.per_frame[query];
+        for_frame.dirty = Some(frame);
+
+        Some(&for_frame.submit)
+    }
+
+    unsafe fn dispose(self, factory: &mut Factory<B>, _aux: &ResWorld) {
+        let Timestamp {
+            per_frame,
+            query_pool,
+            mut command_pool,
+            ..
+        } = self;
+
+        command_pool.free_buffers(
+            per_frame
+                .into_iter()
+                .map(|for_frame| for_frame.command_buffer.mark_complete()),
+        );
+
+        command_pool.dispose(factory);
+
+        factory.device().destroy_query_pool(query_pool);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::event::ChangeEvent;
+use crate::world::ResWorld;
+use anyhow::Error;
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use rendy::factory::Factory;
+use rendy::hal::pso::ShaderStageFlags;
+use rendy::hal::Backend;
+use rendy::shader::{ShaderSet, ShaderSetBuilder, SpirvShader};
+use std::fs::read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Watches a directory of compiled shaders, e.g. `assets/shaders`. While it is in the world the
+/// nodes read their shaders from that directory instead of using the compiled in ones, and every
+/// change of a `.spv` file rebuilds the graph.
+pub struct ShaderWatcher {
+    directory: PathBuf,
+    changed: ChangeEvent,
+    // neither the receiver nor the watcher are sync, resources have to be
+    events: Mutex<Receiver<DebouncedEvent>>,
+    _watcher: Mutex<RecommendedWatcher>,
+}
+
+impl ShaderWatcher {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Result<Self, Error> {
+        let (sender, events) = channel();
+
+        let mut watcher = watcher(sender, Duration::from_millis(200))?;
+        watcher.watch(directory.as_ref(), RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            directory: directory.as_ref().to_path_buf(),
+            changed: ChangeEvent::new(),
+            events: Mutex::new(events),
+            _watcher: Mutex::new(watcher),
+        })
+    }
+
+    /// Takes the file events since the last poll and signals a change if a shader was written
+    pub fn poll(&mut self) {
+        let events = self
+            .events
+            .get_mut()
+            .expect("shader watcher events were poisoned");
+
+        let written_shaders = events
+            .try_iter()
+            .filter_map(|event| match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path) => Some(path),
+                _ => None,
+            })
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "spv")
+            })
+            .count();
+
+        if written_shaders > 0 {
+            println!("shaders changed, rebuilding the graph");
+            self.changed.change();
+        }
+    }
+
+    pub fn changed(&self) -> &ChangeEvent {
+        &self.changed
+    }
+
+    /// Reads `<name>.vert.spv` and `<name>.frag.spv` from the watched directory
+    pub fn load(&self, name: &str) -> Result<ShaderSetBuilder, Error> {
+        let vertex = self.load_shader(name, "vert", ShaderStageFlags::VERTEX)?;
+        let fragment = self.load_shader(name, "frag", ShaderStageFlags::FRAGMENT)?;
+
+        ShaderSetBuilder::default()
+            .with_vertex(&vertex)
+            .and_then(|shaders| shaders.with_fragment(&fragment))
+            .map_err(|err| anyhow!("{:?}", err))
+    }
+
+    fn load_shader(
+        &self,
+        name: &str,
+        stage: &str,
+        stage_flags: ShaderStageFlags,
+    ) -> Result<SpirvShader, Error> {
+        let path = self.directory.join(format!("{}.{}.spv", name, stage));
+
+        SpirvShader::from_bytes(&read(&path)?, stage_flags, "main")
+            .map_err(|err| anyhow!("could not load {:?}: {:?}", path, err))
+    }
+}
+
+/// Builds the shader set of a node from the shaders of the `ShaderWatcher`, if there is one in the
+/// world, or else from the compiled in `shaders`. Shaders that fail to load fall back to the
+/// compiled in ones, so a broken shader does not end the session.
+pub fn load_shader_set<B: Backend>(
+    factory: &mut Factory<B>,
+    aux: &ResWorld,
+    shaders: &ShaderSetBuilder,
+    name: &str,
+) -> ShaderSet<B> {
+    if let Some(shader_watcher) = aux.resources.get::<ShaderWatcher>() {
+        let shader_set = shader_watcher.load(name).and_then(|watched| {
+            watched
+                .build(factory, Default::default())
+                .map_err(|err| anyhow!("{:?}", err))
+        });
+
+        match shader_set {
+            Ok(shader_set) => return shader_set,
+            Err(err) => println!(
+                "warning: could not load the {} shaders, using the compiled in ones: {}",
+                name, err
+            ),
+        }
+    }
+
+    shaders
+        .build(factory, Default::default())
+        .expect("failed to compile shader set")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::StateId;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::time::Instant;
+
+    /// Polls the watcher until it reports a change or a second has passed
+    fn changed_within_a_second(shader_watcher: &mut ShaderWatcher, state_id: &mut StateId) -> bool {
+        let start = Instant::now();
+
+        while start.elapsed() < Duration::from_secs(1) {
+            shader_watcher.poll();
+
+            if shader_watcher.changed().has_changed(state_id) {
+                return true;
+            }
+
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        false
+    }
+
+    #[test]
+    fn only_written_shaders_rebuild_the_graph() {
+        let directory = std::env::temp_dir().join("rendy-sphere-visualizer-watched-shaders");
+        let _ = remove_dir_all(&directory);
+        create_dir_all(&directory).unwrap();
+
+        let mut shader_watcher = ShaderWatcher::new(&directory).unwrap();
+        let mut state_id = shader_watcher.changed().register();
+
+        write(directory.join("notes.txt"), "not a shader").unwrap();
+        assert!(!changed_within_a_second(&mut shader_watcher, &mut state_id));
+
+        write(directory.join("sphere.frag.spv"), [0u8; 4]).unwrap();
+        assert!(changed_within_a_second(&mut shader_watcher, &mut state_id));
+
+        // a shader that does not exist or is no spir-v keeps the compiled in one
+        assert!(shader_watcher.load("sphere").is_err());
+    }
+}
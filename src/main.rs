@@ -4,16 +4,19 @@ extern crate rendy;
 extern crate lazy_static;
 #[macro_use]
 extern crate anyhow;
-#[macro_use]
-extern crate shrinkwraprs;
 
 #[cfg(test)]
 #[macro_use]
 extern crate assert_approx_eq;
 
+use crate::graph::node::comp::AlphaMode;
+use crate::graph::node::fxaa::{Antialiasing, FxaaQuality};
+use crate::graph::node::gbuffer::DepthConvention;
+use crate::graph::shader::ShaderWatcher;
 use crate::graph::{
-    choose_format, CaptureOutput, RenderingSystem, SavePng, SphereVisualizerGraphCreator,
-    SurfaceOutput,
+    choose_format, format_supported, CaptureOutput, GBufferDump, GBufferTarget, GraphConfig,
+    ImageFormat, Output, QualityPreset, RenderStyle, RenderingSystem, SaveExr, SaveImage,
+    SaveSingleImage, SaveVideo, SphereVisualizerGraphCreator, SurfaceOutput,
 };
 
 use anyhow::Error;
@@ -24,79 +27,200 @@ use rendy::hal::format::Format;
 
 use rendy::hal::format::ImageFeature;
 use rendy::hal::Backend;
-use rendy::init::winit::event::{Event, WindowEvent};
+use rendy::init::winit::dpi::PhysicalSize;
+use rendy::init::winit::event::{Event, VirtualKeyCode, WindowEvent};
 use rendy::init::winit::event_loop::{ControlFlow, EventLoop};
-use rendy::init::winit::window::Window;
-use rendy::resource::Tiling;
+use rendy::init::winit::window::{Icon, Window, WindowBuilder};
+use rendy::resource::{Filter, Tiling};
 
 use crate::animation::Frame;
 use crate::application::{application_bundle, ApplicationBundleParams};
+use crate::audio::drain_in_real_time;
+use crate::backend::{init_first, preferred_backends};
 use crate::bundle::{Bundle, BundlePhase1};
+use crate::manifest::{Manifest, OutputFiles};
+use crate::progress::Progress;
+use crate::world::camera::{OrbitCamera, Projection};
+use crate::world::color_ramp::ColorRamp;
+use crate::world::gpu_profiler::GpuProfiler;
+use crate::world::input::Input;
 use crate::world::resolution::Resolution;
-use crate::world::sphere::{LoadMode, SphereLimits};
-use crate::world::time::HeadlessTime;
+use crate::world::scene::{SceneDescription, SphereSource};
+use crate::world::snapshot::Snapshot;
+use crate::world::sphere::{
+    add_sphere, remove_sphere, AgcParams, Analyzer, BandSplit, Layout, LoadMode, RestShape, Sphere,
+    SphereLimits,
+};
+use crate::world::time::{HeadlessTime, Time};
+use crate::verify::{GoldenAction, GoldenDifference};
 use crate::world::ResWorld;
-use clap::{App, Arg, ArgGroup};
+use clap::{App, Arg, ArgGroup, ArgMatches};
 use image::ColorType;
 use legion::prelude::*;
+use nalgebra_glm::{vec3, zero, Vec3};
 use rendy::wsi::Surface;
-use rodio::{default_output_device, play_raw, Decoder, Sample, Source};
+use rodio::source::Zero;
+use rodio::{
+    default_output_device, output_devices, play_raw, Decoder, Device, DeviceTrait, Sample, Source,
+};
+use serde::Serialize;
 use serde::export::fmt::Debug;
 use std::fs::File;
 use std::io::BufReader;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 pub mod animation;
 pub mod application;
 pub mod audio;
+pub mod backend;
 pub mod bundle;
 pub mod cubemap;
 pub mod event;
 pub mod ext;
 pub mod graph;
+pub mod manifest;
 pub mod mem;
 pub mod physics;
+pub mod prelude;
+pub mod progress;
+pub mod verify;
 pub mod world;
 
+#[cfg(all(test, any(feature = "dx12", feature = "metal", feature = "vulkan")))]
+mod test_support;
+
 lazy_static! {
     static ref ENVIRONMENT_MAP_PATH: PathBuf =
         crate::application_root_dir().join("assets/environment/sides/");
+    static ref SHADER_PATH: PathBuf = crate::application_root_dir().join("assets/shaders/");
+    static ref GOLDEN_SCENE_PATH: PathBuf =
+        crate::application_root_dir().join("assets/golden/scene.json");
+    static ref GOLDEN_IMAGE_PATH: PathBuf =
+        crate::application_root_dir().join("assets/golden/sphere.png");
 }
 
 fn render<
     B: Backend,
     P: 'static + AsRef<Path> + Clone + Send + Sync + Debug,
-    P2: 'static + AsRef<Path>,
+    P2: 'static + AsRef<Path> + Serialize,
     S: Source,
 >(
     mut world: ResWorld,
     factory: Factory<B>,
     families: Families<B>,
     output_directory: P,
+    image_format: ImageFormat,
+    hdr: bool,
+    video: Option<PathBuf>,
     application_bundle_params: ApplicationBundleParams<P2>,
     source: S,
+    audio: bool,
+    width: u32,
+    height: u32,
+    fps: f32,
+    strict_length: bool,
+    graph_config: GraphConfig,
+    physics_pre_roll: usize,
+    gravity: Vec3,
+    aspect: Option<f32>,
+    orbit: Option<OrbitCamera>,
+    projection: Option<Projection>,
+    scene: SceneDescription,
+    snapshot: Option<Snapshot>,
+    max_frames: Option<usize>,
+    start_frame: usize,
+    end_frame: Option<usize>,
+    skip_existing: bool,
+    audio_offset: f32,
+    max_pending_saves: usize,
+    interrupted: Arc<AtomicBool>,
 ) -> Result<(), Error>
 where
     S::Item: Sample,
 {
-    let resolution = Resolution::new(3840, 2160);
-    let fps = 60.0f32;
+    let resolution = Resolution::new(width, height);
 
-    let gpu_format = choose_format(
-        &factory,
-        &[Format::Rgb8Srgb, Format::Rgba8Srgb],
-        Tiling::Optimal,
-        ImageFeature::COLOR_ATTACHMENT | ImageFeature::COLOR_ATTACHMENT_BLEND,
-    )
-    .ok_or(anyhow!("there is no gpu format compatible with PNG"))?;
+    let frame_extension = if hdr { "exr" } else { image_format.extension() };
 
-    let cpu_format = match gpu_format {
-        Format::Rgb8Srgb => ColorType::Rgb8,
-        Format::Rgba8Srgb => ColorType::Rgba8,
-        _ => bail!("this should never happen"),
-    };
+    let config = serde_json::to_value(&application_bundle_params)?;
+    let output_files = OutputFiles::new();
+
+    let output: Box<dyn Output<B>> = if hdr {
+        let gpu_format = SaveExr::<P>::FORMATS
+            .iter()
+            .copied()
+            .find(|format| {
+                format_supported(
+                    &factory,
+                    *format,
+                    Tiling::Optimal,
+                    ImageFeature::COLOR_ATTACHMENT | ImageFeature::COLOR_ATTACHMENT_BLEND,
+                )
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "the gpu can not render into any of {:?}",
+                    SaveExr::<P>::FORMATS
+                )
+            })?;
+
+        println!("gpu format: {:?}", gpu_format);
+
+        let output_directory = output_directory.clone();
+        let output_files = output_files.clone();
+        let save_exr = move |_: &Resolution| -> Result<_, Error> {
+            Ok(SaveExr::new(output_directory.clone())?
+                .with_max_pending(max_pending_saves)
+                .with_output_files(output_files.clone()))
+        };
+
+        // the channels are read back as the type of their size
+        match gpu_format {
+            Format::Rgba16Sfloat => Box::new(CaptureOutput::<_, _, u16>::new(save_exr, gpu_format)),
+            _ => Box::new(CaptureOutput::<_, _, f32>::new(save_exr, gpu_format)),
+        }
+    } else if let Some(video) = video {
+        if graph_config.alpha_mode != AlphaMode::Opaque {
+            bail!("a video can not store the alpha channel, use --alpha-mode opaque");
+        }
+
+        let (gpu_format, cpu_format) = capture_formats(&factory, ImageFormat::Png, false)?;
+
+        println!("gpu format: {:?}, cpu format: {:?}", gpu_format, cpu_format);
+
+        let output_files = output_files.clone();
+        Box::new(CaptureOutput::new(
+            move |resolution| {
+                Ok(SaveVideo::new(&video, cpu_format, resolution, fps)?
+                    .with_output_files(output_files.clone()))
+            },
+            gpu_format,
+        ))
+    } else {
+        let (gpu_format, cpu_format) = capture_formats(
+            &factory,
+            image_format,
+            graph_config.alpha_mode != AlphaMode::Opaque,
+        )?;
 
-    println!("gpu format: {:?}, cpu format: {:?}", gpu_format, cpu_format);
+        println!("gpu format: {:?}, cpu format: {:?}", gpu_format, cpu_format);
+
+        let output_directory = output_directory.clone();
+        let output_files = output_files.clone();
+        Box::new(CaptureOutput::new(
+            move |_| {
+                Ok(SaveImage::new(output_directory.clone(), cpu_format)?
+                    .with_format(image_format)
+                    .with_max_pending(max_pending_saves)
+                    .with_output_files(output_files.clone()))
+            },
+            gpu_format,
+        ))
+    };
 
     let (bundle, mut source) = application_bundle::<B, _, _>(
         factory,
@@ -105,20 +229,22 @@ where
         None,
         application_bundle_params,
         Mode::Headless,
+        fps,
         source,
+        physics_pre_roll,
+        gravity,
+        aspect,
+        orbit,
+        projection,
+        &scene,
     )?;
 
     let mut schedule = bundle
         .add_entities_and_resources(&mut world)?
         .build_schedule(&world)?;
 
-    let graph_creator = SphereVisualizerGraphCreator::<B, _>::new(
-        &world,
-        CaptureOutput::new(
-            || SavePng::new(output_directory.clone(), cpu_format),
-            gpu_format,
-        ),
-    );
+    let graph_creator =
+        SphereVisualizerGraphCreator::<B, _>::new(&world, output).with_config(graph_config);
 
     let mut rendering_system = RenderingSystem::new(graph_creator, &mut world)?;
 
@@ -127,20 +253,107 @@ where
         .get::<SphereLimits>()
         .and_then(|sphere_limits| sphere_limits.frame_count());
 
-    let samples_per_frame =
-        ((source.sample_rate() * source.channels() as u32) as f32 / fps) as usize;
-    'a: for frame in 0..frame_count.unwrap_or(std::usize::MAX) {
+    if let Some(frame_count) = frame_count {
+        check_frame_count(frame_count, fps, source.total_duration(), strict_length)?;
+    } else if !audio && max_frames.is_none() && end_frame.is_none() {
+        bail!(
+            "without audio the length comes from the loaded physics, --max-frames or --end-frame"
+        );
+    }
+
+    world
+        .resources
+        .get_mut::<HeadlessTime>()
+        .iter_mut()
+        .for_each(|time| time.set_total(frame_count));
+
+    if let Some(snapshot) = snapshot {
+        snapshot.restore::<B>(&mut world)?;
+        rendering_system.render(&mut world)?;
+        rendering_system.dispose(&mut world);
+
+        let frame = snapshot.frame.max(0.0) as usize;
+
+        Manifest {
+            files: output_files.sorted(),
+            width,
+            height,
+            fps,
+            frames: frame..frame + 1,
+            config,
+        }
+        .save(output_directory.as_ref().join("manifest.json"))?;
+
+        return Ok(());
+    }
+
+    // without audio the silent source is not consumed, the frame count alone ends the render
+    let samples_per_second = if audio {
+        (source.sample_rate() * source.channels() as u32) as f32
+    } else {
+        0.0
+    };
+    let samples_per_frame = (samples_per_second / fps) as usize;
+
+    let total_duration = source.total_duration();
+    let mut delayed_samples = offset_audio(
+        &mut source,
+        audio_offset,
+        samples_per_second,
+        total_duration,
+    )?;
+
+    let last_frame = frame_range(start_frame, end_frame, frame_count, max_frames)?.end;
+
+    let mut progress = Progress::new(if frame_count.is_some() || end_frame.is_some() {
+        Some(last_frame.saturating_sub(start_frame))
+    } else {
+        None
+    });
+
+    // the frames before the start frame and the skipped ones are still simulated, just not
+    // rendered, so the physics, the analysis and the audio stay in sync with a full render
+    let mut simulated_frames = 0;
+    let mut written_frames = 0;
+    // `interrupted` stops after the current frame, so the frames still being saved land on disk
+    // when the graph is disposed
+    'a: for frame in 0..last_frame {
+        if interrupted.load(Ordering::SeqCst) {
+            println!("interrupted after writing {} frames", written_frames);
+            break;
+        }
+
         world
             .resources
             .get_mut::<HeadlessTime>()
             .iter_mut()
-            .for_each(|time| time.set(Frame::new(frame as f32)));
+            .for_each(|time| time.seek(Frame::new(frame as f32)));
 
         schedule.execute(&mut world.world, &mut world.resources);
 
-        rendering_system.render(&mut world)?;
+        if frame >= start_frame {
+            let existing = if skip_existing {
+                existing_frame(output_directory.as_ref(), frame, frame_extension)
+            } else {
+                None
+            };
+
+            if let Some(existing) = existing {
+                output_files.push(existing);
+            } else {
+                rendering_system.render(&mut world)?;
+                written_frames += 1;
+            }
+
+            progress.frame_rendered(frame + 1 - start_frame);
+        }
+
+        simulated_frames = frame + 1;
 
-        for _ in 0..samples_per_frame {
+        let delayed = delayed_samples.min(samples_per_frame);
+        delayed_samples -= delayed;
+
+        for _ in delayed..samples_per_frame {
             if let None = source.next() {
                 if let None = frame_count {
                     break 'a;
@@ -149,157 +362,1519 @@ where
         }
     }
 
+    if frame_count.is_none() && end_frame.is_none() && simulated_frames == last_frame {
+        println!("stopped at the maximum of {} frames", last_frame);
+    }
+
     rendering_system.dispose(&mut world);
 
+    Manifest {
+        files: output_files.sorted(),
+        width,
+        height,
+        fps,
+        frames: start_frame.min(simulated_frames)..simulated_frames,
+        config,
+    }
+    .save(output_directory.as_ref().join("manifest.json"))?;
+
     Ok(())
 }
 
-fn init<B: Backend, T: 'static, P: 'static + AsRef<Path>, S: 'static + Source + Send>(
+/// Applies the `--quality` preset, `auto` choosing one for the resolution rendered at, then the
+/// dfao and proxy flags given explicitly on top of it
+fn configure_quality(
+    mut graph_config: GraphConfig,
+    matches: &ArgMatches,
+    width: u32,
+    height: u32,
+) -> Result<GraphConfig, Error> {
+    let preset = match matches.value_of("quality") {
+        Some("auto") => Some(QualityPreset::for_resolution(width, height)),
+        Some(name) => QualityPreset::from_name(name),
+        None => None,
+    };
+
+    if let Some(preset) = preset {
+        println!("quality preset: {:?}", preset);
+        graph_config = graph_config.with_quality(preset);
+    }
+
+    if let Some(iterations) = matches.value_of("dfao-iterations") {
+        graph_config.dfao.iterations = iterations.parse()?;
+    }
+
+    if let Some(subdivisions) = matches.value_of("proxy-subdivisions") {
+        graph_config.proxy.dfao_subdivisions = subdivisions.parse()?;
+    }
+
+    if let Some(sides) = matches.value_of("proxy-sides") {
+        graph_config.proxy.rtsh_sides = sides.parse()?;
+    }
+
+    if graph_config.proxy.rtsh_sides < 3 {
+        bail!("the shadow proxy needs at least 3 sides");
+    }
+
+    Ok(graph_config)
+}
+
+/// The `--gravity` given as `x,y,z`
+fn parse_gravity(gravity: &str) -> Result<Vec3, Error> {
+    let components = gravity
+        .split(',')
+        .map(|component| component.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match components.as_slice() {
+        &[x, y, z] => Ok(vec3(x, y, z)),
+        _ => bail!("the gravity has to be given as x,y,z"),
+    }
+}
+
+/// The `--max-frames-in-flight` given in every mode
+fn parse_frames_in_flight(frames_in_flight: &str) -> Result<u32, Error> {
+    let frames_in_flight = frames_in_flight.parse::<u32>()?;
+
+    if frames_in_flight < 1 {
+        bail!("at least one frame has to be in flight");
+    }
+
+    Ok(frames_in_flight)
+}
+
+/// The window with the `--title` and `--icon` given
+fn window_builder(matches: &ArgMatches) -> Result<WindowBuilder, Error> {
+    let window_icon = match matches.value_of("icon") {
+        Some(path) => Some(load_window_icon(path)?),
+        None => None,
+    };
+
+    Ok(WindowBuilder::new()
+        .with_title(matches.value_of("title").unwrap())
+        .with_window_icon(window_icon)
+        .with_maximized(true))
+}
+
+/// Loads an image as the icon of the window
+fn load_window_icon<P: AsRef<Path>>(path: P) -> Result<Icon, Error> {
+    let image = image::open(path)?.to_rgba();
+    let (width, height) = image.dimensions();
+
+    Icon::from_rgba(image.into_raw(), width, height)
+        .map_err(|err| anyhow!("invalid window icon: {:?}", err))
+}
+
+/// The formats frames are rendered in and saved as. `alpha` requires a format with an alpha
+/// channel.
+fn capture_formats<B: Backend>(
+    factory: &Factory<B>,
+    image_format: ImageFormat,
+    alpha: bool,
+) -> Result<(Format, ColorType), Error> {
+    if alpha && !image_format.supports_alpha() {
+        bail!(
+            "{} can not store the alpha channel, use --alpha-mode opaque",
+            image_format.extension()
+        );
+    }
+
+    let formats: &[Format] = if alpha {
+        &[Format::Rgba8Srgb]
+    } else {
+        &[Format::Rgb8Srgb, Format::Rgba8Srgb]
+    };
+
+    let gpu_format = choose_format(
+        factory,
+        formats,
+        Tiling::Optimal,
+        ImageFeature::COLOR_ATTACHMENT | ImageFeature::COLOR_ATTACHMENT_BLEND,
+    )
+    .ok_or(anyhow!(
+        "there is no gpu format compatible with {}",
+        image_format.extension()
+    ))?;
+
+    let cpu_format = match gpu_format {
+        Format::Rgb8Srgb => ColorType::Rgb8,
+        Format::Rgba8Srgb => ColorType::Rgba8,
+        _ => bail!("this should never happen"),
+    };
+
+    Ok((gpu_format, cpu_format))
+}
+
+const VERIFY_TOLERANCE: f32 = 0.02;
+
+/// Adds the golden scene to `world` at the resolution of the golden image and returns the
+/// schedule animating it
+fn golden_scene<B: Backend>(
+    world: &mut ResWorld,
+    factory: Factory<B>,
+    families: Families<B>,
+) -> Result<Schedule, Error> {
+    let (bundle, _source) = application_bundle::<B, _, _>(
+        factory,
+        families,
+        Resolution::new(64, 64),
+        None,
+        ApplicationBundleParams::Load {
+            path: GOLDEN_SCENE_PATH.clone(),
+            load_mode: LoadMode::PositionRadius,
+            color_ramp: None,
+            spring_factor: None,
+            damping: 1.0,
+            seed: None,
+            layout: Layout::Line,
+            physics: true,
+        },
+        Mode::Headless,
+        60.0,
+        Zero::<f32>::new(1, 44100),
+        0,
+        zero(),
+        None,
+        None,
+        None,
+        &SceneDescription::default(),
+    )?;
+
+    bundle
+        .add_entities_and_resources(world)?
+        .build_schedule(world)
+}
+
+/// Renders the single sphere of the golden scene at a low resolution and compares it against
+/// the golden image, or replaces the golden image if `update` is set
+fn verify<B: Backend>(
     mut world: ResWorld,
     factory: Factory<B>,
     families: Families<B>,
-    surface: Surface<B>,
-    window: Window,
-    event_loop: EventLoop<T>,
+    golden: PathBuf,
+    update: bool,
+) -> Result<(), Error> {
+    if !update && !golden.exists() {
+        bail!(
+            "the golden image {:?} does not exist, render it with --verify --update-golden",
+            golden
+        );
+    }
+
+    let (gpu_format, cpu_format) = capture_formats(&factory, ImageFormat::Png, false)?;
+
+    let mut schedule = golden_scene(&mut world, factory, families)?;
+
+    let difference = GoldenDifference::new();
+
+    let graph_creator = SphereVisualizerGraphCreator::<B, _>::new(
+        &world,
+        CaptureOutput::new(
+            |_| {
+                Ok(GoldenAction::new(
+                    golden.clone(),
+                    cpu_format,
+                    update,
+                    difference.clone(),
+                ))
+            },
+            gpu_format,
+        ),
+    );
+
+    let mut rendering_system = RenderingSystem::new(graph_creator, &mut world)?;
+
+    schedule.execute(&mut world.world, &mut world.resources);
+
+    rendering_system.render(&mut world)?;
+
+    rendering_system.dispose(&mut world);
+
+    if update {
+        println!("updated golden image {:?}", golden);
+        return Ok(());
+    }
+
+    let difference = difference
+        .get()
+        .ok_or(anyhow!("no frame was compared to the golden image"))?;
+
+    if difference > VERIFY_TOLERANCE {
+        bail!(
+            "the rendered frame differs from the golden image by {} (tolerance {})",
+            difference,
+            VERIFY_TOLERANCE
+        );
+    }
+
+    println!("the rendered frame matches the golden image");
+
+    Ok(())
+}
+
+/// Renders the single frame `frame` and saves it to `path`, the image format follows the
+/// extension of `path`. The schedule only runs once, so physics and the audio analysis are not
+/// simulated up to `frame`, a loaded animation however shows the spheres of that frame.
+fn screenshot<B: Backend, P: 'static + AsRef<Path>, S: Source>(
+    mut world: ResWorld,
+    factory: Factory<B>,
+    families: Families<B>,
+    frame: f32,
+    path: PathBuf,
     application_bundle_params: ApplicationBundleParams<P>,
     source: S,
+    width: u32,
+    height: u32,
+    fps: f32,
+    graph_config: GraphConfig,
+    physics_pre_roll: usize,
+    gravity: Vec3,
+    aspect: Option<f32>,
+    orbit: Option<OrbitCamera>,
+    projection: Option<Projection>,
+    scene: SceneDescription,
 ) -> Result<(), Error>
 where
     S::Item: Sample,
 {
-    unsafe {
-        println!("surface format: {:?}", surface.format(factory.physical()));
-    }
+    let image_format = match path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ImageFormat::from_name)
+    {
+        Some(image_format) => image_format,
+        None => bail!("can not save a screenshot as {:?}", path),
+    };
 
-    let resolution = Resolution::from_physical_size(window.inner_size());
+    let (gpu_format, cpu_format) = capture_formats(&factory, image_format, false)?;
 
-    let (bundle, source) = application_bundle::<B, _, _>(
+    let (bundle, _source) = application_bundle::<B, _, _>(
         factory,
         families,
-        resolution,
-        Some(window),
+        Resolution::new(width, height),
+        None,
         application_bundle_params,
-        Mode::Realtime,
+        Mode::Headless,
+        fps,
         source,
+        physics_pre_roll,
+        gravity,
+        aspect,
+        orbit,
+        projection,
+        &scene,
     )?;
 
     let mut schedule = bundle
         .add_entities_and_resources(&mut world)?
         .build_schedule(&world)?;
 
-    let graph_creator =
-        SphereVisualizerGraphCreator::<B, _>::new(&world, SurfaceOutput::new(Some(surface)));
+    let graph_creator = SphereVisualizerGraphCreator::<B, _>::new(
+        &world,
+        CaptureOutput::new(
+            move |_| Ok(SaveSingleImage::new(path.clone(), cpu_format)?.with_format(image_format)),
+            gpu_format,
+        ),
+    )
+    .with_config(graph_config);
 
     let mut rendering_system = RenderingSystem::new(graph_creator, &mut world)?;
 
-    let mut fps = fps_counter::FPSCounter::new();
+    let frame_count = world
+        .resources
+        .get::<SphereLimits>()
+        .and_then(|sphere_limits| sphere_limits.frame_count());
 
-    play_raw(
-        &default_output_device().expect("No default output device found"),
-        source.convert_samples::<f32>(),
-    );
+    world
+        .resources
+        .get_mut::<HeadlessTime>()
+        .iter_mut()
+        .for_each(|time| {
+            time.set_total(frame_count);
+            time.seek(Frame::new(frame));
+        });
 
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::MainEventsCleared => {
-            let window = world
-                .resources
-                .get::<Window>()
-                .expect("window was not inserted into world");
+    schedule.execute(&mut world.world, &mut world.resources);
 
-            window.request_redraw();
+    rendering_system.render(&mut world)?;
+
+    // drops the capture action, which waits until the file is written
+    rendering_system.dispose(&mut world);
+
+    Ok(())
+}
+
+const MAX_LENGTH_MISMATCH: Duration = Duration::from_secs(1);
+
+/// Resizes the window to the rendered size if the locked aspect ratio letterboxes it. The passes
+/// always render to the whole surface, which can not show a letterboxed region.
+fn fit_window(window: &Window, resolution: &Resolution) {
+    if resolution.letterboxed() {
+        window.set_inner_size(PhysicalSize::new(resolution.width(), resolution.height()));
+    }
+}
+
+/// Space pauses and resumes the time, the left and right arrow keys seek by `SEEK_SECONDS`. The
+/// audio can not seek, it only pauses.
+fn control_time(world: &ResWorld, audio_paused: &AtomicBool) {
+    const SEEK_SECONDS: f32 = 5.0;
+
+    let input = world
+        .resources
+        .get::<Input>()
+        .expect("input was not inserted into world");
+
+    if let Some(mut time) = world.resources.get_mut::<Time>() {
+        if input.key_pressed(VirtualKeyCode::Space) {
+            time.toggle_paused();
+            audio_paused.store(time.paused(), Ordering::SeqCst);
         }
-        Event::WindowEvent { event: w, .. } => match w {
-            WindowEvent::CloseRequested => {
-                rendering_system.dispose(&mut world);
-                *control_flow = ControlFlow::Exit
-            }
-            WindowEvent::Resized(size) => {
-                world
-                    .resources
-                    .get_mut::<Resolution>()
-                    .expect("resolution was not inserted into world")
-                    .set_from_physical_size(size);
-            }
-            _ => (),
-        },
-        Event::RedrawRequested(_) => {
-            schedule.execute(&mut world.world, &mut world.resources);
 
-            rendering_system
-                .render(&mut world)
-                .expect("could not render image");
+        if input.key_pressed(VirtualKeyCode::Left) {
+            time.seek(-SEEK_SECONDS);
+        }
 
-            println!("FPS: {}", fps.tick());
+        if input.key_pressed(VirtualKeyCode::Right) {
+            time.seek(SEEK_SECONDS);
         }
-        _ => (),
-    });
+    }
 }
 
-#[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
-fn main() -> Result<(), Error> {
-    use rendy::factory::Config;
-    use rendy::init::winit::window::WindowBuilder;
-    use rendy::init::{AnyRendy, AnyWindowedRendy};
+/// Plus adds a sphere and minus removes one, the graph is rebuilt for the new sphere count before
+/// the next frame. Adding fails once the maximum sphere count is reached.
+fn control_spheres(world: &mut ResWorld) {
+    let (add, remove) = {
+        let input = world
+            .resources
+            .get::<Input>()
+            .expect("input was not inserted into world");
 
-    let matches = App::new("rendy sphere visualizer")
-        .arg(
-            Arg::with_name("pre-calculated-physics")
-                .short("p")
-                .long("pre-calculated-physics")
-                .value_name("FILE"),
-        )
-        .arg(
-            Arg::with_name("real-time-physics")
-                .short("r")
-                .long("real-time-physics")
-                .value_name("FILE"),
-        )
-        .arg(
-            Arg::with_name("real-time-analyser")
-                .required(true)
-                .value_name("FILE"),
-        )
-        .arg(
-            Arg::with_name("headless")
-                .short("h")
-                .long("headless")
-                .required(false)
-                .value_name("DIRECTORY"),
-        )
-        .group(
-            ArgGroup::with_name("mode")
-                .multiple(false)
-                .args(&["pre-calculated-physics", "real-time-physics"]),
+        (
+            input.key_pressed(VirtualKeyCode::Add) || input.key_pressed(VirtualKeyCode::Equals),
+            input.key_pressed(VirtualKeyCode::Subtract) || input.key_pressed(VirtualKeyCode::Minus),
         )
-        .get_matches();
+    };
 
-    let decoder = Decoder::new(BufReader::new(File::open(
-        matches.value_of("real-time-analyser").unwrap(),
-    )?))?;
+    if add {
+        if let Err(err) = add_sphere(world) {
+            println!("could not add a sphere: {}", err);
+        }
+    }
 
-    let sphere_bundle_params =
-        if let Some(real_time_physics) = matches.value_of("real-time-physics") {
+    if remove {
+        // the spheres added at runtime are found last
+        let sphere = <Read<Sphere>>::query()
+            .iter_entities(&world.world)
+            .map(|(entity, _)| entity)
+            .last();
+
+        if let Some(sphere) = sphere {
+            if let Err(err) = remove_sphere(world, sphere) {
+                println!("could not remove a sphere: {}", err);
+            }
+        }
+    }
+}
+
+/// The output device called `name`, or the default one
+fn output_device(name: Option<&str>) -> Result<Device, Error> {
+    match name {
+        Some(name) => {
+            let mut names = vec![];
+
+            for device in output_devices()? {
+                let device_name = device.name()?;
+
+                if device_name == name {
+                    return Ok(device);
+                }
+
+                names.push(device_name);
+            }
+
+            bail!(
+                "there is no audio device called {:?}, the devices are {:?}",
+                name,
+                names
+            )
+        }
+        None => default_output_device().ok_or(anyhow!("no default audio device found")),
+    }
+}
+
+/// Warns if the loaded animation and the audio differ in length by more than
+/// `MAX_LENGTH_MISMATCH`, or fails if `strict` is set
+fn check_frame_count(
+    frame_count: usize,
+    fps: f32,
+    total_duration: Option<Duration>,
+    strict: bool,
+) -> Result<(), Error> {
+    if let Some(message) =
+        total_duration.and_then(|total_duration| length_mismatch(frame_count, fps, total_duration))
+    {
+        if strict {
+            bail!(message);
+        }
+
+        println!("warning: {}", message);
+    }
+
+    Ok(())
+}
+
+/// Describes the mismatch if the loaded animation and the audio differ in length by more than
+/// `MAX_LENGTH_MISMATCH`
+fn length_mismatch(frame_count: usize, fps: f32, total_duration: Duration) -> Option<String> {
+    let frames_duration = Frame::new(frame_count as f32).duration(fps);
+
+    let mismatch = if frames_duration > total_duration {
+        frames_duration - total_duration
+    } else {
+        total_duration - frames_duration
+    };
+
+    if mismatch > MAX_LENGTH_MISMATCH {
+        Some(format!(
+            "the loaded animation is {:?} long, but the audio is {:?} long",
+            frames_duration, total_duration
+        ))
+    } else {
+        None
+    }
+}
+
+/// The frames a headless render renders, `start_frame..end_frame` cut to the length of the
+/// render. The length is the frame count of the loaded animation, or `max_frames` if the render
+/// runs until the audio ends.
+fn frame_range(
+    start_frame: usize,
+    end_frame: Option<usize>,
+    frame_count: Option<usize>,
+    max_frames: Option<usize>,
+) -> Result<Range<usize>, Error> {
+    if let Some(end_frame) = end_frame {
+        if end_frame <= start_frame {
+            bail!("the end frame has to be after the start frame");
+        }
+    }
+
+    // without a frame count the render runs until the source ends, which a looping source never
+    // does, so it can be capped
+    let last_frame = frame_count.unwrap_or_else(|| max_frames.unwrap_or(std::usize::MAX));
+    let last_frame = end_frame.map_or(last_frame, |end_frame| end_frame.min(last_frame));
+
+    if start_frame >= last_frame {
+        bail!(
+            "the start frame {} is beyond the end of the render at frame {}",
+            start_frame,
+            last_frame
+        );
+    }
+
+    Ok(start_frame..last_frame)
+}
+
+/// Applies the `--audio-offset` to `source` and returns the number of samples to hold back. A
+/// positive offset skips the beginning of the audio, a negative one holds the audio back so the
+/// first frames see silence. An offset beyond the end of the audio is an error, when the length
+/// of the audio is unknown the render simply ends with the audio.
+fn offset_audio<I: Iterator>(
+    source: &mut I,
+    audio_offset: f32,
+    samples_per_second: f32,
+    total_duration: Option<Duration>,
+) -> Result<usize, Error> {
+    if let Some(total_duration) = total_duration {
+        if audio_offset >= total_duration.as_secs_f32() {
+            bail!(
+                "the audio offset of {}s is beyond the end of the audio, which is {:?} long",
+                audio_offset,
+                total_duration
+            );
+        }
+    }
+
+    let offset_samples = (audio_offset.abs() * samples_per_second) as usize;
+
+    if audio_offset < 0.0 {
+        Ok(offset_samples)
+    } else {
+        for _ in 0..offset_samples {
+            source.next();
+        }
+
+        Ok(0)
+    }
+}
+
+/// The file a previous render wrote for `frame` into `directory`, if there is one
+fn existing_frame(directory: &Path, frame: usize, extension: &str) -> Option<PathBuf> {
+    let path = directory.join(format!("{:08}.{}", frame, extension));
+
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn init<B: Backend, T: 'static, P: 'static + AsRef<Path>, S: 'static + Source + Send>(
+    mut world: ResWorld,
+    factory: Factory<B>,
+    families: Families<B>,
+    surface: Surface<B>,
+    window: Window,
+    event_loop: EventLoop<T>,
+    application_bundle_params: ApplicationBundleParams<P>,
+    source: S,
+    audio: bool,
+    mute: bool,
+    audio_device: Option<String>,
+    locked_aspect: Option<f32>,
+    fps: f32,
+    graph_config: GraphConfig,
+    physics_pre_roll: usize,
+    gravity: Vec3,
+    aspect: Option<f32>,
+    orbit: Option<OrbitCamera>,
+    projection: Option<Projection>,
+    scene: SceneDescription,
+    snapshot_path: PathBuf,
+    watch_shaders: bool,
+    gpu_profiler: Option<GpuProfiler>,
+) -> Result<(), Error>
+where
+    S::Item: Sample,
+{
+    unsafe {
+        println!("surface format: {:?}", surface.format(factory.physical()));
+    }
+
+    let resolution =
+        Resolution::from_physical_size(window.inner_size()).with_locked_aspect(locked_aspect);
+
+    fit_window(&window, &resolution);
+
+    let (bundle, source) = application_bundle::<B, _, _>(
+        factory,
+        families,
+        resolution,
+        Some(window),
+        application_bundle_params,
+        Mode::Realtime,
+        fps,
+        source,
+        physics_pre_roll,
+        gravity,
+        aspect,
+        orbit,
+        projection,
+        &scene,
+    )?;
+
+    let mut schedule = bundle
+        .add_entities_and_resources(&mut world)?
+        .build_schedule(&world)?;
+
+    if watch_shaders {
+        world
+            .resources
+            .insert(ShaderWatcher::new(SHADER_PATH.as_path())?);
+    }
+
+    if let Some(gpu_profiler) = gpu_profiler {
+        world.resources.insert(gpu_profiler);
+    }
+
+    let graph_creator =
+        SphereVisualizerGraphCreator::<B, _>::new(&world, SurfaceOutput::new(Some(surface)))
+            .with_config(graph_config);
+
+    let mut rendering_system = RenderingSystem::new(graph_creator, &mut world)?;
+
+    let mut fps = fps_counter::FPSCounter::new();
+
+    // the audio pauses with the time, so the analysis does not run ahead of the paused animation
+    let audio_paused = Arc::new(AtomicBool::new(false));
+    let source = {
+        let audio_paused = audio_paused.clone();
+
+        source
+            .pausable(false)
+            .periodic_access(Duration::from_millis(5), move |source| {
+                source.set_paused(audio_paused.load(Ordering::SeqCst))
+            })
+    };
+
+    if audio {
+        if mute {
+            drain_in_real_time(source);
+        } else {
+            play_raw(
+                &output_device(audio_device.as_deref())?,
+                source.convert_samples::<f32>(),
+            );
+        }
+    }
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::MainEventsCleared => {
+            let window = world
+                .resources
+                .get::<Window>()
+                .expect("window was not inserted into world");
+
+            window.request_redraw();
+        }
+        Event::WindowEvent { event: w, .. } => {
+            world
+                .resources
+                .get_mut::<Input>()
+                .expect("input was not inserted into world")
+                .handle_event(&w);
+
+            match w {
+                WindowEvent::CloseRequested => {
+                    rendering_system.dispose(&mut world);
+
+                    if let Some(gpu_profiler) = world.resources.get::<GpuProfiler>() {
+                        println!("{}", gpu_profiler.summary());
+                    }
+
+                    *control_flow = ControlFlow::Exit
+                }
+                WindowEvent::Resized(size) => {
+                    let mut resolution = world
+                        .resources
+                        .get_mut::<Resolution>()
+                        .expect("resolution was not inserted into world");
+
+                    resolution.set_from_physical_size(size);
+
+                    let window = world
+                        .resources
+                        .get::<Window>()
+                        .expect("window was not inserted into world");
+
+                    fit_window(&window, &resolution);
+                }
+                _ => (),
+            }
+        }
+        Event::RedrawRequested(_) => {
+            schedule.execute(&mut world.world, &mut world.resources);
+
+            let save_snapshot = world
+                .resources
+                .get::<Input>()
+                .expect("input was not inserted into world")
+                .key_pressed(VirtualKeyCode::F5);
+
+            if save_snapshot {
+                match Snapshot::capture::<B>(&world).save(&snapshot_path) {
+                    Ok(()) => println!("saved snapshot to {:?}", snapshot_path),
+                    Err(err) => println!("could not save snapshot: {}", err),
+                }
+            }
+
+            control_time(&world, &audio_paused);
+            control_spheres(&mut world);
+
+            world
+                .resources
+                .get_mut::<Input>()
+                .expect("input was not inserted into world")
+                .end_frame();
+
+            if let Some(mut shader_watcher) = world.resources.get_mut::<ShaderWatcher>() {
+                shader_watcher.poll();
+            }
+
+            rendering_system
+                .render(&mut world)
+                .expect("could not render image");
+
+            println!("FPS: {}", fps.tick());
+        }
+        _ => (),
+    });
+}
+
+/// The command line interface
+fn app() -> App<'static, 'static> {
+    App::new("rendy sphere visualizer")
+        .arg(
+            Arg::with_name("pre-calculated-physics")
+                .short("p")
+                .long("pre-calculated-physics")
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("real-time-physics")
+                .short("r")
+                .long("real-time-physics")
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("real-time-analyser")
+                .required_unless_one(&[
+                    "verify",
+                    "pre-calculated-physics",
+                    "real-time-physics",
+                    "scene",
+                ])
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("verify").long("verify"),
+        )
+        .arg(
+            Arg::with_name("update-golden")
+                .long("update-golden")
+                .requires("verify"),
+        )
+        .arg(
+            Arg::with_name("strict-length")
+                .long("strict-length")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("comp-filter")
+                .long("comp-filter")
+                .possible_values(&["nearest", "linear"])
+                .default_value("nearest")
+                .value_name("FILTER"),
+        )
+        .arg(
+            Arg::with_name("render-style")
+                .long("render-style")
+                .possible_values(&["lit", "glow"])
+                .default_value("lit")
+                .value_name("STYLE"),
+        )
+        .arg(
+            Arg::with_name("alpha-mode")
+                .long("alpha-mode")
+                .requires("headless")
+                .possible_values(&["opaque", "premultiplied", "straight"])
+                .value_name("MODE"),
+        )
+        .arg(
+            Arg::with_name("depth-convention")
+                .long("depth-convention")
+                .possible_values(&["standard", "reversed"])
+                .default_value("standard")
+                .value_name("CONVENTION"),
+        )
+        .arg(Arg::with_name("bloom").long("bloom"))
+        .arg(Arg::with_name("depth-of-field").long("depth-of-field"))
+        .arg(
+            Arg::with_name("antialiasing")
+                .long("antialiasing")
+                .possible_values(&["none", "fxaa"])
+                .default_value("none")
+                .value_name("METHOD"),
+        )
+        .arg(
+            Arg::with_name("fxaa-quality")
+                .long("fxaa-quality")
+                .possible_values(&["low", "medium", "high"])
+                .default_value("medium")
+                .value_name("QUALITY"),
+        )
+        .arg(
+            Arg::with_name("proxy-subdivisions")
+                .long("proxy-subdivisions")
+                .value_name("SUBDIVISIONS"),
+        )
+        .arg(
+            Arg::with_name("proxy-sides")
+                .long("proxy-sides")
+                .value_name("SIDES"),
+        )
+        .arg(
+            Arg::with_name("aspect")
+                .long("aspect")
+                .value_name("RATIO"),
+        )
+        .arg(
+            Arg::with_name("lock-aspect")
+                .long("lock-aspect")
+                .conflicts_with_all(&["headless", "screenshot", "verify"])
+                .value_name("RATIO"),
+        )
+        .arg(
+            Arg::with_name("dfao-iterations")
+                .long("dfao-iterations")
+                .value_name("ITERATIONS"),
+        )
+        .arg(
+            Arg::with_name("quality")
+                .long("quality")
+                .possible_values(&["low", "medium", "high", "ultra", "auto"])
+                .value_name("PRESET"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .short("h")
+                .long("headless")
+                .required(false)
+                .value_name("DIRECTORY"),
+        )
+        .arg(
+            Arg::with_name("watch-shaders")
+                .long("watch-shaders")
+                .conflicts_with_all(&["headless", "screenshot", "verify"]),
+        )
+        .arg(
+            Arg::with_name("mute")
+                .long("mute")
+                .required(false)
+                .conflicts_with_all(&["headless", "screenshot", "verify"]),
+        )
+        .arg(
+            Arg::with_name("audio-device")
+                .long("audio-device")
+                .conflicts_with_all(&["headless", "screenshot", "verify", "mute"])
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .conflicts_with_all(&["headless", "screenshot", "verify"]),
+        )
+        .arg(
+            Arg::with_name("profile-frames")
+                .long("profile-frames")
+                .requires("profile")
+                .value_name("FRAMES"),
+        )
+        .arg(
+            Arg::with_name("timestamp-period")
+                .long("timestamp-period")
+                .requires("profile")
+                .value_name("NANOSECONDS"),
+        )
+        .arg(
+            Arg::with_name("screenshot")
+                .long("screenshot")
+                .conflicts_with("headless")
+                .number_of_values(2)
+                .value_names(&["FRAME", "FILE"]),
+        )
+        .arg(
+            Arg::with_name("dump-gbuffer")
+                .long("dump-gbuffer")
+                .requires("headless")
+                .value_name("DIRECTORY"),
+        )
+        .arg(
+            Arg::with_name("dump-gbuffer-targets")
+                .long("dump-gbuffer-targets")
+                .requires("dump-gbuffer")
+                .possible_values(&["position", "normal", "albedo", "n"])
+                .multiple(true)
+                .use_delimiter(true)
+                .value_name("TARGETS"),
+        )
+        .arg(
+            Arg::with_name("rest-shape")
+                .long("rest-shape")
+                .conflicts_with_all(&["pre-calculated-physics", "real-time-physics"])
+                .possible_values(&["flat", "hump"])
+                .value_name("SHAPE"),
+        )
+        .arg(
+            Arg::with_name("analyzer")
+                .long("analyzer")
+                .conflicts_with_all(&["pre-calculated-physics", "real-time-physics"])
+                .possible_values(&["filter", "fft"])
+                .value_name("ANALYZER"),
+        )
+        .arg(
+            Arg::with_name("fft-size")
+                .long("fft-size")
+                .default_value("4096")
+                .value_name("SAMPLES"),
+        )
+        .arg(
+            Arg::with_name("low-frequency")
+                .long("low-frequency")
+                .default_value("20")
+                .value_name("HZ"),
+        )
+        .arg(
+            Arg::with_name("high-frequency")
+                .long("high-frequency")
+                .value_name("HZ"),
+        )
+        .arg(
+            Arg::with_name("band-multiplier")
+                .long("band-multiplier")
+                .conflicts_with("high-frequency")
+                .value_name("FACTOR"),
+        )
+        .arg(
+            Arg::with_name("band-overlap")
+                .long("band-overlap")
+                .default_value("0")
+                .value_name("BANDS"),
+        )
+        .arg(
+            Arg::with_name("smoothing")
+                .long("smoothing")
+                .conflicts_with_all(&["pre-calculated-physics", "real-time-physics"])
+                .value_name("FACTOR"),
+        )
+        .arg(
+            Arg::with_name("stereo")
+                .long("stereo")
+                .conflicts_with_all(&["pre-calculated-physics", "real-time-physics"]),
+        )
+        .arg(
+            Arg::with_name("agc-target")
+                .long("agc-target")
+                .conflicts_with_all(&["pre-calculated-physics", "real-time-physics"])
+                .value_name("LEVEL"),
+        )
+        .arg(
+            Arg::with_name("agc-adaptation")
+                .long("agc-adaptation")
+                .default_value("2")
+                .value_name("SECONDS"),
+        )
+        .arg(
+            Arg::with_name("color-ramp")
+                .long("color-ramp")
+                .value_name("FILE"),
+        )
+        .arg(Arg::with_name("scene").long("scene").value_name("FILE"))
+        .arg(
+            Arg::with_name("save-scene")
+                .long("save-scene")
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("orthographic")
+                .long("orthographic")
+                .value_name("HEIGHT"),
+        )
+        .arg(
+            Arg::with_name("orbit")
+                .long("orbit")
+                .value_name("DEGREES_PER_FRAME"),
+        )
+        .arg(
+            Arg::with_name("orbit-radius")
+                .long("orbit-radius")
+                .default_value("10")
+                .value_name("RADIUS"),
+        )
+        .arg(
+            Arg::with_name("orbit-height")
+                .long("orbit-height")
+                .default_value("0")
+                .value_name("HEIGHT"),
+        )
+        .arg(
+            Arg::with_name("physics-pre-roll")
+                .long("physics-pre-roll")
+                .conflicts_with("pre-calculated-physics")
+                .value_name("STEPS"),
+        )
+        .arg(
+            Arg::with_name("gravity")
+                .long("gravity")
+                .default_value("0,0,0")
+                .value_name("X,Y,Z")
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("no-physics")
+                .long("no-physics")
+                .conflicts_with("pre-calculated-physics")
+                .conflicts_with("spring-factor")
+                .conflicts_with("damping"),
+        )
+        .arg(
+            Arg::with_name("spring-factor")
+                .long("spring-factor")
+                .conflicts_with("pre-calculated-physics")
+                .value_name("FACTOR"),
+        )
+        .arg(
+            Arg::with_name("damping")
+                .long("damping")
+                .conflicts_with("pre-calculated-physics")
+                .value_name("DAMPING"),
+        )
+        .arg(
+            Arg::with_name("layout")
+                .long("layout")
+                .conflicts_with("pre-calculated-physics")
+                .possible_values(&["line", "grid", "circle", "spiral"])
+                .value_name("LAYOUT"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .conflicts_with_all(&["pre-calculated-physics", "no-physics"])
+                .value_name("SEED"),
+        )
+        .arg(
+            Arg::with_name("analysis-log")
+                .long("analysis-log")
+                .requires("headless")
+                .conflicts_with_all(&["pre-calculated-physics", "real-time-physics"])
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .default_value("3840")
+                .value_name("PIXELS"),
+        )
+        .arg(
+            Arg::with_name("height")
+                .long("height")
+                .default_value("2160")
+                .value_name("PIXELS"),
+        )
+        .arg(
+            Arg::with_name("fps")
+                .long("fps")
+                .default_value("60")
+                .value_name("FPS"),
+        )
+        .arg(
+            Arg::with_name("max-frames")
+                .long("max-frames")
+                .requires("headless")
+                .conflicts_with_all(&["pre-calculated-physics", "real-time-physics"])
+                .value_name("FRAMES"),
+        )
+        .arg(
+            Arg::with_name("start-frame")
+                .long("start-frame")
+                .requires("headless")
+                .value_name("FRAME"),
+        )
+        .arg(
+            Arg::with_name("end-frame")
+                .long("end-frame")
+                .requires("headless")
+                .value_name("FRAME"),
+        )
+        .arg(
+            Arg::with_name("skip-existing")
+                .long("skip-existing")
+                .requires("headless")
+                .conflicts_with("video"),
+        )
+        .arg(
+            Arg::with_name("max-pending-saves")
+                .long("max-pending-saves")
+                .requires("headless")
+                .value_name("FRAMES"),
+        )
+        .arg(
+            Arg::with_name("audio-offset")
+                .long("audio-offset")
+                .requires("headless")
+                .value_name("SECONDS")
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("title")
+                .long("title")
+                .default_value("Ball Visualizer")
+                .value_name("TITLE"),
+        )
+        .arg(
+            Arg::with_name("icon")
+                .long("icon")
+                .conflicts_with("headless")
+                .value_name("PNG"),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .long("snapshot")
+                .default_value("snapshot.json")
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("restore-snapshot")
+                .long("restore-snapshot")
+                .requires("headless")
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .default_value("png")
+                .possible_values(&["png", "jpeg", "jpg", "bmp"])
+                .value_name("FORMAT"),
+        )
+        .arg(
+            Arg::with_name("hdr")
+                .long("hdr")
+                .requires("headless"),
+        )
+        .arg(
+            Arg::with_name("video")
+                .long("video")
+                .requires("headless")
+                .conflicts_with("hdr")
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("jpeg-quality")
+                .long("jpeg-quality")
+                .default_value("90")
+                .value_name("QUALITY"),
+        )
+        .arg(
+            Arg::with_name("max-frames-in-flight")
+                .long("max-frames-in-flight")
+                .default_value("3")
+                .value_name("FRAMES"),
+        )
+        .group(
+            ArgGroup::with_name("mode")
+                .multiple(false)
+                .args(&["pre-calculated-physics", "real-time-physics"]),
+        )
+}
+
+#[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+fn main() -> Result<(), Error> {
+    use rendy::factory::Config;
+    use rendy::init::{AnyRendy, AnyWindowedRendy};
+
+    let matches = app().get_matches();
+
+    if matches.is_present("verify") {
+        let universe = Universe::new();
+        let world = ResWorld::new(Resources::default(), universe.create_world());
+
+        let config: Config = Default::default();
+
+        let (backend, rendy) = init_first(preferred_backends(), |backend| {
+            AnyRendy::init(*backend, &config)
+        })?;
+
+        println!("initialized {:?}", backend);
+
+        let update = matches.is_present("update-golden");
+
+        with_any_rendy ! ((rendy) (factory, families) => {
+            verify(world, factory, families, GOLDEN_IMAGE_PATH.clone(), update)?
+        });
+
+        return Ok(());
+    }
+
+    // loaded spheres move without audio, a silent source stands in for it then
+    let audio = matches.is_present("real-time-analyser");
+    let source: Box<dyn Source<Item = i16> + Send> = match matches.value_of("real-time-analyser") {
+        Some(path) => Box::new(Decoder::new(BufReader::new(File::open(path)?))?),
+        None => Box::new(Zero::<i16>::new(2, 44100)),
+    };
+
+    let color_ramp = matches.value_of("color-ramp").map(str::to_string);
+
+    let scene = match matches.value_of("scene") {
+        Some(path) => SceneDescription::load(path)?,
+        None => SceneDescription::default(),
+    };
+
+    // the audio is only optional for a scene that loads its spheres, the analyze mode needs it
+    if !audio
+        && scene.spheres.is_none()
+        && !matches.is_present("pre-calculated-physics")
+        && !matches.is_present("real-time-physics")
+        && !matches.is_present("verify")
+    {
+        bail!("the scene has no sphere source, so an audio file is needed to analyze");
+    }
+
+    let physics = !matches.is_present("no-physics");
+
+    let spring_factor = match matches.value_of("spring-factor") {
+        Some(spring_factor) => {
+            let spring_factor = spring_factor.parse::<f32>()?;
+
+            if !(spring_factor > 0.0) {
+                bail!("the spring factor has to be positive");
+            }
+
+            Some(spring_factor)
+        }
+        None => None,
+    };
+
+    let damping = match matches.value_of("damping") {
+        Some(damping) => damping.parse::<f32>()?,
+        None => 1.0,
+    };
+
+    if !(damping > 0.0 && damping <= 1.0) {
+        bail!("the damping has to be above 0 and at most 1");
+    }
+
+    let smoothing = matches
+        .value_of("smoothing")
+        .unwrap_or("0")
+        .parse::<f32>()?;
+
+    if !(smoothing >= 0.0 && smoothing < 1.0) {
+        bail!("the smoothing has to be at least 0 and below 1");
+    }
+
+    let layout = matches
+        .value_of("layout")
+        .and_then(Layout::from_name)
+        .unwrap_or(Layout::Line);
+
+    let seed = match matches.value_of("seed") {
+        Some(seed) => Some(seed.parse::<u64>()?),
+        None => None,
+    };
+
+    let sphere_bundle_params =
+        if let Some(real_time_physics) = matches.value_of("real-time-physics") {
             ApplicationBundleParams::Load {
                 load_mode: LoadMode::Radius,
                 path: real_time_physics.to_string(),
+                color_ramp,
+                spring_factor,
+                damping,
+                seed,
+                layout,
+                physics,
             }
         } else if let Some(pre_calculated_physics) = matches.value_of("pre-calculated-physics") {
             ApplicationBundleParams::Load {
                 load_mode: LoadMode::PositionRadius,
                 path: pre_calculated_physics.to_string(),
+                color_ramp,
+                spring_factor,
+                damping,
+                seed,
+                layout,
+                physics,
+            }
+        } else if let Some(spheres) = &scene.spheres {
+            ApplicationBundleParams::Load {
+                load_mode: spheres.load_mode(),
+                path: spheres.path.clone(),
+                color_ramp,
+                spring_factor,
+                damping,
+                seed,
+                layout,
+                physics,
             }
         } else {
             ApplicationBundleParams::Analyze {
                 min_radius: 0.1,
+                rest_shape: match matches.value_of("rest-shape") {
+                    Some("hump") => RestShape::Gaussian {
+                        center: 0.5,
+                        width: 0.2,
+                        height: 0.3,
+                    },
+                    _ => RestShape::Flat,
+                },
+                analyzer: match matches.value_of("analyzer") {
+                    Some("fft") => Analyzer::Fft {
+                        size: matches.value_of("fft-size").unwrap().parse()?,
+                    },
+                    _ => Analyzer::Filter,
+                },
                 sphere_count: 64,
-                low: 20.0,
-                high: 20000.0,
+                stereo: matches.is_present("stereo"),
+                agc: match matches.value_of("agc-target") {
+                    Some(target) => Some(AgcParams {
+                        target: target.parse()?,
+                        adaptation: matches.value_of("agc-adaptation").unwrap().parse()?,
+                    }),
+                    None => None,
+                },
+                band_split: BandSplit {
+                    low: matches.value_of("low-frequency").unwrap().parse()?,
+                    high: matches
+                        .value_of("high-frequency")
+                        .unwrap_or("20000")
+                        .parse()?,
+                    multiplier: match matches.value_of("band-multiplier") {
+                        Some(multiplier) => Some(multiplier.parse()?),
+                        None => None,
+                    },
+                    overlap: matches.value_of("band-overlap").unwrap().parse()?,
+                },
                 attack: 0.005,
                 release: 0.4,
                 threshold: 0.1,
+                smoothing,
+                analysis_log: matches.value_of("analysis-log").map(PathBuf::from),
+                color_ramp,
+                spring_factor,
+                damping,
+                seed,
+                layout,
+                physics,
             }
         };
 
+    // records what the command line overrides, so the saved scene reproduces this run
+    if let Some(path) = matches.value_of("save-scene") {
+        let mut saved_scene = scene.clone().with_defaults()?;
+
+        if let Some(color_ramp) = sphere_bundle_params.color_ramp() {
+            saved_scene.color_ramp = Some(
+                ColorRamp::from_json(color_ramp)?
+                    .colors()
+                    .iter()
+                    .map(|color| [color.x, color.y, color.z])
+                    .collect(),
+            );
+        }
+
+        if let ApplicationBundleParams::Load {
+            path, load_mode, ..
+        } = &sphere_bundle_params
+        {
+            saved_scene.spheres = Some(SphereSource {
+                path: path.clone(),
+                pre_calculated_physics: match load_mode {
+                    LoadMode::PositionRadius => true,
+                    LoadMode::Radius => false,
+                },
+            });
+        }
+
+        saved_scene.save(path)?;
+    }
+
+    let comp_filter = match matches.value_of("comp-filter") {
+        Some("linear") => Filter::Linear,
+        _ => Filter::Nearest,
+    };
+
+    let render_style = matches
+        .value_of("render-style")
+        .and_then(RenderStyle::from_name)
+        .unwrap_or(RenderStyle::Lit);
+
+    let depth_convention = matches
+        .value_of("depth-convention")
+        .and_then(DepthConvention::from_name)
+        .unwrap_or(DepthConvention::Standard);
+
+    let antialiasing = match matches.value_of("antialiasing") {
+        Some("fxaa") => Antialiasing::Fxaa(
+            matches
+                .value_of("fxaa-quality")
+                .and_then(FxaaQuality::from_name)
+                .unwrap_or(FxaaQuality::Medium),
+        ),
+        _ => Antialiasing::None,
+    };
+
+    let frames_in_flight =
+        parse_frames_in_flight(matches.value_of("max-frames-in-flight").unwrap())?;
+
+    let gpu_profiler = if matches.is_present("profile") {
+        let report_interval = matches
+            .value_of("profile-frames")
+            .unwrap_or("100")
+            .parse::<usize>()?;
+        let timestamp_period = matches
+            .value_of("timestamp-period")
+            .unwrap_or("1")
+            .parse::<f32>()?;
+
+        Some(GpuProfiler::new(report_interval).with_timestamp_period(timestamp_period))
+    } else {
+        None
+    };
+
+    let mut graph_config = GraphConfig {
+        render_style,
+        comp_filter,
+        depth_convention,
+        bloom: matches.is_present("bloom"),
+        depth_of_field: matches.is_present("depth-of-field"),
+        antialiasing,
+        frames_in_flight,
+        ..Default::default()
+    };
+
+    let physics_pre_roll = matches
+        .value_of("physics-pre-roll")
+        .unwrap_or("0")
+        .parse::<usize>()?;
+
+    let gravity = parse_gravity(matches.value_of("gravity").unwrap())?;
+
+    let aspect = match matches.value_of("aspect") {
+        Some(aspect) => {
+            let aspect = aspect.parse::<f32>()?;
+
+            if !(aspect > 0.0) {
+                bail!("the aspect ratio has to be positive");
+            }
+
+            Some(aspect)
+        }
+        None => None,
+    };
+
+    let locked_aspect = match matches.value_of("lock-aspect") {
+        Some(locked_aspect) => {
+            let locked_aspect = locked_aspect.parse::<f32>()?;
+
+            if !(locked_aspect > 0.0) {
+                bail!("the locked aspect ratio has to be positive");
+            }
+
+            Some(locked_aspect)
+        }
+        None => None,
+    };
+
+    let projection = match matches.value_of("orthographic") {
+        Some(height) => {
+            let height = height.parse::<f32>()?;
+
+            if !(height > 0.0) {
+                bail!("the orthographic height has to be positive");
+            }
+
+            Some(Projection::Orthographic { height })
+        }
+        None => None,
+    };
+
+    let orbit = match matches.value_of("orbit") {
+        Some(speed) => Some(OrbitCamera::new(
+            matches.value_of("orbit-radius").unwrap().parse()?,
+            matches.value_of("orbit-height").unwrap().parse()?,
+            speed.parse::<f32>()?.to_radians(),
+        )),
+        None => None,
+    };
+
+    // the realtime mode converts its playhead to frames with the same fps a render uses, so
+    // keyframes play at the same speed in both
+    let fps = matches.value_of("fps").unwrap().parse::<f32>()?;
+
+    if !(fps > 0.0) {
+        bail!("the fps have to be positive");
+    }
+
     let universe = Universe::new();
 
     let world = universe.create_world();
@@ -308,28 +1883,149 @@ fn main() -> Result<(), Error> {
 
     let res_world = ResWorld::new(resources, world);
 
+    if let Some(mut values) = matches.values_of("screenshot") {
+        let frame = values.next().unwrap().parse::<f32>()?;
+        let path = PathBuf::from(values.next().unwrap());
+
+        let width = matches.value_of("width").unwrap().parse::<u32>()?;
+        let height = matches.value_of("height").unwrap().parse::<u32>()?;
+
+        if width == 0 || height == 0 {
+            bail!("the resolution has to be at least 1x1");
+        }
+
+        let graph_config = configure_quality(graph_config, &matches, width, height)?;
+
+        let config: Config = Default::default();
+
+        let (backend, rendy) = init_first(preferred_backends(), |backend| {
+            AnyRendy::init(*backend, &config)
+        })?;
+
+        println!("initialized {:?}", backend);
+
+        with_any_rendy ! ((rendy) (factory, families) => {
+            screenshot(res_world, factory, families, frame, path, sphere_bundle_params, source, width, height, fps, graph_config, physics_pre_roll, gravity, aspect, orbit, projection, scene)?
+        });
+
+        return Ok(());
+    }
+
     match matches.value_of("headless") {
         Some(output_dir) => {
+            let strict_length = matches.is_present("strict-length");
+
+            let gbuffer_dump = matches
+                .value_of("dump-gbuffer")
+                .map(|directory| GBufferDump {
+                    directory: PathBuf::from(directory),
+                    targets: match matches.values_of("dump-gbuffer-targets") {
+                        Some(targets) => targets.filter_map(GBufferTarget::from_name).collect(),
+                        None => GBufferTarget::ALL.to_vec(),
+                    },
+                });
+
+            graph_config.gbuffer_dump = gbuffer_dump;
+            graph_config.alpha_mode = matches
+                .value_of("alpha-mode")
+                .and_then(AlphaMode::from_name)
+                .unwrap_or(AlphaMode::Opaque);
+
+            let image_format = match matches.value_of("format").and_then(ImageFormat::from_name) {
+                Some(ImageFormat::Jpeg { .. }) => {
+                    let quality = matches.value_of("jpeg-quality").unwrap().parse::<u8>()?;
+
+                    if quality < 1 || quality > 100 {
+                        bail!("the jpeg quality has to be between 1 and 100");
+                    }
+
+                    ImageFormat::Jpeg { quality }
+                }
+                Some(image_format) => image_format,
+                None => bail!("unknown output format"),
+            };
+
+            let max_frames = match matches.value_of("max-frames") {
+                Some(max_frames) => Some(max_frames.parse::<usize>()?),
+                None => None,
+            };
+
+            let start_frame = matches
+                .value_of("start-frame")
+                .unwrap_or("0")
+                .parse::<usize>()?;
+
+            let end_frame = match matches.value_of("end-frame") {
+                Some(end_frame) => Some(end_frame.parse::<usize>()?),
+                None => None,
+            };
+
+            // checked before the render starts, the length is checked again once it is known
+            frame_range(start_frame, end_frame, None, None)?;
+
+            let audio_offset = matches
+                .value_of("audio-offset")
+                .unwrap_or("0")
+                .parse::<f32>()?;
+
+            let max_pending_saves = matches
+                .value_of("max-pending-saves")
+                .unwrap_or("8")
+                .parse::<usize>()?;
+
+            if max_pending_saves < 1 {
+                bail!("at least one frame has to be able to wait to be saved");
+            }
+
+            let snapshot = match matches.value_of("restore-snapshot") {
+                Some(path) => Some(Snapshot::load(path)?),
+                None => None,
+            };
+
+            let width = matches.value_of("width").unwrap().parse::<u32>()?;
+            let height = matches.value_of("height").unwrap().parse::<u32>()?;
+
+            if width == 0 || height == 0 {
+                bail!("the resolution has to be at least 1x1");
+            }
+
+            let graph_config = configure_quality(graph_config, &matches, width, height)?;
+
             let config: Config = Default::default();
 
-            let rendy = AnyRendy::init_auto(&config).map_err(|e| anyhow!(e))?;
+            let (backend, rendy) = init_first(preferred_backends(), |backend| {
+                AnyRendy::init(*backend, &config)
+            })?;
+
+            println!("initialized {:?}", backend);
+
+            let interrupted = Arc::new(AtomicBool::new(false));
+            {
+                let interrupted = interrupted.clone();
+                ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))?;
+            }
 
             with_any_rendy ! ((rendy) (factory, families) => {
-                render(res_world, factory, families, output_dir.to_string(), sphere_bundle_params, decoder).expect("could not render")
+                render(res_world, factory, families, output_dir.to_string(), image_format, matches.is_present("hdr"), matches.value_of("video").map(PathBuf::from), sphere_bundle_params, source, audio, width, height, fps, strict_length, graph_config, physics_pre_roll, gravity, aspect, orbit, projection, scene, snapshot, max_frames, start_frame, end_frame, matches.is_present("skip-existing"), audio_offset, max_pending_saves, interrupted).expect("could not render")
             });
         }
         None => {
+            let snapshot_path = PathBuf::from(matches.value_of("snapshot").unwrap());
+
             let config: Config = Default::default();
-            let window_builder = WindowBuilder::new()
-                .with_title("Ball Visualizer")
-                .with_maximized(true);
+            let window_builder = window_builder(&matches)?;
 
             let event_loop = EventLoop::new();
-            let rendy = AnyWindowedRendy::init_auto(&config, window_builder, &event_loop)
-                .map_err(|e| anyhow!(e))?;
+            let (backend, rendy) = init_first(preferred_backends(), |backend| {
+                AnyWindowedRendy::init(*backend, &config, window_builder.clone(), &event_loop)
+            })?;
+
+            println!("initialized {:?}", backend);
 
             with_any_windowed_rendy!((rendy) (factory, families, surface, window) => {
-                init(res_world, factory, families, surface, window, event_loop, sphere_bundle_params, decoder).expect("failed to open window")
+                let size = window.inner_size();
+                let graph_config = configure_quality(graph_config, &matches, size.width, size.height)?;
+                init(res_world, factory, families, surface, window, event_loop, sphere_bundle_params, source, audio, matches.is_present("mute"), matches.value_of("audio-device").map(str::to_string), locked_aspect, fps, graph_config, physics_pre_roll, gravity, aspect, orbit, projection, scene, snapshot_path, matches.is_present("watch-shaders"), gpu_profiler).expect("failed to open window")
             });
         }
     }
@@ -358,7 +2054,470 @@ pub fn application_root_dir() -> PathBuf {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub enum Mode {
     Realtime,
     Headless,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    use crate::test_support::{empty_world, headless};
+    use rendy::init::winit::event::ElementState;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    #[test]
+    fn end_frame_has_to_be_after_start_frame() {
+        assert!(frame_range(10, Some(5), None, None).is_err());
+        assert!(frame_range(10, Some(10), None, None).is_err());
+        assert_eq!(frame_range(10, Some(20), None, None).unwrap(), 10..20);
+        assert_eq!(frame_range(10, None, None, Some(20)).unwrap(), 10..20);
+    }
+
+    #[test]
+    fn end_frame_is_cut_to_the_length() {
+        // the frame count of 10 seconds of audio at 60 fps
+        assert_eq!(frame_range(0, Some(1000), Some(600), None).unwrap(), 0..600);
+        assert_eq!(frame_range(0, Some(50), None, Some(100)).unwrap(), 0..50);
+        assert_eq!(frame_range(0, Some(1000), None, Some(100)).unwrap(), 0..100);
+
+        // nothing would be rendered
+        assert!(frame_range(600, Some(1000), Some(600), None).is_err());
+        assert!(frame_range(100, None, None, Some(100)).is_err());
+    }
+
+    #[test]
+    fn length_mismatch_warns_or_fails() {
+        let audio = Duration::from_secs(10);
+
+        assert_eq!(length_mismatch(600, 60.0, audio), None);
+        assert_eq!(length_mismatch(630, 60.0, audio), None);
+        assert!(length_mismatch(1200, 60.0, audio).is_some());
+        assert!(length_mismatch(300, 60.0, audio).is_some());
+
+        assert!(check_frame_count(1200, 60.0, Some(audio), false).is_ok());
+        assert!(check_frame_count(1200, 60.0, Some(audio), true).is_err());
+        assert!(check_frame_count(600, 60.0, Some(audio), true).is_ok());
+
+        // the length of a stream is unknown, so there is nothing to compare
+        assert!(check_frame_count(1200, 60.0, None, true).is_ok());
+    }
+
+    #[test]
+    fn audio_offset_skips_or_holds_back_samples() {
+        let length = Some(Duration::from_secs(1));
+
+        let mut source = 0..100;
+        assert_eq!(offset_audio(&mut source, 0.25, 100.0, length).unwrap(), 0);
+        assert_eq!(source.next(), Some(25));
+
+        let mut source = 0..100;
+        assert_eq!(offset_audio(&mut source, -0.25, 100.0, length).unwrap(), 25);
+        assert_eq!(source.next(), Some(0));
+
+        let mut source = 0..100;
+        assert!(offset_audio(&mut source, 1.0, 100.0, length).is_err());
+
+        // a stream of unknown length is skipped as far as it goes
+        let mut source = 0..100;
+        assert_eq!(offset_audio(&mut source, 2.0, 100.0, None).unwrap(), 0);
+        assert_eq!(source.next(), None);
+    }
+
+    #[test]
+    fn skip_existing_finds_the_written_frames() {
+        let directory = temp_dir().join("rendy-sphere-visualizer-skip-existing");
+        create_dir_all(&directory).unwrap();
+        File::create(directory.join("00000003.png")).unwrap();
+
+        assert_eq!(
+            existing_frame(&directory, 3, "png"),
+            Some(directory.join("00000003.png"))
+        );
+        assert_eq!(existing_frame(&directory, 4, "png"), None);
+        assert_eq!(existing_frame(&directory, 3, "exr"), None);
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn title_and_icon_reach_the_window_builder() {
+        let matches = app().get_matches_from(vec!["rendy-sphere-visualizer", "song.wav"]);
+        let window = window_builder(&matches).unwrap().window;
+        assert_eq!(window.title, "Ball Visualizer");
+        assert!(window.window_icon.is_none());
+
+        let directory = temp_dir().join("rendy-sphere-visualizer-window-icon");
+        create_dir_all(&directory).unwrap();
+        let icon = directory.join("icon.png");
+        image::RgbaImage::new(16, 16).save(&icon).unwrap();
+
+        let matches = app().get_matches_from(vec![
+            "rendy-sphere-visualizer",
+            "song.wav",
+            "--title",
+            "Spheres",
+            "--icon",
+            icon.to_str().unwrap(),
+        ]);
+        let window = window_builder(&matches).unwrap().window;
+        assert_eq!(window.title, "Spheres");
+        assert!(window.window_icon.is_some());
+
+        let matches = app().get_matches_from(vec![
+            "rendy-sphere-visualizer",
+            "song.wav",
+            "--icon",
+            directory.join("missing.png").to_str().unwrap(),
+        ]);
+        assert!(window_builder(&matches).is_err());
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn gravity_is_parsed_from_three_components() {
+        let matches = app().get_matches_from(vec!["rendy-sphere-visualizer", "song.wav"]);
+        let gravity = parse_gravity(matches.value_of("gravity").unwrap()).unwrap();
+        assert_eq!(gravity, vec3(0.0, 0.0, 0.0));
+
+        let matches = app().get_matches_from(vec![
+            "rendy-sphere-visualizer",
+            "song.wav",
+            "--gravity",
+            "0, -9.81,0",
+        ]);
+        let gravity = parse_gravity(matches.value_of("gravity").unwrap()).unwrap();
+        assert_eq!(gravity, vec3(0.0, -9.81, 0.0));
+
+        for invalid in &["0,-9.81", "0,-9.81,0,0", "x,y,z", ""] {
+            assert!(parse_gravity(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn frames_in_flight_are_parsed_in_every_mode() {
+        let frames_in_flight = |args: &[&str]| {
+            let matches =
+                app().get_matches_from([&["rendy-sphere-visualizer", "song.wav"], args].concat());
+            parse_frames_in_flight(matches.value_of("max-frames-in-flight").unwrap())
+        };
+
+        assert_eq!(frames_in_flight(&[]).unwrap(), 3);
+        assert_eq!(
+            frames_in_flight(&["--max-frames-in-flight", "1"]).unwrap(),
+            1
+        );
+
+        let screenshot = [
+            "--screenshot",
+            "10",
+            "frame.png",
+            "--max-frames-in-flight",
+            "2",
+        ];
+        assert_eq!(frames_in_flight(&screenshot).unwrap(), 2);
+
+        for invalid in &["0", "-1", "two"] {
+            assert!(parse_frames_in_flight(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn profile_options_only_need_profile_when_given() {
+        let matches = |args: &[&str]| {
+            app().get_matches_from_safe([&["rendy-sphere-visualizer", "song.wav"], args].concat())
+        };
+
+        let realtime = matches(&[]).unwrap();
+        assert!(!realtime.is_present("profile"));
+
+        assert!(matches(&["--profile-frames", "10"]).is_err());
+        assert!(matches(&["--profile", "--profile-frames", "10"]).is_ok());
+    }
+
+    /// Parses `args` like the command line
+    fn parse(args: &[&str]) -> Result<ArgMatches<'static>, clap::Error> {
+        app().get_matches_from_safe([&["rendy-sphere-visualizer"], args].concat())
+    }
+
+    #[test]
+    fn band_multiplier_replaces_the_high_frequency() {
+        let multiplier = ["song.wav", "--band-multiplier", "1.5"];
+
+        let matches = parse(&multiplier).unwrap();
+        assert_eq!(matches.value_of("band-multiplier"), Some("1.5"));
+        assert_eq!(matches.value_of("high-frequency"), None);
+
+        let both = [&multiplier[..], &["--high-frequency", "8000"]].concat();
+        assert!(parse(&both).is_err());
+    }
+
+    #[test]
+    fn loaded_spheres_parse_without_the_analyze_options() {
+        let analyze_options = [
+            ("--rest-shape", "hump"),
+            ("--analyzer", "fft"),
+            ("--smoothing", "0.5"),
+            ("--physics-pre-roll", "10"),
+            ("--layout", "grid"),
+        ];
+
+        for loaded in &[
+            ["--pre-calculated-physics", "physics.json"],
+            ["--real-time-physics", "spheres.json"],
+        ] {
+            let matches = parse(loaded).unwrap();
+
+            // the defaults are applied where the values are read
+            for (option, _) in &analyze_options {
+                assert_eq!(matches.value_of(&option[2..]), None);
+            }
+        }
+
+        let precalculated = ["--pre-calculated-physics", "physics.json"];
+        for (option, value) in &analyze_options {
+            assert!(parse(&[&precalculated[..], &[option, value]].concat()).is_err());
+        }
+    }
+
+    #[test]
+    fn audio_is_only_required_to_analyze() {
+        assert!(parse(&[]).is_err());
+
+        for loaded in &[
+            ["--pre-calculated-physics", "physics.json"],
+            ["--real-time-physics", "spheres.json"],
+            ["--scene", "scene.json"],
+        ] {
+            let matches = parse(loaded).unwrap();
+            assert!(!matches.is_present("real-time-analyser"));
+        }
+    }
+
+    #[test]
+    fn mute_and_audio_device_only_apply_to_the_realtime_mode() {
+        let matches = parse(&["song.wav", "--audio-device", "speakers"]).unwrap();
+        assert_eq!(matches.value_of("audio-device"), Some("speakers"));
+        assert!(parse(&["song.wav", "--mute"]).unwrap().is_present("mute"));
+
+        assert!(parse(&["song.wav", "--mute", "--audio-device", "speakers"]).is_err());
+        assert!(parse(&["song.wav", "--mute", "--headless", "frames"]).is_err());
+    }
+
+    #[test]
+    fn locked_aspect_only_applies_to_the_realtime_mode() {
+        let locked = |args: &[&str]| parse(&[&["song.wav", "--lock-aspect", "1.5"], args].concat());
+
+        let matches = locked(&[]).unwrap();
+        assert_eq!(matches.value_of("lock-aspect"), Some("1.5"));
+
+        assert!(locked(&["--headless", "frames"]).is_err());
+        assert!(locked(&["--screenshot", "0", "a.png"]).is_err());
+        assert!(locked(&["--verify"]).is_err());
+    }
+
+    #[test]
+    fn space_pauses_the_time_and_the_audio_and_arrows_seek() {
+        let mut world = ResWorld::new(Resources::default(), Universe::new().create_world());
+        world.resources.insert(Input::new());
+        world.resources.insert(Time::new(60.0));
+
+        let audio_paused = AtomicBool::new(false);
+
+        let press = |world: &mut ResWorld, key| {
+            {
+                let mut input = world.resources.get_mut::<Input>().unwrap();
+                input.end_frame();
+                input.key_input(key, ElementState::Pressed);
+                input.key_input(key, ElementState::Released);
+            }
+
+            control_time(world, &audio_paused);
+
+            let time = world.resources.get::<Time>().unwrap();
+            (time.paused(), time.playhead().as_secs_f32())
+        };
+
+        let (paused, playhead) = press(&mut world, VirtualKeyCode::Space);
+        assert!(paused);
+        assert!(audio_paused.load(Ordering::SeqCst));
+
+        let (_, sought) = press(&mut world, VirtualKeyCode::Right);
+        assert_approx_eq!(sought, playhead + 5.0);
+
+        let (paused, _) = press(&mut world, VirtualKeyCode::Space);
+        assert!(!paused);
+        assert!(!audio_paused.load(Ordering::SeqCst));
+    }
+
+    /// Analyzes a silent source with 4 spheres and without physics
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn silent_params() -> ApplicationBundleParams<PathBuf> {
+        ApplicationBundleParams::Analyze {
+            sphere_count: 4,
+            min_radius: 0.1,
+            rest_shape: RestShape::Flat,
+            analyzer: Analyzer::Filter,
+            band_split: BandSplit {
+                low: 20.0,
+                high: 20000.0,
+                multiplier: None,
+                overlap: 0.0,
+            },
+            stereo: false,
+            agc: None,
+            attack: 0.005,
+            release: 0.4,
+            threshold: 0.1,
+            smoothing: 0.0,
+            analysis_log: None,
+            color_ramp: None,
+            spring_factor: None,
+            damping: 1.0,
+            seed: Some(0),
+            layout: Layout::Line,
+            physics: false,
+        }
+    }
+
+    /// Renders the silent source into `directory` from `start_frame` on, capped at 3 frames, and
+    /// loads the manifest
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn render_silence(
+        directory: &Path,
+        start_frame: usize,
+        skip_existing: bool,
+        interrupted: bool,
+    ) -> serde_json::Value {
+        create_dir_all(directory).unwrap();
+
+        let interrupted = Arc::new(AtomicBool::new(interrupted));
+
+        with_any_rendy!((headless()) (factory, families) => {
+            render(empty_world(), factory, families, directory.to_path_buf(), ImageFormat::Png, false, None, silent_params(), Zero::<f32>::new(1, 44100), true, 64, 64, 60.0, false, GraphConfig::default(), 0, zero(), None, None, None, SceneDescription::default(), None, Some(3), start_frame, None, skip_existing, 0.0, 8, interrupted)
+        })
+        .unwrap();
+
+        serde_json::from_reader(File::open(directory.join("manifest.json")).unwrap()).unwrap()
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn render_without_audio_needs_a_length() {
+        let directory = temp_dir().join("rendy-sphere-visualizer-without-audio");
+        create_dir_all(&directory).unwrap();
+
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        // the analyze mode has no frame count and the silent source never ends
+        let err = with_any_rendy!((headless()) (factory, families) => {
+            render(empty_world(), factory, families, directory.clone(), ImageFormat::Png, false, None, silent_params(), Zero::<f32>::new(1, 44100), false, 64, 64, 60.0, false, GraphConfig::default(), 0, zero(), None, None, None, SceneDescription::default(), None, None, 0, None, false, 0.0, 8, interrupted.clone())
+        })
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "without audio the length comes from the loaded physics, --max-frames or --end-frame"
+        );
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn analyze_render_stops_at_max_frames() {
+        let directory = temp_dir().join("rendy-sphere-visualizer-max-frames");
+
+        // the silent source never ends, only the cap stops the render
+        let manifest = render_silence(&directory, 0, false, false);
+        assert_eq!(
+            manifest["frames"],
+            serde_json::json!({ "start": 0, "end": 3 })
+        );
+        assert_eq!(manifest["files"].as_array().unwrap().len(), 3);
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn resumed_render_keeps_the_frame_numbers_and_existing_files() {
+        let directory = temp_dir().join("rendy-sphere-visualizer-resumed");
+        create_dir_all(&directory).unwrap();
+        File::create(directory.join("00000002.png")).unwrap();
+
+        let manifest = render_silence(&directory, 1, true, false);
+        assert_eq!(
+            manifest["frames"],
+            serde_json::json!({ "start": 1, "end": 3 })
+        );
+        assert_eq!(
+            manifest["files"],
+            serde_json::json!([
+                directory.join("00000001.png"),
+                directory.join("00000002.png")
+            ])
+        );
+
+        // the existing frame was listed, not rendered again
+        let existing = directory.join("00000002.png").metadata().unwrap();
+        assert_eq!(existing.len(), 0);
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn interrupted_render_still_writes_the_manifest() {
+        let directory = temp_dir().join("rendy-sphere-visualizer-interrupted");
+
+        let manifest = render_silence(&directory, 0, false, true);
+        assert_eq!(
+            manifest["frames"],
+            serde_json::json!({ "start": 0, "end": 0 })
+        );
+        assert!(manifest["files"].as_array().unwrap().is_empty());
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    /// Renders the golden scene and compares it against `golden`, or replaces `golden`
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    fn verify_against(golden: &Path, update: bool) -> Result<(), Error> {
+        with_any_rendy!((headless()) (factory, families) => {
+            verify(empty_world(), factory, families, golden.to_path_buf(), update)
+        })
+    }
+
+    #[cfg(any(feature = "dx12", feature = "metal", feature = "vulkan"))]
+    #[test]
+    fn verify_compares_the_rendered_frame() {
+        let directory = temp_dir().join("rendy-sphere-visualizer-verify");
+        create_dir_all(&directory).unwrap();
+        let golden = directory.join("sphere.png");
+
+        verify_against(&golden, true).unwrap();
+        verify_against(&golden, false).unwrap();
+
+        // a golden image of some other frame fails the verification
+        let mut other = image::open(&golden).unwrap().to_rgba();
+        for pixel in other.pixels_mut() {
+            pixel.0 = [255, 0, 255, 255];
+        }
+        other.save(&golden).unwrap();
+
+        let err = verify_against(&golden, false).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("the rendered frame differs from the golden image"),
+            "{}",
+            err
+        );
+
+        remove_dir_all(&directory).unwrap();
+    }
+}
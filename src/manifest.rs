@@ -0,0 +1,105 @@
+use anyhow::Error;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub files: Vec<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f32,
+    pub frames: Range<usize>,
+    pub config: serde_json::Value,
+}
+
+impl Manifest {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OutputFiles(Arc<Mutex<Vec<PathBuf>>>);
+
+impl OutputFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, path: PathBuf) {
+        self.0.lock().unwrap().push(path)
+    }
+
+    pub fn sorted(&self) -> Vec<PathBuf> {
+        let mut files = self.0.lock().unwrap().clone();
+        files.sort();
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::node::capture::CaptureAction;
+    use crate::graph::SaveImage;
+    use crate::world::resolution::Resolution;
+    use crate::world::ResWorld;
+    use image::ColorType;
+    use legion::prelude::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    #[test]
+    fn lists_the_saved_frames() {
+        let directory = temp_dir().join("rendy-sphere-visualizer-manifest");
+        create_dir_all(&directory).unwrap();
+
+        let mut world = ResWorld::new(Resources::default(), Universe::new().create_world());
+        world.resources.insert(Resolution::new(2, 2));
+
+        let output_files = OutputFiles::new();
+
+        {
+            let mut save_image = SaveImage::new(directory.clone(), ColorType::Rgba8)
+                .unwrap()
+                .with_output_files(output_files.clone());
+
+            // the frames are written on a thread pool, so they finish in any order
+            for frame in &[7, 5, 6] {
+                save_image.exec(&world, &[0; 2 * 2 * 4], *frame).unwrap();
+            }
+
+            // dropping waits for the pending writes
+        }
+
+        let manifest = Manifest {
+            files: output_files.sorted(),
+            width: 2,
+            height: 2,
+            fps: 60.0,
+            frames: 5..8,
+            config: serde_json::Value::Null,
+        };
+
+        let path = directory.join("manifest.json");
+        manifest.save(&path).unwrap();
+
+        let saved: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+
+        let files = (5..8)
+            .map(|frame| directory.join(format!("{:08}.png", frame)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(saved["files"], serde_json::to_value(&files).unwrap());
+        assert_eq!(saved["frames"], serde_json::json!({ "start": 5, "end": 8 }));
+        assert!(files.iter().all(|file| file.exists()));
+
+        remove_dir_all(&directory).unwrap();
+    }
+}
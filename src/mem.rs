@@ -97,3 +97,52 @@ impl CombinedBufferCalculator {
         Some(range.start)..Some(range.end)
     }
 }
+
+/// Remembers the data last uploaded into every frame in flight of a buffer, so that unchanged data
+/// does not have to be uploaded again
+#[derive(Debug)]
+pub struct DirtyTracker<T> {
+    frames: Vec<Option<T>>,
+}
+
+impl<T: PartialEq> DirtyTracker<T> {
+    /// Creates a new Dirty Tracker where every frame is dirty
+    pub fn new(frames: usize) -> Self {
+        Self {
+            frames: (0..frames).map(|_| None).collect(),
+        }
+    }
+
+    /// Stores the data of one frame and returns it if it differs from the previously stored data
+    pub fn update(&mut self, frame: usize, value: T) -> Option<&T> {
+        let slot = &mut self.frames[frame];
+
+        if slot.as_ref() == Some(&value) {
+            None
+        } else {
+            *slot = Some(value);
+            slot.as_ref()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_data_is_not_uploaded_again() {
+        let mut uploads = DirtyTracker::new(2);
+
+        assert_eq!(uploads.update(0, 1), Some(&1));
+        assert_eq!(uploads.update(0, 1), None);
+
+        // every frame in flight has its own buffer range, which is still empty
+        assert_eq!(uploads.update(1, 1), Some(&1));
+        assert_eq!(uploads.update(1, 1), None);
+
+        assert_eq!(uploads.update(0, 2), Some(&2));
+        assert_eq!(uploads.update(0, 2), None);
+        assert_eq!(uploads.update(1, 2), Some(&2));
+    }
+}
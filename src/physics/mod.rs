@@ -1,11 +1,11 @@
-use crate::bundle::{Bundle, BundlePhase1};
-use crate::world::sphere::PositionComponent;
+use crate::bundle::Bundle;
+use crate::world::sphere::{PositionComponent, COLLIDING_SPRING_FACTOR, SPRING_FACTOR};
 use crate::world::ResWorld;
 use anyhow::Error;
 use legion::prelude::*;
-use legion::systems::schedule::Builder;
 use nalgebra::RealField;
 use nalgebra_glm::Vec3;
+use ncollide3d::pipeline::CollisionGroups;
 use nphysics3d::force_generator::{DefaultForceGeneratorHandle, DefaultForceGeneratorSet};
 use nphysics3d::joint::{DefaultJointConstraintHandle, DefaultJointConstraintSet};
 use nphysics3d::material::MaterialHandle;
@@ -16,18 +16,25 @@ use nphysics3d::object::{
 use nphysics3d::world::{DefaultGeometricalWorld, DefaultMechanicalWorld, MechanicalWorld};
 use std::ops::DerefMut;
 
+/// Inserts the physics resources. The `physics_system` is not added, it has to be added after
+/// the systems changing the colliders, e.g. `sphere_shape_system`, so every step sees the shapes
+/// of the current frame.
 pub struct PhysicsBundle {
     gravity: Vec3,
+    collisions_enabled: bool,
 }
 
 impl PhysicsBundle {
-    pub fn new(gravity: Vec3) -> Self {
-        PhysicsBundle { gravity }
+    pub fn new(gravity: Vec3, collisions_enabled: bool) -> Self {
+        PhysicsBundle {
+            gravity,
+            collisions_enabled,
+        }
     }
 }
 
 impl Bundle for PhysicsBundle {
-    type Phase1 = PhysicsBundlePhase1;
+    type Phase1 = ();
 
     fn add_entities_and_resources(self, world: &mut ResWorld) -> Result<Self::Phase1, Error> {
         let mechanical_world = DefaultMechanicalWorld::<f32>::new(self.gravity);
@@ -44,16 +51,65 @@ impl Bundle for PhysicsBundle {
         world.resources.insert(colliders);
         world.resources.insert(joint_constraints);
         world.resources.insert(force_generators);
+        world.resources.insert(Collisions(self.collisions_enabled));
 
-        Ok(PhysicsBundlePhase1)
+        Ok(())
     }
 }
 
-pub struct PhysicsBundlePhase1;
+/// Whether the sphere colliders collide with each other, inserted by `PhysicsBundle`
+#[derive(Clone, Copy, Debug)]
+pub struct Collisions(pub bool);
 
-impl BundlePhase1 for PhysicsBundlePhase1 {
-    fn add_systems(self, _world: &ResWorld, builder: Builder) -> Result<Builder, Error> {
-        Ok(builder.add_system(physics_system()))
+impl Collisions {
+    /// The collision group every sphere collider is a member of
+    pub const SPHERE_GROUP: usize = 0;
+
+    pub fn sphere_collision_groups(&self) -> CollisionGroups {
+        let collision_groups = CollisionGroups::new().with_membership(&[Self::SPHERE_GROUP]);
+
+        if self.0 {
+            collision_groups
+        } else {
+            collision_groups.with_blacklist(&[Self::SPHERE_GROUP])
+        }
+    }
+
+    /// The factor of the `DragSpring` of every sphere. The spring cancels the velocity every
+    /// step, so colliding spheres need a softer pull for the contacts to push them apart instead
+    /// of jittering against their home positions.
+    pub fn spring_factor(&self) -> f32 {
+        if self.0 {
+            COLLIDING_SPRING_FACTOR
+        } else {
+            SPRING_FACTOR
+        }
+    }
+}
+
+/// Steps the physics a number of times while the world is set up, so bodies settle from their
+/// spawn positions before the first frame. Has to be added after the bundles creating the bodies.
+pub struct PhysicsPreRollBundle {
+    steps: usize,
+}
+
+impl PhysicsPreRollBundle {
+    pub fn new(steps: usize) -> Self {
+        PhysicsPreRollBundle { steps }
+    }
+}
+
+impl Bundle for PhysicsPreRollBundle {
+    type Phase1 = ();
+
+    fn add_entities_and_resources(self, world: &mut ResWorld) -> Result<Self::Phase1, Error> {
+        let mut schedule = Schedule::builder().add_system(physics_system()).build();
+
+        for _ in 0..self.steps {
+            schedule.execute(&mut world.world, &mut world.resources);
+        }
+
+        Ok(())
     }
 }
 
@@ -115,3 +171,136 @@ pub type DefaultBodyPartHandleComponent = BodyPartHandleComponent<DefaultBodyHan
 pub type DefaultColliderHandleComponent = ColliderHandleComponent<DefaultColliderHandle>;
 pub struct DefaultForceGeneratorHandleComponent(pub DefaultForceGeneratorHandle);
 pub struct DefaultJointConstraintHandleComponent(pub DefaultJointConstraintHandle);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+    use nalgebra_glm::vec3;
+    use ncollide3d::shape::{Ball, ShapeHandle};
+    use nphysics3d::algebra::Velocity3;
+    use nphysics3d::object::{ColliderDesc, RigidBodyDesc};
+
+    /// The x position of a body moving along x at 1 unit per second after pre-rolling `steps`
+    fn pre_rolled_position(steps: usize) -> f32 {
+        let mut world = ResWorld::new(Resources::default(), Universe::new().create_world());
+
+        PhysicsBundle::new(vec3(0.0, 0.0, 0.0), false)
+            .add_entities_and_resources(&mut world)
+            .unwrap();
+
+        let body_part_handle = {
+            let mut bodies = world.resources.get_mut::<DefaultBodySet<f32>>().unwrap();
+            let mut colliders = world
+                .resources
+                .get_mut::<DefaultColliderSet<f32>>()
+                .unwrap();
+
+            let rigid_body = RigidBodyDesc::<f32>::new()
+                .velocity(Velocity3::linear(1.0, 0.0, 0.0))
+                .build();
+            let body_part_handle = BodyPartHandle(bodies.insert(rigid_body), 0);
+
+            let collider = ColliderDesc::new(ShapeHandle::<f32>::new(Ball::new(1.0)))
+                .density(1.0)
+                .build(body_part_handle);
+            colliders.insert(collider);
+
+            body_part_handle
+        };
+
+        let entity = world.world.insert(
+            (),
+            vec![(
+                BodyPartHandleComponent(body_part_handle),
+                PositionComponent(vec3(0.0, 0.0, 0.0)),
+            )],
+        )[0];
+
+        PhysicsPreRollBundle::new(steps)
+            .add_entities_and_resources(&mut world)
+            .unwrap();
+
+        world
+            .world
+            .get_component::<PositionComponent>(entity)
+            .unwrap()
+            .0
+            .x
+    }
+
+    #[test]
+    fn pre_roll_advances_the_bodies_before_the_first_frame() {
+        assert_eq!(pre_rolled_position(0), 0.0);
+
+        let dt = DefaultMechanicalWorld::<f32>::new(vec3(0.0, 0.0, 0.0)).timestep();
+        assert_approx_eq!(pre_rolled_position(30), 30.0 * dt, 1e-4);
+    }
+
+    /// The distance of two overlapping unit balls after a second of steps
+    fn separated_distance(collisions_enabled: bool) -> f32 {
+        let mut world = ResWorld::new(Resources::default(), Universe::new().create_world());
+
+        PhysicsBundle::new(vec3(0.0, 0.0, 0.0), collisions_enabled)
+            .add_entities_and_resources(&mut world)
+            .unwrap();
+
+        let collision_groups = world
+            .resources
+            .get::<Collisions>()
+            .unwrap()
+            .sphere_collision_groups();
+
+        let components = [-0.25, 0.25]
+            .iter()
+            .map(|x| {
+                let mut bodies = world.resources.get_mut::<DefaultBodySet<f32>>().unwrap();
+                let mut colliders = world
+                    .resources
+                    .get_mut::<DefaultColliderSet<f32>>()
+                    .unwrap();
+
+                let rigid_body = RigidBodyDesc::<f32>::new()
+                    .translation(Vector3::new(*x, 0.0, 0.0))
+                    .build();
+                let body_part_handle = BodyPartHandle(bodies.insert(rigid_body), 0);
+
+                let collider = ColliderDesc::new(ShapeHandle::<f32>::new(Ball::new(1.0)))
+                    .density(1.0)
+                    .collision_groups(collision_groups)
+                    .build(body_part_handle);
+                colliders.insert(collider);
+
+                (
+                    BodyPartHandleComponent(body_part_handle),
+                    PositionComponent(vec3(*x, 0.0, 0.0)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let entities = world.world.insert((), components).to_vec();
+
+        PhysicsPreRollBundle::new(60)
+            .add_entities_and_resources(&mut world)
+            .unwrap();
+
+        let x = |entity| {
+            world
+                .world
+                .get_component::<PositionComponent>(entity)
+                .unwrap()
+                .0
+                .x
+        };
+        x(entities[1]) - x(entities[0])
+    }
+
+    #[test]
+    fn colliding_spheres_push_each_other_apart() {
+        assert_eq!(separated_distance(false), 0.5);
+        assert!(separated_distance(true) > 1.0);
+
+        // the contacts need a softer pull home to win against the spring
+        assert!(Collisions(true).spring_factor() < Collisions(false).spring_factor());
+    }
+}
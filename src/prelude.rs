@@ -0,0 +1,36 @@
+pub use crate::world::beat::BeatEvent;
+pub use crate::world::bloom::BloomParams;
+pub use crate::world::camera::Camera;
+pub use crate::world::color_ramp::ColorRamp;
+pub use crate::world::debug_render::DebugRender;
+pub use crate::world::depth_of_field::DepthOfField;
+pub use crate::world::environment::Environment;
+pub use crate::world::gpu_profiler::GpuProfiler;
+pub use crate::world::light::Light;
+pub use crate::world::post_effects::PostEffects;
+pub use crate::world::resolution::Resolution;
+pub use crate::world::shading::Shading;
+pub use crate::world::sphere_mesh::SphereMeshParams;
+pub use crate::world::tonemapping::Tonemapping;
+pub use crate::world::ResWorld;
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    #[test]
+    fn prelude_resolves_to_the_world_types() {
+        assert_eq!(
+            TypeId::of::<super::Camera>(),
+            TypeId::of::<crate::world::camera::Camera>()
+        );
+        assert_eq!(
+            TypeId::of::<super::Light>(),
+            TypeId::of::<crate::world::light::Light>()
+        );
+        assert_eq!(
+            TypeId::of::<super::ColorRamp>(),
+            TypeId::of::<crate::world::color_ramp::ColorRamp>()
+        );
+    }
+}
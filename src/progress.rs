@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The number of frames the remaining time is estimated from
+const WINDOW: usize = 60;
+
+/// How often the progress is reported at most
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reports the progress of a headless render on stderr. Every report is a whole line, so it does
+/// not tear apart the lines the saving threads print to stdout in between. With a known frame
+/// count the remaining time is estimated from the average duration of the last frames, otherwise
+/// only the throughput is reported.
+pub struct Progress {
+    total: Option<usize>,
+    durations: VecDeque<Duration>,
+    last_frame: Instant,
+    last_report: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: Option<usize>) -> Self {
+        Self {
+            total,
+            durations: VecDeque::with_capacity(WINDOW),
+            last_frame: Instant::now(),
+            last_report: None,
+        }
+    }
+
+    /// Has to be called after every rendered frame, `rendered` being the number of frames
+    /// rendered so far
+    pub fn frame_rendered(&mut self, rendered: usize) {
+        let now = Instant::now();
+
+        if self.durations.len() == WINDOW {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(now - self.last_frame);
+        self.last_frame = now;
+
+        let finished = self.total.map_or(false, |total| rendered >= total);
+        let due = self
+            .last_report
+            .map_or(true, |last_report| now - last_report >= INTERVAL);
+
+        if due || finished {
+            self.last_report = Some(now);
+            eprintln!("{}", self.report(rendered));
+        }
+    }
+
+    fn report(&self, rendered: usize) -> String {
+        let frame_duration =
+            self.durations.iter().sum::<Duration>().as_secs_f32() / self.durations.len() as f32;
+
+        match self.total {
+            Some(total) => format!(
+                "frame {}/{} ({:.1}%), {} remaining",
+                rendered,
+                total,
+                rendered as f32 / total as f32 * 100.0,
+                format_duration(frame_duration * total.saturating_sub(rendered) as f32)
+            ),
+            None => format!(
+                "frame {}, {:.1} frames per second",
+                rendered,
+                1.0 / frame_duration
+            ),
+        }
+    }
+}
+
+/// Formats seconds as e.g. `1h02m03s`, `4m12s` or `12s`
+fn format_duration(seconds: f32) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A progress whose last frames all took `frame_duration`
+    fn progress(total: Option<usize>, frame_duration: Duration) -> Progress {
+        let mut progress = Progress::new(total);
+        progress.durations = vec![frame_duration; WINDOW].into();
+        progress
+    }
+
+    #[test]
+    fn durations_are_formatted_with_their_largest_unit() {
+        assert_eq!(format_duration(12.4), "12s");
+        assert_eq!(format_duration(252.0), "4m12s");
+        assert_eq!(format_duration(3723.0), "1h02m03s");
+    }
+
+    #[test]
+    fn remaining_time_is_estimated_from_the_last_frames() {
+        let progress = progress(Some(200), Duration::from_millis(500));
+
+        assert_eq!(progress.report(50), "frame 50/200 (25.0%), 1m15s remaining");
+        assert_eq!(progress.report(200), "frame 200/200 (100.0%), 0s remaining");
+    }
+
+    #[test]
+    fn unknown_totals_report_the_throughput() {
+        let progress = progress(None, Duration::from_millis(40));
+
+        assert_eq!(progress.report(7), "frame 7, 25.0 frames per second");
+    }
+
+    #[test]
+    fn only_the_last_frames_are_kept() {
+        let mut progress = Progress::new(None);
+
+        for rendered in 1..=WINDOW * 2 {
+            progress.frame_rendered(rendered);
+        }
+
+        assert_eq!(progress.durations.len(), WINDOW);
+    }
+}
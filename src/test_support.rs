@@ -0,0 +1,87 @@
+//! Helpers for the tests that need a device. rendy only creates the `GraphContext` a node is built
+//! with while it builds a graph, so a node is tested by building a throwaway graph around it. The
+//! device is a software implementation like lavapipe or SwiftShader whenever one is installed, so
+//! the tests run without a gpu or a window, `cargo test --features vulkan` runs them in ci.
+
+use crate::backend::{init_first, preferred_backends};
+use crate::world::ResWorld;
+use anyhow::Error;
+use legion::prelude::*;
+use rendy::command::Families;
+use rendy::factory::{Config, DevicesConfigure, Factory};
+use rendy::graph::{GraphBuilder, ImageId};
+use rendy::hal::adapter::{Adapter, DeviceType};
+use rendy::hal::command::{ClearColor, ClearValue};
+use rendy::hal::format::Format;
+use rendy::hal::image::Kind;
+use rendy::hal::Backend;
+use rendy::init::AnyRendy;
+
+/// Picks the first software device, and the first device if there is none
+#[derive(Debug, Default)]
+pub struct SoftwareDevice;
+
+impl DevicesConfigure for SoftwareDevice {
+    fn pick<B: Backend>(&self, adapters: &[Adapter<B>]) -> usize {
+        adapters
+            .iter()
+            .position(|adapter| adapter.info.device_type == DeviceType::Cpu)
+            .unwrap_or(0)
+    }
+}
+
+/// The first enabled backend that initializes on a `SoftwareDevice`, without a window or
+/// swapchain
+pub fn headless() -> AnyRendy {
+    let config = Config {
+        devices: SoftwareDevice,
+        heaps: Default::default(),
+        queues: Default::default(),
+    };
+
+    let (_, rendy) = init_first(preferred_backends(), |backend| {
+        AnyRendy::init(*backend, &config)
+    })
+    .expect("no backend could be initialized");
+
+    rendy
+}
+
+/// A world without any resources, for nodes that read nothing from it while they are built
+pub fn empty_world() -> ResWorld {
+    ResWorld::new(Resources::default(), Universe::new().create_world())
+}
+
+/// A 2d image for the node under test to read or write, cleared to `clear`
+pub fn image<B: Backend>(
+    graph_builder: &mut GraphBuilder<B, ResWorld>,
+    width: u32,
+    height: u32,
+    format: Format,
+    clear: [f32; 4],
+) -> ImageId {
+    graph_builder.create_image(
+        Kind::D2(width, height, 1, 1),
+        1,
+        format,
+        Some(ClearValue {
+            color: ClearColor { float32: clear },
+        }),
+    )
+}
+
+/// Builds the graph, which builds every node in it, and disposes it right away
+pub fn build_and_dispose<B: Backend>(
+    graph_builder: GraphBuilder<B, ResWorld>,
+    factory: &mut Factory<B>,
+    families: &mut Families<B>,
+    world: &ResWorld,
+) -> Result<(), Error> {
+    let graph = graph_builder
+        .build(factory, families, world)
+        .map_err(|e| anyhow!("{:?}", e))?;
+
+    graph.dispose(factory, world);
+
+    Ok(())
+}
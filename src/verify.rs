@@ -0,0 +1,134 @@
+use crate::graph::node::capture::CaptureAction;
+use crate::world::resolution::Resolution;
+use crate::world::ResWorld;
+use anyhow::Error;
+use image::{ColorType, RgbaImage};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The largest per channel difference (in `0..1`) between a rendered frame and the golden image
+#[derive(Clone, Debug, Default)]
+pub struct GoldenDifference(Arc<Mutex<Option<f32>>>);
+
+impl GoldenDifference {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> Option<f32> {
+        *self.0.lock().unwrap()
+    }
+
+    fn add(&self, difference: f32) {
+        let mut max = self.0.lock().unwrap();
+        *max = Some(max.unwrap_or(0.0).max(difference));
+    }
+}
+
+/// Compares captured frames against a golden image, or replaces the golden image with them
+#[derive(Debug)]
+pub struct GoldenAction {
+    path: PathBuf,
+    color_type: ColorType,
+    update: bool,
+    difference: GoldenDifference,
+}
+
+impl GoldenAction {
+    pub fn new(
+        path: PathBuf,
+        color_type: ColorType,
+        update: bool,
+        difference: GoldenDifference,
+    ) -> Self {
+        Self {
+            path,
+            color_type,
+            update,
+            difference,
+        }
+    }
+}
+
+impl CaptureAction<u8> for GoldenAction {
+    fn exec(&mut self, world: &ResWorld, image_data: &[u8], _frame: u64) -> Result<(), Error> {
+        let resolution = world
+            .resources
+            .get::<Resolution>()
+            .expect("Resolution was not inserted into world");
+
+        let (width, height) = (resolution.width(), resolution.height());
+
+        if self.update {
+            image::save_buffer(&self.path, image_data, width, height, self.color_type)?;
+            return Ok(());
+        }
+
+        let golden = image::open(&self.path)?.to_rgba();
+
+        let rendered = match self.color_type {
+            ColorType::Rgba8 => image_data.to_vec(),
+            ColorType::Rgb8 => image_data
+                .chunks_exact(3)
+                .flat_map(|pixel| vec![pixel[0], pixel[1], pixel[2], 255])
+                .collect(),
+            color_type => bail!("can not compare {:?} to the golden image", color_type),
+        };
+
+        self.difference
+            .add(difference(&golden, &rendered, width, height));
+
+        Ok(())
+    }
+}
+
+fn difference(golden: &RgbaImage, rendered: &[u8], width: u32, height: u32) -> f32 {
+    if golden.dimensions() != (width, height) || golden.len() != rendered.len() {
+        return 1.0;
+    }
+
+    golden
+        .iter()
+        .zip(rendered)
+        .map(|(golden, rendered)| (*golden as i32 - *rendered as i32).abs())
+        .max()
+        .unwrap_or(0) as f32
+        / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(4, 3, |x, y| Rgba([x as u8 * 60, y as u8 * 80, 128, 255]))
+    }
+
+    #[test]
+    fn identical_images_do_not_differ() {
+        let golden = image();
+
+        assert_eq!(difference(&golden, &image().into_raw(), 4, 3), 0.0);
+    }
+
+    #[test]
+    fn one_pixel_differs() {
+        let golden = image();
+        let mut rendered = image();
+        rendered.put_pixel(2, 1, Rgba([120, 80, 77, 255]));
+
+        assert_eq!(
+            difference(&golden, &rendered.into_raw(), 4, 3),
+            51.0 / 255.0
+        );
+    }
+
+    #[test]
+    fn size_mismatch_differs_completely() {
+        let golden = image();
+
+        assert_eq!(difference(&golden, &image().into_raw(), 3, 4), 1.0);
+        assert_eq!(difference(&golden, &[0; 4 * 3 * 3], 4, 3), 1.0);
+    }
+}
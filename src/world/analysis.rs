@@ -0,0 +1,84 @@
+use anyhow::Error;
+use legion::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write as IoWrite};
+use std::path::Path;
+
+/// The envelope output of the band of one sphere before it is clamped to the minimal radius
+pub struct AnalysisValue(pub f32);
+
+/// Writes the analysis values of every frame as one row of comma separated values, in the order
+/// the spheres were created
+pub struct AnalysisLog {
+    writer: BufWriter<File>,
+}
+
+impl AnalysisLog {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn write_row<I: IntoIterator<Item = f32>>(&mut self, values: I) -> Result<(), Error> {
+        let row = values
+            .into_iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        writeln!(self.writer, "{}", row)?;
+
+        Ok(())
+    }
+}
+
+pub fn analysis_log_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("analysis_log_system")
+        .with_query(<Read<AnalysisValue>>::query())
+        .write_resource::<AnalysisLog>()
+        .build(|_, world, log, query| {
+            log.write_row(query.iter(world).map(|value| value.0))
+                .expect("failed to write analysis log");
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read_to_string, remove_file};
+
+    #[test]
+    fn one_row_per_frame_with_a_column_per_sphere() {
+        let path = std::env::temp_dir().join("rendy-sphere-visualizer-analysis-log.csv");
+
+        let mut world = Universe::new().create_world();
+        world.insert(
+            (),
+            vec![
+                (AnalysisValue(0.0),),
+                (AnalysisValue(0.5),),
+                (AnalysisValue(1.0),),
+            ],
+        );
+
+        let mut resources = Resources::default();
+        resources.insert(AnalysisLog::create(&path).unwrap());
+
+        let mut schedule = Schedule::builder()
+            .add_system(analysis_log_system())
+            .build();
+
+        for _ in 0..4 {
+            schedule.execute(&mut world, &mut resources);
+        }
+
+        // the rows are flushed once the log is dropped
+        drop(resources);
+
+        let log = read_to_string(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(log.lines().collect::<Vec<_>>(), vec!["0,0.5,1"; 4]);
+    }
+}
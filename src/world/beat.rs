@@ -0,0 +1,233 @@
+use crate::audio::SamplesResource;
+use crate::event::{ChangeEvent, StateId};
+use legion::prelude::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Detects beats in the captured samples by comparing the energy of the latest block of samples
+/// with the average energy of the blocks before it
+#[derive(Debug, Clone)]
+pub struct BeatDetector {
+    block_size: usize,
+    window: usize,
+    sensitivity: f32,
+    history: VecDeque<f32>,
+    block_energy: f32,
+    block_samples: usize,
+}
+
+impl BeatDetector {
+    /// 1024 samples at 44.1 kHz are about 23 ms, a window of 43 blocks is about a second
+    pub const DEFAULT_BLOCK_SIZE: usize = 1024;
+    pub const DEFAULT_WINDOW: usize = 43;
+    pub const DEFAULT_SENSITIVITY: f32 = 1.3;
+
+    pub fn new() -> Self {
+        Self {
+            block_size: Self::DEFAULT_BLOCK_SIZE,
+            window: Self::DEFAULT_WINDOW,
+            sensitivity: Self::DEFAULT_SENSITIVITY,
+            history: VecDeque::new(),
+            block_energy: 0.0,
+            block_samples: 0,
+        }
+    }
+
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.set_block_size(block_size);
+        self
+    }
+
+    /// The number of blocks the average energy is taken over
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.set_window(window);
+        self
+    }
+
+    /// How many times the average energy a block needs to count as a beat
+    pub fn with_sensitivity(mut self, sensitivity: f32) -> Self {
+        self.set_sensitivity(sensitivity);
+        self
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.block_size = block_size.max(1);
+        self.block_energy = 0.0;
+        self.block_samples = 0;
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+
+        while self.history.len() > self.window {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn sensitivity(&self) -> f32 {
+        self.sensitivity
+    }
+
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity
+    }
+
+    /// Returns true if `sample` completes a block that is a beat. There are no beats until the
+    /// window is filled.
+    pub fn push(&mut self, sample: f32) -> bool {
+        self.block_energy += sample * sample;
+        self.block_samples += 1;
+
+        if self.block_samples < self.block_size {
+            return false;
+        }
+
+        let energy = self.block_energy / self.block_samples as f32;
+        self.block_energy = 0.0;
+        self.block_samples = 0;
+
+        let beat = self.history.len() == self.window && {
+            let average = self.history.iter().sum::<f32>() / self.history.len() as f32;
+            energy > self.sensitivity * average
+        };
+
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back(energy);
+
+        beat
+    }
+}
+
+impl Default for BeatDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Changes on every beat the `BeatDetector` finds, systems poll it with a `StateId`, e.g. to pulse
+/// the camera or flash the colors
+pub struct BeatEvent {
+    changed: ChangeEvent,
+    beats: usize,
+}
+
+impl BeatEvent {
+    pub fn new() -> Self {
+        Self {
+            changed: ChangeEvent::new(),
+            beats: 0,
+        }
+    }
+
+    pub fn beat(&mut self) {
+        self.beats += 1;
+        self.changed.change();
+    }
+
+    /// The beats since the start
+    pub fn beats(&self) -> usize {
+        self.beats
+    }
+
+    pub fn register(&self) -> StateId {
+        self.changed.register()
+    }
+
+    /// True if there was a beat since `state` was registered or last checked
+    pub fn has_changed(&self, state: &mut StateId) -> bool {
+        self.changed.has_changed(state)
+    }
+}
+
+impl Default for BeatEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Has to run before the analyzer system, which clears the samples. It writes the samples, so the
+/// schedule does not run the two side by side.
+pub fn beat_detection_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("beat_detection_system")
+        .write_resource::<Arc<Mutex<SamplesResource>>>()
+        .write_resource::<BeatDetector>()
+        .write_resource::<BeatEvent>()
+        .build(|_, _, (samples, beat_detector, beat_event), _| {
+            let samples = samples.lock().unwrap();
+
+            for sample in samples.mono() {
+                if beat_detector.push(sample) {
+                    beat_event.beat();
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 44100;
+
+    /// The blocks of `samples` that are detected as beats
+    fn beat_blocks(
+        beat_detector: &mut BeatDetector,
+        samples: impl Iterator<Item = f32>,
+    ) -> Vec<usize> {
+        let block_size = beat_detector.block_size();
+
+        samples
+            .enumerate()
+            .filter(|(_, sample)| beat_detector.push(*sample))
+            .map(|(i, _)| i / block_size)
+            .collect()
+    }
+
+    #[test]
+    fn click_track() {
+        // 10 ms blocks and a window of a second, so a beat at 120 bpm is exactly 50 blocks long
+        let block_size = SAMPLE_RATE / 100;
+        let beat_length = SAMPLE_RATE / 2;
+        let mut beat_detector = BeatDetector::new()
+            .with_block_size(block_size)
+            .with_window(100);
+
+        // a decaying click at the start of every beat over a quiet tone
+        let click_track = (0..10 * SAMPLE_RATE).map(|i| {
+            let tone = 0.05 * (i as f32 * 0.37).sin();
+            let in_beat = i % beat_length;
+
+            if in_beat < block_size {
+                tone + 0.8 * (1.0 - in_beat as f32 / block_size as f32)
+            } else {
+                tone
+            }
+        });
+
+        // the beats of the first second fill the window
+        assert_eq!(
+            beat_blocks(&mut beat_detector, click_track),
+            (2..20).map(|beat| beat * 50).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn steady_tone_has_no_beats() {
+        let mut beat_detector = BeatDetector::new();
+
+        let tone = (0..10 * SAMPLE_RATE)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / SAMPLE_RATE as f32).sin());
+
+        assert!(beat_blocks(&mut beat_detector, tone).is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+/// How the bloom pass lets the bright parts of the image bleed into their surroundings. Only
+/// has an effect if the graph was built with bloom, see `GraphConfig::bloom`.
+#[derive(Debug, Clone)]
+pub struct BloomParams {
+    threshold: f32,
+    intensity: f32,
+}
+
+impl BloomParams {
+    pub fn new() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.5,
+        }
+    }
+
+    /// The luminance above which the linear, exposed colors start to bloom. With the default of 1
+    /// only colors brighter than what can be displayed bloom.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// How strongly the blurred bright parts are added back onto the image, 0 disables the bloom
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity
+    }
+}
+
+impl Default for BloomParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,27 +1,54 @@
+use crate::animation::Frame;
 use crate::bundle::{Bundle, BundlePhase1};
+use crate::world::input::Input;
 use crate::world::resolution::Resolution;
+use crate::world::time::{HeadlessTime, Time};
 use crate::world::ResWorld;
 use anyhow::Error;
 use legion::prelude::*;
 use legion::systems::schedule::Builder;
-use nalgebra_glm::{diagonal4x4, vec4, zero, Mat4};
+use nalgebra_glm::{diagonal4x4, inverse, look_at, vec3, vec4, zero, Mat4, Vec3};
+use rendy::init::winit::event::{MouseButton, VirtualKeyCode};
+use std::f32::consts::FRAC_PI_2;
 
 pub struct CameraBundle {
     view_matrix: Mat4,
-    fov: f32,
+    projection: Projection,
     near: f32,
     far: f32,
+    aspect: Option<f32>,
+    orbit: Option<OrbitCamera>,
 }
 
 impl CameraBundle {
     pub fn new(view_matrix: Mat4, fov: f32, near: f32, far: f32) -> Self {
         Self {
             view_matrix,
-            fov,
+            projection: Projection::Perspective { fov },
             near,
             far,
+            aspect: None,
+            orbit: None,
         }
     }
+
+    /// See `Camera::with_aspect`
+    pub fn with_aspect(mut self, aspect: Option<f32>) -> Self {
+        self.aspect = aspect;
+        self
+    }
+
+    /// See `Camera::with_projection`
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// Moves the camera along `orbit` instead of keeping the view matrix
+    pub fn with_orbit(mut self, orbit: Option<OrbitCamera>) -> Self {
+        self.orbit = orbit;
+        self
+    }
 }
 
 impl Bundle for CameraBundle {
@@ -30,9 +57,11 @@ impl Bundle for CameraBundle {
     fn add_entities_and_resources(self, world: &mut ResWorld) -> Result<Self::Phase1, Error> {
         let CameraBundle {
             view_matrix,
-            fov,
+            projection,
             near,
             far,
+            aspect,
+            orbit,
         } = self;
 
         let (width, height) = {
@@ -44,56 +73,272 @@ impl Bundle for CameraBundle {
             (resolution.width(), resolution.height())
         };
 
-        world
-            .resources
-            .insert(Camera::new(view_matrix, fov, near, far, width, height));
-        Ok(CameraBundlePhase1)
+        world.resources.insert(
+            Camera::new(view_matrix, projection, near, far, width, height).with_aspect(aspect),
+        );
+
+        let orbiting = orbit.is_some();
+        if let Some(orbit) = orbit {
+            world.resources.insert(orbit);
+        }
+
+        // the orbit moves the camera on its own, so the user only controls a static camera
+        let controlled = !orbiting && world.resources.contains::<Input>();
+        if controlled {
+            world
+                .resources
+                .insert(CameraController::from_view_matrix(&view_matrix));
+        }
+
+        Ok(CameraBundlePhase1 {
+            orbit: orbiting,
+            controller: controlled,
+        })
     }
 }
 
-pub struct CameraBundlePhase1;
+pub struct CameraBundlePhase1 {
+    orbit: bool,
+    controller: bool,
+}
 
 impl BundlePhase1 for CameraBundlePhase1 {
-    fn add_systems(self, world: &ResWorld, builder: Builder) -> Result<Builder, Error> {
-        Ok(builder.add_system(camera_resize_system(world)))
+    fn add_systems(self, world: &ResWorld, mut builder: Builder) -> Result<Builder, Error> {
+        builder = builder.add_system(camera_resize_system(world));
+
+        if self.orbit {
+            builder = if world.resources.contains::<HeadlessTime>() {
+                builder.add_system(orbit_camera_system_headless())
+            } else {
+                builder.add_system(orbit_camera_system_realtime())
+            };
+        }
+
+        if self.controller {
+            builder = builder.add_system(camera_controller_system());
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Circles the camera around `center` at `radius` and `height` above it, always looking at
+/// `center`. `speed` is in radians per frame, so headless renders orbit the same at any fps.
+#[derive(Clone, Debug)]
+pub struct OrbitCamera {
+    pub center: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub speed: f32,
+}
+
+impl OrbitCamera {
+    pub fn new(radius: f32, height: f32, speed: f32) -> Self {
+        Self {
+            center: zero(),
+            radius,
+            height,
+            speed,
+        }
+    }
+
+    pub fn view_matrix(&self, frame: &Frame) -> Mat4 {
+        let angle = frame.frame() * self.speed;
+
+        let eye = self.center
+            + vec3(
+                angle.sin() * self.radius,
+                self.height,
+                angle.cos() * self.radius,
+            );
+
+        look_at(&eye, &self.center, &vec3(0.0, 1.0, 0.0))
+    }
+}
+
+/// Flies the camera with the keyboard and mouse of the window. WASD moves along the view
+/// direction, Q and E move down and up, dragging with the left mouse button looks around.
+#[derive(Clone, Debug)]
+pub struct CameraController {
+    position: Vec3,
+    yaw: f32,
+    pitch: f32,
+    /// in units per frame
+    move_speed: f32,
+    /// in radians per pixel
+    look_speed: f32,
+    last_frame: Option<f32>,
+}
+
+impl CameraController {
+    /// keeps the camera from flipping over when looking straight up or down
+    pub const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+    pub fn from_view_matrix(view_matrix: &Mat4) -> Self {
+        let camera_matrix = inverse(view_matrix);
+        let position = camera_matrix.column(3).xyz();
+        let forward = -camera_matrix.column(2).xyz().normalize();
+
+        Self {
+            position,
+            yaw: forward.x.atan2(-forward.z),
+            pitch: forward.y.asin().max(-Self::MAX_PITCH).min(Self::MAX_PITCH),
+            move_speed: 0.1,
+            look_speed: 0.005,
+            last_frame: None,
+        }
     }
+
+    fn forward(&self) -> Vec3 {
+        vec3(
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            -self.yaw.cos() * self.pitch.cos(),
+        )
+    }
+
+    /// Applies the input since the last update and returns the new view matrix
+    pub fn update(&mut self, input: &Input, frame: &Frame) -> Mat4 {
+        let frames = frame.frame() - self.last_frame.unwrap_or(frame.frame());
+        self.last_frame = Some(frame.frame());
+
+        if input.mouse_button(MouseButton::Left) {
+            let delta = input.cursor_delta();
+
+            self.yaw += delta.x * self.look_speed;
+            self.pitch = (self.pitch - delta.y * self.look_speed)
+                .max(-Self::MAX_PITCH)
+                .min(Self::MAX_PITCH);
+        }
+
+        let up = vec3(0.0, 1.0, 0.0);
+        let forward = self.forward();
+        let right = forward.cross(&up).normalize();
+
+        let axis = |positive: VirtualKeyCode, negative: VirtualKeyCode| {
+            input.key(positive) as i32 as f32 - input.key(negative) as i32 as f32
+        };
+
+        let movement = forward * axis(VirtualKeyCode::W, VirtualKeyCode::S)
+            + right * axis(VirtualKeyCode::D, VirtualKeyCode::A)
+            + up * axis(VirtualKeyCode::E, VirtualKeyCode::Q);
+
+        self.position += movement * self.move_speed * frames;
+
+        look_at(&self.position, &(self.position + forward), &up)
+    }
+}
+
+/// How the camera projects the scene onto the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// `fov` is the horizontal field of view in radians
+    Perspective { fov: f32 },
+    /// Projects along the view direction without foreshortening, e.g. for diagram like renders.
+    /// `height` is the height of the visible area in world units.
+    Orthographic { height: f32 },
 }
 
 pub struct Camera {
     view_matrix: Mat4,
-    fov: f32, // in rad
+    projection: Projection,
     near: f32,
     far: f32,
+    aspect: Option<f32>,
+    width: u32,
+    height: u32,
     proj_matrix: Mat4,
 }
 
 impl Camera {
-    pub fn new(view_matrix: Mat4, fov: f32, near: f32, far: f32, width: u32, height: u32) -> Self {
+    pub fn new(
+        view_matrix: Mat4,
+        projection: Projection,
+        near: f32,
+        far: f32,
+        width: u32,
+        height: u32,
+    ) -> Self {
         Self {
             view_matrix,
-            fov,
+            projection,
             near,
             far,
-            proj_matrix: Self::create_projection_matrix(fov, near, far, width, height),
+            aspect: None,
+            width,
+            height,
+            proj_matrix: Self::create_projection_matrix(
+                projection,
+                near,
+                far,
+                width as f32 / height as f32,
+            ),
         }
     }
 
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.set_projection(projection);
+        self
+    }
+
+    pub fn get_projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        self.update_projection_matrix();
+    }
+
+    /// Projects with the aspect ratio (width / height) given instead of the one of the image, e.g.
+    /// to frame a 2.39:1 composition in a 16:9 image. `None` uses the aspect ratio of the image.
+    pub fn with_aspect(mut self, aspect: Option<f32>) -> Self {
+        self.set_aspect(aspect);
+        self
+    }
+
+    pub fn get_aspect(&self) -> f32 {
+        self.aspect
+            .unwrap_or(self.width as f32 / self.height as f32)
+    }
+
+    pub fn set_aspect(&mut self, aspect: Option<f32>) {
+        self.aspect = aspect;
+        self.update_projection_matrix();
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.update_projection_matrix();
+    }
+
+    fn update_projection_matrix(&mut self) {
         self.proj_matrix =
-            Self::create_projection_matrix(self.fov, self.near, self.far, width, height);
+            Self::create_projection_matrix(self.projection, self.near, self.far, self.get_aspect());
     }
 
-    fn create_projection_matrix(fov: f32, near: f32, far: f32, width: u32, height: u32) -> Mat4 {
+    fn create_projection_matrix(projection: Projection, near: f32, far: f32, aspect: f32) -> Mat4 {
         let mut mat: Mat4 = zero();
 
-        let aspect = height as f32 / width as f32;
-        let tan_half_fov = (fov / 2.0).tan();
+        match projection {
+            Projection::Perspective { fov } => {
+                let tan_half_fov = (fov / 2.0).tan();
 
-        mat[(0, 0)] = 1.0 / tan_half_fov;
-        mat[(1, 1)] = 1.0 / (aspect * tan_half_fov);
-        mat[(2, 2)] = -(far + near) / (far - near);
-        mat[(2, 3)] = -(2.0 * far * near) / (far - near);
-        mat[(3, 2)] = -1.0;
+                mat[(0, 0)] = 1.0 / tan_half_fov;
+                mat[(1, 1)] = aspect / tan_half_fov;
+                mat[(2, 2)] = -(far + near) / (far - near);
+                mat[(2, 3)] = -(2.0 * far * near) / (far - near);
+                mat[(3, 2)] = -1.0;
+            }
+            Projection::Orthographic { height } => {
+                mat[(0, 0)] = 2.0 / (height * aspect);
+                mat[(1, 1)] = 2.0 / height;
+                mat[(2, 2)] = -2.0 / (far - near);
+                mat[(2, 3)] = -(far + near) / (far - near);
+                mat[(3, 3)] = 1.0;
+            }
+        }
 
         mat * diagonal4x4(&vec4(1.0, -1.0, 1.0, 1.0))
     }
@@ -106,14 +351,22 @@ impl Camera {
         self.far
     }
 
-    pub fn get_fov(&self) -> f32 {
-        self.fov
+    /// `None` for an orthographic projection
+    pub fn get_fov(&self) -> Option<f32> {
+        match self.projection {
+            Projection::Perspective { fov } => Some(fov),
+            Projection::Orthographic { .. } => None,
+        }
     }
 
     pub fn get_view_matrix(&self) -> &Mat4 {
         &self.view_matrix
     }
 
+    pub fn set_view_matrix(&mut self, view_matrix: Mat4) {
+        self.view_matrix = view_matrix
+    }
+
     pub fn get_proj_matrix(&self) -> &Mat4 {
         &self.proj_matrix
     }
@@ -136,3 +389,181 @@ pub fn camera_resize_system(world: &ResWorld) -> Box<dyn Schedulable> {
             }
         })
 }
+
+pub fn orbit_camera_system_realtime() -> Box<dyn Schedulable> {
+    SystemBuilder::new("orbit_camera_system")
+        .read_resource::<OrbitCamera>()
+        .read_resource::<Time>()
+        .write_resource::<Camera>()
+        .build(|_, _, (orbit_camera, time, camera), ()| {
+            camera.set_view_matrix(orbit_camera.view_matrix(&time.current_frame()))
+        })
+}
+
+pub fn orbit_camera_system_headless() -> Box<dyn Schedulable> {
+    SystemBuilder::new("orbit_camera_system")
+        .read_resource::<OrbitCamera>()
+        .read_resource::<HeadlessTime>()
+        .write_resource::<Camera>()
+        .build(|_, _, (orbit_camera, time, camera), ()| {
+            camera.set_view_matrix(orbit_camera.view_matrix(&time.current_frame()))
+        })
+}
+
+pub fn camera_controller_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("camera_controller_system")
+        .read_resource::<Input>()
+        .read_resource::<Time>()
+        .write_resource::<CameraController>()
+        .write_resource::<Camera>()
+        .build(|_, _, (input, time, controller, camera), ()| {
+            camera.set_view_matrix(controller.update(input, &time.current_frame()))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext::transform_point;
+    use nalgebra_glm::{half_pi, identity, inverse, length, vec2};
+    use rendy::init::winit::event::ElementState;
+
+    fn camera(width: u32, height: u32) -> Camera {
+        Camera::new(
+            identity(),
+            Projection::Perspective { fov: 1.0 },
+            0.1,
+            100.0,
+            width,
+            height,
+        )
+    }
+
+    #[test]
+    fn aspect_override_changes_the_projection_but_not_the_image() {
+        let image_aspect = camera(1920, 1080);
+        let mut letterboxed = camera(1920, 1080).with_aspect(Some(2.39));
+
+        assert_approx_eq!(image_aspect.get_aspect(), 1920.0 / 1080.0);
+        assert_eq!(letterboxed.get_aspect(), 2.39);
+        assert_eq!((letterboxed.width, letterboxed.height), (1920, 1080));
+
+        // the horizontal field of view stays, the vertical one follows the aspect ratio
+        let image_matrix = image_aspect.get_proj_matrix();
+        let letterboxed_matrix = letterboxed.get_proj_matrix().clone();
+        assert_eq!(letterboxed_matrix[(0, 0)], image_matrix[(0, 0)]);
+        assert_approx_eq!(
+            letterboxed_matrix[(1, 1)] / image_matrix[(1, 1)],
+            2.39 / (1920.0 / 1080.0)
+        );
+
+        // resizing the image keeps the override
+        letterboxed.resize(1280, 720);
+        assert_eq!(letterboxed.get_proj_matrix(), &letterboxed_matrix);
+
+        letterboxed.set_aspect(None);
+        assert_approx_eq!(letterboxed.get_aspect(), 1280.0 / 720.0);
+        assert_approx_eq!(letterboxed.get_proj_matrix()[(1, 1)], image_matrix[(1, 1)]);
+    }
+
+    #[test]
+    fn orbit_circles_the_center_while_looking_at_it() {
+        let orbit = OrbitCamera::new(10.0, 2.0, half_pi());
+
+        for (frame, eye) in &[
+            (0.0, vec3(0.0, 2.0, 10.0)),
+            (1.0, vec3(10.0, 2.0, 0.0)),
+            (2.0, vec3(0.0, 2.0, -10.0)),
+        ] {
+            let view_matrix = orbit.view_matrix(&Frame::new(*frame));
+
+            let camera_position = transform_point(&zero(), &inverse(&view_matrix));
+            assert_approx_eq!(length(&(camera_position - eye)), 0.0, 1e-4);
+
+            // the center lies straight ahead, along the negative z axis of the view
+            let center = transform_point(&orbit.center, &view_matrix);
+            assert_approx_eq!(center.x, 0.0, 1e-4);
+            assert_approx_eq!(center.y, 0.0, 1e-4);
+            assert_approx_eq!(center.z, -length(&(eye - orbit.center)), 1e-4);
+        }
+    }
+
+    #[test]
+    fn orthographic_projection_keeps_sizes_and_the_y_flip() {
+        let perspective = camera(200, 100);
+        let orthographic =
+            camera(200, 100).with_projection(Projection::Orthographic { height: 4.0 });
+        assert_eq!(orthographic.get_fov(), None);
+
+        let proj_matrix = orthographic.get_proj_matrix();
+
+        // the corner of the visible area lands in the corner of the image at every depth
+        for depth in &[0.1f32, 50.0, 100.0] {
+            let corner = transform_point(&vec3(4.0, 2.0, -depth), proj_matrix);
+            assert_approx_eq!(corner.x, 1.0);
+            assert_approx_eq!(corner.y, -1.0);
+        }
+
+        let near = transform_point(&vec3(0.0, 0.0, -0.1), proj_matrix);
+        let far = transform_point(&vec3(0.0, 0.0, -100.0), proj_matrix);
+        assert_approx_eq!(near.z, -1.0);
+        assert_approx_eq!(far.z, 1.0);
+
+        // both projections flip y the same way
+        let above = vec4(0.0, 1.0, -1.0, 1.0);
+        assert!((orthographic.get_proj_matrix() * above).y < 0.0);
+        assert!((perspective.get_proj_matrix() * above).y < 0.0);
+    }
+
+    /// A controller at (0, 0, 10) looking at the origin, updated once so the next update moves
+    fn controller() -> CameraController {
+        let view_matrix = look_at(&vec3(0.0, 0.0, 10.0), &zero(), &vec3(0.0, 1.0, 0.0));
+        let mut controller = CameraController::from_view_matrix(&view_matrix);
+        controller.update(&Input::new(), &Frame::new(0.0));
+        controller
+    }
+
+    #[test]
+    fn held_keys_move_along_the_view_per_frame() {
+        let mut controller = controller();
+        let mut input = Input::new();
+        input.key_input(VirtualKeyCode::W, ElementState::Pressed);
+
+        controller.update(&input, &Frame::new(10.0));
+
+        assert_approx_eq!(
+            length(&(controller.position - vec3(0.0, 0.0, 9.0))),
+            0.0,
+            1e-4
+        );
+    }
+
+    #[test]
+    fn cursor_motion_only_turns_while_dragging() {
+        let mut controller = controller();
+        let mut input = Input::new();
+        input.cursor_moved(vec2(0.0, 0.0));
+        input.cursor_moved(vec2(100.0, 0.0));
+
+        controller.update(&input, &Frame::new(1.0));
+        assert_approx_eq!(controller.yaw, 0.0);
+
+        input.mouse_input(MouseButton::Left, ElementState::Pressed);
+        controller.update(&input, &Frame::new(2.0));
+        assert_approx_eq!(controller.yaw, 100.0 * controller.look_speed);
+    }
+
+    #[test]
+    fn pitch_stops_short_of_the_poles() {
+        let mut controller = controller();
+        let mut input = Input::new();
+        input.mouse_input(MouseButton::Left, ElementState::Pressed);
+        input.cursor_moved(vec2(0.0, 0.0));
+        input.cursor_moved(vec2(0.0, -100_000.0));
+
+        let view_matrix = controller.update(&input, &Frame::new(1.0));
+
+        assert_eq!(controller.pitch, CameraController::MAX_PITCH);
+        assert!(view_matrix.iter().all(|value| value.is_finite()));
+    }
+}
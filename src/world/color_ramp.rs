@@ -1,23 +1,299 @@
-use nalgebra_glm::Vec3;
+use anyhow::Error;
+use nalgebra_glm::{comp_max, comp_min, make_vec3, vec3, Vec3};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// The color space `ColorRamp` blends neighbouring colors in. The colors are linear rgb in every
+/// case, only the blending differs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorSpace {
+    Rgb,
+    /// blends the hue along the shorter way around the color wheel, which keeps saturated colors
+    /// saturated
+    Hsv,
+    /// perceptually uniform, blends without muddy or overly bright midpoints
+    OkLab,
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(ColorSpace::Rgb),
+            "hsv" => Some(ColorSpace::Hsv),
+            "oklab" => Some(ColorSpace::OkLab),
+            _ => None,
+        }
+    }
+
+    fn lerp(&self, a: &Vec3, b: &Vec3, t: f32) -> Vec3 {
+        match self {
+            ColorSpace::Rgb => a * (1.0 - t) + b * t,
+            ColorSpace::Hsv => {
+                let a = rgb_to_hsv(a);
+                let b = rgb_to_hsv(b);
+
+                let mut hue_delta = b.x - a.x;
+                if hue_delta > 0.5 {
+                    hue_delta -= 1.0;
+                } else if hue_delta < -0.5 {
+                    hue_delta += 1.0;
+                }
+
+                let mut hsv = a * (1.0 - t) + b * t;
+                hsv.x = (a.x + hue_delta * t).rem_euclid(1.0);
+
+                hsv_to_rgb(&hsv)
+            }
+            ColorSpace::OkLab => oklab_to_rgb(&(rgb_to_oklab(a) * (1.0 - t) + rgb_to_oklab(b) * t)),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ColorRamp {
     colors: Vec<Vec3>,
+    domain: (f32, f32),
+    color_space: ColorSpace,
 }
 
 impl ColorRamp {
     pub fn new(colors: Vec<Vec3>) -> Self {
-        ColorRamp { colors }
+        ColorRamp {
+            colors,
+            domain: (0.0, 1.0),
+            color_space: ColorSpace::Rgb,
+        }
+    }
+
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    pub fn colors(&self) -> &[Vec3] {
+        &self.colors
+    }
+
+    /// Loads the colors from a json array of `[r, g, b]` arrays, evenly spaced along the ramp
+    pub fn from_json<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let colors: Vec<[f32; 3]> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+        if colors.is_empty() {
+            bail!("a color ramp needs at least one color");
+        }
+
+        Ok(Self::new(
+            colors.iter().map(|color| make_vec3(color)).collect(),
+        ))
     }
 
+    /// The range of values `color` maps onto the ramp, `min` picks the first and `max` the last
+    /// color
+    pub fn with_domain(mut self, min: f32, max: f32) -> Self {
+        self.domain = (min, max);
+        self
+    }
+
+    pub fn domain(&self) -> (f32, f32) {
+        self.domain
+    }
+
+    /// The color of `value` in the domain of the ramp
+    pub fn color(&self, value: f32) -> Vec3 {
+        let (min, max) = self.domain;
+
+        self.interpolate((value - min) / (max - min))
+    }
+
+    /// The color at `t` along the ramp, values outside of [0, 1] get the first or last color
     pub fn interpolate(&self, t: f32) -> Vec3 {
-        let i = t * (self.colors.len() - 1) as f32;
+        let i = t.max(0.0).min(1.0) * (self.colors.len() - 1) as f32;
         let fract = f32::fract(i);
-        let floor = f32::floor(i);
+        let floor = f32::floor(i) as usize;
+
+        let a = &self.colors[floor];
+
+        // exactly on a color, which also covers the last one, where there is nothing to blend with
+        if fract == 0.0 {
+            return a.clone();
+        }
+
+        let b = &self.colors[(floor + 1).min(self.colors.len() - 1)];
+
+        self.color_space.lerp(a, b, fract)
+    }
+}
+
+/// hue, saturation and value, all in [0, 1]
+fn rgb_to_hsv(rgb: &Vec3) -> Vec3 {
+    let max = comp_max(rgb);
+    let min = comp_min(rgb);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == rgb.x {
+        ((rgb.y - rgb.z) / delta).rem_euclid(6.0)
+    } else if max == rgb.y {
+        (rgb.z - rgb.x) / delta + 2.0
+    } else {
+        (rgb.x - rgb.y) / delta + 4.0
+    };
+
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    vec3(hue / 6.0, saturation, max)
+}
+
+fn hsv_to_rgb(hsv: &Vec3) -> Vec3 {
+    let hue = hsv.x * 6.0;
+    let chroma = hsv.z * hsv.y;
+    let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+
+    let rgb = match hue as u32 {
+        0 => vec3(chroma, x, 0.0),
+        1 => vec3(x, chroma, 0.0),
+        2 => vec3(0.0, chroma, x),
+        3 => vec3(0.0, x, chroma),
+        4 => vec3(x, 0.0, chroma),
+        _ => vec3(chroma, 0.0, x),
+    };
+
+    rgb.add_scalar(hsv.z - chroma)
+}
+
+fn rgb_to_oklab(rgb: &Vec3) -> Vec3 {
+    let l = (0.412_221_47 * rgb.x + 0.536_332_55 * rgb.y + 0.051_445_995 * rgb.z).cbrt();
+    let m = (0.211_903_5 * rgb.x + 0.680_699_5 * rgb.y + 0.107_396_96 * rgb.z).cbrt();
+    let s = (0.088_302_46 * rgb.x + 0.281_718_85 * rgb.y + 0.629_978_7 * rgb.z).cbrt();
+
+    vec3(
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    )
+}
+
+fn oklab_to_rgb(lab: &Vec3) -> Vec3 {
+    let l = (lab.x + 0.396_337_78 * lab.y + 0.215_803_76 * lab.z).powi(3);
+    let m = (lab.x - 0.105_561_346 * lab.y - 0.063_854_17 * lab.z).powi(3);
+    let s = (lab.x - 0.089_484_18 * lab.y - 1.291_485_5 * lab.z).powi(3);
+
+    vec3(
+        4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_color(color: Vec3, expected: Vec3) {
+        assert_approx_eq!(color.x, expected.x, 1e-5);
+        assert_approx_eq!(color.y, expected.y, 1e-5);
+        assert_approx_eq!(color.z, expected.z, 1e-5);
+    }
+
+    fn black_to_white() -> ColorRamp {
+        ColorRamp::new(vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0)])
+    }
+
+    #[test]
+    fn domain_maps_onto_the_ramp() {
+        let color_ramp = black_to_white().with_domain(10.0, 20.0);
+
+        assert_color(color_ramp.color(10.0), vec3(0.0, 0.0, 0.0));
+        assert_color(color_ramp.color(12.5), vec3(0.25, 0.25, 0.25));
+        assert_color(color_ramp.color(20.0), vec3(1.0, 1.0, 1.0));
+
+        let reversed = black_to_white().with_domain(1.0, -1.0);
+
+        assert_color(reversed.color(1.0), vec3(0.0, 0.0, 0.0));
+        assert_color(reversed.color(0.0), vec3(0.5, 0.5, 0.5));
+        assert_color(reversed.color(-1.0), vec3(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn hsv_keeps_the_midpoint_saturated() {
+        let red_to_green = vec![vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)];
+
+        let rgb = ColorRamp::new(red_to_green.clone());
+        assert_color(rgb.interpolate(0.5), vec3(0.5, 0.5, 0.0));
+
+        let hsv = ColorRamp::new(red_to_green).with_color_space(ColorSpace::Hsv);
+        assert_color(hsv.interpolate(0.5), vec3(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn hsv_takes_the_shorter_way_around() {
+        // from blue the shorter way to red passes magenta, not green
+        let blue_to_red = vec![vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0)];
+
+        let rgb = ColorRamp::new(blue_to_red.clone());
+        assert_color(rgb.interpolate(0.5), vec3(0.5, 0.0, 0.5));
+
+        let hsv = ColorRamp::new(blue_to_red).with_color_space(ColorSpace::Hsv);
+        assert_color(hsv.interpolate(0.5), vec3(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn color_spaces_keep_the_colors() {
+        let colors = vec![vec3(0.2, 0.4, 0.8), vec3(0.9, 0.1, 0.3)];
+
+        for color_space in &[ColorSpace::Rgb, ColorSpace::Hsv, ColorSpace::OkLab] {
+            let color_ramp = ColorRamp::new(colors.clone()).with_color_space(*color_space);
+
+            assert_color(color_ramp.interpolate(1e-6), colors[0]);
+            assert_color(color_ramp.interpolate(1.0 - 1e-6), colors[1]);
+        }
+    }
+
+    #[test]
+    fn interpolate_clamps_t() {
+        let color_ramp = ColorRamp::new(vec![
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+        ]);
+
+        assert_color(color_ramp.interpolate(-0.5), vec3(1.0, 0.0, 0.0));
+        assert_color(color_ramp.interpolate(0.0), vec3(1.0, 0.0, 0.0));
+        assert_color(color_ramp.interpolate(1.0), vec3(0.0, 0.0, 1.0));
+        assert_color(color_ramp.interpolate(2.0), vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn single_color_ramp() {
+        let color_ramp = ColorRamp::new(vec![vec3(0.1, 0.2, 0.3)]);
+
+        for t in &[-0.5, 0.0, 0.5, 1.0, 2.0] {
+            assert_color(color_ramp.interpolate(*t), vec3(0.1, 0.2, 0.3));
+        }
+    }
+
+    #[test]
+    fn ramp_loads_from_json() {
+        let directory = std::env::temp_dir().join("rendy-sphere-visualizer-color-ramp");
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let path = directory.join("ramp.json");
+        std::fs::write(&path, "[[0, 0, 0], [0.5, 1, 0.25]]").unwrap();
+        let color_ramp = ColorRamp::from_json(&path).unwrap();
+        assert_color(color_ramp.interpolate(0.0), vec3(0.0, 0.0, 0.0));
+        assert_color(color_ramp.interpolate(1.0), vec3(0.5, 1.0, 0.25));
 
-        let a = &self.colors[(floor as usize).min(self.colors.len() - 1).max(0)];
-        let b = &self.colors[(floor as usize + 1).min(self.colors.len() - 1).max(0)];
+        let empty = directory.join("empty.json");
+        std::fs::write(&empty, "[]").unwrap();
+        let err = ColorRamp::from_json(&empty).unwrap_err();
+        assert_eq!(err.to_string(), "a color ramp needs at least one color");
 
-        return (a * (1.0 - fract)) + (b * fract);
+        std::fs::remove_dir_all(directory).unwrap();
     }
 }
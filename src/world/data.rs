@@ -1,14 +1,54 @@
 use nalgebra_glm::{vec3, Vec3};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SphereData {
     #[serde(rename = "size")]
     pub radius: f32,
     pub position: PositionData,
+    /// linear rgb, overrides the color ramp for this sphere, see `ColorComponent`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<[f32; 3]>,
+    /// the index of refraction, see `Material`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<f32>,
 }
 
-#[derive(Serialize, Deserialize)]
+/// One frame of a loaded animation. Frames without an explicit `frame` are placed at their index
+/// in the file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FrameData {
+    Timed(TimedFrameData),
+    Spheres(Vec<SphereData>),
+}
+
+impl FrameData {
+    pub fn frame(&self, index: usize) -> f32 {
+        match self {
+            FrameData::Timed(TimedFrameData {
+                frame: Some(frame), ..
+            }) => *frame,
+            _ => index as f32,
+        }
+    }
+
+    pub fn into_spheres(self) -> Vec<SphereData> {
+        match self {
+            FrameData::Timed(data) => data.spheres,
+            FrameData::Spheres(spheres) => spheres,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimedFrameData {
+    #[serde(default)]
+    pub frame: Option<f32>,
+    pub spheres: Vec<SphereData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PositionData {
     pub x: f32,
     pub y: f32,
@@ -16,7 +56,73 @@ pub struct PositionData {
 }
 
 impl PositionData {
+    pub fn from_vec3(vec: &Vec3) -> Self {
+        Self {
+            x: vec.x,
+            y: vec.y,
+            z: vec.z,
+        }
+    }
+
     pub fn to_vec3(&self) -> Vec3 {
         vec3(self.x, self.y, self.z)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPHERE: &str = r#"{"size": 1.5, "position": {"x": 1, "y": 2, "z": 3}}"#;
+
+    fn frame_data(json: &str) -> FrameData {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn timed_frames_keep_their_frame() {
+        let frame = frame_data(&format!(r#"{{"frame": 12.5, "spheres": [{}]}}"#, SPHERE));
+
+        assert_eq!(frame.frame(3), 12.5);
+        assert_eq!(frame.into_spheres()[0].radius, 1.5);
+    }
+
+    #[test]
+    fn untimed_frames_are_placed_at_their_index() {
+        let plain = frame_data(&format!("[{}]", SPHERE));
+        let without_frame = frame_data(&format!(r#"{{"spheres": [{}]}}"#, SPHERE));
+
+        assert_eq!(plain.frame(3), 3.0);
+        assert_eq!(without_frame.frame(3), 3.0);
+        assert_eq!(plain.into_spheres(), without_frame.into_spheres());
+    }
+
+    #[test]
+    fn colors_are_optional_and_only_saved_when_given() {
+        let plain: SphereData = serde_json::from_str(SPHERE).unwrap();
+        assert_eq!(plain.color, None);
+        assert!(!serde_json::to_string(&plain).unwrap().contains("color"));
+
+        let colored: SphereData = serde_json::from_str(
+            r#"{"size": 1.5, "position": {"x": 1, "y": 2, "z": 3}, "color": [1, 0.5, 0]}"#,
+        )
+        .unwrap();
+        assert_eq!(colored.color, Some([1.0, 0.5, 0.0]));
+
+        let saved = serde_json::to_string(&colored).unwrap();
+        assert_eq!(serde_json::from_str::<SphereData>(&saved).unwrap(), colored);
+    }
+
+    #[test]
+    fn indices_of_refraction_are_optional() {
+        let plain: SphereData = serde_json::from_str(SPHERE).unwrap();
+        assert_eq!(plain.n, None);
+        assert!(!serde_json::to_string(&plain).unwrap().contains("\"n\""));
+
+        let water: SphereData = serde_json::from_str(
+            r#"{"size": 1.5, "position": {"x": 1, "y": 2, "z": 3}, "n": 1.33}"#,
+        )
+        .unwrap();
+        assert_eq!(water.n, Some(1.33));
+    }
+}
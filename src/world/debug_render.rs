@@ -0,0 +1,66 @@
+/// How the gbuffer pass draws the spheres
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolygonMode {
+    Fill,
+    /// only the edges of the triangles, needs the non fill polygon mode feature of the gpu
+    Line,
+}
+
+impl PolygonMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fill" => Some(PolygonMode::Fill),
+            "line" => Some(PolygonMode::Line),
+            _ => None,
+        }
+    }
+}
+
+/// Visualizations for debugging the scene, e.g. the placement of the spheres. They are read when
+/// the graph is built, so a change only shows after the next rebuild.
+#[derive(Debug, Clone)]
+pub struct DebugRender {
+    polygon_mode: PolygonMode,
+}
+
+impl DebugRender {
+    pub fn new() -> Self {
+        Self {
+            polygon_mode: PolygonMode::Fill,
+        }
+    }
+
+    /// Falls back to `PolygonMode::Fill` with a warning if the gpu can only fill polygons
+    pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn polygon_mode(&self) -> PolygonMode {
+        self.polygon_mode
+    }
+
+    pub fn set_polygon_mode(&mut self, polygon_mode: PolygonMode) {
+        self.polygon_mode = polygon_mode
+    }
+}
+
+impl Default for DebugRender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygon_modes_are_parsed_by_name() {
+        assert_eq!(PolygonMode::from_name("fill"), Some(PolygonMode::Fill));
+        assert_eq!(PolygonMode::from_name("line"), Some(PolygonMode::Line));
+        assert_eq!(PolygonMode::from_name("point"), None);
+
+        assert_eq!(DebugRender::default().polygon_mode(), PolygonMode::Fill);
+    }
+}
@@ -0,0 +1,53 @@
+/// The focus of the depth of field pass. Only has an effect if the graph was built with it, see
+/// `GraphConfig::depth_of_field`.
+#[derive(Debug, Clone)]
+pub struct DepthOfField {
+    focal_distance: f32,
+    aperture: f32,
+}
+
+impl DepthOfField {
+    /// Focuses on the center of the scene as seen from the default camera
+    pub fn new() -> Self {
+        Self {
+            focal_distance: 10.0,
+            aperture: 4.0,
+        }
+    }
+
+    /// The distance from the camera, in world units, at which everything is sharp
+    pub fn with_focal_distance(mut self, focal_distance: f32) -> Self {
+        self.focal_distance = focal_distance;
+        self
+    }
+
+    /// The blur radius in pixels of what is infinitely far away, e.g. the background. Closer to
+    /// the focal plane the radius shrinks, in front of it the radius grows with the inverse
+    /// distance. 0 keeps everything sharp.
+    pub fn with_aperture(mut self, aperture: f32) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn focal_distance(&self) -> f32 {
+        self.focal_distance
+    }
+
+    pub fn set_focal_distance(&mut self, focal_distance: f32) {
+        self.focal_distance = focal_distance
+    }
+
+    pub fn aperture(&self) -> f32 {
+        self.aperture
+    }
+
+    pub fn set_aperture(&mut self, aperture: f32) {
+        self.aperture = aperture
+    }
+}
+
+impl Default for DepthOfField {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,11 +1,14 @@
-use crate::bundle::Bundle;
+use crate::bundle::{Bundle, BundlePhase1};
 use crate::cubemap::HdrCubeMapBuilder;
 use crate::ext::CUBEMAP_SAMPLER_DESC;
-use crate::world::light::Light;
+use crate::world::light::{
+    light_animation_system_headless, light_animation_system_realtime, Light, LightAnimation,
+};
 use crate::world::ResWorld;
+use crate::Mode;
 use anyhow::Error;
 use legion::prelude::*;
-use legion::prelude::*;
+use legion::systems::schedule::Builder;
 use nalgebra_glm::Vec3;
 use rendy::command::QueueId;
 use rendy::factory::{Factory, ImageState};
@@ -16,11 +19,23 @@ use rendy::texture::Texture;
 use serde::export::PhantomData;
 use std::path::Path;
 
+/// The side length of the cubemap faces sampled from an equirectangular environment map
+pub const DEFAULT_FACE_SIZE: u32 = 512;
+
+/// The side length of the faces of the irradiance map
+pub const IRRADIANCE_FACE_SIZE: u32 = 32;
+
+/// The environment map path is either a directory containing the six faces `0001.hdr` to
+/// `0006.hdr` or a single equirectangular `.hdr` panorama.
 pub struct EnvironmentBundle<P, B> {
     ambient_light: Vec3,
+    ambient_intensity: f32,
     light: Light,
+    lights: Vec<Light>,
     environment_map_path: P,
+    face_size: u32,
     queue: QueueId,
+    light_animation: Option<(LightAnimation, Mode)>,
     phantom_data: PhantomData<B>,
 }
 
@@ -28,23 +43,54 @@ impl<P: AsRef<Path>, B: Backend> EnvironmentBundle<P, B> {
     pub fn new(ambient_light: Vec3, light: Light, environment_map_path: P, queue: QueueId) -> Self {
         Self {
             ambient_light,
+            ambient_intensity: Environment::<B>::DEFAULT_AMBIENT_INTENSITY,
             light,
+            lights: vec![],
             environment_map_path,
+            face_size: DEFAULT_FACE_SIZE,
             queue,
+            light_animation: None,
             phantom_data: PhantomData,
         }
     }
+
+    /// See `Environment::with_light`
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    /// See `Environment::with_ambient_intensity`
+    pub fn with_ambient_intensity(mut self, ambient_intensity: f32) -> Self {
+        self.ambient_intensity = ambient_intensity;
+        self
+    }
+
+    /// Only used for an equirectangular environment map
+    pub fn with_face_size(mut self, face_size: u32) -> Self {
+        self.face_size = face_size;
+        self
+    }
+
+    pub fn with_light_animation(mut self, light_animation: LightAnimation, mode: Mode) -> Self {
+        self.light_animation = Some((light_animation, mode));
+        self
+    }
 }
 
 impl<P: AsRef<Path>, B: Backend> Bundle for EnvironmentBundle<P, B> {
-    type Phase1 = ();
+    type Phase1 = EnvironmentBundlePhase1<B>;
 
     fn add_entities_and_resources(self, world: &mut ResWorld) -> Result<Self::Phase1, Error> {
         let EnvironmentBundle {
             ambient_light,
+            ambient_intensity,
             light,
+            lights,
             environment_map_path,
+            face_size,
             queue,
+            light_animation,
             ..
         } = self;
 
@@ -55,57 +101,151 @@ impl<P: AsRef<Path>, B: Backend> Bundle for EnvironmentBundle<P, B> {
             .get_mut::<Factory<B>>()
             .expect("factory was not inserted into world");
 
-        let environment_map = {
-            let state = ImageState {
-                queue,
-                stage: PipelineStage::FRAGMENT_SHADER,
-                access: IAccess::SHADER_READ,
-                layout: ILayout::ShaderReadOnlyOptimal,
+        let state = ImageState {
+            queue,
+            stage: PipelineStage::FRAGMENT_SHADER,
+            access: IAccess::SHADER_READ,
+            layout: ILayout::ShaderReadOnlyOptimal,
+        };
+
+        let (environment_map, irradiance_map) = {
+            let builder = if environment_map_path.is_dir() {
+                HdrCubeMapBuilder::new()
+                    .with_side(environment_map_path.join("0001.hdr"), CubeFace::PosX)?
+                    .with_side(environment_map_path.join("0002.hdr"), CubeFace::NegX)?
+                    .with_side(environment_map_path.join("0003.hdr"), CubeFace::PosY)?
+                    .with_side(environment_map_path.join("0004.hdr"), CubeFace::NegY)?
+                    .with_side(environment_map_path.join("0005.hdr"), CubeFace::PosZ)?
+                    .with_side(environment_map_path.join("0006.hdr"), CubeFace::NegZ)?
+            } else {
+                HdrCubeMapBuilder::from_equirectangular(environment_map_path, face_size)?
             };
 
-            HdrCubeMapBuilder::new()
-                .with_side(environment_map_path.join("0001.hdr"), CubeFace::PosX)?
-                .with_side(environment_map_path.join("0002.hdr"), CubeFace::NegX)?
-                .with_side(environment_map_path.join("0003.hdr"), CubeFace::PosY)?
-                .with_side(environment_map_path.join("0004.hdr"), CubeFace::NegY)?
-                .with_side(environment_map_path.join("0005.hdr"), CubeFace::PosZ)?
-                .with_side(environment_map_path.join("0006.hdr"), CubeFace::NegZ)?
+            let irradiance_map = builder
+                .irradiance(IRRADIANCE_FACE_SIZE)?
+                .with_sampler_info(CUBEMAP_SAMPLER_DESC)
+                .build(state, &mut factory)?;
+
+            let environment_map = builder
+                .with_generated_mips()
                 .with_sampler_info(CUBEMAP_SAMPLER_DESC)
-                .build(state, &mut factory)?
+                .build(state, &mut factory)?;
+
+            (environment_map, irradiance_map)
         };
 
-        world
-            .resources
-            .insert(Environment::new(ambient_light, light, environment_map));
+        let environment = lights.into_iter().fold(
+            Environment::new(ambient_light, light, environment_map, irradiance_map)
+                .with_ambient_intensity(ambient_intensity),
+            Environment::with_light,
+        );
 
-        Ok(())
+        world.resources.insert(environment);
+
+        Ok(EnvironmentBundlePhase1 {
+            light_animation,
+            phantom_data: PhantomData,
+        })
     }
 }
 
+pub struct EnvironmentBundlePhase1<B> {
+    light_animation: Option<(LightAnimation, Mode)>,
+    phantom_data: PhantomData<B>,
+}
+
+impl<B: Backend> BundlePhase1 for EnvironmentBundlePhase1<B> {
+    fn add_systems(self, _world: &ResWorld, builder: Builder) -> Result<Builder, Error> {
+        Ok(match self.light_animation {
+            Some((light_animation, Mode::Realtime)) => {
+                builder.add_system(light_animation_system_realtime::<B>(light_animation))
+            }
+            Some((light_animation, Mode::Headless)) => {
+                builder.add_system(light_animation_system_headless::<B>(light_animation))
+            }
+            None => builder,
+        })
+    }
+}
+
+/// The first light is the key light, the only one casting shadows. The comp pass lights with at
+/// most `comp::MAX_LIGHTS` lights and ignores the rest.
 pub struct Environment<B: Backend> {
     ambient_light: Vec3,
-    light: Light,
+    ambient_intensity: f32,
+    lights: Vec<Light>,
     environment_map: Texture<B>,
+    irradiance_map: Texture<B>,
 }
 
 impl<B: Backend> Environment<B> {
-    pub fn new(ambient_light: Vec3, light: Light, environment_map: Texture<B>) -> Self {
+    pub const DEFAULT_AMBIENT_INTENSITY: f32 = 1.0;
+
+    pub fn new(
+        ambient_light: Vec3,
+        light: Light,
+        environment_map: Texture<B>,
+        irradiance_map: Texture<B>,
+    ) -> Self {
         Self {
             ambient_light,
-            light,
+            ambient_intensity: Self::DEFAULT_AMBIENT_INTENSITY,
+            lights: vec![light],
             environment_map,
+            irradiance_map,
         }
     }
 
+    /// Adds a light that does not cast shadows, e.g. a fill light, usually dimmer than the key
+    /// light and placed opposite of it to brighten the shadowed sides
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    /// Scales the ambient light without changing its tint, the comp pass lights with
+    /// `ambient_light * ambient_intensity * irradiance`
+    pub fn with_ambient_intensity(mut self, ambient_intensity: f32) -> Self {
+        self.set_ambient_intensity(ambient_intensity);
+        self
+    }
+
+    /// The tint of the ambient light
     pub fn ambient_light(&self) -> &Vec3 {
         &self.ambient_light
     }
 
+    pub fn ambient_intensity(&self) -> f32 {
+        self.ambient_intensity
+    }
+
+    pub fn set_ambient_intensity(&mut self, ambient_intensity: f32) {
+        self.ambient_intensity = ambient_intensity
+    }
+
     pub fn environment_map(&self) -> &Texture<B> {
         &self.environment_map
     }
 
+    /// The cosine convolved environment map, see `HdrCubeMapBuilder::irradiance`
+    pub fn irradiance_map(&self) -> &Texture<B> {
+        &self.irradiance_map
+    }
+
+    /// The key light
     pub fn light(&self) -> &Light {
-        &self.light
+        &self.lights[0]
+    }
+
+    pub fn light_mut(&mut self) -> &mut Light {
+        &mut self.lights[0]
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut [Light] {
+        &mut self.lights
     }
 }
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+/// Collects the gpu timestamps written between the passes and prints the average time of every
+/// pass. While it is in the world the graph adds a timestamp before the first pass and behind
+/// every profiled pass, the time between two timestamps is attributed to the pass behind it. The
+/// last section also contains the passes behind the comp pass and the output.
+#[derive(Debug, Clone)]
+pub struct GpuProfiler {
+    report_interval: usize,
+    timestamp_period: f32,
+    labels: Vec<&'static str>,
+    pending: BTreeMap<u64, Vec<Option<u64>>>,
+    interval: Timings,
+    total: Timings,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Timings {
+    frames: usize,
+    /// nanoseconds per section
+    sections: Vec<f64>,
+}
+
+impl Timings {
+    fn add(&mut self, durations: &[f64]) {
+        self.sections.resize(durations.len(), 0.0);
+
+        for (section, duration) in self.sections.iter_mut().zip(durations) {
+            *section += duration;
+        }
+
+        self.frames += 1;
+    }
+
+    fn report(&self, labels: &[&'static str]) -> String {
+        let frames = self.frames.max(1) as f64;
+
+        let sections = labels
+            .iter()
+            .skip(1)
+            .zip(&self.sections)
+            .map(|(label, duration)| format!("{} {:.3} ms", label, duration / frames / 1e6))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let total = self.sections.iter().sum::<f64>() / frames / 1e6;
+
+        format!(
+            "gpu timings over {} frames: {}, total {:.3} ms",
+            self.frames, sections, total
+        )
+    }
+}
+
+impl GpuProfiler {
+    /// Prints the averages every `report_interval` frames
+    pub fn new(report_interval: usize) -> Self {
+        Self {
+            report_interval: report_interval.max(1),
+            timestamp_period: 1.0,
+            labels: vec![],
+            pending: BTreeMap::new(),
+            interval: Timings::default(),
+            total: Timings::default(),
+        }
+    }
+
+    /// The nanoseconds per timestamp tick. The gpu reports the period, but it is not exposed by
+    /// gfx-hal yet, so it has to be given. 1 is right for most desktop gpus.
+    pub fn with_timestamp_period(mut self, timestamp_period: f32) -> Self {
+        self.timestamp_period = timestamp_period;
+        self
+    }
+
+    pub fn timestamp_period(&self) -> f32 {
+        self.timestamp_period
+    }
+
+    pub fn set_timestamp_period(&mut self, timestamp_period: f32) {
+        self.timestamp_period = timestamp_period
+    }
+
+    /// Called when the graph is built, `labels[i]` names the pass in front of timestamp `i`, the
+    /// first label is not reported. The totals are kept if the passes stay the same, e.g. when
+    /// the window was resized.
+    pub fn set_labels(&mut self, labels: Vec<&'static str>) {
+        if self.interval.frames > 0 {
+            println!("{}", self.interval.report(&self.labels));
+        }
+
+        if labels != self.labels {
+            self.total = Timings::default();
+        }
+
+        self.labels = labels;
+        self.pending.clear();
+        self.interval = Timings::default();
+    }
+
+    /// The labels the graph was built with, empty if it was not profiled
+    pub fn labels(&self) -> &[&'static str] {
+        &self.labels
+    }
+
+    /// Records the timestamp `index` of `frame` in ticks
+    pub fn record(&mut self, frame: u64, index: usize, ticks: u64) {
+        let timestamp_count = self.labels.len();
+
+        if index >= timestamp_count {
+            return;
+        }
+
+        let timestamps = self
+            .pending
+            .entry(frame)
+            .or_insert_with(|| vec![None; timestamp_count]);
+
+        timestamps[index] = Some(ticks);
+
+        let timestamps = match timestamps.iter().cloned().collect::<Option<Vec<_>>>() {
+            Some(timestamps) => timestamps,
+            None => return,
+        };
+
+        // every timestamp of the frame arrived, older frames never complete anymore
+        self.pending = self.pending.split_off(&(frame + 1));
+
+        let timestamp_period = self.timestamp_period as f64;
+        let durations = timestamps
+            .windows(2)
+            .map(|ticks| ticks[1].saturating_sub(ticks[0]) as f64 * timestamp_period)
+            .collect::<Vec<_>>();
+
+        self.interval.add(&durations);
+        self.total.add(&durations);
+
+        if self.interval.frames >= self.report_interval {
+            println!("{}", self.interval.report(&self.labels));
+            self.interval = Timings::default();
+        }
+    }
+
+    /// The averages over every profiled frame
+    pub fn summary(&self) -> String {
+        self.total.report(&self.labels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiler() -> GpuProfiler {
+        let mut profiler = GpuProfiler::new(100).with_timestamp_period(2.0);
+        profiler.set_labels(vec!["start", "gbuffer", "comp"]);
+        profiler
+    }
+
+    #[test]
+    fn passes_are_averaged_over_the_complete_frames() {
+        let mut profiler = profiler();
+
+        // 1 and 2 ms of ticks at 2 ns per tick, then 3 and 4 ms
+        for (frame, ticks) in [[0, 500_000, 1_500_000], [0, 1_500_000, 3_500_000]]
+            .iter()
+            .enumerate()
+        {
+            for (index, ticks) in ticks.iter().enumerate() {
+                profiler.record(frame as u64, index, *ticks);
+            }
+        }
+
+        // a frame missing a timestamp does not count
+        profiler.record(2, 0, 0);
+
+        assert_eq!(
+            profiler.summary(),
+            "gpu timings over 2 frames: gbuffer 2.000 ms, comp 3.000 ms, total 5.000 ms"
+        );
+    }
+
+    #[test]
+    fn new_passes_restart_the_totals() {
+        let mut profiler = profiler();
+
+        for (index, ticks) in [0, 500_000, 1_000_000].iter().enumerate() {
+            profiler.record(0, index, *ticks);
+        }
+
+        // unknown timestamps are ignored
+        profiler.record(1, 3, 0);
+
+        profiler.set_labels(vec!["start", "gbuffer", "comp"]);
+        assert!(profiler.summary().starts_with("gpu timings over 1 frames"));
+
+        profiler.set_labels(vec!["start", "gbuffer", "rtsh", "comp"]);
+        assert!(profiler.summary().starts_with("gpu timings over 0 frames"));
+        assert_eq!(profiler.labels(), &["start", "gbuffer", "rtsh", "comp"]);
+    }
+}
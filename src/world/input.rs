@@ -0,0 +1,200 @@
+use nalgebra_glm::{vec2, zero, Vec2};
+use rendy::init::winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+use std::collections::HashSet;
+
+/// Keyboard and mouse state of the window, updated from the winit events of the event loop
+#[derive(Debug)]
+pub struct Input {
+    keys: HashSet<VirtualKeyCode>,
+    pressed_keys: HashSet<VirtualKeyCode>,
+    mouse_buttons: HashSet<MouseButton>,
+    pressed_mouse_buttons: HashSet<MouseButton>,
+    cursor_position: Option<Vec2>,
+    cursor_delta: Vec2,
+    scroll_delta: Vec2,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            keys: HashSet::new(),
+            pressed_keys: HashSet::new(),
+            mouse_buttons: HashSet::new(),
+            pressed_mouse_buttons: HashSet::new(),
+            cursor_position: None,
+            cursor_delta: zero(),
+            scroll_delta: zero(),
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.key_input(*key, *state),
+            WindowEvent::MouseInput { state, button, .. } => self.mouse_input(*button, *state),
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_moved(vec2(position.x as f32, position.y as f32))
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => self.scroll_delta += vec2(*x, *y),
+                MouseScrollDelta::PixelDelta(position) => {
+                    self.scroll_delta += vec2(position.x as f32, position.y as f32)
+                }
+            },
+            WindowEvent::Focused(false) => {
+                self.keys.clear();
+                self.mouse_buttons.clear();
+            }
+            _ => (),
+        }
+    }
+
+    pub(crate) fn key_input(&mut self, key: VirtualKeyCode, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.keys.insert(key) {
+                    self.pressed_keys.insert(key);
+                }
+            }
+            ElementState::Released => {
+                self.keys.remove(&key);
+            }
+        }
+    }
+
+    pub(crate) fn mouse_input(&mut self, button: MouseButton, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.mouse_buttons.insert(button) {
+                    self.pressed_mouse_buttons.insert(button);
+                }
+            }
+            ElementState::Released => {
+                self.mouse_buttons.remove(&button);
+            }
+        }
+    }
+
+    pub(crate) fn cursor_moved(&mut self, position: Vec2) {
+        if let Some(last_position) = &self.cursor_position {
+            self.cursor_delta += &position - last_position;
+        }
+
+        self.cursor_position = Some(position);
+    }
+
+    /// Resets the per frame state. Has to be called after the systems of a frame ran.
+    pub fn end_frame(&mut self) {
+        self.pressed_keys.clear();
+        self.pressed_mouse_buttons.clear();
+        self.cursor_delta = zero();
+        self.scroll_delta = zero();
+    }
+
+    /// Whether the key is held down
+    pub fn key(&self, key: VirtualKeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    /// Whether the key was pressed down this frame
+    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    /// Whether the mouse button is held down
+    pub fn mouse_button(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.contains(&button)
+    }
+
+    /// Whether the mouse button was pressed down this frame
+    pub fn mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.pressed_mouse_buttons.contains(&button)
+    }
+
+    pub fn cursor_position(&self) -> Option<&Vec2> {
+        self.cursor_position.as_ref()
+    }
+
+    pub fn cursor_delta(&self) -> &Vec2 {
+        &self.cursor_delta
+    }
+
+    pub fn scroll_delta(&self) -> &Vec2 {
+        &self.scroll_delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_repeat_is_pressed_once() {
+        let mut input = Input::new();
+
+        input.key_input(VirtualKeyCode::Space, ElementState::Pressed);
+        assert!(input.key(VirtualKeyCode::Space));
+        assert!(input.key_pressed(VirtualKeyCode::Space));
+
+        input.end_frame();
+
+        // a held key repeats the pressed event
+        input.key_input(VirtualKeyCode::Space, ElementState::Pressed);
+        assert!(input.key(VirtualKeyCode::Space));
+        assert!(!input.key_pressed(VirtualKeyCode::Space));
+
+        input.key_input(VirtualKeyCode::Space, ElementState::Released);
+        assert!(!input.key(VirtualKeyCode::Space));
+    }
+
+    #[test]
+    fn press_and_release_in_one_frame() {
+        let mut input = Input::new();
+
+        input.mouse_input(MouseButton::Left, ElementState::Pressed);
+        input.mouse_input(MouseButton::Left, ElementState::Released);
+
+        assert!(!input.mouse_button(MouseButton::Left));
+        assert!(input.mouse_button_pressed(MouseButton::Left));
+
+        input.end_frame();
+        assert!(!input.mouse_button_pressed(MouseButton::Left));
+    }
+
+    #[test]
+    fn cursor_delta_accumulates_per_frame() {
+        let mut input = Input::new();
+
+        // the first position has nothing to move from
+        input.cursor_moved(vec2(10.0, 10.0));
+        input.cursor_moved(vec2(12.0, 9.0));
+        input.cursor_moved(vec2(15.0, 5.0));
+        assert_eq!(input.cursor_delta(), &vec2(5.0, -5.0));
+        assert_eq!(input.cursor_position(), Some(&vec2(15.0, 5.0)));
+
+        input.end_frame();
+        assert_eq!(input.cursor_delta(), &vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let mut input = Input::new();
+
+        input.key_input(VirtualKeyCode::W, ElementState::Pressed);
+        input.mouse_input(MouseButton::Right, ElementState::Pressed);
+        input.handle_event(&WindowEvent::Focused(false));
+
+        assert!(!input.key(VirtualKeyCode::W));
+        assert!(!input.mouse_button(MouseButton::Right));
+    }
+}
@@ -1,13 +1,98 @@
-use nalgebra_glm::Vec3;
+use crate::animation::{Animation, Property, State};
+use crate::ext::transform_point;
+use crate::world::environment::Environment;
+use crate::world::time::{HeadlessTime, Time};
+use legion::prelude::*;
+use nalgebra_glm::{inverse, Mat4, Vec3};
+use rendy::hal::Backend;
 
+/// The space the position of a light is given in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightSpace {
+    /// the light stays fixed in the scene while the camera moves
+    World,
+    /// the light moves with the camera, like a headlight
+    View,
+}
+
+/// How the light falls off with the distance `d`, by `1 / (constant + quadratic * d^2)`. A
+/// `radius` additionally fades the light out smoothly until it reaches zero at that distance,
+/// so the light has a finite reach. Only the shading is attenuated, shadows are cast the same.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub quadratic: f32,
+    pub radius: Option<f32>,
+}
+
+impl Default for Attenuation {
+    /// the plain inverse square law
+    fn default() -> Self {
+        Self {
+            constant: 0.0,
+            quadratic: 1.0,
+            radius: None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Light {
     position: Vec3,
     color: Vec3,
+    intensity: f32,
+    space: LightSpace,
+    attenuation: Attenuation,
 }
 
 impl Light {
     pub fn new(position: Vec3, color: Vec3) -> Self {
-        Light { position, color }
+        Light {
+            position,
+            color,
+            intensity: 1.0,
+            space: LightSpace::World,
+            attenuation: Attenuation::default(),
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_space(mut self, space: LightSpace) -> Self {
+        self.space = space;
+        self
+    }
+
+    pub fn get_space(&self) -> LightSpace {
+        self.space
+    }
+
+    pub fn with_attenuation(mut self, attenuation: Attenuation) -> Self {
+        self.attenuation = attenuation;
+        self
+    }
+
+    pub fn get_attenuation(&self) -> Attenuation {
+        self.attenuation
+    }
+
+    /// The position of the light in view space
+    pub fn view_position(&self, view_matrix: &Mat4) -> Vec3 {
+        match self.space {
+            LightSpace::World => transform_point(&self.position, view_matrix),
+            LightSpace::View => self.position.clone(),
+        }
+    }
+
+    /// The position of the light in world space
+    pub fn world_position(&self, view_matrix: &Mat4) -> Vec3 {
+        match self.space {
+            LightSpace::World => self.position.clone(),
+            LightSpace::View => transform_point(&self.position, &inverse(view_matrix)),
+        }
     }
 
     pub fn get_position(&self) -> &Vec3 {
@@ -17,4 +102,138 @@ impl Light {
     pub fn get_color(&self) -> &Vec3 {
         &self.color
     }
+
+    pub fn get_intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn get_radiance(&self) -> Vec3 {
+        &self.color * self.intensity
+    }
+}
+
+impl Property<LightState> for Light {
+    fn set_property(&mut self, state: LightState) {
+        self.position = state.position;
+        self.color = state.color;
+        self.intensity = state.intensity;
+    }
+}
+
+pub struct LightState {
+    position: Vec3,
+    color: Vec3,
+    intensity: f32,
+}
+
+impl LightState {
+    pub fn new(position: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            position,
+            color,
+            intensity,
+        }
+    }
+
+    pub fn from_light(light: &Light) -> Self {
+        Self::new(light.position.clone(), light.color.clone(), light.intensity)
+    }
+}
+
+impl State for LightState {
+    fn weigth_sum<'a, F: FnMut(usize) -> &'a Self>(mut states: F, factors: &[(usize, f32)]) -> Self
+    where
+        Self: 'a,
+    {
+        Self {
+            position: Vec3::weigth_sum(|index| &(states)(index).position, factors),
+            color: Vec3::weigth_sum(|index| &(states)(index).color, factors),
+            intensity: f32::weigth_sum(|index| &(states)(index).intensity, factors),
+        }
+    }
+}
+
+pub type LightAnimation = Animation<LightState>;
+
+pub fn light_animation_system_realtime<B: Backend>(
+    animation: LightAnimation,
+) -> Box<dyn Schedulable> {
+    SystemBuilder::new("light_animation_system")
+        .read_resource::<Time>()
+        .write_resource::<Environment<B>>()
+        .build(move |_, _, (time, environment), ()| {
+            environment
+                .light_mut()
+                .set_property(animation.interpolate(time.current_frame()))
+        })
+}
+
+pub fn light_animation_system_headless<B: Backend>(
+    animation: LightAnimation,
+) -> Box<dyn Schedulable> {
+    SystemBuilder::new("light_animation_system")
+        .read_resource::<HeadlessTime>()
+        .write_resource::<Environment<B>>()
+        .build(move |_, _, (time, environment), ()| {
+            environment
+                .light_mut()
+                .set_property(animation.interpolate(time.current_frame()))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{DynFactorGenerator, DynLoopingFunction, Frame, Keyframe};
+    use nalgebra_glm::vec3;
+
+    fn animation() -> LightAnimation {
+        Animation::with_times(
+            vec![
+                Keyframe::new(
+                    Frame::new(0.0),
+                    LightState::new(vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 1.0),
+                ),
+                Keyframe::new(
+                    Frame::new(10.0),
+                    LightState::new(vec3(10.0, -4.0, 2.0), vec3(0.0, 1.0, 0.0), 3.0),
+                ),
+            ],
+            DynLoopingFunction::empty(),
+            DynFactorGenerator::smoothstep(),
+        )
+    }
+
+    fn light_at(frame: f32) -> Light {
+        let mut light =
+            Light::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0)).with_space(LightSpace::View);
+        light.set_property(animation().interpolate(Frame::new(frame)));
+        light
+    }
+
+    #[test]
+    fn light_state_interpolates_every_field() {
+        let light = light_at(5.0);
+
+        assert_eq!(light.get_position(), &vec3(5.0, -2.0, 1.0));
+        assert_eq!(light.get_color(), &vec3(0.5, 0.5, 0.0));
+        assert_eq!(light.get_intensity(), 2.0);
+        assert_eq!(light.get_space(), LightSpace::View);
+    }
+
+    #[test]
+    fn light_state_holds_the_keyframes() {
+        for frame in &[-5.0, 0.0] {
+            let light = light_at(*frame);
+            assert_eq!(light.get_position(), &vec3(0.0, 0.0, 0.0));
+            assert_eq!(light.get_intensity(), 1.0);
+        }
+
+        for frame in &[10.0, 20.0] {
+            let light = light_at(*frame);
+            assert_eq!(light.get_position(), &vec3(10.0, -4.0, 2.0));
+            assert_eq!(light.get_color(), &vec3(0.0, 1.0, 0.0));
+            assert_eq!(light.get_intensity(), 3.0);
+        }
+    }
 }
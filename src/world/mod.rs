@@ -19,14 +19,27 @@ use std::ops::{Add, Deref, DerefMut, Index, Mul};
 use std::path::{Iter, Path};
 use std::time::{Duration, Instant};
 
+pub mod analysis;
+pub mod beat;
+pub mod bloom;
 pub mod camera;
 pub mod color_ramp;
 pub mod data;
+pub mod debug_render;
+pub mod depth_of_field;
 pub mod environment;
+pub mod gpu_profiler;
+pub mod input;
 pub mod light;
+pub mod post_effects;
 pub mod resolution;
+pub mod scene;
+pub mod shading;
+pub mod snapshot;
 pub mod sphere;
+pub mod sphere_mesh;
 pub mod time;
+pub mod tonemapping;
 
 pub struct ResWorld {
     pub resources: Resources,
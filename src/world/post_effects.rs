@@ -0,0 +1,46 @@
+/// Effects the comp pass applies to the shaded image, spheres and background alike
+#[derive(Debug, Clone)]
+pub struct PostEffects {
+    vignette_intensity: f32,
+    vignette_radius: f32,
+}
+
+impl PostEffects {
+    pub fn new() -> Self {
+        Self {
+            vignette_intensity: 0.0,
+            vignette_radius: 0.5,
+        }
+    }
+
+    /// Darkens the image towards the corners. `intensity` is how much the corners are darkened,
+    /// 0 disables the vignette and 1 makes them black. `radius` is the distance from the center
+    /// the darkening starts at, where 1 is the distance of the corners, so it has to be below 1.
+    pub fn with_vignette(mut self, intensity: f32, radius: f32) -> Self {
+        self.vignette_intensity = intensity;
+        self.vignette_radius = radius;
+        self
+    }
+
+    pub fn vignette_intensity(&self) -> f32 {
+        self.vignette_intensity
+    }
+
+    pub fn set_vignette_intensity(&mut self, vignette_intensity: f32) {
+        self.vignette_intensity = vignette_intensity
+    }
+
+    pub fn vignette_radius(&self) -> f32 {
+        self.vignette_radius
+    }
+
+    pub fn set_vignette_radius(&mut self, vignette_radius: f32) {
+        self.vignette_radius = vignette_radius
+    }
+}
+
+impl Default for PostEffects {
+    fn default() -> Self {
+        Self::new()
+    }
+}
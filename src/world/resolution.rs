@@ -3,9 +3,14 @@ use rendy::hal::window::Extent2D;
 use rendy::init::winit::dpi::PhysicalSize;
 use rendy::resource::{Extent, Kind};
 
+/// The size everything is rendered at. With a locked aspect ratio it is the largest region of that
+/// aspect ratio fitting into the surface, the surface size is kept as well.
 pub struct Resolution {
     width: u32,
     height: u32,
+    surface_width: u32,
+    surface_height: u32,
+    locked_aspect: Option<f32>,
     changed: ChangeEvent,
 }
 
@@ -14,10 +19,19 @@ impl Resolution {
         Self {
             width,
             height,
+            surface_width: width,
+            surface_height: height,
+            locked_aspect: None,
             changed: ChangeEvent::new(),
         }
     }
 
+    /// See `set_locked_aspect`
+    pub fn with_locked_aspect(mut self, locked_aspect: Option<f32>) -> Self {
+        self.set_locked_aspect(locked_aspect);
+        self
+    }
+
     pub fn from_physical_size(size: PhysicalSize<u32>) -> Self {
         Self::new(size.width, size.height)
     }
@@ -30,7 +44,13 @@ impl Resolution {
         Self::new(extent.width, extent.height)
     }
 
+    /// Sets the size of the surface, the rendered size fits into it if the aspect ratio is locked
     pub fn set(&mut self, width: u32, height: u32) {
+        self.surface_width = width;
+        self.surface_height = height;
+
+        let (width, height) = self.fit(width, height);
+
         if width != self.width || height != self.height {
             self.width = width;
             self.height = height;
@@ -38,6 +58,19 @@ impl Resolution {
         }
     }
 
+    fn fit(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.locked_aspect {
+            Some(aspect) if width > 0 && height > 0 => {
+                if width as f32 / height as f32 > aspect {
+                    (((height as f32 * aspect).round() as u32).max(1), height)
+                } else {
+                    (width, ((width as f32 / aspect).round() as u32).max(1))
+                }
+            }
+            _ => (width, height),
+        }
+    }
+
     pub fn set_from_physical_size(&mut self, size: PhysicalSize<u32>) {
         self.set(size.width, size.height)
     }
@@ -50,6 +83,17 @@ impl Resolution {
         self.set(extent.width, extent.height)
     }
 
+    pub fn locked_aspect(&self) -> Option<f32> {
+        self.locked_aspect
+    }
+
+    /// Keeps the rendered size at the aspect ratio (width / height), `None` renders at the size
+    /// of the surface
+    pub fn set_locked_aspect(&mut self, locked_aspect: Option<f32>) {
+        self.locked_aspect = locked_aspect;
+        self.set(self.surface_width, self.surface_height);
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -58,6 +102,27 @@ impl Resolution {
         self.height
     }
 
+    pub fn surface_width(&self) -> u32 {
+        self.surface_width
+    }
+
+    pub fn surface_height(&self) -> u32 {
+        self.surface_height
+    }
+
+    /// The offset of the rendered region centered in the surface
+    pub fn offset(&self) -> (u32, u32) {
+        (
+            self.surface_width.saturating_sub(self.width) / 2,
+            self.surface_height.saturating_sub(self.height) / 2,
+        )
+    }
+
+    /// True if the surface is larger than the rendered size, because the aspect ratio is locked
+    pub fn letterboxed(&self) -> bool {
+        self.width != self.surface_width || self.height != self.surface_height
+    }
+
     pub fn changed(&self) -> &ChangeEvent {
         &self.changed
     }
@@ -66,3 +131,60 @@ impl Resolution {
         Kind::D2(self.width, self.height, 1, 1)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locked_aspect_fits_the_largest_region_into_the_surface() {
+        let mut resolution = Resolution::new(1920, 1080);
+        assert!(!resolution.letterboxed());
+
+        // a wider surface is pillarboxed
+        resolution.set_locked_aspect(Some(4.0 / 3.0));
+        assert_eq!((resolution.width(), resolution.height()), (1440, 1080));
+        assert_eq!(resolution.offset(), (240, 0));
+        assert!(resolution.letterboxed());
+
+        // a taller surface is letterboxed
+        resolution.set(800, 800);
+        assert_eq!((resolution.width(), resolution.height()), (800, 600));
+        assert_eq!(resolution.offset(), (0, 100));
+        assert_eq!(
+            (resolution.surface_width(), resolution.surface_height()),
+            (800, 800)
+        );
+
+        resolution.set_locked_aspect(None);
+        assert_eq!((resolution.width(), resolution.height()), (800, 800));
+        assert!(!resolution.letterboxed());
+    }
+
+    #[test]
+    fn only_a_changed_rendered_size_is_a_change() {
+        let mut resolution = Resolution::new(800, 800).with_locked_aspect(Some(2.0));
+        let mut state = resolution.changed().register();
+
+        // the surface grows, but the rendered region keeps its size
+        resolution.set(900, 400);
+        assert_eq!((resolution.width(), resolution.height()), (800, 400));
+        assert!(!resolution.changed().has_changed(&mut state));
+
+        resolution.set(1000, 600);
+        assert_eq!((resolution.width(), resolution.height()), (1000, 500));
+        assert!(resolution.changed().has_changed(&mut state));
+    }
+
+    #[test]
+    fn minimized_surfaces_are_not_fitted() {
+        let mut resolution = Resolution::new(800, 600).with_locked_aspect(Some(16.0 / 9.0));
+
+        resolution.set(0, 0);
+        assert_eq!((resolution.width(), resolution.height()), (0, 0));
+
+        // tiny surfaces still render at least a pixel
+        resolution.set(1, 100);
+        assert_eq!((resolution.width(), resolution.height()), (1, 1));
+    }
+}
@@ -0,0 +1,380 @@
+use crate::animation::{Animation, DynFactorGenerator, DynLoopingFunction, Frame, Keyframe};
+use crate::world::color_ramp::ColorRamp;
+use crate::world::light::{Light, LightAnimation, LightState};
+use crate::world::sphere::{LoadMode, SphereLimits};
+use anyhow::Error;
+use nalgebra_glm::{identity, make_vec3, pi, translate, vec3, Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+const DEFAULT_LIGHT_POSITION: [f32; 3] = [-10.0, 10.0, 10.0];
+const DEFAULT_LIGHT_COLOR: [f32; 3] = [400.0, 400.0, 400.0];
+const DEFAULT_AMBIENT: [f32; 3] = [1.0, 1.0, 1.0];
+const DEFAULT_COLOR_RAMP: [[f32; 3]; 6] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0],
+    [0.5, 0.0, 1.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.5, 1.0],
+    [0.0, 0.1, 1.0],
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct CameraDescription {
+    /// column major
+    pub view_matrix: Option<Vec<f32>>,
+    /// the horizontal field of view in radians
+    pub fov: Option<f32>,
+    pub near: Option<f32>,
+    pub far: Option<f32>,
+}
+
+impl CameraDescription {
+    /// Looks at the origin from 10 units away
+    pub fn view_matrix(&self) -> Result<Mat4, Error> {
+        match &self.view_matrix {
+            Some(view_matrix) if view_matrix.len() == 16 => {
+                Ok(Mat4::from_column_slice(view_matrix))
+            }
+            Some(_) => bail!("the view matrix of the scene does not have 16 elements"),
+            None => Ok(translate(&identity(), &vec3(0.0, 0.0, -10.0))),
+        }
+    }
+
+    pub fn fov(&self) -> f32 {
+        self.fov.unwrap_or(pi::<f32>() / 2.0)
+    }
+
+    pub fn near(&self) -> f32 {
+        self.near.unwrap_or(0.1)
+    }
+
+    pub fn far(&self) -> f32 {
+        self.far.unwrap_or(1000.0)
+    }
+}
+
+/// The key light, the fill light is not part of the description
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct LightDescription {
+    pub position: Option<[f32; 3]>,
+    pub color: Option<[f32; 3]>,
+    pub intensity: Option<f32>,
+}
+
+impl LightDescription {
+    pub fn light(&self) -> Light {
+        Light::new(
+            make_vec3(&self.position.unwrap_or(DEFAULT_LIGHT_POSITION)),
+            make_vec3(&self.color.unwrap_or(DEFAULT_LIGHT_COLOR)),
+        )
+        .with_intensity(self.intensity.unwrap_or(1.0))
+    }
+}
+
+/// A keyframe of the key light, the fields it leaves out keep the value of the scene light
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct LightKeyframeDescription {
+    pub frame: f32,
+    pub position: Option<[f32; 3]>,
+    pub color: Option<[f32; 3]>,
+    pub intensity: Option<f32>,
+}
+
+/// Animates the key light through its keyframes, which have to be sorted by their frame
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct LightAnimationDescription {
+    pub keyframes: Vec<LightKeyframeDescription>,
+    /// `smoothstep` (the default) or `cubic`
+    pub interpolation: Option<String>,
+    /// `once` (the default), `repeat` or `ping-pong`, a loop lasts until the last keyframe
+    pub looping: Option<String>,
+}
+
+impl LightAnimationDescription {
+    pub fn light_animation(&self, light: &LightDescription) -> Result<LightAnimation, Error> {
+        let last_frame = match self.keyframes.last() {
+            Some(keyframe) => keyframe.frame,
+            None => bail!("a light animation needs at least one keyframe"),
+        };
+
+        if self
+            .keyframes
+            .windows(2)
+            .any(|keyframes| keyframes[0].frame > keyframes[1].frame)
+        {
+            bail!("the keyframes of the light animation are not sorted by their frame");
+        }
+
+        let factors = match self.interpolation.as_deref() {
+            None | Some("smoothstep") => DynFactorGenerator::smoothstep(),
+            Some("cubic") => DynFactorGenerator::cubic(),
+            Some(name) => bail!("unknown light interpolation {:?}", name),
+        };
+
+        let looping = match self.looping.as_deref() {
+            None | Some("once") => DynLoopingFunction::empty(),
+            Some(_) if last_frame <= 0.0 => {
+                bail!("a looping light animation has to end after frame 0")
+            }
+            Some("repeat") => DynLoopingFunction::repeat(last_frame),
+            Some("ping-pong") => DynLoopingFunction::ping_pong(last_frame),
+            Some(name) => bail!("unknown light looping {:?}", name),
+        };
+
+        let keyframes = self
+            .keyframes
+            .iter()
+            .map(|keyframe| {
+                Keyframe::new(
+                    Frame::new(keyframe.frame),
+                    LightState::new(
+                        make_vec3(
+                            &keyframe
+                                .position
+                                .or(light.position)
+                                .unwrap_or(DEFAULT_LIGHT_POSITION),
+                        ),
+                        make_vec3(
+                            &keyframe
+                                .color
+                                .or(light.color)
+                                .unwrap_or(DEFAULT_LIGHT_COLOR),
+                        ),
+                        keyframe.intensity.or(light.intensity).unwrap_or(1.0),
+                    ),
+                )
+            })
+            .collect();
+
+        Ok(Animation::with_times(keyframes, looping, factors))
+    }
+}
+
+/// A sphere file like the ones given to `--real-time-physics` or `--pre-calculated-physics`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SphereSource {
+    pub path: String,
+    /// whether the file holds the positions along with the radii, like the files of
+    /// `--pre-calculated-physics`
+    #[serde(default)]
+    pub pre_calculated_physics: bool,
+}
+
+impl SphereSource {
+    pub fn load_mode(&self) -> LoadMode {
+        if self.pre_calculated_physics {
+            LoadMode::PositionRadius
+        } else {
+            LoadMode::Radius
+        }
+    }
+}
+
+/// The parts of the scene `application_bundle` otherwise sets up with built in values. Every
+/// field is optional and falls back to the built in value, so a file only has to contain what it
+/// changes. Without a sphere source the spheres are analyzed from the audio.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    pub light: LightDescription,
+    pub ambient: Option<[f32; 3]>,
+    /// linear rgb colors evenly spaced along the ramp, see `ColorRamp::from_json`
+    pub color_ramp: Option<Vec<[f32; 3]>>,
+    pub spheres: Option<SphereSource>,
+    pub light_animation: Option<LightAnimationDescription>,
+    /// the most spheres the nodes allocate their buffers for, see `SphereLimits`
+    pub max_spheres: Option<usize>,
+}
+
+impl SceneDescription {
+    pub fn ambient(&self) -> Vec3 {
+        make_vec3(&self.ambient.unwrap_or(DEFAULT_AMBIENT))
+    }
+
+    pub fn max_spheres(&self) -> usize {
+        self.max_spheres
+            .unwrap_or(SphereLimits::DEFAULT_MAX_SPHERE_COUNT)
+    }
+
+    pub fn color_ramp(&self) -> Result<ColorRamp, Error> {
+        let colors = match &self.color_ramp {
+            Some(colors) if colors.is_empty() => bail!("a color ramp needs at least one color"),
+            Some(colors) => colors.clone(),
+            None => DEFAULT_COLOR_RAMP.to_vec(),
+        };
+
+        Ok(ColorRamp::new(
+            colors.iter().map(|color| make_vec3(color)).collect(),
+        ))
+    }
+
+    /// Fills every missing field with its built in value, so the saved file shows everything
+    /// that can be changed. The sphere source stays as it is, since analyzing has no file.
+    pub fn with_defaults(self) -> Result<Self, Error> {
+        let view_matrix = self.camera.view_matrix()?;
+        let max_spheres = self.max_spheres();
+
+        Ok(Self {
+            camera: CameraDescription {
+                view_matrix: Some(view_matrix.as_slice().to_vec()),
+                fov: Some(self.camera.fov()),
+                near: Some(self.camera.near()),
+                far: Some(self.camera.far()),
+            },
+            light: LightDescription {
+                position: Some(self.light.position.unwrap_or(DEFAULT_LIGHT_POSITION)),
+                color: Some(self.light.color.unwrap_or(DEFAULT_LIGHT_COLOR)),
+                intensity: Some(self.light.intensity.unwrap_or(1.0)),
+            },
+            ambient: Some(self.ambient.unwrap_or(DEFAULT_AMBIENT)),
+            color_ramp: Some(
+                self.color_ramp
+                    .unwrap_or_else(|| DEFAULT_COLOR_RAMP.to_vec()),
+            ),
+            spheres: self.spheres,
+            light_animation: self.light_animation,
+            max_spheres: Some(max_spheres),
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::Property;
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+
+    fn keyframe(frame: f32, intensity: Option<f32>) -> LightKeyframeDescription {
+        LightKeyframeDescription {
+            frame,
+            intensity,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn light_keyframes_fall_back_to_the_scene_light() {
+        let description = LightAnimationDescription {
+            keyframes: vec![keyframe(0.0, None), keyframe(10.0, Some(3.0))],
+            ..Default::default()
+        };
+        let scene_light = LightDescription {
+            color: Some([1.0, 2.0, 3.0]),
+            intensity: Some(5.0),
+            ..Default::default()
+        };
+
+        let animation = description.light_animation(&scene_light).unwrap();
+
+        let mut light = scene_light.light();
+        light.set_property(animation.interpolate(Frame::new(0.0)));
+        assert_eq!(light.get_position(), &make_vec3(&DEFAULT_LIGHT_POSITION));
+        assert_eq!(light.get_color(), &vec3(1.0, 2.0, 3.0));
+        assert_eq!(light.get_intensity(), 5.0);
+
+        light.set_property(animation.interpolate(Frame::new(10.0)));
+        assert_eq!(light.get_intensity(), 3.0);
+    }
+
+    #[test]
+    fn empty_scene_falls_back_to_the_built_in_values() {
+        let scene: SceneDescription = serde_json::from_str("{}").unwrap();
+        assert_eq!(scene, SceneDescription::default());
+
+        assert_eq!(
+            scene.camera.view_matrix().unwrap(),
+            translate(&identity(), &vec3(0.0, 0.0, -10.0))
+        );
+        assert_eq!(scene.camera.fov(), pi::<f32>() / 2.0);
+        assert_eq!(scene.ambient(), make_vec3(&DEFAULT_AMBIENT));
+        assert_eq!(
+            scene.light.light().get_position(),
+            &make_vec3(&DEFAULT_LIGHT_POSITION)
+        );
+        assert!(scene.spheres.is_none());
+
+        let scene: SceneDescription =
+            serde_json::from_str(r#"{"camera": {"view_matrix": [1.0, 0.0]}}"#).unwrap();
+        assert!(scene.camera.view_matrix().is_err());
+
+        let scene: SceneDescription = serde_json::from_str(r#"{"color_ramp": []}"#).unwrap();
+        assert!(scene.color_ramp().is_err());
+    }
+
+    #[test]
+    fn saved_scene_loads_back_with_every_default_filled_in() {
+        let path = temp_dir().join("rendy-sphere-visualizer-scene.json");
+
+        let scene: SceneDescription = serde_json::from_str(
+            r#"{"ambient": [0.5, 0.5, 0.5], "spheres": {"path": "spheres.json"}}"#,
+        )
+        .unwrap();
+        let scene = scene.with_defaults().unwrap();
+        scene.save(&path).unwrap();
+
+        let loaded = SceneDescription::load(&path).unwrap();
+        assert_eq!(loaded, scene);
+        assert_eq!(loaded.ambient, Some([0.5, 0.5, 0.5]));
+        assert_eq!(loaded.camera.fov, Some(pi::<f32>() / 2.0));
+        assert_eq!(loaded.color_ramp, Some(DEFAULT_COLOR_RAMP.to_vec()));
+        assert!(!loaded.spheres.unwrap().pre_calculated_physics);
+
+        // filling in the defaults again changes nothing
+        assert_eq!(scene.clone().with_defaults().unwrap(), scene);
+
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn max_spheres_fall_back_to_the_sphere_limits() {
+        let scene = SceneDescription::default();
+        assert_eq!(scene.max_spheres(), SphereLimits::DEFAULT_MAX_SPHERE_COUNT);
+        assert_eq!(
+            scene.with_defaults().unwrap().max_spheres,
+            Some(SphereLimits::DEFAULT_MAX_SPHERE_COUNT)
+        );
+
+        let scene: SceneDescription = serde_json::from_str(r#"{"max_spheres": 16}"#).unwrap();
+        assert_eq!(scene.max_spheres(), 16);
+    }
+
+    #[test]
+    fn invalid_light_animations_are_rejected() {
+        let light = LightDescription::default();
+
+        let unsorted = LightAnimationDescription {
+            keyframes: vec![keyframe(10.0, None), keyframe(0.0, None)],
+            ..Default::default()
+        };
+        assert!(unsorted.light_animation(&light).is_err());
+
+        let empty = LightAnimationDescription::default();
+        assert!(empty.light_animation(&light).is_err());
+
+        let instant_loop = LightAnimationDescription {
+            keyframes: vec![keyframe(0.0, None)],
+            looping: Some("repeat".to_string()),
+            ..Default::default()
+        };
+        assert!(instant_loop.light_animation(&light).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use nalgebra_glm::{vec3, Vec3};
+
+/// An exposure and a tint the comp pass multiplies a shaded color by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    exposure: f32,
+    tint: Vec3,
+}
+
+impl Tone {
+    pub fn new(exposure: f32, tint: Vec3) -> Self {
+        Self { exposure, tint }
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn tint(&self) -> &Vec3 {
+        &self.tint
+    }
+
+    /// the factor the color is multiplied by
+    pub fn scale(&self) -> Vec3 {
+        self.tint * self.exposure
+    }
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self::new(1.0, vec3(1.0, 1.0, 1.0))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Shading {
+    ambient: Vec3,
+    fresnel_strength: f32,
+    fresnel_f0: Option<f32>,
+    background_tone: Tone,
+    sphere_tone: Tone,
+    occlusion_strength: f32,
+    shadow_strength: f32,
+    roughness: f32,
+}
+
+impl Shading {
+    pub fn new(ambient: Vec3) -> Self {
+        Self {
+            ambient,
+            fresnel_strength: 1.0,
+            fresnel_f0: None,
+            background_tone: Tone::default(),
+            sphere_tone: Tone::default(),
+            occlusion_strength: 1.0,
+            shadow_strength: 1.0,
+            roughness: 0.0,
+        }
+    }
+
+    pub fn with_ambient(mut self, ambient: Vec3) -> Self {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn ambient(&self) -> &Vec3 {
+        &self.ambient
+    }
+
+    pub fn set_ambient(&mut self, ambient: Vec3) {
+        self.ambient = ambient
+    }
+
+    /// scales the fresnel reflectance of the environment
+    pub fn with_fresnel_strength(mut self, fresnel_strength: f32) -> Self {
+        self.fresnel_strength = fresnel_strength;
+        self
+    }
+
+    pub fn fresnel_strength(&self) -> f32 {
+        self.fresnel_strength
+    }
+
+    pub fn set_fresnel_strength(&mut self, fresnel_strength: f32) {
+        self.fresnel_strength = fresnel_strength
+    }
+
+    /// the reflectance at normal incidence used for every sphere instead of the one derived from
+    /// the index of refraction of the sphere
+    pub fn with_fresnel_f0(mut self, fresnel_f0: f32) -> Self {
+        self.fresnel_f0 = Some(fresnel_f0);
+        self
+    }
+
+    pub fn fresnel_f0(&self) -> Option<f32> {
+        self.fresnel_f0
+    }
+
+    pub fn set_fresnel_f0(&mut self, fresnel_f0: Option<f32>) {
+        self.fresnel_f0 = fresnel_f0
+    }
+
+    /// the tone of the pixels not covered by a sphere, e.g. to darken the environment
+    pub fn with_background_tone(mut self, background_tone: Tone) -> Self {
+        self.background_tone = background_tone;
+        self
+    }
+
+    pub fn background_tone(&self) -> &Tone {
+        &self.background_tone
+    }
+
+    pub fn set_background_tone(&mut self, background_tone: Tone) {
+        self.background_tone = background_tone
+    }
+
+    /// the tone of the pixels covered by a sphere
+    pub fn with_sphere_tone(mut self, sphere_tone: Tone) -> Self {
+        self.sphere_tone = sphere_tone;
+        self
+    }
+
+    pub fn sphere_tone(&self) -> &Tone {
+        &self.sphere_tone
+    }
+
+    pub fn set_sphere_tone(&mut self, sphere_tone: Tone) {
+        self.sphere_tone = sphere_tone
+    }
+
+    /// how much of the dfao occlusion is applied, 0 ignores it and 1 applies it fully
+    pub fn with_occlusion_strength(mut self, occlusion_strength: f32) -> Self {
+        self.occlusion_strength = occlusion_strength;
+        self
+    }
+
+    pub fn occlusion_strength(&self) -> f32 {
+        self.occlusion_strength
+    }
+
+    pub fn set_occlusion_strength(&mut self, occlusion_strength: f32) {
+        self.occlusion_strength = occlusion_strength
+    }
+
+    /// how much of the rtsh shadow is applied, 0 ignores it and 1 applies it fully
+    pub fn with_shadow_strength(mut self, shadow_strength: f32) -> Self {
+        self.shadow_strength = shadow_strength;
+        self
+    }
+
+    pub fn shadow_strength(&self) -> f32 {
+        self.shadow_strength
+    }
+
+    pub fn set_shadow_strength(&mut self, shadow_strength: f32) {
+        self.shadow_strength = shadow_strength
+    }
+
+    /// how blurry the environment reflection is, 0 samples the sharpest mip level of the
+    /// environment map and 1 the smallest
+    pub fn with_roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn roughness(&self) -> f32 {
+        self.roughness
+    }
+
+    pub fn set_roughness(&mut self, roughness: f32) {
+        self.roughness = roughness
+    }
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Self::new(vec3(0.2, 0.2, 0.2))
+    }
+}
@@ -0,0 +1,203 @@
+use crate::animation::{Frame, Property};
+use crate::world::camera::Camera;
+use crate::world::data::{PositionData, SphereData};
+use crate::world::environment::Environment;
+use crate::world::light::LightState;
+use crate::world::sphere::{PositionComponent, PositionState, Sphere, SphereState};
+use crate::world::time::{HeadlessTime, Time};
+use crate::world::ResWorld;
+use anyhow::Error;
+use legion::prelude::*;
+use nalgebra_glm::Mat4;
+use rendy::hal::Backend;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CameraData {
+    /// column major
+    pub view_matrix: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LightData {
+    pub position: PositionData,
+    pub color: PositionData,
+    pub intensity: f32,
+}
+
+/// The state of the scene at one moment: the spheres, the camera, the light and the frame the
+/// animations were at. Restoring it into a world that was set up with the same spheres puts the
+/// scene back into that moment, e.g. to render a moment found in the realtime mode headless.
+///
+/// Spheres are matched by the order they are queried in, which is the order they were created
+/// in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub spheres: Vec<SphereData>,
+    pub camera: CameraData,
+    pub light: LightData,
+    pub frame: f32,
+}
+
+impl Snapshot {
+    pub fn capture<B: Backend>(world: &ResWorld) -> Self {
+        let query = <(Read<Sphere>, Read<PositionComponent>)>::query();
+
+        let spheres = query
+            .iter(&world.world)
+            .map(|(sphere, position)| SphereData {
+                radius: sphere.radius(),
+                position: PositionData::from_vec3(&position.0),
+                color: None,
+                n: None,
+            })
+            .collect();
+
+        let camera = world
+            .resources
+            .get::<Camera>()
+            .expect("camera was not inserted into world");
+
+        let environment = world
+            .resources
+            .get::<Environment<B>>()
+            .expect("environment was not inserted into world");
+
+        let light = environment.light();
+
+        Self {
+            spheres,
+            camera: CameraData {
+                view_matrix: camera.get_view_matrix().as_slice().to_vec(),
+            },
+            light: LightData {
+                position: PositionData::from_vec3(light.get_position()),
+                color: PositionData::from_vec3(light.get_color()),
+                intensity: light.get_intensity(),
+            },
+            frame: current_frame(world).frame(),
+        }
+    }
+
+    /// Overwrites the scene state of the world. The systems animating the scene overwrite it
+    /// again the next time they run, so the restored moment is meant to be rendered right away.
+    pub fn restore<B: Backend>(&self, world: &mut ResWorld) -> Result<(), Error> {
+        let query = <(Write<Sphere>, Write<PositionComponent>)>::query();
+
+        let sphere_count = query.iter_mut(&mut world.world).count();
+        if sphere_count != self.spheres.len() {
+            bail!(
+                "the snapshot contains {} spheres, but the world {}",
+                self.spheres.len(),
+                sphere_count
+            );
+        }
+
+        if self.camera.view_matrix.len() != 16 {
+            bail!("the view matrix of the snapshot does not have 16 elements");
+        }
+
+        query
+            .iter_mut(&mut world.world)
+            .zip(&self.spheres)
+            .for_each(|((mut sphere, mut position), data)| {
+                sphere.set_property(SphereState::new(data.radius));
+                position.set_property(PositionState::from_position_data(&data.position));
+            });
+
+        world
+            .resources
+            .get_mut::<Camera>()
+            .expect("camera was not inserted into world")
+            .set_view_matrix(Mat4::from_column_slice(&self.camera.view_matrix));
+
+        world
+            .resources
+            .get_mut::<Environment<B>>()
+            .expect("environment was not inserted into world")
+            .light_mut()
+            .set_property(LightState::new(
+                self.light.position.to_vec3(),
+                self.light.color.to_vec3(),
+                self.light.intensity,
+            ));
+
+        if let Some(mut time) = world.resources.get_mut::<HeadlessTime>() {
+            time.seek(Frame::new(self.frame));
+        }
+
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
+
+fn current_frame(world: &ResWorld) -> Frame {
+    if let Some(time) = world.resources.get::<HeadlessTime>() {
+        return time.current_frame();
+    }
+
+    world
+        .resources
+        .get::<Time>()
+        .map(|time| time.current_frame())
+        .unwrap_or_else(|| Frame::new(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let position = |x, y, z| PositionData { x, y, z };
+
+        Snapshot {
+            spheres: vec![
+                SphereData {
+                    radius: 0.5,
+                    position: position(1.0, -2.0, 3.5),
+                    color: None,
+                    n: None,
+                },
+                SphereData {
+                    radius: 1.25,
+                    position: position(-0.125, 0.0, 7.0),
+                    color: None,
+                    n: None,
+                },
+            ],
+            camera: CameraData {
+                view_matrix: (0..16).map(|i| i as f32 * 0.5).collect(),
+            },
+            light: LightData {
+                position: position(-10.0, 10.0, 10.0),
+                color: position(400.0, 300.0, 200.0),
+                intensity: 0.75,
+            },
+            frame: 123.0,
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+
+        let snapshot = snapshot();
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), snapshot);
+    }
+}
@@ -1,11 +1,15 @@
-use crate::animation::{Animation, Frame, LerpFactorGenerator, LoopEmpty, Property, State};
-use crate::audio::{Envelope, Filter, IIRFilter, SamplesResource};
+use crate::animation::{
+    Animation, Frame, Keyframe, LerpFactorGenerator, LoopEmpty, Property, State,
+};
+use crate::audio::{Agc, Envelope, FftAnalyzer, Filter, IIRFilter, SamplesResource};
 use crate::bundle::{Bundle, BundlePhase1};
+use crate::event::ChangeEvent;
 use crate::physics::{
-    BodyPartHandleComponent, ColliderHandleComponent, DefaultBodyPartHandleComponent,
+    BodyPartHandleComponent, ColliderHandleComponent, Collisions, DefaultBodyPartHandleComponent,
     DefaultColliderHandleComponent, DefaultForceGeneratorHandleComponent,
 };
-use crate::world::data::{PositionData, SphereData};
+use crate::world::analysis::{analysis_log_system, AnalysisLog, AnalysisValue};
+use crate::world::data::{FrameData, PositionData, SphereData};
 use crate::world::time::{HeadlessTime, Time};
 use crate::world::ResWorld;
 use crate::Mode;
@@ -15,7 +19,7 @@ use legion::prelude::*;
 use legion::storage::Component;
 use legion::systems::schedule::Builder;
 use nalgebra::{Isometry3, Translation, UnitQuaternion};
-use nalgebra_glm::{vec3, Vec3};
+use nalgebra_glm::{make_vec3, vec3, Vec3};
 use ncollide3d::shape::{Ball, ShapeHandle};
 use nphysics3d::algebra::{Force3, ForceType};
 use nphysics3d::force_generator::{DefaultForceGeneratorSet, ForceGenerator};
@@ -26,88 +30,374 @@ use nphysics3d::object::{
 };
 use nphysics3d::solver::IntegrationParameters;
 use nphysics3d::world::DefaultMechanicalWorld;
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, RngCore, SeedableRng};
 use serde::export::PhantomData;
+use serde::Serialize;
+use std::f32::consts::PI;
 use std::fs::File;
 use std::io::BufReader;
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 type DynFilter = Box<dyn Filter + Send + Sync>;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Serialize)]
 pub enum LoadMode {
     PositionRadius,
     Radius,
 }
 
+/// The radii the spheres of the analyze mode rest at while the audio is silent, by band. The
+/// analysis can only grow a sphere beyond its rest radius.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum RestShape {
+    /// every sphere rests at the minimum radius
+    Flat,
+    /// A gaussian hump across the bands. `center` and `width` are in band space, where 0 is the
+    /// lowest and 1 the highest band. `height` is added to the minimum radius at the peak.
+    Gaussian {
+        center: f32,
+        width: f32,
+        height: f32,
+    },
+}
+
+impl RestShape {
+    pub fn radius(&self, min_radius: f32, band: usize, band_count: usize) -> f32 {
+        match *self {
+            RestShape::Flat => min_radius,
+            RestShape::Gaussian {
+                center,
+                width,
+                height,
+            } => {
+                let x = if band_count > 1 {
+                    band as f32 / (band_count - 1) as f32
+                } else {
+                    0.5
+                };
+
+                min_radius + height * (-(x - center).powi(2) / (2.0 * width * width)).exp()
+            }
+        }
+    }
+}
+
+/// The logarithmic split of the spectrum into one band per sphere. Band `i` spans
+/// `low * multiplier^i` to `low * multiplier^(i + 1)`, widened by `overlap` bands on both sides.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BandSplit {
+    pub low: f32,
+    /// the upper end of the last band, only used to derive the multiplier if none is given
+    pub high: f32,
+    pub multiplier: Option<f32>,
+    pub overlap: f32,
+}
+
+impl BandSplit {
+    /// Bands are clamped below the nyquist frequency, the filters degenerate at it
+    pub fn band(&self, band: usize, band_count: usize, sample_rate: f32) -> Band {
+        let multiplier = self
+            .multiplier
+            .unwrap_or_else(|| (self.high / self.low).powf(1.0 / band_count as f32));
+
+        let max_frequency = sample_rate * 0.49;
+
+        let high =
+            (self.low * multiplier.powf(band as f32 + 1.0 + self.overlap)).min(max_frequency);
+        let low = (self.low * multiplier.powf(band as f32 - self.overlap)).min(high);
+
+        Band { low, high }
+    }
+}
+
+/// See `Agc`
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct AgcParams {
+    pub target: f32,
+    pub adaptation: f32,
+}
+
+impl AgcParams {
+    pub fn agc(&self, sample_rate: f32) -> Agc<()> {
+        Agc::new((), self.target, self.adaptation, sample_rate)
+    }
+}
+
+/// How the analyze mode splits the audio into the bands of the spheres
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum Analyzer {
+    /// a band pass and an envelope per sphere, ticked for every sample
+    Filter,
+    /// one fft over the last `size` samples per frame, `size` has to be a power of two
+    Fft { size: usize },
+}
+
+/// Where the spheres are placed at the start, or kept without physics. Every layout places the
+/// spheres in index order, so neighbouring bands of the analyze mode stay next to each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Layout {
+    /// a horizontal line
+    Line,
+    /// rows running back and forth, so the end of a row is next to the start of the next one
+    Grid,
+    Circle,
+    /// an archimedean spiral from the center outwards
+    Spiral,
+}
+
+impl Layout {
+    /// The spheres span about 16 units in every layout
+    const SIZE: f32 = 16.0;
+    const SPIRAL_TURNS: f32 = 3.0;
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "line" => Some(Layout::Line),
+            "grid" => Some(Layout::Grid),
+            "circle" => Some(Layout::Circle),
+            "spiral" => Some(Layout::Spiral),
+            _ => None,
+        }
+    }
+
+    pub fn position(&self, i: usize, sphere_count: usize) -> Vec3 {
+        match self {
+            Layout::Line => {
+                let offset = (sphere_count - 1) as f32 * 0.5;
+                let factor = Self::SIZE / sphere_count as f32;
+
+                vec3((i as f32 - offset) * factor, 0.0, 0.0)
+            }
+            Layout::Grid => {
+                let columns = (sphere_count as f32).sqrt().ceil() as usize;
+                let rows = (sphere_count + columns - 1) / columns;
+                let factor = Self::SIZE / columns as f32;
+
+                let row = i / columns;
+                let column = if row % 2 == 0 {
+                    i % columns
+                } else {
+                    columns - 1 - i % columns
+                };
+
+                vec3(
+                    (column as f32 - (columns - 1) as f32 * 0.5) * factor,
+                    ((rows - 1) as f32 * 0.5 - row as f32) * factor,
+                    0.0,
+                )
+            }
+            Layout::Circle => {
+                let angle = i as f32 / sphere_count as f32 * 2.0 * PI;
+
+                vec3(-angle.cos(), angle.sin(), 0.0) * Self::SIZE * 0.5
+            }
+            Layout::Spiral => {
+                // the square root spaces the spheres evenly along the spiral
+                let t = if sphere_count > 1 {
+                    (i as f32 / (sphere_count - 1) as f32).sqrt()
+                } else {
+                    0.0
+                };
+                let angle = t * Self::SPIRAL_TURNS * 2.0 * PI;
+
+                vec3(-angle.cos(), angle.sin(), 0.0) * t * Self::SIZE * 0.5
+            }
+        }
+    }
+}
+
+/// The parts of `SphereBundleParams::Analyze` a single sphere of the analyze mode is built from
+#[derive(Clone, Copy)]
+struct AnalysisParams {
+    min_radius: f32,
+    rest_shape: RestShape,
+    band_split: BandSplit,
+    stereo: bool,
+    agc: Option<AgcParams>,
+    attack: f32,
+    release: f32,
+    threshold: f32,
+    sample_rate: f32,
+}
+
+impl AnalysisParams {
+    fn rest_radius(&self, i: usize, sphere_count: usize) -> f32 {
+        self.rest_shape.radius(self.min_radius, i, sphere_count)
+    }
+
+    /// The components of sphere `i` out of `sphere_count`, without its position
+    fn components(
+        &self,
+        i: usize,
+        sphere_count: usize,
+    ) -> (
+        Sphere,
+        DynFilter,
+        Band,
+        AudioChannel,
+        AnalysisValue,
+        RestRadius,
+    ) {
+        let sample_rate = self.sample_rate;
+
+        let rest_radius = self.rest_radius(i, sphere_count);
+
+        let band = self.band_split.band(i, sphere_count, sample_rate);
+
+        let agc = self.agc.map(|agc| agc.agc(sample_rate));
+
+        let low_pass = IIRFilter::low_pass(agc, band.high, 1.0, sample_rate);
+
+        let high_pass = IIRFilter::high_pass(low_pass, band.low, 1.0, sample_rate);
+
+        let envelope = Envelope::new(
+            high_pass,
+            self.threshold,
+            self.attack,
+            self.release,
+            sample_rate,
+        );
+
+        let filter: DynFilter = Box::new(envelope);
+
+        (
+            Sphere::new(rest_radius),
+            filter,
+            band,
+            AudioChannel(if self.stereo { i % 2 } else { 0 }),
+            AnalysisValue(0.0),
+            RestRadius(rest_radius),
+        )
+    }
+}
+
 pub enum SphereBundleParams<P> {
     Load {
         path: P,
         load_mode: LoadMode,
         mode: Mode,
+        spring_factor: Option<f32>,
+        /// the fraction of the velocity the `DragSpring` cancels every step
+        damping: f32,
+        /// seeds the jitter of the physics layout, `None` jitters differently every run
+        seed: Option<u64>,
+        layout: Layout,
     },
     Analyze {
         sphere_count: usize,
         min_radius: f32,
-        low: f32,
-        high: f32,
+        rest_shape: RestShape,
+        analyzer: Analyzer,
+        band_split: BandSplit,
+        stereo: bool,
+        agc: Option<AgcParams>,
         attack: f32,
         release: f32,
         threshold: f32,
+        /// the fraction of the last radius kept every frame, 0 follows the analysis directly
+        smoothing: f32,
         sample_rate: f32,
+        analysis_log: Option<PathBuf>,
+        spring_factor: Option<f32>,
+        /// the fraction of the velocity the `DragSpring` cancels every step
+        damping: f32,
+        /// seeds the jitter of the physics layout, `None` jitters differently every run
+        seed: Option<u64>,
+        layout: Layout,
     },
 }
 
 pub struct SphereBundle<P> {
     params: SphereBundleParams<P>,
+    physics: bool,
+    max_sphere_count: usize,
 }
 
 impl<P: AsRef<Path>> SphereBundle<P> {
     pub fn new(params: SphereBundleParams<P>) -> Self {
-        Self { params }
+        Self {
+            params,
+            physics: true,
+            max_sphere_count: SphereLimits::DEFAULT_MAX_SPHERE_COUNT,
+        }
+    }
+
+    /// Without physics the spheres of the radius loading and the analyze mode stay at their
+    /// layout positions and only their radii change, so every run moves the same. The physics
+    /// resources of `PhysicsBundle` are not needed then.
+    pub fn with_physics(mut self, physics: bool) -> Self {
+        self.physics = physics;
+        self
     }
 
+    /// More spheres than `max_sphere_count` are rejected, see `SphereLimits`
+    pub fn with_max_sphere_count(mut self, max_sphere_count: usize) -> Self {
+        self.max_sphere_count = max_sphere_count;
+        self
+    }
+
+    /// The position of a sphere keyed from the positions loaded for it. Only
+    /// `LoadMode::PositionRadius` reads them, the other modes place the spheres by the configured
+    /// `Layout`.
     fn position_animation(
         data: &[SphereData],
+        times: &[f32],
     ) -> (
         PositionComponent,
         Animation<PositionState, LoopEmpty, LerpFactorGenerator>,
     ) {
         let position = PositionComponent::from_position_data(&data[0].position);
-        let position_states = data
+        let position_keyframes = data
             .iter()
-            .map(|sphere_data| PositionState::from_position_data(&sphere_data.position))
+            .zip(times)
+            .map(|(sphere_data, time)| {
+                Keyframe::new(
+                    Frame::new(*time),
+                    PositionState::from_position_data(&sphere_data.position),
+                )
+            })
             .collect::<Vec<_>>();
 
         let position_animation =
-            Animation::without_times(position_states, LoopEmpty, LerpFactorGenerator);
+            Animation::with_times(position_keyframes, LoopEmpty, LerpFactorGenerator);
 
         (position, position_animation)
     }
 
     fn sphere_animation(
         data: &[SphereData],
+        times: &[f32],
     ) -> (
         Sphere,
         Animation<SphereState, LoopEmpty, LerpFactorGenerator>,
     ) {
         let sphere = Sphere::new(data[0].radius);
-        let sphere_states = data
+        let sphere_keyframes = data
             .iter()
-            .map(|sphere_data| SphereState::new(sphere_data.radius))
+            .zip(times)
+            .map(|(sphere_data, time)| {
+                Keyframe::new(Frame::new(*time), SphereState::new(sphere_data.radius))
+            })
             .collect::<Vec<_>>();
 
         let sphere_animation =
-            Animation::without_times(sphere_states, LoopEmpty, LerpFactorGenerator);
+            Animation::with_times(sphere_keyframes, LoopEmpty, LerpFactorGenerator);
 
         (sphere, sphere_animation)
     }
 
+    /// A `spring_factor` of `None` picks `SPRING_FACTOR` or `COLLIDING_SPRING_FACTOR` depending
+    /// on whether the spheres collide. The same `seed` always jitters the layout the same way.
     fn sphere_physics<'a, F: 'a + FnMut(usize) -> f32>(
         world: &'a mut ResWorld,
         limits: &SphereLimits,
+        spring_factor: Option<f32>,
+        damping: f32,
+        seed: Option<u64>,
+        layout: Layout,
         mut radius: F,
     ) -> impl 'a
            + Iterator<
@@ -119,10 +409,10 @@ impl<P: AsRef<Path>> SphereBundle<P> {
             DefaultForceGeneratorHandleComponent,
         ),
     > {
-        let mut rng = thread_rng();
-
-        let offset = (limits.sphere_count() - 1) as f32 * 0.5;
-        let factor = 16.0 / limits.sphere_count() as f32;
+        let mut rng: Box<dyn RngCore> = match seed {
+            Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+            None => Box::new(thread_rng()),
+        };
 
         let mut body_set = world
             .resources
@@ -139,71 +429,61 @@ impl<P: AsRef<Path>> SphereBundle<P> {
             .get_mut::<DefaultForceGeneratorSet<f32>>()
             .expect("force generator set was not inserted into world");
 
-        (0..limits.sphere_count()).map(move |i| {
-            let position = PositionComponent(vec3(
-                (i as f32 - offset) * factor,
-                rng.gen_range(-0.05, 0.05),
-                rng.gen_range(-0.05, 0.05),
-            ));
-
-            let rigid_body = RigidBodyDesc::<f32>::new()
-                .translation(position.0.clone())
-                .gravity_enabled(false)
-                .status(BodyStatus::Dynamic)
-                .build();
-
-            let rigid_body_handle = BodyPartHandle(body_set.insert(rigid_body), 0);
-
-            let shape_handle = ShapeHandle::<f32>::new(Ball::new((radius)(i)));
-            let collider = ColliderDesc::new(shape_handle)
-                .density(2190.0)
-                .build(rigid_body_handle);
-
-            let collider_handle = collider_set.insert(collider);
-
-            let force_generator = DragSpring::new(rigid_body_handle, position.0.clone(), 0.1);
-
-            let force_generator_handle = force_generator_set.insert(Box::new(force_generator));
-
-            (
-                i,
-                position,
-                BodyPartHandleComponent(rigid_body_handle),
-                ColliderHandleComponent(collider_handle),
-                DefaultForceGeneratorHandleComponent(force_generator_handle),
-            )
+        let collisions = world
+            .resources
+            .get::<Collisions>()
+            .map(|collisions| *collisions)
+            .unwrap_or(Collisions(false));
+
+        let spring_factor = spring_factor.unwrap_or_else(|| collisions.spring_factor());
+
+        let sphere_count = limits.sphere_count();
+
+        (0..sphere_count).map(move |i| {
+            // the jitter keeps the spheres from lining up perfectly once they collide
+            let position = PositionComponent(
+                layout.position(i, sphere_count)
+                    + vec3(0.0, rng.gen_range(-0.05, 0.05), rng.gen_range(-0.05, 0.05)),
+            );
+
+            let (rigid_body, collider, force_generator) = sphere_body(
+                &mut body_set,
+                &mut collider_set,
+                &mut force_generator_set,
+                &collisions,
+                spring_factor,
+                damping,
+                &position,
+                (radius)(i),
+            );
+
+            (i, position, rigid_body, collider, force_generator)
         })
     }
 
-    fn update_physics_handles(world: &mut ResWorld, entities: &[Entity]) {
-        let mut body_set = world
-            .resources
-            .get_mut::<DefaultBodySet<f32>>()
-            .expect("body set was not inserted into world");
-
-        let mut collider_set = world
-            .resources
-            .get_mut::<DefaultColliderSet<f32>>()
-            .expect("body set was not inserted into world");
-
-        for entity in entities {
-            if let Some(rigid_body_handle) =
-                world.get_component::<DefaultBodyHandle>(entity.clone())
-            {
-                if let Some(rigid_body) = body_set.rigid_body_mut(rigid_body_handle.deref().clone())
-                {
-                    rigid_body.set_user_data(Some(Box::new(entity.clone())));
-                }
+    /// Gives the spheres with a color in the loaded file a `ColorComponent` and the ones with an
+    /// index of refraction a `Material`. A sphere keeps the first value given for it, neither is
+    /// animated.
+    fn add_appearance(
+        world: &mut ResWorld,
+        entities: &[Entity],
+        transposed_data: &[Vec<SphereData>],
+    ) -> Result<(), Error> {
+        for (entity, data) in entities.iter().zip(transposed_data) {
+            if let Some(color) = data.iter().find_map(|sphere_data| sphere_data.color) {
+                world
+                    .add_component(entity.clone(), ColorComponent(make_vec3(&color)))
+                    .map_err(|err| anyhow!("could not add the color of a sphere: {:?}", err))?;
             }
 
-            if let Some(collider_handle) =
-                world.get_component::<DefaultColliderHandle>(entity.clone())
-            {
-                if let Some(collider) = collider_set.get_mut(collider_handle.deref().clone()) {
-                    collider.set_user_data(Some(Box::new(entity.clone())));
-                }
+            if let Some(n) = data.iter().find_map(|sphere_data| sphere_data.n) {
+                world
+                    .add_component(entity.clone(), Material::new(n))
+                    .map_err(|err| anyhow!("could not add the material of a sphere: {:?}", err))?;
             }
         }
+
+        Ok(())
     }
 }
 
@@ -211,31 +491,51 @@ impl<P: AsRef<Path>> Bundle for SphereBundle<P> {
     type Phase1 = SphereBundlePhase1;
 
     fn add_entities_and_resources(self, world: &mut ResWorld) -> Result<Self::Phase1, Error> {
-        match self.params {
+        let SphereBundle {
+            params,
+            physics,
+            max_sphere_count,
+        } = self;
+
+        match params {
             SphereBundleParams::Load {
                 path,
                 load_mode,
                 mode,
+                spring_factor,
+                damping,
+                seed,
+                layout,
             } => {
-                match &mode {
-                    Mode::Realtime => {
-                        let time = Time::new(60.0);
+                let data: Vec<FrameData> =
+                    serde_json::from_reader(BufReader::new(File::open(path.as_ref())?))?;
 
-                        world.resources.insert(time);
-                    }
-                    Mode::Headless => {
-                        let time = HeadlessTime::new(Frame::new(0.0));
+                let times = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, frame_data)| frame_data.frame(i))
+                    .collect::<Vec<_>>();
 
-                        world.resources.insert(time);
-                    }
+                if times.windows(2).any(|times| times[0] >= times[1]) {
+                    bail!("the frames of the loaded animation are not in increasing order");
                 }
 
-                let data: Vec<Vec<SphereData>> =
-                    serde_json::from_reader(BufReader::new(File::open(path.as_ref())?))?;
+                let data = data
+                    .into_iter()
+                    .map(FrameData::into_spheres)
+                    .collect::<Vec<_>>();
 
                 let sphere_count = data.iter().map(|i| i.len()).max().unwrap();
 
-                let limits = SphereLimits::new(sphere_count, Some(data.len()));
+                // the animation ends at its last keyframe, which is the frame count for untimed
+                // frames
+                let frame_count = times.last().map_or(0, |time| time.ceil() as usize + 1);
+
+                let limits = SphereLimits::with_max_sphere_count(
+                    sphere_count,
+                    Some(frame_count),
+                    max_sphere_count,
+                )?;
 
                 let mut transposed_data: Vec<Vec<SphereData>> = vec![];
 
@@ -248,91 +548,164 @@ impl<P: AsRef<Path>> Bundle for SphereBundle<P> {
                     }
                 }
 
-                match &load_mode {
-                    LoadMode::PositionRadius => {
-                        world.insert(
+                let entities = match &load_mode {
+                    LoadMode::PositionRadius => world
+                        .insert(
                             (),
-                            transposed_data.into_iter().map(|data| {
+                            transposed_data.iter().map(|data| {
                                 let (position, position_animation) =
-                                    Self::position_animation(&data);
+                                    Self::position_animation(data, &times);
 
-                                let (sphere, sphere_animation) = Self::sphere_animation(&data);
+                                let (sphere, sphere_animation) =
+                                    Self::sphere_animation(data, &times);
 
                                 (position, position_animation, sphere, sphere_animation)
                             }),
-                        );
+                        )
+                        .to_vec(),
+                    LoadMode::Radius if !physics => {
+                        let sphere_count = limits.sphere_count();
+
+                        world
+                            .insert(
+                                (),
+                                transposed_data.iter().enumerate().map(|(i, data)| {
+                                    let position =
+                                        PositionComponent(layout.position(i, sphere_count));
+
+                                    let (sphere, sphere_animation) =
+                                        Self::sphere_animation(data, &times);
+
+                                    (sphere, sphere_animation, position)
+                                }),
+                            )
+                            .to_vec()
                     }
                     LoadMode::Radius => {
                         let entity_data = {
-                            Self::sphere_physics(world, &limits, |i| transposed_data[i][0].radius)
-                                .zip(&transposed_data)
-                                .map(
-                                    |(
-                                        (_, position, rigid_body, collider, force_generator),
-                                        data,
-                                    )| {
-                                        let (sphere, sphere_animation) =
-                                            Self::sphere_animation(data);
-
-                                        (
-                                            sphere,
-                                            sphere_animation,
-                                            position,
-                                            rigid_body,
-                                            collider,
-                                            force_generator,
-                                        )
-                                    },
-                                )
-                                .collect::<Vec<_>>()
+                            Self::sphere_physics(
+                                world,
+                                &limits,
+                                spring_factor,
+                                damping,
+                                seed,
+                                layout,
+                                |i| transposed_data[i][0].radius,
+                            )
+                            .zip(&transposed_data)
+                            .map(
+                                |((_, position, rigid_body, collider, force_generator), data)| {
+                                    let (sphere, sphere_animation) =
+                                        Self::sphere_animation(data, &times);
+
+                                    (
+                                        sphere,
+                                        sphere_animation,
+                                        position,
+                                        rigid_body,
+                                        collider,
+                                        force_generator,
+                                    )
+                                },
+                            )
+                            .collect::<Vec<_>>()
                         };
 
                         let entities = world.insert((), entity_data).to_vec();
 
-                        Self::update_physics_handles(world, &entities)
+                        update_physics_handles(world, &entities);
+
+                        entities
                     }
-                }
+                };
+
+                Self::add_appearance(world, &entities, &transposed_data)?;
 
                 world.resources.insert(limits);
 
+                world.resources.insert(SphereTemplate {
+                    spheres: TemplateSpheres::Load {
+                        load_mode,
+                        radius: transposed_data.last().map(|data| data[0].radius),
+                    },
+                    // the loaded positions of `LoadMode::PositionRadius` replace the physics
+                    physics: match load_mode {
+                        LoadMode::PositionRadius => false,
+                        LoadMode::Radius => physics,
+                    },
+                    spring_factor,
+                    damping,
+                    layout,
+                });
+
                 Ok(SphereBundlePhase1 {
-                    params: SphereBundlePhase1Params::Load { mode, load_mode },
+                    params: SphereBundlePhase1Params::Load {
+                        mode,
+                        load_mode,
+                        physics,
+                    },
                 })
             }
             SphereBundleParams::Analyze {
                 sphere_count,
                 min_radius,
-                low,
-                high,
+                rest_shape,
+                analyzer,
+                band_split,
+                stereo,
+                agc,
                 attack,
                 release,
                 threshold,
+                smoothing,
                 sample_rate,
+                analysis_log,
+                spring_factor,
+                damping,
+                seed,
+                layout,
             } => {
-                let limits = SphereLimits::new(sphere_count, None);
+                let limits =
+                    SphereLimits::with_max_sphere_count(sphere_count, None, max_sphere_count)?;
+
+                let analysis = AnalysisParams {
+                    min_radius,
+                    rest_shape,
+                    band_split,
+                    stereo,
+                    agc,
+                    attack,
+                    release,
+                    threshold,
+                    sample_rate,
+                };
 
-                let entity_data = {
-                    Self::sphere_physics(world, &limits, |_| min_radius)
+                let sphere_count = limits.sphere_count();
+                let rest_radius = move |i| analysis.rest_radius(i, sphere_count);
+                let analysis_components = |i| analysis.components(i, sphere_count);
+
+                if physics {
+                    let entity_data = {
+                        Self::sphere_physics(
+                            world,
+                            &limits,
+                            spring_factor,
+                            damping,
+                            seed,
+                            layout,
+                            rest_radius,
+                        )
                         .map(|(i, position, rigid_body, collider, force_generator)| {
-                            let sphere = Sphere::new(min_radius);
-
-                            let exponent = (high / low).powf(1.0 / limits.sphere_count() as f32);
-                            let low_cutoff = low * exponent.powf(i as f32);
-                            let high_cutoff = low * exponent.powf((i + 1) as f32);
-
-                            let low_pass = IIRFilter::low_pass((), high_cutoff, 1.0, sample_rate);
-
-                            let high_pass =
-                                IIRFilter::high_pass(low_pass, low_cutoff, 1.0, sample_rate);
-
-                            let envelope =
-                                Envelope::new(high_pass, threshold, attack, release, sample_rate);
-
-                            let filter: DynFilter = Box::new(envelope);
+                            let (sphere, filter, band, channel, analysis_value, rest_radius) =
+                                analysis_components(i);
 
                             (
                                 sphere,
                                 filter,
+                                band,
+                                channel,
+                                analysis_value,
+                                rest_radius,
                                 position,
                                 rigid_body,
                                 collider,
@@ -340,17 +713,74 @@ impl<P: AsRef<Path>> Bundle for SphereBundle<P> {
                             )
                         })
                         .collect::<Vec<_>>()
-                };
+                    };
+
+                    let entities = world.insert((), entity_data).to_vec();
 
-                let entities = world.insert((), entity_data).to_vec();
+                    update_physics_handles(world, &entities);
+                } else {
+                    world.insert(
+                        (),
+                        (0..sphere_count).map(|i| {
+                            let (sphere, filter, band, channel, analysis_value, rest_radius) =
+                                analysis_components(i);
 
-                Self::update_physics_handles(world, &entities);
+                            let position = PositionComponent(layout.position(i, sphere_count));
+
+                            (
+                                sphere,
+                                filter,
+                                band,
+                                channel,
+                                analysis_value,
+                                rest_radius,
+                                position,
+                            )
+                        }),
+                    );
+                }
 
                 world.resources.insert(limits);
 
+                world.resources.insert(SphereTemplate {
+                    spheres: TemplateSpheres::Analyze(analysis),
+                    physics,
+                    spring_factor,
+                    damping,
+                    layout,
+                });
+
+                let fft = match analyzer {
+                    Analyzer::Filter => false,
+                    Analyzer::Fft { size } => {
+                        let channels = if stereo { 2 } else { 1 };
+
+                        let analyzers = (0..channels)
+                            .map(|_| {
+                                Ok(FftAnalyzer::new(size, sample_rate)?
+                                    .with_agc(agc.map(|agc| agc.agc(sample_rate))))
+                            })
+                            .collect::<Result<Vec<_>, Error>>()?;
+
+                        world.resources.insert(analyzers);
+                        true
+                    }
+                };
+
+                let log = match analysis_log {
+                    Some(path) => {
+                        world.resources.insert(AnalysisLog::create(path)?);
+                        true
+                    }
+                    None => false,
+                };
+
                 Ok(SphereBundlePhase1 {
                     params: SphereBundlePhase1Params::Analyze {
-                        min_size: min_radius,
+                        log,
+                        fft,
+                        smoothing,
+                        physics,
                     },
                 })
             }
@@ -359,8 +789,17 @@ impl<P: AsRef<Path>> Bundle for SphereBundle<P> {
 }
 
 pub enum SphereBundlePhase1Params {
-    Load { mode: Mode, load_mode: LoadMode },
-    Analyze { min_size: f32 },
+    Load {
+        mode: Mode,
+        load_mode: LoadMode,
+        physics: bool,
+    },
+    Analyze {
+        log: bool,
+        fft: bool,
+        smoothing: f32,
+        physics: bool,
+    },
 }
 
 pub struct SphereBundlePhase1 {
@@ -373,6 +812,7 @@ impl BundlePhase1 for SphereBundlePhase1 {
             SphereBundlePhase1Params::Load {
                 mode: Mode::Realtime,
                 load_mode: LoadMode::PositionRadius,
+                ..
             } => {
                 builder =
                     builder.add_system(sphere_animation_system_realtime::<Sphere, SphereState>());
@@ -384,6 +824,7 @@ impl BundlePhase1 for SphereBundlePhase1 {
             SphereBundlePhase1Params::Load {
                 mode: Mode::Headless,
                 load_mode: LoadMode::PositionRadius,
+                ..
             } => {
                 builder =
                     builder.add_system(sphere_animation_system_headless::<Sphere, SphereState>());
@@ -395,6 +836,7 @@ impl BundlePhase1 for SphereBundlePhase1 {
             SphereBundlePhase1Params::Load {
                 mode,
                 load_mode: LoadMode::Radius,
+                physics,
             } => {
                 match mode {
                     Mode::Realtime => {
@@ -407,12 +849,31 @@ impl BundlePhase1 for SphereBundlePhase1 {
                     }
                 }
 
-                builder = builder.add_system(sphere_shape_system());
+                if physics {
+                    builder = builder.add_system(sphere_shape_system());
+                }
             }
-            SphereBundlePhase1Params::Analyze { min_size } => {
-                builder = builder
-                    .add_system(sphere_analyzer_system(min_size))
-                    .add_system(sphere_shape_system())
+            SphereBundlePhase1Params::Analyze {
+                log,
+                fft,
+                smoothing,
+                physics,
+            } => {
+                let analyzer_system = if fft {
+                    sphere_fft_system(smoothing)
+                } else {
+                    sphere_analyzer_system(smoothing)
+                };
+
+                builder = builder.add_system(analyzer_system);
+
+                if physics {
+                    builder = builder.add_system(sphere_shape_system());
+                }
+
+                if log {
+                    builder = builder.add_system(analysis_log_system());
+                }
             }
         };
 
@@ -424,6 +885,21 @@ pub struct Sphere {
     radius: f32,
 }
 
+/// The channel of the captured samples the analyze mode feeds a sphere from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioChannel(pub usize);
+
+/// The frequencies the analyze mode maps to a sphere
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub low: f32,
+    pub high: f32,
+}
+
+/// The radius a sphere of the analyze mode does not shrink below, see `RestShape`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RestRadius(pub f32);
+
 impl Sphere {
     pub fn new(radius: f32) -> Self {
         Sphere { radius }
@@ -461,6 +937,32 @@ impl State for SphereState {
     }
 }
 
+/// Colors the sphere instead of looking its radius up in the `ColorRamp`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorComponent(pub Vec3);
+
+/// The surface of the sphere for the comp pass. Spheres without one use `Material::default`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    /// the index of refraction
+    pub n: f32,
+}
+
+impl Material {
+    /// The index of refraction of glass
+    pub const DEFAULT_N: f32 = 1.45;
+
+    pub fn new(n: f32) -> Self {
+        Self { n }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_N)
+    }
+}
+
 pub struct PositionComponent(pub Vec3);
 
 impl PositionComponent {
@@ -495,23 +997,351 @@ impl State for PositionState {
 pub struct SphereLimits {
     sphere_count: usize,
     frame_count: Option<usize>,
+    max_sphere_count: usize,
+    changed: ChangeEvent,
 }
 
 impl SphereLimits {
-    pub fn new(sphere_count: usize, frame_count: Option<usize>) -> Self {
-        Self {
+    /// Every node allocates per frame buffers sized by the sphere count, so an absurd count is
+    /// rejected before anything is allocated
+    pub const DEFAULT_MAX_SPHERE_COUNT: usize = 1 << 16;
+
+    pub fn new(sphere_count: usize, frame_count: Option<usize>) -> Result<Self, Error> {
+        Self::with_max_sphere_count(sphere_count, frame_count, Self::DEFAULT_MAX_SPHERE_COUNT)
+    }
+
+    pub fn with_max_sphere_count(
+        sphere_count: usize,
+        frame_count: Option<usize>,
+        max_sphere_count: usize,
+    ) -> Result<Self, Error> {
+        Self::check_sphere_count(sphere_count, max_sphere_count)?;
+
+        Ok(Self {
             sphere_count,
             frame_count,
+            max_sphere_count,
+            changed: ChangeEvent::new(),
+        })
+    }
+
+    fn check_sphere_count(sphere_count: usize, max_sphere_count: usize) -> Result<(), Error> {
+        if sphere_count > max_sphere_count {
+            bail!(
+                "{} spheres exceed the maximum of {} spheres",
+                sphere_count,
+                max_sphere_count
+            );
         }
+
+        Ok(())
     }
 
     pub fn sphere_count(&self) -> usize {
         self.sphere_count
     }
 
+    /// Changes the sphere count at runtime. The graph is rebuilt on the next frame, so the per
+    /// frame buffers of the nodes match the new count.
+    pub fn set_sphere_count(&mut self, sphere_count: usize) -> Result<(), Error> {
+        Self::check_sphere_count(sphere_count, self.max_sphere_count)?;
+
+        if self.sphere_count != sphere_count {
+            self.sphere_count = sphere_count;
+            self.changed.change();
+        }
+
+        Ok(())
+    }
+
+    pub fn max_sphere_count(&self) -> usize {
+        self.max_sphere_count
+    }
+
     pub fn frame_count(&self) -> Option<usize> {
         self.frame_count.clone()
     }
+
+    pub fn changed(&self) -> &ChangeEvent {
+        &self.changed
+    }
+}
+
+/// Creates the rigid body, the collider and the `DragSpring` of a sphere resting at `position`
+fn sphere_body(
+    body_set: &mut DefaultBodySet<f32>,
+    collider_set: &mut DefaultColliderSet<f32>,
+    force_generator_set: &mut DefaultForceGeneratorSet<f32>,
+    collisions: &Collisions,
+    spring_factor: f32,
+    damping: f32,
+    position: &PositionComponent,
+    radius: f32,
+) -> (
+    DefaultBodyPartHandleComponent,
+    DefaultColliderHandleComponent,
+    DefaultForceGeneratorHandleComponent,
+) {
+    let rigid_body = RigidBodyDesc::<f32>::new()
+        .translation(position.0.clone())
+        .gravity_enabled(true)
+        .status(BodyStatus::Dynamic)
+        .build();
+
+    let rigid_body_handle = BodyPartHandle(body_set.insert(rigid_body), 0);
+
+    let shape_handle = ShapeHandle::<f32>::new(Ball::new(radius));
+    let collider = ColliderDesc::new(shape_handle)
+        .density(2190.0)
+        .collision_groups(collisions.sphere_collision_groups())
+        .build(rigid_body_handle);
+
+    let collider_handle = collider_set.insert(collider);
+
+    let force_generator =
+        DragSpring::new(rigid_body_handle, position.0.clone(), spring_factor).with_damping(damping);
+
+    let force_generator_handle = force_generator_set.insert(Box::new(force_generator));
+
+    (
+        BodyPartHandleComponent(rigid_body_handle),
+        ColliderHandleComponent(collider_handle),
+        DefaultForceGeneratorHandleComponent(force_generator_handle),
+    )
+}
+
+fn update_physics_handles(world: &mut ResWorld, entities: &[Entity]) {
+    let mut body_set = world
+        .resources
+        .get_mut::<DefaultBodySet<f32>>()
+        .expect("body set was not inserted into world");
+
+    let mut collider_set = world
+        .resources
+        .get_mut::<DefaultColliderSet<f32>>()
+        .expect("body set was not inserted into world");
+
+    for entity in entities {
+        if let Some(rigid_body_handle) = world.get_component::<DefaultBodyHandle>(entity.clone()) {
+            if let Some(rigid_body) = body_set.rigid_body_mut(rigid_body_handle.deref().clone()) {
+                rigid_body.set_user_data(Some(Box::new(entity.clone())));
+            }
+        }
+
+        if let Some(collider_handle) = world.get_component::<DefaultColliderHandle>(entity.clone())
+        {
+            if let Some(collider) = collider_set.get_mut(collider_handle.deref().clone()) {
+                collider.set_user_data(Some(Box::new(entity.clone())));
+            }
+        }
+    }
+}
+
+/// How `add_sphere` builds a sphere at runtime, inserted by `SphereBundle` for the mode it set up
+#[derive(Clone, Copy)]
+pub struct SphereTemplate {
+    spheres: TemplateSpheres,
+    physics: bool,
+    spring_factor: Option<f32>,
+    damping: f32,
+    layout: Layout,
+}
+
+#[derive(Clone, Copy)]
+enum TemplateSpheres {
+    /// The loaded animation has no keyframes for new spheres, they keep `radius` in every frame
+    /// and with `LoadMode::PositionRadius` their layout position as well. `radius` is the one the
+    /// last loaded sphere starts with.
+    Load {
+        load_mode: LoadMode,
+        radius: Option<f32>,
+    },
+    Analyze(AnalysisParams),
+}
+
+/// The animation holding `state` in every frame, for spheres without loaded keyframes
+fn still_animation<S: State>(state: S) -> Animation<S, LoopEmpty, LerpFactorGenerator> {
+    Animation::with_times(
+        vec![Keyframe::new(Frame::new(0.0), state)],
+        LoopEmpty,
+        LerpFactorGenerator,
+    )
+}
+
+/// Adds a sphere to the running world and bumps the sphere count of `SphereLimits`, so the graph
+/// is rebuilt with buffers for it. The sphere is built like the ones of `SphereBundle` by the
+/// `SphereTemplate`. It takes the next index of the layout and in the analyze mode the band of
+/// that index, existing spheres keep their place and band. With physics it gets a rigid body, a
+/// collider and a `DragSpring`.
+pub fn add_sphere(world: &mut ResWorld) -> Result<Entity, Error> {
+    let template = *world
+        .resources
+        .get::<SphereTemplate>()
+        .expect("sphere template was not inserted into world");
+
+    let i = world
+        .resources
+        .get::<SphereLimits>()
+        .expect("limits was not inserted into world")
+        .sphere_count();
+    let sphere_count = i + 1;
+
+    let radius = match template.spheres {
+        TemplateSpheres::Load { radius, .. } => {
+            radius.ok_or(anyhow!("there is no loaded sphere to take the radius from"))?
+        }
+        TemplateSpheres::Analyze(analysis) => analysis.rest_radius(i, sphere_count),
+    };
+
+    world
+        .resources
+        .get_mut::<SphereLimits>()
+        .expect("limits was not inserted into world")
+        .set_sphere_count(sphere_count)?;
+
+    let sphere = Sphere::new(radius);
+    let position = PositionComponent(template.layout.position(i, sphere_count));
+
+    let entity = if template.physics {
+        let entity_data = {
+            let mut body_set = world
+                .resources
+                .get_mut::<DefaultBodySet<f32>>()
+                .expect("body set was not inserted into world");
+
+            let mut collider_set = world
+                .resources
+                .get_mut::<DefaultColliderSet<f32>>()
+                .expect("body set was not inserted into world");
+
+            let mut force_generator_set = world
+                .resources
+                .get_mut::<DefaultForceGeneratorSet<f32>>()
+                .expect("force generator set was not inserted into world");
+
+            let collisions = world
+                .resources
+                .get::<Collisions>()
+                .map(|collisions| *collisions)
+                .unwrap_or(Collisions(false));
+
+            let spring_factor = template
+                .spring_factor
+                .unwrap_or_else(|| collisions.spring_factor());
+
+            // the same jitter as the spheres of `SphereBundle`
+            let mut rng = thread_rng();
+            let position = PositionComponent(
+                position.0 + vec3(0.0, rng.gen_range(-0.05, 0.05), rng.gen_range(-0.05, 0.05)),
+            );
+
+            let (rigid_body, collider, force_generator) = sphere_body(
+                &mut body_set,
+                &mut collider_set,
+                &mut force_generator_set,
+                &collisions,
+                spring_factor,
+                template.damping,
+                &position,
+                radius,
+            );
+
+            (sphere, position, rigid_body, collider, force_generator)
+        };
+
+        let entity = world.insert((), vec![entity_data])[0];
+
+        update_physics_handles(world, &[entity]);
+
+        entity
+    } else {
+        // the loaded positions are animated, a new sphere holds its layout position
+        let position_animation = match template.spheres {
+            TemplateSpheres::Load {
+                load_mode: LoadMode::PositionRadius,
+                ..
+            } => Some(still_animation(PositionState(position.0))),
+            _ => None,
+        };
+
+        let entity = world.insert((), vec![(sphere, position)])[0];
+
+        if let Some(position_animation) = position_animation {
+            world
+                .add_component(entity, position_animation)
+                .map_err(|err| anyhow!("could not add the animation of a sphere: {:?}", err))?;
+        }
+
+        entity
+    };
+
+    let components = match template.spheres {
+        TemplateSpheres::Load { .. } => {
+            world.add_component(entity, still_animation(SphereState::new(radius)))
+        }
+        TemplateSpheres::Analyze(analysis) => {
+            let (_, filter, band, channel, analysis_value, rest_radius) =
+                analysis.components(i, sphere_count);
+
+            world
+                .add_component(entity, filter)
+                .and_then(|_| world.add_component(entity, band))
+                .and_then(|_| world.add_component(entity, channel))
+                .and_then(|_| world.add_component(entity, analysis_value))
+                .and_then(|_| world.add_component(entity, rest_radius))
+        }
+    };
+
+    components.map_err(|err| anyhow!("could not add the components of a sphere: {:?}", err))?;
+
+    Ok(entity)
+}
+
+/// Removes a sphere from the running world together with its rigid body, collider and
+/// `DragSpring`, and lowers the sphere count of `SphereLimits`
+pub fn remove_sphere(world: &mut ResWorld, entity: Entity) -> Result<(), Error> {
+    if world.get_component::<Sphere>(entity).is_none() {
+        bail!("the entity is not a sphere");
+    }
+
+    if let Some(force_generator) = world
+        .get_component::<DefaultForceGeneratorHandleComponent>(entity)
+        .map(|force_generator| force_generator.0)
+    {
+        if let Some(mut force_generator_set) =
+            world.resources.get_mut::<DefaultForceGeneratorSet<f32>>()
+        {
+            force_generator_set.remove(force_generator);
+        }
+    }
+
+    if let Some(collider) = world
+        .get_component::<DefaultColliderHandleComponent>(entity)
+        .map(|collider| collider.0)
+    {
+        if let Some(mut collider_set) = world.resources.get_mut::<DefaultColliderSet<f32>>() {
+            collider_set.remove(collider);
+        }
+    }
+
+    if let Some(rigid_body) = world
+        .get_component::<DefaultBodyPartHandleComponent>(entity)
+        .map(|rigid_body| rigid_body.0)
+    {
+        if let Some(mut body_set) = world.resources.get_mut::<DefaultBodySet<f32>>() {
+            body_set.remove(rigid_body.0);
+        }
+    }
+
+    world.delete(entity);
+
+    let mut limits = world
+        .resources
+        .get_mut::<SphereLimits>()
+        .expect("limits was not inserted into world");
+
+    let sphere_count = limits.sphere_count().saturating_sub(1);
+    limits.set_sphere_count(sphere_count)
 }
 
 pub fn sphere_animation_system_realtime<
@@ -563,29 +1393,88 @@ pub fn sphere_shape_system() -> Box<dyn Schedulable> {
         })
 }
 
-pub fn sphere_analyzer_system(min_size: f32) -> Box<dyn Schedulable> {
+/// Blends the radius of the last frame with the analysis `value`, `smoothing` is the fraction of
+/// the last radius kept. The rest radius still bounds the smoothed radius.
+fn smoothed_radius(radius: f32, value: f32, rest_radius: f32, smoothing: f32) -> f32 {
+    (smoothing * radius + (1.0 - smoothing) * value).max(rest_radius)
+}
+
+pub fn sphere_analyzer_system(smoothing: f32) -> Box<dyn Schedulable> {
     SystemBuilder::new("sphere_analyzer_system")
-        .with_query(<(Write<Sphere>, Write<DynFilter>)>::query())
+        .with_query(<(
+            Write<Sphere>,
+            Write<DynFilter>,
+            Write<AnalysisValue>,
+            Read<RestRadius>,
+            Read<AudioChannel>,
+        )>::query())
         .read_resource::<Arc<Mutex<SamplesResource>>>()
         .build(move |_, world, samples, query| {
             let mut samples = samples.lock().unwrap();
 
-            query.iter_mut(world).for_each(|(mut sphere, mut filter)| {
-                let mut value = sphere.radius;
-                for sample in samples.iter() {
-                    value = filter.tick(*sample) * 2.0;
+            query.iter_mut(world).for_each(
+                |(mut sphere, mut filter, mut analysis_value, rest_radius, channel)| {
+                    let mut value = sphere.radius;
+                    for sample in samples.channel(channel.0) {
+                        value = filter.tick(sample) * 2.0;
+                    }
+                    analysis_value.0 = value;
+                    sphere.radius = smoothed_radius(sphere.radius, value, rest_radius.0, smoothing)
+                },
+            );
+
+            samples.clear();
+        })
+}
+
+/// The fft counterpart of `sphere_analyzer_system`, with the same scale and rest radius
+pub fn sphere_fft_system(smoothing: f32) -> Box<dyn Schedulable> {
+    SystemBuilder::new("sphere_fft_system")
+        .with_query(<(
+            Write<Sphere>,
+            Write<AnalysisValue>,
+            Read<Band>,
+            Read<RestRadius>,
+            Read<AudioChannel>,
+        )>::query())
+        .read_resource::<Arc<Mutex<SamplesResource>>>()
+        .write_resource::<Vec<FftAnalyzer>>()
+        .build(move |_, world, (samples, analyzers), query| {
+            let mut samples = samples.lock().unwrap();
+
+            // one analyzer per channel the spheres are routed to
+            for (channel, analyzer) in analyzers.iter_mut().enumerate() {
+                for sample in samples.channel(channel) {
+                    analyzer.push(sample);
                 }
-                sphere.radius = value.max(min_size)
-            });
+
+                analyzer.analyze();
+            }
 
             samples.clear();
+
+            query.iter_mut(world).for_each(
+                |(mut sphere, mut analysis_value, band, rest_radius, channel)| {
+                    let analyzer = &analyzers[channel.0.min(analyzers.len() - 1)];
+                    let value = analyzer.band(band.low, band.high) * 2.0;
+                    analysis_value.0 = value;
+                    sphere.radius = smoothed_radius(sphere.radius, value, rest_radius.0, smoothing)
+                },
+            );
         })
 }
 
+/// The factor of the `DragSpring` of every sphere when the spheres do not collide
+pub const SPRING_FACTOR: f32 = 0.1;
+
+/// The factor of the `DragSpring` of every sphere when the spheres collide
+pub const COLLIDING_SPRING_FACTOR: f32 = 0.03;
+
 pub struct DragSpring<H: BodyHandle> {
     part: BodyPartHandle<H>,
     center: Vec3,
     factor: f32,
+    damping: f32,
 }
 
 impl<H: BodyHandle> DragSpring<H> {
@@ -594,8 +1483,17 @@ impl<H: BodyHandle> DragSpring<H> {
             part,
             center,
             factor,
+            damping: 1.0,
         }
     }
+
+    /// The fraction of the velocity cancelled every step. 1 cancels all of it, so the body moves
+    /// straight back to its center without overshooting. Values near 0 keep most of the velocity
+    /// and oscillate around the center for a long time.
+    pub fn with_damping(mut self, damping: f32) -> Self {
+        self.damping = damping;
+        self
+    }
 }
 
 impl<H: BodyHandle> ForceGenerator<f32, H> for DragSpring<H> {
@@ -611,7 +1509,7 @@ impl<H: BodyHandle> ForceGenerator<f32, H> for DragSpring<H> {
 
                 body.apply_force(
                     self.part.1,
-                    &Force3::linear(-velocity.linear),
+                    &Force3::linear(-velocity.linear * self.damping),
                     ForceType::VelocityChange,
                     false,
                 );
@@ -629,3 +1527,417 @@ impl<H: BodyHandle> ForceGenerator<f32, H> for DragSpring<H> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::PhysicsBundle;
+    use nphysics3d::algebra::Velocity3;
+
+    #[test]
+    fn sphere_counts_above_the_maximum_are_rejected() {
+        assert!(SphereLimits::with_max_sphere_count(5, None, 4).is_err());
+        assert!(SphereLimits::new(SphereLimits::DEFAULT_MAX_SPHERE_COUNT + 1, None).is_err());
+
+        let mut limits = SphereLimits::with_max_sphere_count(4, None, 4).unwrap();
+        assert!(limits.set_sphere_count(5).is_err());
+        assert_eq!(limits.sphere_count(), 4);
+    }
+
+    #[test]
+    fn changed_sphere_count_rebuilds_the_graph() {
+        let mut limits = SphereLimits::with_max_sphere_count(4, None, 4).unwrap();
+
+        // the graph creator registers like this and rebuilds once a change is reported
+        let mut state_id = limits.changed().register();
+
+        limits.set_sphere_count(4).unwrap();
+        assert!(!limits.changed().has_changed(&mut state_id));
+
+        limits.set_sphere_count(3).unwrap();
+        assert!(limits.changed().has_changed(&mut state_id));
+        assert!(!limits.changed().has_changed(&mut state_id));
+
+        // a rejected count keeps the graph as it is
+        assert!(limits.set_sphere_count(5).is_err());
+        assert!(!limits.changed().has_changed(&mut state_id));
+    }
+
+    #[test]
+    fn rest_radii_follow_the_rest_shape() {
+        let radii = |rest_shape: RestShape| {
+            (0..5)
+                .map(|band| rest_shape.radius(0.1, band, 5))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(radii(RestShape::Flat), vec![0.1; 5]);
+
+        let hump = radii(RestShape::Gaussian {
+            center: 0.5,
+            width: 0.25,
+            height: 1.0,
+        });
+
+        // the peak sits on the middle band and falls off symmetrically
+        assert_approx_eq!(hump[2], 1.1);
+        assert_approx_eq!(hump[1], hump[3]);
+        assert_approx_eq!(hump[0], hump[4]);
+        assert!(hump[0] < hump[1] && hump[1] < hump[2]);
+        assert_approx_eq!(hump[1], 0.1 + (-0.5f32).exp());
+        assert!(hump.iter().all(|radius| *radius > 0.1));
+
+        // silence keeps a sphere at its rest radius
+        assert_eq!(smoothed_radius(hump[2], 0.0, hump[2], 0.5), hump[2]);
+    }
+
+    #[test]
+    fn smoothing_blends_the_last_radius_with_the_analysis() {
+        // without smoothing the radius follows the analysis
+        assert_eq!(smoothed_radius(2.0, 1.0, 0.1, 0.0), 1.0);
+
+        assert_approx_eq!(smoothed_radius(2.0, 1.0, 0.1, 0.5), 1.5);
+        assert_approx_eq!(smoothed_radius(2.0, 1.0, 0.1, 0.75), 1.75);
+
+        // the rest radius still bounds the blend
+        assert_eq!(smoothed_radius(0.2, 0.0, 0.3, 0.5), 0.3);
+
+        // a constant analysis value is approached a bit every frame
+        let radius = (0..32).fold(0.1, |radius, _| smoothed_radius(radius, 1.0, 0.1, 0.8));
+        assert!(radius < 1.0 && radius > 0.99);
+    }
+
+    #[test]
+    fn layouts_place_the_spheres_apart_within_their_size() {
+        let positions = |layout: Layout, sphere_count| {
+            (0..sphere_count)
+                .map(|i| layout.position(i, sphere_count))
+                .collect::<Vec<_>>()
+        };
+
+        // the grid snakes through its rows
+        let grid = positions(Layout::Grid, 4);
+        assert_eq!(
+            grid,
+            vec![
+                vec3(-4.0, 4.0, 0.0),
+                vec3(4.0, 4.0, 0.0),
+                vec3(4.0, -4.0, 0.0),
+                vec3(-4.0, -4.0, 0.0),
+            ]
+        );
+
+        let circle = positions(Layout::Circle, 4);
+        assert_approx_eq!((circle[0] - vec3(-8.0, 0.0, 0.0)).norm(), 0.0);
+        assert_approx_eq!((circle[1] - vec3(0.0, 8.0, 0.0)).norm(), 0.0);
+
+        // the spiral starts in the center and ends on the outside after full turns
+        let spiral = positions(Layout::Spiral, 16);
+        assert_eq!(spiral[0], vec3(0.0, 0.0, 0.0));
+        assert_approx_eq!((spiral[15] - vec3(-8.0, 0.0, 0.0)).norm(), 0.0, 1e-4);
+
+        for layout in &[Layout::Line, Layout::Grid, Layout::Circle, Layout::Spiral] {
+            assert_eq!(positions(*layout, 1).len(), 1);
+
+            let positions = positions(*layout, 16);
+
+            for position in &positions {
+                assert!(position.x.abs() <= 8.0 + 1e-4 && position.y.abs() <= 8.0 + 1e-4);
+                assert_eq!(position.z, 0.0);
+            }
+
+            for (i, position) in positions.iter().enumerate() {
+                for other in &positions[i + 1..] {
+                    assert!((position - other).norm() > 0.5, "{:?} overlaps", layout);
+                }
+            }
+        }
+
+        assert_eq!(Layout::from_name("spiral"), Some(Layout::Spiral));
+        assert_eq!(Layout::from_name("square"), None);
+    }
+
+    #[test]
+    fn same_seed_jitters_the_layout_the_same_way() {
+        let jittered = |seed| positions(&analyzed_world(8, Layout::Line, true, seed));
+
+        assert_eq!(jittered(Some(1)), jittered(Some(1)));
+        assert_ne!(jittered(Some(1)), jittered(Some(2)));
+    }
+
+    /// The x velocity of a body moving along x at its center after one `DragSpring` step
+    fn damped_velocity(damping: f32) -> f32 {
+        let mut bodies = DefaultBodySet::<f32>::new();
+
+        let rigid_body = RigidBodyDesc::<f32>::new()
+            .velocity(Velocity3::linear(1.0, 0.0, 0.0))
+            .build();
+        let body_part_handle = BodyPartHandle(bodies.insert(rigid_body), 0);
+
+        let mut drag_spring = DragSpring::new(body_part_handle, vec3(0.0, 0.0, 0.0), SPRING_FACTOR)
+            .with_damping(damping);
+        drag_spring.apply(&IntegrationParameters::default(), &mut bodies);
+
+        bodies
+            .rigid_body(body_part_handle.0)
+            .unwrap()
+            .velocity()
+            .linear
+            .x
+    }
+
+    /// A world with `sphere_count` analyzed spheres placed by `layout`, simulated if `physics`
+    fn analyzed_world(
+        sphere_count: usize,
+        layout: Layout,
+        physics: bool,
+        seed: Option<u64>,
+    ) -> ResWorld {
+        let mut world = ResWorld::new(Resources::default(), Universe::new().create_world());
+
+        if physics {
+            PhysicsBundle::new(vec3(0.0, 0.0, 0.0), false)
+                .add_entities_and_resources(&mut world)
+                .unwrap();
+        }
+
+        SphereBundle::<PathBuf>::new(SphereBundleParams::Analyze {
+            sphere_count,
+            min_radius: 0.1,
+            rest_shape: RestShape::Flat,
+            analyzer: Analyzer::Filter,
+            band_split: BandSplit {
+                low: 20.0,
+                high: 20000.0,
+                multiplier: None,
+                overlap: 0.0,
+            },
+            stereo: false,
+            agc: None,
+            attack: 0.005,
+            release: 0.4,
+            threshold: 0.1,
+            smoothing: 0.0,
+            sample_rate: 44100.0,
+            analysis_log: None,
+            spring_factor: None,
+            damping: 1.0,
+            seed,
+            layout,
+        })
+        .with_physics(physics)
+        .add_entities_and_resources(&mut world)
+        .unwrap();
+
+        world
+    }
+
+    fn positions(world: &ResWorld) -> Vec<Vec3> {
+        <Read<PositionComponent>>::query()
+            .iter(&world.world)
+            .map(|position| position.0)
+            .collect()
+    }
+
+    fn body_count(world: &ResWorld) -> usize {
+        <Read<DefaultBodyPartHandleComponent>>::query()
+            .iter(&world.world)
+            .count()
+    }
+
+    #[test]
+    fn spheres_without_physics_rest_exactly_on_the_layout() {
+        let world = analyzed_world(8, Layout::Line, false, None);
+
+        let expected = (0..8)
+            .map(|i| Layout::Line.position(i, 8))
+            .collect::<Vec<_>>();
+
+        assert_eq!(positions(&world), expected);
+        assert_eq!(body_count(&world), 0);
+
+        // with physics every sphere gets a body and only a small jitter off the layout
+        let world = analyzed_world(8, Layout::Line, true, None);
+
+        assert_eq!(body_count(&world), 8);
+        for (position, expected) in positions(&world).iter().zip(&expected) {
+            assert_eq!(position.x, expected.x);
+            assert!((position - expected).norm() < 0.1);
+        }
+    }
+
+    fn sphere_count(world: &ResWorld) -> usize {
+        world
+            .resources
+            .get::<SphereLimits>()
+            .unwrap()
+            .sphere_count()
+    }
+
+    #[test]
+    fn added_spheres_are_analyzed_and_removed_with_their_bodies() {
+        let mut world = analyzed_world(3, Layout::Line, true, Some(0));
+        let mut state_id = world
+            .resources
+            .get::<SphereLimits>()
+            .unwrap()
+            .changed()
+            .register();
+
+        let entity = add_sphere(&mut world).unwrap();
+
+        assert_eq!(sphere_count(&world), 4);
+        assert_eq!(body_count(&world), 4);
+        assert!(world
+            .resources
+            .get::<SphereLimits>()
+            .unwrap()
+            .changed()
+            .has_changed(&mut state_id));
+
+        // the new sphere takes the next band and is analyzed like the others
+        let band = *world.get_component::<Band>(entity).unwrap();
+        let rest_radius = *world.get_component::<RestRadius>(entity).unwrap();
+        let template = *world.resources.get::<SphereTemplate>().unwrap();
+        let (_, _, expected_band, _, _, expected_rest_radius) = match template.spheres {
+            TemplateSpheres::Analyze(analysis) => analysis.components(3, 4),
+            TemplateSpheres::Load { .. } => panic!("the analyze mode inserts an analyze template"),
+        };
+        assert_eq!(band, expected_band);
+        assert_eq!(rest_radius, expected_rest_radius);
+        assert!(world.get_component::<DynFilter>(entity).is_some());
+
+        let rigid_body = world
+            .get_component::<DefaultBodyPartHandleComponent>(entity)
+            .unwrap()
+            .0;
+        let collider = world
+            .get_component::<DefaultColliderHandleComponent>(entity)
+            .unwrap()
+            .0;
+        let force_generator = world
+            .get_component::<DefaultForceGeneratorHandleComponent>(entity)
+            .unwrap()
+            .0;
+
+        let body_user_data = world
+            .resources
+            .get::<DefaultBodySet<f32>>()
+            .unwrap()
+            .rigid_body(rigid_body.0)
+            .and_then(|rigid_body| rigid_body.user_data())
+            .and_then(|user_data| user_data.downcast_ref::<Entity>())
+            .cloned();
+        assert_eq!(body_user_data, Some(entity));
+
+        remove_sphere(&mut world, entity).unwrap();
+
+        assert_eq!(sphere_count(&world), 3);
+        assert_eq!(body_count(&world), 3);
+        assert!(world.get_component::<Sphere>(entity).is_none());
+
+        // no physics handle of the sphere is left behind
+        assert!(world
+            .resources
+            .get::<DefaultBodySet<f32>>()
+            .unwrap()
+            .rigid_body(rigid_body.0)
+            .is_none());
+        assert!(world
+            .resources
+            .get::<DefaultColliderSet<f32>>()
+            .unwrap()
+            .get(collider)
+            .is_none());
+        assert!(world
+            .resources
+            .get::<DefaultForceGeneratorSet<f32>>()
+            .unwrap()
+            .get(force_generator)
+            .is_none());
+
+        assert!(remove_sphere(&mut world, entity).is_err());
+    }
+
+    #[test]
+    fn spheres_are_added_at_the_next_layout_position_up_to_the_maximum() {
+        let mut world = analyzed_world(3, Layout::Circle, false, None);
+
+        let entity = add_sphere(&mut world).unwrap();
+
+        let position = world.get_component::<PositionComponent>(entity).unwrap().0;
+        assert_eq!(position, Layout::Circle.position(3, 4));
+        assert_eq!(body_count(&world), 0);
+
+        world
+            .resources
+            .insert(SphereLimits::with_max_sphere_count(4, None, 4).unwrap());
+
+        assert!(add_sphere(&mut world).is_err());
+        assert_eq!(sphere_count(&world), 4);
+        assert_eq!(<Read<Sphere>>::query().iter(&world.world).count(), 4);
+    }
+
+    fn sphere_data(color: Option<[f32; 3]>, n: Option<f32>) -> SphereData {
+        SphereData {
+            radius: 1.0,
+            position: PositionData::from_vec3(&vec3(0.0, 0.0, 0.0)),
+            color,
+            n,
+        }
+    }
+
+    /// Adds one entity per sphere of `transposed_data` and gives it the loaded appearance
+    fn appearance_world(transposed_data: &[Vec<SphereData>]) -> (ResWorld, Vec<Entity>) {
+        let mut world = ResWorld::new(Resources::default(), Universe::new().create_world());
+
+        let entities = world
+            .insert(
+                (),
+                transposed_data
+                    .iter()
+                    .map(|_| (PositionComponent(vec3(0.0, 0.0, 0.0)),)),
+            )
+            .to_vec();
+
+        SphereBundle::<PathBuf>::add_appearance(&mut world, &entities, transposed_data).unwrap();
+
+        (world, entities)
+    }
+
+    #[test]
+    fn loaded_colors_override_the_color_ramp() {
+        let red = sphere_data(Some([1.0, 0.0, 0.0]), None);
+        let plain = sphere_data(None, None);
+
+        let (world, entities) =
+            appearance_world(&[vec![plain.clone(), red], vec![plain.clone(), plain]]);
+
+        // the first color given for a sphere is kept, spheres without one use the ramp
+        let color = world.get_component::<ColorComponent>(entities[0]).unwrap();
+        assert_eq!(*color, ColorComponent(vec3(1.0, 0.0, 0.0)));
+        assert!(world.get_component::<ColorComponent>(entities[1]).is_none());
+    }
+
+    #[test]
+    fn loaded_indices_of_refraction_become_materials() {
+        let water = sphere_data(None, Some(1.33));
+        let plain = sphere_data(None, None);
+
+        let (world, entities) =
+            appearance_world(&[vec![plain.clone(), water], vec![plain.clone(), plain]]);
+
+        let material = world.get_component::<Material>(entities[0]).unwrap();
+        assert_eq!(*material, Material::new(1.33));
+
+        // spheres without an index of refraction are rendered as glass
+        assert!(world.get_component::<Material>(entities[1]).is_none());
+        assert_eq!(Material::default().n, Material::DEFAULT_N);
+    }
+
+    #[test]
+    fn damping_below_one_keeps_some_velocity() {
+        assert_approx_eq!(damped_velocity(1.0), 0.0);
+        assert_approx_eq!(damped_velocity(0.25), 0.75);
+    }
+}
@@ -0,0 +1,73 @@
+/// The tessellation of the sphere mesh of the gbuffer pass. The mesh is built with the graph, so a
+/// change only shows after the next rebuild, e.g. after the window was resized.
+#[derive(Debug, Clone)]
+pub struct SphereMeshParams {
+    u_segments: usize,
+    v_segments: usize,
+}
+
+impl SphereMeshParams {
+    pub fn new() -> Self {
+        Self {
+            u_segments: 32,
+            v_segments: 16,
+        }
+    }
+
+    /// The segments around the equator, at least 3
+    pub fn with_u_segments(mut self, u_segments: usize) -> Self {
+        self.set_u_segments(u_segments);
+        self
+    }
+
+    /// The segments from pole to pole, at least 2
+    pub fn with_v_segments(mut self, v_segments: usize) -> Self {
+        self.set_v_segments(v_segments);
+        self
+    }
+
+    pub fn u_segments(&self) -> usize {
+        self.u_segments
+    }
+
+    pub fn set_u_segments(&mut self, u_segments: usize) {
+        self.u_segments = u_segments.max(3)
+    }
+
+    pub fn v_segments(&self) -> usize {
+        self.v_segments
+    }
+
+    pub fn set_v_segments(&mut self, v_segments: usize) {
+        self.v_segments = v_segments.max(2)
+    }
+}
+
+impl Default for SphereMeshParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genmesh::generators::{IndexedPolygon, SharedVertex, SphereUv};
+    use genmesh::Triangulate;
+
+    #[test]
+    fn segment_counts_are_clamped_to_a_closed_mesh() {
+        let params = SphereMeshParams::default();
+        assert_eq!((params.u_segments(), params.v_segments()), (32, 16));
+
+        let params = SphereMeshParams::new()
+            .with_u_segments(0)
+            .with_v_segments(1);
+        assert_eq!((params.u_segments(), params.v_segments()), (3, 2));
+
+        // the coarsest mesh is a double pyramid, a triangle fan around each pole
+        let sphere = SphereUv::new(params.u_segments(), params.v_segments());
+        assert_eq!(sphere.shared_vertex_count(), 3 + 2);
+        assert_eq!(sphere.indexed_polygon_iter().triangulate().count(), 2 * 3);
+    }
+}
@@ -1,15 +1,55 @@
 use crate::animation::Frame;
-use std::time::Instant;
+use crate::bundle::Bundle;
+use crate::world::ResWorld;
+use crate::Mode;
+use anyhow::Error;
+use std::time::{Duration, Instant};
 
+pub struct TimeBundle {
+    mode: Mode,
+    fps: f32,
+}
+
+impl TimeBundle {
+    pub fn new(mode: Mode, fps: f32) -> Self {
+        Self { mode, fps }
+    }
+}
+
+impl Bundle for TimeBundle {
+    type Phase1 = ();
+
+    fn add_entities_and_resources(self, world: &mut ResWorld) -> Result<Self::Phase1, Error> {
+        match self.mode {
+            Mode::Realtime => world.resources.insert(Time::new(self.fps)),
+            Mode::Headless => world.resources.insert(HeadlessTime::new(Frame::new(0.0))),
+        }
+
+        Ok(())
+    }
+}
+
+/// The time of the realtime mode. The playhead runs with the wall clock unless it is paused and
+/// can be moved by seeking.
 pub struct Time {
     start_time: Instant,
+    /// the playhead at `resume_time`
+    playhead: Duration,
+    /// when the playhead last started running, it runs from there on unless it is paused
+    resume_time: Instant,
+    paused: bool,
     fps: f32,
 }
 
 impl Time {
     pub fn new(fps: f32) -> Self {
+        let start_time = Instant::now();
+
         Self {
-            start_time: Instant::now(),
+            start_time,
+            playhead: Duration::from_secs(0),
+            resume_time: start_time,
+            paused: false,
             fps,
         }
     }
@@ -22,25 +62,153 @@ impl Time {
         self.fps
     }
 
+    pub fn playhead(&self) -> Duration {
+        if self.paused {
+            self.playhead
+        } else {
+            self.playhead + self.resume_time.elapsed()
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.playhead = self.playhead();
+        self.resume_time = Instant::now();
+        self.paused = paused;
+    }
+
+    pub fn toggle_paused(&mut self) {
+        self.set_paused(!self.paused)
+    }
+
+    /// Moves the playhead by `seconds`, backwards if negative, but not before the start
+    pub fn seek(&mut self, seconds: f32) {
+        let playhead = self.playhead().as_secs_f32() + seconds;
+
+        self.playhead = Duration::from_secs_f32(playhead.max(0.0));
+        self.resume_time = Instant::now();
+    }
+
     pub fn current_frame(&self) -> Frame {
-        Frame::from_duration(&self.start_time.elapsed(), self.fps)
+        Frame::from_duration(&self.playhead(), self.fps)
     }
 }
 
 pub struct HeadlessTime {
     current_frame: Frame,
+    total: Option<usize>,
 }
 
 impl HeadlessTime {
     pub fn new(current_frame: Frame) -> Self {
-        Self { current_frame }
+        Self {
+            current_frame,
+            total: None,
+        }
+    }
+
+    pub fn with_total(mut self, total: usize) -> Self {
+        self.total = Some(total);
+        self
     }
 
     pub fn current_frame(&self) -> Frame {
         self.current_frame.clone()
     }
 
+    pub fn total(&self) -> Option<usize> {
+        self.total
+    }
+
+    pub fn set_total(&mut self, total: Option<usize>) {
+        self.total = total;
+        self.seek(self.current_frame.clone());
+    }
+
     pub fn set(&mut self, current_frame: Frame) {
         self.current_frame = current_frame
     }
+
+    /// Sets the current frame clamped to `[0, total)`
+    pub fn seek(&mut self, frame: Frame) {
+        let mut frame = frame.frame().max(0.0);
+
+        if let Some(total) = self.total {
+            frame = frame.min(total.saturating_sub(1) as f32);
+        }
+
+        self.current_frame = Frame::new(frame);
+    }
+
+    /// The current frame relative to the total length, or `None` if the length is unknown
+    pub fn progress(&self) -> Option<f32> {
+        self.total
+            .filter(|total| *total > 0)
+            .map(|total| self.current_frame.frame() / total as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_clamps_to_the_total() {
+        let mut time = HeadlessTime::new(Frame::new(0.0)).with_total(100);
+
+        time.seek(Frame::new(42.0));
+        assert_eq!(time.current_frame().frame(), 42.0);
+
+        time.seek(Frame::new(-5.0));
+        assert_eq!(time.current_frame().frame(), 0.0);
+
+        time.seek(Frame::new(100.0));
+        assert_eq!(time.current_frame().frame(), 99.0);
+
+        // shrinking the total moves the current frame back inside it
+        time.set_total(Some(50));
+        assert_eq!(time.current_frame().frame(), 49.0);
+
+        time.set_total(None);
+        time.seek(Frame::new(1000.0));
+        assert_eq!(time.current_frame().frame(), 1000.0);
+    }
+
+    #[test]
+    fn progress() {
+        let mut time = HeadlessTime::new(Frame::new(25.0));
+        assert_eq!(time.progress(), None);
+
+        time.set_total(Some(100));
+        assert_eq!(time.progress(), Some(0.25));
+
+        time.seek(Frame::new(0.0));
+        assert_eq!(time.progress(), Some(0.0));
+
+        time.set_total(Some(0));
+        assert_eq!(time.progress(), None);
+    }
+
+    #[test]
+    fn paused_playhead_only_moves_by_seeking() {
+        let mut time = Time::new(60.0);
+
+        time.set_paused(true);
+        let playhead = time.playhead();
+        assert_eq!(time.playhead(), playhead);
+
+        time.seek(5.0);
+        assert_approx_eq!(time.playhead().as_secs_f32(), playhead.as_secs_f32() + 5.0);
+
+        // seeking back stops at the start
+        time.seek(-10.0);
+        assert_eq!(time.playhead(), Duration::from_secs(0));
+
+        time.toggle_paused();
+        assert!(!time.paused());
+        assert!(time.playhead() >= Duration::from_secs(0));
+    }
 }
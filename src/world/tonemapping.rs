@@ -0,0 +1,68 @@
+/// How the comp pass maps the unbounded shaded colors into the displayable range. The values
+/// match `TONEMAP_*` in the shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapMode {
+    /// Clips everything above 1
+    None = 0,
+    /// `color / (1 + color)`, never clips but desaturates the highlights
+    Reinhard = 1,
+    /// The filmic curve of the ACES reference rendering, approximated after Narkowicz
+    Aces = 2,
+}
+
+impl ToneMapMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(ToneMapMode::None),
+            "reinhard" => Some(ToneMapMode::Reinhard),
+            "aces" => Some(ToneMapMode::Aces),
+            _ => None,
+        }
+    }
+}
+
+/// The exposure and the tone mapping the comp pass applies to the final color before it is
+/// encoded to srgb. Float outputs, e.g. the exr capture, get the exposure but not the tone
+/// mapping, so they keep the full range.
+#[derive(Debug, Clone)]
+pub struct Tonemapping {
+    exposure: f32,
+    mode: ToneMapMode,
+}
+
+impl Tonemapping {
+    pub fn new(mode: ToneMapMode) -> Self {
+        Self {
+            exposure: 1.0,
+            mode,
+        }
+    }
+
+    /// the factor the color is multiplied by before it is tone mapped
+    pub fn with_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure
+    }
+
+    pub fn mode(&self) -> ToneMapMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ToneMapMode) {
+        self.mode = mode
+    }
+}
+
+impl Default for Tonemapping {
+    fn default() -> Self {
+        Self::new(ToneMapMode::None)
+    }
+}